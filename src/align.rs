@@ -0,0 +1,129 @@
+#[cfg(feature = "embedding-alignment")]
+pub mod embedding;
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Extra cost of pairing several sentences at once, so that one-to-one pairs win unless lengths disagree
+const MERGE_PENALTY: f64 = 1.0;
+
+/// Consecutive source sentences along with the translated sentences matching them
+pub type AlignedPair<'a> = (Vec<&'a str>, Vec<&'a str>);
+
+/// Pairs up sentences of a source text and its translation, for the features that need to know
+/// which translated sentence is which, e.g. bilingual output.
+pub trait Aligner: Send + Sync {
+    /// Every sentence ends up in exactly one pair, in order. None if the sentences can't be aligned,
+    /// e.g. if one side has many more of them than the other.
+    fn align<'a>(&self, src: &[&'a str], dst: &[&'a str]) -> Option<Vec<AlignedPair<'a>>>;
+}
+
+/// Alignment backend chosen in `alignment.backend`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlignerKind {
+    #[default]
+    Heuristic,
+    /// Needs the `embedding-alignment` build feature, which brings a local embedding model in
+    Embedding,
+}
+
+impl AlignerKind {
+    /// Falls back to the heuristic aligner if the chosen one is unavailable
+    pub fn build(&self) -> Box<dyn Aligner> {
+        match self {
+            AlignerKind::Heuristic => Box::new(HeuristicAligner),
+            #[cfg(feature = "embedding-alignment")]
+            AlignerKind::Embedding => match embedding::EmbeddingAligner::new() {
+                Ok(aligner) => Box::new(aligner),
+                Err(e) => {
+                    log::warn!("Embedding model couldn't be loaded, aligning sentences by length: {e:#}");
+                    Box::new(HeuristicAligner)
+                }
+            },
+            #[cfg(not(feature = "embedding-alignment"))]
+            AlignerKind::Embedding => {
+                log::warn!("Built without the embedding-alignment feature, aligning sentences by length");
+                Box::new(HeuristicAligner)
+            }
+        }
+    }
+}
+
+/// Aligns sentences by their lengths, translated sentences being about as long relative to the source ones
+/// as the whole translation is relative to the whole source. Fast and good enough for most translations.
+pub struct HeuristicAligner;
+
+impl Aligner for HeuristicAligner {
+    fn align<'a>(&self, src: &[&'a str], dst: &[&'a str]) -> Option<Vec<AlignedPair<'a>>> {
+        let len = |sentences: &[&str]| sentences.iter().map(|s| s.chars().count()).sum::<usize>() as f64 + 1.0;
+        let ratio = len(dst) / len(src);
+        align_by_cost(src, dst, |src_range, dst_range| {
+            (len(&dst[dst_range]) / (len(&src[src_range]) * ratio)).ln().abs()
+        })
+    }
+}
+
+/// Pairs sentences one-to-one, one-to-two or two-to-one, picking the pairing with the least total cost
+/// of its pairs, as given by `cost` for the ranges of source and translated sentences.
+pub(crate) fn align_by_cost<'a>(
+    src: &[&'a str],
+    dst: &[&'a str],
+    cost: impl Fn(Range<usize>, Range<usize>) -> f64,
+) -> Option<Vec<AlignedPair<'a>>> {
+    const STEPS: [(usize, usize); 3] = [(1, 1), (1, 2), (2, 1)];
+    // Least cost of aligning the first i source and j translated sentences, along with the last step taken
+    let mut best = vec![vec![None::<(f64, (usize, usize))>; dst.len() + 1]; src.len() + 1];
+    best[0][0] = Some((0.0, (0, 0)));
+    for i in 0..=src.len() {
+        for j in 0..=dst.len() {
+            for (di, dj) in STEPS {
+                if i < di || j < dj {
+                    continue;
+                }
+                let Some((prev, _)) = best[i - di][j - dj] else {
+                    continue;
+                };
+                let penalty = if (di, dj) == (1, 1) { 0.0 } else { MERGE_PENALTY };
+                let total = prev + cost(i - di..i, j - dj..j) + penalty;
+                if best[i][j].is_none_or(|(current, _)| total < current) {
+                    best[i][j] = Some((total, (di, dj)));
+                }
+            }
+        }
+    }
+
+    // Unreachable if the counts are too far apart for the steps
+    best[src.len()][dst.len()]?;
+    let mut pairs = vec![];
+    let (mut i, mut j) = (src.len(), dst.len());
+    while i > 0 || j > 0 {
+        let (_, (di, dj)) = best[i][j].expect("reachable cell");
+        pairs.push((src[i - di..i].to_vec(), dst[j - dj..j].to_vec()));
+        (i, j) = (i - di, j - dj);
+    }
+    pairs.reverse();
+    Some(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_by_length() {
+        let src = ["Hello there.", "This is a rather long sentence that got split.", "Bye."];
+        let dst = ["Привет.", "Это довольно длинное предложение.", "Его разбили на два.", "Пока."];
+        assert_eq!(
+            HeuristicAligner.align(&src, &dst),
+            Some(vec![
+                (vec![src[0]], vec![dst[0]]),
+                (vec![src[1]], vec![dst[1], dst[2]]),
+                (vec![src[2]], vec![dst[3]]),
+            ])
+        );
+
+        assert_eq!(HeuristicAligner.align(&["One.", "Two."], &["Раз.", "Два."]).map(|pairs| pairs.len()), Some(2));
+        assert_eq!(HeuristicAligner.align(&["One.", "Two.", "Three.", "Four."], &["Всё."]), None);
+    }
+}
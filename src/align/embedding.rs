@@ -0,0 +1,44 @@
+use super::{align_by_cost, AlignedPair, Aligner, HeuristicAligner};
+
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+
+/// Aligns sentences by their meaning, with a local multilingual embedding model, which copes with translations
+/// whose sentence lengths differ a lot from the source ones. Model is downloaded on first use.
+pub struct EmbeddingAligner {
+    model: TextEmbedding,
+}
+
+impl EmbeddingAligner {
+    pub fn new() -> anyhow::Result<Self> {
+        let model = TextEmbedding::try_new(InitOptions::new(EmbeddingModel::MultilingualE5Small))?;
+        Ok(EmbeddingAligner { model })
+    }
+}
+
+impl Aligner for EmbeddingAligner {
+    fn align<'a>(&self, src: &[&'a str], dst: &[&'a str]) -> Option<Vec<AlignedPair<'a>>> {
+        let embeddings = match self.model.embed(src.iter().chain(dst).collect(), None) {
+            Ok(embeddings) => embeddings,
+            Err(e) => {
+                log::warn!("Sentences couldn't be embedded, aligning them by length: {e:#}");
+                return HeuristicAligner.align(src, dst);
+            }
+        };
+        let (src_embeddings, dst_embeddings) = embeddings.split_at(src.len());
+        // Sentences of a group mean together about what the sum of their embeddings does
+        let sum = |embeddings: &[Vec<f32>]| {
+            embeddings.iter().fold(vec![0.0; embeddings[0].len()], |acc, e| {
+                acc.iter().zip(e).map(|(a, b)| a + b).collect()
+            })
+        };
+        align_by_cost(src, dst, |src_range, dst_range| {
+            1.0 - cosine_similarity(&sum(&src_embeddings[src_range]), &sum(&dst_embeddings[dst_range]))
+        })
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    let dot = a.iter().zip(b).map(|(a, b)| (a * b) as f64).sum::<f64>();
+    let norm = |v: &[f32]| v.iter().map(|x| (x * x) as f64).sum::<f64>().sqrt();
+    dot / (norm(a) * norm(b)).max(f64::EPSILON)
+}
@@ -0,0 +1,140 @@
+use crate::manifest::RunManifest;
+use crate::review::{export_review, ReviewFormat};
+use crate::{TranslationConfig, TranslationError};
+
+use anyhow::anyhow;
+use config::Config;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+/// Directory of the archive the translated document goes to, so that its name can't clash with the other files
+const DOCUMENT_DIR: &str = "translation";
+
+/// Describes the bundle, stored in it as `bundle.json`
+#[derive(Debug, Serialize)]
+struct BundleManifest {
+    rosetta_version: String,
+    created_at: String,
+    files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct BundleFile {
+    name: String,
+    size: usize,
+    sha256: String,
+}
+
+/// Where the bundle of the given output goes, e.g. `book.bundle.zip` for `book.epub`
+pub fn bundle_path(output: &Path) -> PathBuf {
+    output.with_extension("bundle.zip")
+}
+
+/// Packs the translated document, a bilingual review table and the run manifest into a single archive,
+/// along with checksums of them, to be handed to a client. Files of the archive and the archive itself
+/// are made read-only, so that the delivered translation isn't edited by accident.
+pub async fn make_bundle(
+    settings: &Config,
+    input: &Path,
+    output: &Path,
+    cfg: &TranslationConfig,
+) -> Result<PathBuf, TranslationError> {
+    let review_dir = tempfile::tempdir()?;
+    let review_path = review_dir.path().join("review.md");
+    export_review(settings, input, output, cfg, &review_path, ReviewFormat::Markdown).await?;
+
+    let output_name = output.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut files = vec![
+        (format!("{DOCUMENT_DIR}/{output_name}"), std::fs::read(output)?),
+        ("review.md".to_owned(), std::fs::read(&review_path)?),
+    ];
+    let manifest_path = RunManifest::path_for(output);
+    if manifest_path.exists() {
+        files.push(("run.json".to_owned(), std::fs::read(&manifest_path)?));
+    }
+
+    let path = bundle_path(output);
+    write_bundle(&path, files)?;
+    Ok(path)
+}
+
+/// Writes a read-only archive of the files, along with their checksums in `bundle.json`
+fn write_bundle(path: &Path, mut files: Vec<(String, Vec<u8>)>) -> Result<(), TranslationError> {
+    let manifest = BundleManifest {
+        rosetta_version: env!("CARGO_PKG_VERSION").to_owned(),
+        created_at: chrono::Local::now().to_rfc3339(),
+        files: files
+            .iter()
+            .map(|(name, content)| BundleFile {
+                name: name.clone(),
+                size: content.len(),
+                sha256: format!("{:x}", Sha256::digest(content)),
+            })
+            .collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| TranslationError::OtherError(e.into()))?;
+    files.push(("bundle.json".to_owned(), manifest));
+
+    // Bundle of a previous run is read-only too, it's only writable until it's overwritten
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let mut permissions = metadata.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    let zip_error = |e: zip::result::ZipError| TranslationError::OtherError(anyhow!("Couldn't write the bundle: {e}"));
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o444);
+    for (name, content) in files {
+        zip.start_file(name, options).map_err(zip_error)?;
+        zip.write_all(&content)?;
+    }
+    zip.finish().map_err(zip_error)?;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use std::io::Read;
+
+    #[test]
+    fn document_named_like_bundle_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("review.bundle.zip");
+        let files = vec![
+            (format!("{DOCUMENT_DIR}/review.md"), b"# Translated".to_vec()),
+            ("review.md".to_owned(), b"| # | English | Russian | Status |".to_vec()),
+            ("run.json".to_owned(), b"{}".to_vec()),
+        ];
+        write_bundle(&path, files).unwrap();
+
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let names = zip.file_names().map(str::to_owned).sorted().collect_vec();
+        assert_eq!(names, ["bundle.json", "review.md", "run.json", "translation/review.md"]);
+
+        let mut read = |name: &str| {
+            let mut content = vec![];
+            zip.by_name(name).unwrap().read_to_end(&mut content).unwrap();
+            content
+        };
+        let manifest = serde_json::from_slice::<serde_json::Value>(&read("bundle.json")).unwrap();
+        let listed = manifest["files"].as_array().unwrap();
+        assert_eq!(listed.len(), 3);
+        for file in listed {
+            let content = read(file["name"].as_str().unwrap());
+            assert_eq!(file["size"], content.len());
+            assert_eq!(file["sha256"].as_str().unwrap(), format!("{:x}", Sha256::digest(&content)));
+        }
+    }
+}
@@ -1,19 +1,155 @@
+use crate::llm::ProxyConfig;
 use crate::parser::MarkdownSubsection;
 use crate::TranslationError;
-use rusqlite::Connection;
+use crate::utils::log_preview;
+use anyhow::anyhow;
+use reqwest::StatusCode;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub type CachedValues = HashMap<MarkdownSubsection, MarkdownSubsection>;
 
+type Migration = fn(&Connection) -> Result<(), TranslationError>;
+
+/// Forward migrations of the local cache, n-th one brings the schema from version n to n + 1.
+/// Schema version is kept in SQLite `user_version`, which is 0 for caches created before it was tracked.
+const MIGRATIONS: &[Migration] = &[
+    add_timestamps_if_missing,
+    add_runs_table,
+];
+
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub trait CacheBuilder {
+    type Built: Cache;
+
+    /// Opens a cache for the given language pair. `db_path` is the local database to be used.
+    async fn build(&self, db_path: &Path, src_lang: &str, dst_lang: &str) -> Result<Self::Built, TranslationError>;
+}
+
+/// Translation memory, mapping source subsections to their translations.
+pub trait Cache {
+    async fn get(
+        &mut self,
+        src: &MarkdownSubsection,
+    ) -> Result<Option<MarkdownSubsection>, TranslationError>;
+
+    /// Inserts a new cache entry unless it's a duplicate.
+    async fn insert(
+        &mut self,
+        src: MarkdownSubsection,
+        dst: MarkdownSubsection,
+    ) -> Result<(), TranslationError>;
+
+    /// Inserts a new cache entry or replaces the translation of an existing one.
+    async fn upsert(
+        &mut self,
+        src: MarkdownSubsection,
+        dst: MarkdownSubsection,
+    ) -> Result<(), TranslationError>;
+}
+
+/// Limits on local cache growth, entries used least recently are pruned first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheLimits {
+    pub max_size_mb: Option<u64>,
+    pub max_age_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompactStats {
+    pub pruned_entries: usize,
+    pub reclaimed_bytes: u64,
+}
+
+/// Contents of a local cache as found, e.g. of one sent along with a bug report.
+#[derive(Debug, Clone)]
+pub struct CacheInspection {
+    pub schema_version: u32,
+    pub entries: Vec<InspectedEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InspectedEntry {
+    pub src_lang: String,
+    pub dst_lang: String,
+    pub src: String,
+    pub dst: String,
+    /// Unix time, 0 if unknown
+    pub created_at: i64,
+    /// Unix time, 0 if unknown
+    pub last_used: i64,
+}
+
+impl CacheInspection {
+    /// Language pairs along with their number of entries
+    pub fn language_pairs(&self) -> Vec<(&str, &str, usize)> {
+        let mut pairs: Vec<(&str, &str, usize)> = vec![];
+        for entry in self.entries.iter() {
+            match pairs.iter_mut().find(|(src, dst, _)| *src == entry.src_lang && *dst == entry.dst_lang) {
+                Some((_, _, count)) => *count += 1,
+                None => pairs.push((&entry.src_lang, &entry.dst_lang, 1)),
+            }
+        }
+        pairs
+    }
+}
+
+/// Reads a local cache without touching it: no migrations, no usage tracking.
+/// Caches of any schema version are read, as long as they have the translations table.
+pub fn inspect(db_path: &Path) -> Result<CacheInspection, TranslationError> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let schema_version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0))?;
+    let has_timestamps = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('translated') WHERE name = 'last_used'",
+        (),
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    let query = match has_timestamps {
+        true => "SELECT src_lang_lc, dst_lang_lc, src_section, dst_section, created_at, last_used FROM translated ORDER BY id",
+        false => "SELECT src_lang_lc, dst_lang_lc, src_section, dst_section, 0, 0 FROM translated ORDER BY id",
+    };
+    let mut stmt = conn.prepare(query)?;
+    let entries = stmt
+        .query_map((), |row| {
+            Ok(InspectedEntry {
+                src_lang: row.get(0)?,
+                dst_lang: row.get(1)?,
+                src: row.get(2)?,
+                dst: row.get(3)?,
+                created_at: row.get(4)?,
+                last_used: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CacheInspection { schema_version, entries })
+}
+
+pub struct SqliteCacheBuilder {
+    pub limits: CacheLimits,
+}
+
+impl CacheBuilder for SqliteCacheBuilder {
+    type Built = SqliteCache;
+
+    async fn build(&self, db_path: &Path, src_lang: &str, dst_lang: &str) -> Result<Self::Built, TranslationError> {
+        let mut cache = SqliteCache::new(db_path, src_lang, dst_lang)?;
+        cache.prune(self.limits)?;
+        Ok(cache)
+    }
+}
+
 /// Caches translations in a SQLite database.
-pub struct Cache {
+pub struct SqliteCache {
     conn: Connection,
     src_lang_lc: String,
     dst_lang_lc: String,
 }
 
-impl Cache {
+impl SqliteCache {
     pub fn new(db_path: &Path, src_lang: &str, dst_lang: &str) -> Result<Self, TranslationError> {
         let is_new = !db_path.exists();
 
@@ -26,11 +162,17 @@ impl Cache {
                     src_section  TEXT NOT NULL,
                     dst_section  TEXT NOT NULL,
                     src_lang_lc  TEXT NOT NULL,
-                    dst_lang_lc  TEXT NOT NULL
+                    dst_lang_lc  TEXT NOT NULL,
+                    created_at   INTEGER NOT NULL DEFAULT 0,
+                    last_used    INTEGER NOT NULL DEFAULT 0
                 )",
                 (),
             )?;
-        };
+            add_runs_table(&conn)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        } else {
+            Self::migrate(&conn, db_path)?;
+        }
         Ok(Self {
             conn,
             src_lang_lc: src_lang.trim().to_lowercase(),
@@ -38,8 +180,129 @@ impl Cache {
         })
     }
 
-    pub fn get(
-        &self,
+    /// Brings a cache created by an older version up to date, backing it up first.
+    fn migrate(conn: &Connection, db_path: &Path) -> Result<(), TranslationError> {
+        let version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(TranslationError::CacheSchemaTooNew { found: version, supported: SCHEMA_VERSION });
+        }
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        let backup_path = Self::backup_path(db_path, version);
+        log::info!(
+            "Migrating cache from schema version {} to {}, backup is saved to {}",
+            version,
+            SCHEMA_VERSION,
+            backup_path.display()
+        );
+        conn.execute("VACUUM INTO ?", [backup_path.display().to_string()])?;
+
+        conn.execute_batch("BEGIN")?;
+        let result = MIGRATIONS[version as usize..]
+            .iter()
+            .try_for_each(|migration| migration(conn));
+        if result.is_err() {
+            conn.execute_batch("ROLLBACK")?;
+            return result;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    /// E.g. `book.v0.bak.sqlite` for `book.sqlite` of schema version 0, existing backup is not overwritten
+    fn backup_path(db_path: &Path, version: u32) -> PathBuf {
+        let stem = db_path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut backup_path = db_path.with_file_name(format!("{stem}.v{version}.bak.sqlite"));
+        let mut n = 1;
+        while backup_path.exists() {
+            backup_path = db_path.with_file_name(format!("{stem}.v{version}.bak{n}.sqlite"));
+            n += 1;
+        }
+        backup_path
+    }
+
+    /// Keeps a snapshot of the setup of a run writing into this cache
+    pub fn record_run(&self, snapshot: &str) -> Result<(), TranslationError> {
+        self.conn.execute("INSERT INTO runs (started_at, snapshot) VALUES (unixepoch(), ?)", [snapshot])?;
+        Ok(())
+    }
+
+    /// Snapshot of the last run recorded by [SqliteCache::record_run]
+    pub fn last_run(&self) -> Result<Option<String>, TranslationError> {
+        let mut stmt = self.conn.prepare("SELECT snapshot FROM runs ORDER BY id DESC LIMIT 1")?;
+        let mut rows = stmt.query(())?;
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    /// Removes entries not used for too long, then least recently used entries until the size limit is met.
+    /// Returns the number of removed entries.
+    pub fn prune(&mut self, limits: CacheLimits) -> Result<usize, TranslationError> {
+        const BATCH_SIZE: usize = 100;
+
+        let mut pruned = 0;
+        if let Some(max_age_days) = limits.max_age_days {
+            pruned += self.conn.execute(
+                "DELETE FROM translated WHERE last_used < unixepoch() - ? * 86400",
+                [max_age_days],
+            )?;
+        }
+        if let Some(max_size_mb) = limits.max_size_mb {
+            let max_size = max_size_mb * 1024 * 1024;
+            while self.data_size()? > max_size {
+                pruned += self.conn.execute(
+                    "DELETE FROM translated WHERE id IN (
+                        SELECT id FROM translated ORDER BY last_used, id LIMIT ?
+                    )",
+                    [BATCH_SIZE],
+                )?;
+            }
+        }
+        if pruned > 0 {
+            log::info!("Pruned {} cache entries", pruned);
+        }
+        Ok(pruned)
+    }
+
+    /// Prunes the cache and rebuilds the database file to reclaim the freed space.
+    pub fn compact(&mut self, limits: CacheLimits) -> Result<CompactStats, TranslationError> {
+        let size_before = self.file_size()?;
+        let pruned_entries = self.prune(limits)?;
+        self.conn.execute("VACUUM", ())?;
+        let size_after = self.file_size()?;
+        Ok(CompactStats {
+            pruned_entries,
+            reclaimed_bytes: size_before.saturating_sub(size_after),
+        })
+    }
+
+    /// Size of the cached texts, which is what the database size is dominated by
+    fn data_size(&self) -> Result<u64, TranslationError> {
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(SUM(LENGTH(CAST(src_section AS BLOB)) + LENGTH(CAST(dst_section AS BLOB))), 0)
+            FROM translated",
+            (),
+            |row| row.get::<_, i64>(0),
+        )? as u64)
+    }
+
+    fn file_size(&self) -> Result<u64, TranslationError> {
+        Ok(self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            (),
+            |row| row.get::<_, i64>(0),
+        )? as u64)
+    }
+}
+
+impl Cache for SqliteCache {
+    async fn get(
+        &mut self,
         src: &MarkdownSubsection,
     ) -> Result<Option<MarkdownSubsection>, TranslationError> {
         let query_res = self.conn.query_row(
@@ -53,25 +316,408 @@ impl Cache {
         );
 
         match query_res {
-            Ok(dst) => Ok(Some(MarkdownSubsection(dst))),
+            Ok(dst) => {
+                self.conn.execute(
+                    "UPDATE translated
+                    SET last_used = unixepoch()
+                    WHERE src_section = ?
+                      AND src_lang_lc = ?
+                      AND dst_lang_lc = ?",
+                    [&src.0, &self.src_lang_lc, &self.dst_lang_lc],
+                )?;
+                Ok(Some(MarkdownSubsection(dst)))
+            }
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(TranslationError::DatabaseError(e)),
         }
     }
 
-    /// Inserts a new cache entry unless it's a duplicate.
-    pub fn insert(
+    async fn insert(
         &mut self,
         src: MarkdownSubsection,
         dst: MarkdownSubsection,
     ) -> Result<(), TranslationError> {
-        if self.get(&src)?.is_none() {
+        if self.get(&src).await?.is_none() {
             self.conn.execute(
-                "INSERT INTO translated (src_section, dst_section, src_lang_lc, dst_lang_lc)
-                VALUES (?, ?, ?, ?)",
+                "INSERT INTO translated (src_section, dst_section, src_lang_lc, dst_lang_lc, created_at, last_used)
+                VALUES (?, ?, ?, ?, unixepoch(), unixepoch())",
                 [&src.0, &dst.0, &self.src_lang_lc, &self.dst_lang_lc],
             )?;
         }
         Ok(())
     }
+
+    async fn upsert(
+        &mut self,
+        src: MarkdownSubsection,
+        dst: MarkdownSubsection,
+    ) -> Result<(), TranslationError> {
+        let updated = self.conn.execute(
+            "UPDATE translated
+            SET dst_section = ?
+            WHERE src_section = ?
+              AND src_lang_lc = ?
+              AND dst_lang_lc = ?",
+            [&dst.0, &src.0, &self.src_lang_lc, &self.dst_lang_lc],
+        )?;
+        if updated == 0 {
+            self.insert(src, dst).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Caches created before usage was tracked have no timestamps, treat all entries as fresh.
+/// Such caches predate schema versions as well, so whether the columns exist has to be checked.
+fn add_timestamps_if_missing(conn: &Connection) -> Result<(), TranslationError> {
+    let has_timestamps = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('translated') WHERE name = 'last_used'",
+        (),
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    if !has_timestamps {
+        conn.execute_batch(
+            "ALTER TABLE translated ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE translated ADD COLUMN last_used INTEGER NOT NULL DEFAULT 0;
+            UPDATE translated SET created_at = unixepoch(), last_used = unixepoch();",
+        )?;
+    }
+    Ok(())
+}
+
+fn add_runs_table(conn: &Connection) -> Result<(), TranslationError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at  INTEGER NOT NULL,
+            snapshot    TEXT NOT NULL
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Remote cache requests not done by then are given up on, if `cache.remote_timeout_secs` isn't set
+pub const DEFAULT_REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct RemoteCacheBuilder {
+    pub base_url: String,
+    pub token: Option<String>,
+    pub limits: CacheLimits,
+    /// Once a request times out, the local cache is used alone for the rest of the run
+    pub request_timeout: Option<Duration>,
+    pub proxy: Option<ProxyConfig>,
+}
+
+impl CacheBuilder for RemoteCacheBuilder {
+    type Built = RemoteCache;
+
+    async fn build(&self, db_path: &Path, src_lang: &str, dst_lang: &str) -> Result<Self::Built, TranslationError> {
+        let mut local = SqliteCache::new(db_path, src_lang, dst_lang)?;
+        local.prune(self.limits)?;
+        Ok(RemoteCache {
+            remote: RemoteClient {
+                client: crate::llm::http_client(self.request_timeout, self.proxy.as_ref()),
+                base_url: self.base_url.trim_end_matches('/').to_owned(),
+                token: self.token.clone(),
+                timed_out: false,
+            },
+            src_lang_lc: local.src_lang_lc.clone(),
+            dst_lang_lc: local.dst_lang_lc.clone(),
+            local,
+        })
+    }
+}
+
+/// Translation memory shared by a team through an HTTP server,
+/// with a local SQLite database used as a read-through cache, and used alone if the server stops responding.
+///
+/// Server is expected to accept JSON [RemoteEntry] on these endpoints:
+/// * `POST /lookup` - responds with an entry, or 404 if there's none;
+/// * `POST /translations` - creates an entry, or responds with 409 and the existing entry;
+/// * `PUT /translations` - creates or replaces an entry.
+pub struct RemoteCache {
+    remote: RemoteClient,
+    src_lang_lc: String,
+    dst_lang_lc: String,
+    local: SqliteCache,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteEntry {
+    pub src_lang: String,
+    pub dst_lang: String,
+    pub src: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst: Option<String>,
+}
+
+impl RemoteCache {
+    fn entry(&self, src: &MarkdownSubsection, dst: Option<&MarkdownSubsection>) -> RemoteEntry {
+        RemoteEntry {
+            src_lang: self.src_lang_lc.clone(),
+            dst_lang: self.dst_lang_lc.clone(),
+            src: src.0.clone(),
+            dst: dst.map(|dst| dst.0.clone()),
+        }
+    }
+}
+
+struct RemoteClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+    /// Server didn't respond in time, so it's not asked anymore
+    timed_out: bool,
+}
+
+impl RemoteClient {
+    /// None if the server doesn't respond in time, now or before
+    async fn send(
+        &mut self,
+        method: reqwest::Method,
+        path: &str,
+        entry: &RemoteEntry,
+    ) -> Result<Option<reqwest::Response>, TranslationError> {
+        if self.timed_out {
+            return Ok(None);
+        }
+        let mut req = self.client
+            .request(method, format!("{}/{}", self.base_url, path))
+            .json(entry);
+        if let Some(ref token) = self.token {
+            req = req.bearer_auth(token);
+        }
+        match req.send().await {
+            Ok(resp) => Ok(Some(resp)),
+            Err(e) if e.is_timeout() => {
+                log::warn!("Remote cache isn't responding, using the local one for the rest of the run: {e}");
+                self.timed_out = true;
+                Ok(None)
+            }
+            Err(e) => Err(remote_error(e)),
+        }
+    }
+}
+
+impl Cache for RemoteCache {
+    async fn get(
+        &mut self,
+        src: &MarkdownSubsection,
+    ) -> Result<Option<MarkdownSubsection>, TranslationError> {
+        if let Some(dst) = self.local.get(src).await? {
+            return Ok(Some(dst));
+        }
+
+        let entry = self.entry(src, None);
+        let Some(resp) = self.remote.send(reqwest::Method::POST, "lookup", &entry).await? else {
+            return Ok(None);
+        };
+        let dst = match resp.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if status.is_success() => {
+                let entry = resp.json::<RemoteEntry>().await.map_err(remote_error)?;
+                // Entry without a translation isn't one to keep, the section is translated anew
+                let Some(dst) = entry.dst.filter(|dst| !dst.trim().is_empty()) else {
+                    log::warn!("Remote cache has no translation of {}, ignoring it", log_preview(&src.0));
+                    return Ok(None);
+                };
+                MarkdownSubsection(dst)
+            }
+            status => return Err(unexpected_status(status)),
+        };
+
+        self.local.insert(src.clone(), dst.clone()).await?;
+        Ok(Some(dst))
+    }
+
+    async fn insert(
+        &mut self,
+        src: MarkdownSubsection,
+        dst: MarkdownSubsection,
+    ) -> Result<(), TranslationError> {
+        let entry = self.entry(&src, Some(&dst));
+        let Some(resp) = self.remote.send(reqwest::Method::POST, "translations", &entry).await? else {
+            return self.local.insert(src, dst).await;
+        };
+        match resp.status() {
+            StatusCode::CONFLICT => {
+                // Someone else has translated it first, theirs takes priority
+                let existing = resp.json::<RemoteEntry>().await.map_err(remote_error)?;
+                let Some(existing_dst) = existing.dst.filter(|dst| !dst.trim().is_empty()) else {
+                    return Err(TranslationError::OtherError(anyhow!(
+                        "Remote cache reported a translation of {} without responding with it",
+                        log_preview(&src.0)
+                    )));
+                };
+                log::info!("Remote cache already had a translation of {}, using it", log_preview(&src.0));
+                self.local.upsert(src, MarkdownSubsection(existing_dst)).await
+            }
+            status if status.is_success() => self.local.insert(src, dst).await,
+            status => Err(unexpected_status(status)),
+        }
+    }
+
+    async fn upsert(
+        &mut self,
+        src: MarkdownSubsection,
+        dst: MarkdownSubsection,
+    ) -> Result<(), TranslationError> {
+        let entry = self.entry(&src, Some(&dst));
+        let Some(resp) = self.remote.send(reqwest::Method::PUT, "translations", &entry).await? else {
+            return self.local.upsert(src, dst).await;
+        };
+        match resp.status() {
+            status if status.is_success() => self.local.upsert(src, dst).await,
+            status => Err(unexpected_status(status)),
+        }
+    }
+}
+
+fn unexpected_status(status: StatusCode) -> TranslationError {
+    TranslationError::OtherError(anyhow!("Remote cache responded with {status}"))
+}
+
+fn remote_error(e: reqwest::Error) -> TranslationError {
+    TranslationError::OtherError(anyhow!("Remote cache request failed: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Remote cache server responding to every request with the given status and JSON body, returns its URL
+    async fn mock_remote(status: u16, body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                // Whole request is read first, so that the client doesn't see the connection reset
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap_or(0);
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    let complete = text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                        let len = head
+                            .lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(str::to_owned))
+                            .and_then(|len| len.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        body.len() >= len
+                    });
+                    if n == 0 || complete {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\
+                    connection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn remote_entry_without_translation() {
+        let entry = r#"{"src_lang": "english", "dst_lang": "russian", "src": "Hello"}"#;
+        let src = MarkdownSubsection("Hello".to_owned());
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("book.sqlite");
+        let remote = |base_url| RemoteCacheBuilder {
+            base_url,
+            token: None,
+            limits: Default::default(),
+            request_timeout: None,
+            proxy: None,
+        };
+
+        let mut cache = remote(mock_remote(200, entry).await).build(&db_path, "English", "Russian").await.unwrap();
+        assert_eq!(cache.get(&src).await.unwrap(), None);
+        assert_eq!(cache.local.get(&src).await.unwrap(), None);
+
+        let mut cache = remote(mock_remote(409, entry).await).build(&db_path, "English", "Russian").await.unwrap();
+        assert!(cache.insert(src.clone(), MarkdownSubsection("Привет".to_owned())).await.is_err());
+        assert_eq!(cache.local.get(&src).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn unresponsive_remote() {
+        // Accepts connections and never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let remote = RemoteCacheBuilder {
+            base_url,
+            token: None,
+            limits: Default::default(),
+            request_timeout: Some(Duration::from_millis(200)),
+            proxy: None,
+        };
+        let mut cache = remote.build(&dir.path().join("book.sqlite"), "English", "Russian").await.unwrap();
+
+        let src = MarkdownSubsection("Hello".to_owned());
+        assert_eq!(cache.get(&src).await.unwrap(), None);
+        assert!(cache.remote.timed_out);
+        cache.insert(src.clone(), MarkdownSubsection("Привет".to_owned())).await.unwrap();
+        assert_eq!(cache.get(&src).await.unwrap(), Some(MarkdownSubsection("Привет".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn migrate_unversioned_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("book.sqlite");
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE translated (
+                    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+                    src_section  TEXT NOT NULL,
+                    dst_section  TEXT NOT NULL,
+                    src_lang_lc  TEXT NOT NULL,
+                    dst_lang_lc  TEXT NOT NULL
+                );
+                INSERT INTO translated (src_section, dst_section, src_lang_lc, dst_lang_lc)
+                VALUES ('Hello', 'Привет', 'english', 'russian');",
+            )
+            .unwrap();
+        }
+
+        let mut cache = SqliteCache::new(&db_path, "English", "Russian").unwrap();
+        let cached = cache.get(&MarkdownSubsection("Hello".to_owned())).await.unwrap();
+        assert_eq!(cached, Some(MarkdownSubsection("Привет".to_owned())));
+        let version = cache.conn.pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert!(dir.path().join("book.v0.bak.sqlite").exists());
+        assert_eq!(cache.last_run().unwrap(), None);
+        cache.record_run("{}").unwrap();
+        assert_eq!(cache.last_run().unwrap().as_deref(), Some("{}"));
+
+        cache.conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        drop(cache);
+        assert!(matches!(
+            SqliteCache::new(&db_path, "English", "Russian"),
+            Err(TranslationError::CacheSchemaTooNew { .. })
+        ));
+
+        // Inspection reads what the current version can't open, backup is an unversioned cache as well
+        let inspection = inspect(&db_path).unwrap();
+        assert_eq!(inspection.schema_version, SCHEMA_VERSION + 1);
+        assert_eq!(inspection.entries[0].dst, "Привет");
+        let backup = inspect(&dir.path().join("book.v0.bak.sqlite")).unwrap();
+        assert_eq!(backup.schema_version, 0);
+        assert_eq!(backup.language_pairs(), vec![("english", "russian", 1)]);
+        assert_eq!(backup.entries[0].last_used, 0);
+    }
 }
@@ -0,0 +1,92 @@
+use crate::TranslationError;
+
+use anyhow::anyhow;
+use chrono::Local;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+#[cfg(unix)]
+type Connection = tokio::net::UnixStream;
+#[cfg(windows)]
+type Connection = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// Stage of the run an event is about
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum Phase {
+    Started,
+    /// Section is done, either translated, taken from the cache or kept as is
+    Section {
+        section: usize,
+        processed_sections: usize,
+        total_sections: usize,
+        duration_ms: u64,
+        /// Whether the whole section came from the cache, without asking the LLM
+        cache_hit: bool,
+    },
+    Finished {
+        duration_ms: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    time: String,
+    /// Tells apart several instances reporting to the same listener
+    pid: u32,
+    input: &'a Path,
+    #[serde(flatten)]
+    phase: Phase,
+}
+
+/// Sends run events as JSON lines to a listener on a UNIX socket (a named pipe on Windows),
+/// so that external dashboards can follow several runs without parsing logs.
+/// Connection is dropped after the first failed write, the run goes on without it.
+pub struct CheckpointSink {
+    path: PathBuf,
+    input: PathBuf,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl CheckpointSink {
+    /// Connects to a listener that is already waiting on `path`
+    pub async fn connect(path: &Path, input: &Path) -> Result<Self, TranslationError> {
+        #[cfg(unix)]
+        let connection = tokio::net::UnixStream::connect(path).await;
+        #[cfg(windows)]
+        let connection = tokio::net::windows::named_pipe::ClientOptions::new().open(path);
+
+        let connection = connection.map_err(|e| {
+            TranslationError::OtherError(anyhow!("Couldn't connect to checkpoint listener {}: {e}", path.display()))
+        })?;
+        Ok(CheckpointSink {
+            path: path.to_owned(),
+            input: input.to_owned(),
+            connection: Mutex::new(Some(connection)),
+        })
+    }
+
+    pub async fn send(&self, phase: Phase) {
+        let mut connection = self.connection.lock().await;
+        let Some(ref mut stream) = *connection else {
+            return;
+        };
+        let event = Event {
+            time: Local::now().to_rfc3339(),
+            pid: std::process::id(),
+            input: &self.input,
+            phase,
+        };
+        let mut line = serde_json::to_string(&event).expect("event serialization");
+        line.push('\n');
+        if let Err(e) = stream.write_all(line.as_bytes()).await {
+            log::warn!("Checkpoint listener {} is gone, not sending events anymore: {}", self.path.display(), e);
+            *connection = None;
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::utils::is_same_language;
+
+use std::ops::Range;
+
+/// Stand-ins of the protected parts of a line while its quotes are converted, see [protected_spans]
+const PROTECTED_START: char = '\u{E000}';
+const PROTECTED_END: char = '\u{E001}';
+
+/// Typographic conventions for quotations and dialogues in a language.
+struct QuoteStyle {
+    open: &'static str,
+    close: &'static str,
+    /// Dialogue lines are introduced by an em-dash rather than enclosed in quotes
+    dialogue_dash: bool,
+}
+
+fn quote_style(lang: &str) -> Option<QuoteStyle> {
+    let is = |name: &str| is_same_language(lang, name);
+    if is("Russian") || is("Ukrainian") || is("Belarusian") {
+        Some(QuoteStyle { open: "«", close: "»", dialogue_dash: true })
+    } else if is("German") {
+        Some(QuoteStyle { open: "„", close: "“", dialogue_dash: false })
+    } else if is("French") {
+        Some(QuoteStyle { open: "«\u{202F}", close: "\u{202F}»", dialogue_dash: false })
+    } else if is("English") {
+        Some(QuoteStyle { open: "“", close: "”", dialogue_dash: false })
+    } else {
+        None
+    }
+}
+
+/// Converts quotations and dialogues in the translated text to the conventions of the destination language,
+/// since LLMs tend to carry over the source conventions.
+/// Lines with unbalanced quotes are left intact, and so are code, raw HTML tags and link destinations,
+/// whose quotes are Markdown syntax rather than text.
+pub fn convert_dialogue(text: &str, dst_lang: &str) -> String {
+    let Some(style) = quote_style(dst_lang) else {
+        return text.to_owned();
+    };
+
+    let mut in_fence = false;
+    text.split('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+                return line.to_owned();
+            }
+            if in_fence {
+                return line.to_owned();
+            }
+            convert_line(line, &style).unwrap_or_else(|| line.to_owned())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn convert_line(line: &str, style: &QuoteStyle) -> Option<String> {
+    // Protected parts are swapped for numbered stand-ins, to be put back once the quotes are converted
+    let spans = protected_spans(line);
+    let mut masked = String::with_capacity(line.len());
+    let mut last = 0;
+    for (idx, span) in spans.iter().enumerate() {
+        masked += &line[last..span.start];
+        masked.push(PROTECTED_START);
+        masked += &idx.to_string();
+        masked.push(PROTECTED_END);
+        last = span.end;
+    }
+    masked += &line[last..];
+
+    let converted = convert_quotes(&masked, style)?;
+    let mut result = String::with_capacity(converted.len());
+    let mut rest = converted.as_str();
+    while let Some(start) = rest.find(PROTECTED_START) {
+        let end = rest[start..].find(PROTECTED_END)? + start;
+        let idx = rest[start + PROTECTED_START.len_utf8()..end].parse::<usize>().ok()?;
+        result += &rest[..start];
+        result += &line[spans.get(idx)?.clone()];
+        rest = &rest[end + PROTECTED_END.len_utf8()..];
+    }
+    result += rest;
+    Some(result)
+}
+
+/// Byte ranges of inline code, raw HTML tags and link or image destinations (along with their titles)
+fn protected_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let mut spans = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let span = match bytes[i] {
+            b'`' => {
+                let run = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+                let fence = "`".repeat(run);
+                // Closing run has to be of the same length, unmatched backticks are plain text
+                let mut from = i + run;
+                let mut end = None;
+                while let Some(pos) = line[from..].find(&fence) {
+                    let pos = from + pos;
+                    let len = bytes[pos..].iter().take_while(|&&b| b == b'`').count();
+                    if len == run {
+                        end = Some(pos + run);
+                        break;
+                    }
+                    from = pos + len;
+                }
+                match end {
+                    Some(end) => Some(i..end),
+                    None => {
+                        i += run;
+                        continue;
+                    }
+                }
+            }
+            b'<' if bytes.get(i + 1).is_some_and(|b| b.is_ascii_alphabetic() || b"/!?".contains(b)) => {
+                line[i..].find('>').map(|end| i..i + end + 1)
+            }
+            b']' if bytes.get(i + 1) == Some(&b'(') => {
+                let mut depth = 0;
+                bytes[i + 1..]
+                    .iter()
+                    .position(|&b| {
+                        match b {
+                            b'(' => depth += 1,
+                            b')' => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .map(|end| i + 1..i + 1 + end + 1)
+            }
+            _ => None,
+        };
+        match span {
+            Some(span) => {
+                i = span.end;
+                spans.push(span);
+            }
+            None => i += 1,
+        }
+    }
+    spans
+}
+
+fn convert_quotes(line: &str, style: &QuoteStyle) -> Option<String> {
+    let segments = split_quoted(line)?;
+    if !segments.iter().any(|(quoted, _)| *quoted) {
+        return None;
+    }
+
+    let result = if style.dialogue_dash && segments.first().is_some_and(|(quoted, _)| *quoted) {
+        // "Hello," he said. "Bye." -> — Hello, — he said. — Bye.
+        let mut result = String::new();
+        for (quoted, text) in segments {
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            if !quoted && text.chars().all(|c| c.is_ascii_punctuation()) {
+                result += text;
+                continue;
+            }
+            result += if result.is_empty() { "— " } else { " — " };
+            result += text;
+        }
+        result
+    } else {
+        segments
+            .into_iter()
+            .map(|(quoted, text)| {
+                if quoted {
+                    format!("{}{}{}", style.open, text.trim(), style.close)
+                } else {
+                    text
+                }
+            })
+            .collect()
+    };
+    Some(result)
+}
+
+/// Splits the line into quoted and unquoted segments, returns `None` if quotes are unbalanced.
+/// Nested quotes are not recognized.
+fn split_quoted(line: &str) -> Option<Vec<(bool, String)>> {
+    let mut segments = vec![];
+    let mut current = String::new();
+    let mut in_quote = false;
+    for c in line.chars() {
+        let is_quote = match c {
+            '"' => true,
+            '«' | '„' => !in_quote,
+            '»' | '”' => in_quote,
+            '“' => true,
+            _ => false,
+        };
+        if is_quote {
+            segments.push((in_quote, std::mem::take(&mut current)));
+            in_quote = !in_quote;
+        } else {
+            current.push(c);
+        }
+    }
+    if in_quote {
+        return None;
+    }
+    segments.push((false, current));
+    segments.retain(|(quoted, text)| *quoted || !text.is_empty());
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn russian_dialogue() {
+        assert_eq!(
+            convert_dialogue(r#""Привет," сказал он. "Пока.""#, "Russian"),
+            "— Привет, — сказал он. — Пока."
+        );
+        assert_eq!(convert_dialogue(r#""Привет.""#, "Russian"), "— Привет.");
+    }
+
+    #[test]
+    fn russian_quotation() {
+        assert_eq!(
+            convert_dialogue(r#"Он прочитал "Войну и мир" дважды."#, "Russian"),
+            "Он прочитал «Войну и мир» дважды."
+        );
+    }
+
+    #[test]
+    fn german_quotation() {
+        assert_eq!(
+            convert_dialogue("“Hallo”, sagte er.", "German"),
+            "„Hallo“, sagte er."
+        );
+    }
+
+    #[test]
+    fn unbalanced_quotes_left_intact() {
+        assert_eq!(
+            convert_dialogue(r#"Он сказал: "Привет"#, "Russian"),
+            r#"Он сказал: "Привет"#
+        );
+    }
+
+    #[test]
+    fn markdown_syntax_left_intact() {
+        assert_eq!(
+            convert_dialogue(r#"Он открыл <a href="https://example.com">"Войну и мир"</a>."#, "Russian"),
+            r#"Он открыл <a href="https://example.com">«Войну и мир»</a>."#
+        );
+        assert_eq!(
+            convert_dialogue(r#"См. [книгу](https://example.com "Война и мир") и "сноску"."#, "Russian"),
+            r#"См. [книгу](https://example.com "Война и мир") и «сноску»."#
+        );
+        assert_eq!(
+            convert_dialogue(r#"![Обложка](cover.png "Обложка") с надписью "Мир"."#, "Russian"),
+            r#"![Обложка](cover.png "Обложка") с надписью «Мир»."#
+        );
+        assert_eq!(
+            convert_dialogue(r#"Вызовите `print("hi")`, чтобы вывести "hi"."#, "Russian"),
+            r#"Вызовите `print("hi")`, чтобы вывести «hi»."#
+        );
+        assert_eq!(
+            convert_dialogue("Код:\n```\nlet s = \"hi\";\n```\nОн сказал \"да\".", "Russian"),
+            "Код:\n```\nlet s = \"hi\";\n```\nОн сказал «да»."
+        );
+    }
+
+    #[test]
+    fn unknown_language_left_intact() {
+        assert_eq!(convert_dialogue(r#""Hola""#, "Klingon"), r#""Hola""#);
+    }
+}
@@ -1,7 +1,16 @@
+pub mod headings;
+pub mod localization;
 pub mod pandoc;
+pub mod template;
+pub mod transcript;
 
-use crate::parser::MarkdownSection;
+use crate::align::Aligner;
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::split_sentences;
 use crate::TranslationError;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 use std::path::Path;
 
 pub trait GeneratorBuilder {
@@ -11,7 +20,117 @@ pub trait GeneratorBuilder {
 }
 
 pub trait Generator {
-    async fn write(&mut self, md: MarkdownSection) -> Result<(), TranslationError>;
+    async fn write(&mut self, src: &MarkdownSection, md: MarkdownSection) -> Result<(), TranslationError>;
 
     async fn finalize(&mut self) -> Result<(), TranslationError>;
 }
+
+/// How to present source text alongside its translation in bilingual output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BilingualStyle {
+    /// Translation follows each source sentence in italics
+    Italics,
+    /// Translation follows each source sentence in gray (HTML output only)
+    Color,
+    /// Each source sentence can be expanded to reveal its translation (HTML output only)
+    Details,
+}
+
+impl BilingualStyle {
+    pub const ALL: [BilingualStyle; 3] = [
+        BilingualStyle::Italics,
+        BilingualStyle::Color,
+        BilingualStyle::Details,
+    ];
+
+    fn format_pair(&self, src: &str, dst: &str) -> String {
+        match self {
+            BilingualStyle::Italics => format!("{src} *{dst}*"),
+            BilingualStyle::Color => format!(r#"{src} <span style="color: gray">{dst}</span>"#),
+            BilingualStyle::Details => format!("<details><summary>{src}</summary>{dst}</details>"),
+        }
+    }
+}
+
+impl Display for BilingualStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BilingualStyle::Italics => write!(f, "Bilingual, italics"),
+            BilingualStyle::Color => write!(f, "Bilingual, colored"),
+            BilingualStyle::Details => write!(f, "Bilingual, collapsible"),
+        }
+    }
+}
+
+/// Interleaves source and translated text sentence by sentence, as paired up by the aligner.
+/// Falls back to subsection granularity where sentences can't be aligned,
+/// and to whole sections where subsection counts differ.
+pub fn interleave(
+    style: BilingualStyle,
+    aligner: &dyn Aligner,
+    src: &MarkdownSection,
+    dst: &MarkdownSection,
+) -> MarkdownSection {
+    if src == dst {
+        // Section was kept as is
+        return dst.clone();
+    }
+    if src.0.len() != dst.0.len() {
+        return MarkdownSection(src.0.iter().chain(dst.0.iter()).cloned().collect());
+    }
+
+    let subsections = src.0.iter().zip(dst.0.iter()).map(|(src_ss, dst_ss)| {
+        let src_sentences = split_sentences(&src_ss.0);
+        let dst_sentences = split_sentences(&dst_ss.0);
+        let text = match aligner.align(&src_sentences, &dst_sentences) {
+            Some(pairs) => pairs
+                .into_iter()
+                .map(|(src_s, dst_s)| style.format_pair(&src_s.join(" "), &dst_s.join(" ")))
+                .join(if style == BilingualStyle::Details { "\n" } else { " " }),
+            None => style.format_pair(&src_ss.0, &dst_ss.0),
+        };
+        MarkdownSubsection(text)
+    });
+
+    MarkdownSection(subsections.collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::HeuristicAligner;
+
+    fn section(subsections: &[&str]) -> MarkdownSection {
+        MarkdownSection(subsections.iter().map(|s| MarkdownSubsection(s.to_string())).collect())
+    }
+
+    #[test]
+    fn interleave_sentences() {
+        let src = section(&["One. Two."]);
+        let dst = section(&["Раз. Два."]);
+
+        let result = interleave(BilingualStyle::Italics, &HeuristicAligner, &src, &dst);
+
+        assert_eq!(result, section(&["One. *Раз.* Two. *Два.*"]));
+    }
+
+    #[test]
+    fn interleave_mismatched_sentences() {
+        let src = section(&["One. Two."]);
+        let dst = section(&["Раз и два."]);
+
+        let result = interleave(BilingualStyle::Italics, &HeuristicAligner, &src, &dst);
+
+        assert_eq!(result, section(&["One. Two. *Раз и два.*"]));
+    }
+
+    #[test]
+    fn interleave_mismatched_subsections() {
+        let src = section(&["One.", "Two."]);
+        let dst = section(&["Раз и два."]);
+
+        let result = interleave(BilingualStyle::Details, &HeuristicAligner, &src, &dst);
+
+        assert_eq!(result, section(&["One.", "Two.", "Раз и два."]));
+    }
+}
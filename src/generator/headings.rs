@@ -0,0 +1,198 @@
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Word for chapter by destination language, used as the prefix of localized top-level headings
+const CHAPTER_WORDS: &[(&str, &str)] = &[
+    ("English", "Chapter"),
+    ("Russian", "Глава"),
+    ("Ukrainian", "Розділ"),
+    ("Belarusian", "Раздзел"),
+    ("Polish", "Rozdział"),
+    ("Czech", "Kapitola"),
+    ("German", "Kapitel"),
+    ("French", "Chapitre"),
+    ("Spanish", "Capítulo"),
+    ("Portuguese", "Capítulo"),
+    ("Italian", "Capitolo"),
+    ("Dutch", "Hoofdstuk"),
+];
+
+const MAX_HEADING_LEVEL: usize = 6;
+
+/// What happens to the numbers of translated headings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeadingNumbering {
+    /// Kept the way the provider translated them
+    #[default]
+    Preserve,
+    /// Existing numbers are replaced with `1.`, `1.1.` and so on, by heading level
+    Regenerate,
+    /// Regenerated, with top-level headings prefixed with the word for chapter, e.g. `Глава 1.`
+    Localize,
+}
+
+impl HeadingNumbering {
+    pub const ALL: [HeadingNumbering; 3] = [
+        HeadingNumbering::Preserve,
+        HeadingNumbering::Regenerate,
+        HeadingNumbering::Localize,
+    ];
+}
+
+impl Display for HeadingNumbering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadingNumbering::Preserve => write!(f, "As translated"),
+            HeadingNumbering::Regenerate => write!(f, "Renumbered"),
+            HeadingNumbering::Localize => write!(f, "Renumbered, with chapter prefix"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadingOptions {
+    pub numbering: HeadingNumbering,
+    /// Top-level numbers are written as Roman numerals, e.g. `IV.` and `IV.2.`
+    pub roman: bool,
+    /// Word for chapter to use instead of the one of the destination language, e.g. "Part"
+    pub chapter_word: Option<String>,
+}
+
+impl HeadingOptions {
+    /// Fills the word for chapter in from the destination language, unless it's given
+    pub fn for_language(&self, dst_lang: &str) -> HeadingOptions {
+        let chapter_word = self.chapter_word.clone().filter(|word| !word.trim().is_empty()).or_else(|| {
+            CHAPTER_WORDS
+                .iter()
+                .find(|(lang, _)| lang.eq_ignore_ascii_case(dst_lang.trim()))
+                .map(|(_, word)| (*word).to_owned())
+        });
+        HeadingOptions { chapter_word, ..self.clone() }
+    }
+}
+
+/// Numbers headings as they are written, counting them across sections, so that the same document
+/// always gets the same heading text whatever the provider did to the numbers.
+pub struct HeadingNumberer {
+    options: HeadingOptions,
+    /// Heading number the provider translated, along with the word for chapter in any of the known languages
+    number_regex: Regex,
+    /// Headings of each level so far, deeper levels restart with every heading
+    counters: [usize; MAX_HEADING_LEVEL],
+}
+
+impl HeadingNumberer {
+    pub fn new(options: HeadingOptions) -> Self {
+        let words = CHAPTER_WORDS
+            .iter()
+            .map(|(_, word)| *word)
+            .chain(options.chapter_word.as_deref())
+            .map(regex::escape)
+            .unique()
+            .join("|");
+        let number = r"(?:\d+|[IVXLCDM]+)(?:\.\d+)*";
+        let number_regex = Regex::new(&format!(
+            r"^(?:(?i:{words})\s+{number}[.:]?|{number}[.)]|\d+(?:\.\d+)+)(?:\s+|$)"
+        ))
+        .expect("valid regex");
+        HeadingNumberer { options, number_regex, counters: [0; MAX_HEADING_LEVEL] }
+    }
+
+    pub fn renumber(&mut self, md: MarkdownSection) -> MarkdownSection {
+        if self.options.numbering == HeadingNumbering::Preserve {
+            return md;
+        }
+        MarkdownSection(md.0.into_iter().map(|ss| self.renumber_heading(ss)).collect())
+    }
+
+    fn renumber_heading(&mut self, ss: MarkdownSubsection) -> MarkdownSubsection {
+        let level = ss.0.chars().take_while(|&c| c == '#').count();
+        let Some(title) = ss.0[level..].strip_prefix(' ').filter(|title| !title.contains('\n')) else {
+            return ss;
+        };
+        // Pandoc syntax of headings meant to stay unnumbered
+        let unnumbered = title.contains(".unnumbered") || title.trim_end().ends_with("{-}");
+        if !(1..=MAX_HEADING_LEVEL).contains(&level) || unnumbered {
+            return ss;
+        }
+        let title = title.trim();
+        let title = self.number_regex.find(title).map_or(title, |number| &title[number.end()..]);
+
+        self.counters[level - 1] += 1;
+        self.counters[level..].fill(0);
+        let number = self.counters[..level]
+            .iter()
+            .enumerate()
+            .map(|(idx, &n)| match idx == 0 && self.options.roman {
+                true => to_roman(n),
+                false => n.to_string(),
+            })
+            .join(".");
+        let number = match (self.options.numbering, &self.options.chapter_word) {
+            (HeadingNumbering::Localize, Some(word)) if level == 1 => format!("{word} {number}"),
+            _ => number,
+        };
+        match title.is_empty() {
+            true => MarkdownSubsection(format!("{} {}", "#".repeat(level), number)),
+            false => MarkdownSubsection(format!("{} {}. {}", "#".repeat(level), number, title)),
+        }
+    }
+}
+
+fn to_roman(mut n: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            roman += numeral;
+            n -= value;
+        }
+    }
+    roman
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renumber(numberer: &mut HeadingNumberer, subsections: &[&str]) -> Vec<String> {
+        let md = MarkdownSection(subsections.iter().map(|s| MarkdownSubsection(s.to_string())).collect());
+        numberer.renumber(md).0.into_iter().map(|ss| ss.0).collect()
+    }
+
+    #[test]
+    fn renumbers_headings_across_sections() {
+        let options = HeadingOptions { numbering: HeadingNumbering::Regenerate, ..Default::default() };
+        let mut numberer = HeadingNumberer::new(options.for_language("Russian"));
+        assert_eq!(renumber(&mut numberer, &["# 3. Введение", "Текст"]), vec!["# 1. Введение", "Текст"]);
+        assert_eq!(renumber(&mut numberer, &["## 1.1 Цели", "## Задачи"]), vec!["## 1.1. Цели", "## 1.2. Задачи"]);
+        assert_eq!(renumber(&mut numberer, &["# 1984 год"]), vec!["# 2. 1984 год"]);
+        assert_eq!(renumber(&mut numberer, &["## Итоги {-}"]), vec!["## Итоги {-}"]);
+
+        let options = HeadingOptions { numbering: HeadingNumbering::Localize, roman: true, chapter_word: None };
+        let mut numberer = HeadingNumberer::new(options.for_language("Russian"));
+        assert_eq!(
+            renumber(&mut numberer, &["# Chapter 1: Введение", "# Глава 5", "## II. Цели"]),
+            vec!["# Глава I. Введение", "# Глава II", "## II.1. Цели"]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use super::{Generator, GeneratorBuilder};
+use crate::parser::localization::{Message, MessageFormat};
+use crate::parser::MarkdownSection;
+use crate::utils::read_to_string_lossy;
+use crate::TranslationError;
+
+use itertools::Itertools;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Writes translated messages back into a copy of the source file, keeping its structure.
+pub struct LocalizationGeneratorBuilder<F> {
+    pub input: PathBuf,
+    pub format: F,
+}
+
+impl<F: MessageFormat> GeneratorBuilder for LocalizationGeneratorBuilder<F> {
+    type Built = LocalizationGenerator<F>;
+
+    async fn build(&self, output_path: &Path) -> Result<Self::Built, TranslationError> {
+        // Read the same way as by the parser, so that message ranges match
+        let content = read_to_string_lossy(&self.input).await?;
+        let messages = self.format.messages(&content);
+
+        Ok(LocalizationGenerator {
+            format: self.format.clone(),
+            output_path: output_path.to_owned(),
+            content,
+            messages,
+            translations: vec![],
+        })
+    }
+}
+
+pub struct LocalizationGenerator<F> {
+    format: F,
+    output_path: PathBuf,
+    content: String,
+    messages: Vec<Message>,
+    translations: Vec<String>,
+}
+
+impl<F: MessageFormat> Generator for LocalizationGenerator<F> {
+    async fn write(&mut self, _src: &MarkdownSection, md: MarkdownSection) -> Result<(), TranslationError> {
+        // Annotations have no place in a resource file
+        let text = md.0.iter().filter(|ss| !ss.is_annotation()).map(|ss| &ss.0).join(" ");
+        self.translations.push(text);
+        Ok(())
+    }
+
+    async fn finalize(&mut self) -> Result<(), TranslationError> {
+        let replacements = self
+            .messages
+            .iter()
+            .zip(self.translations.iter())
+            .flat_map(|(msg, translation)| {
+                let escaped = self.format.escape(translation);
+                msg.targets.iter().map(move |range| (range.clone(), escaped.clone()))
+            })
+            .sorted_by_key(|(range, _)| range.start);
+
+        let mut result = String::with_capacity(self.content.len());
+        let mut pos = 0;
+        for (range, escaped) in replacements {
+            result += &self.content[pos..range.start];
+            result += &escaped;
+            pos = range.end;
+        }
+        result += &self.content[pos..];
+
+        fs::write(&self.output_path, result).await?;
+        Ok(())
+    }
+}
@@ -1,19 +1,139 @@
-use super::{Generator, GeneratorBuilder};
-use crate::parser::MarkdownSection;
+use super::headings::{HeadingNumberer, HeadingOptions};
+use crate::align::{Aligner, AlignerKind};
+use super::{interleave, BilingualStyle, Generator, GeneratorBuilder};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::execute_pandoc;
 use crate::TranslationError;
 
+use anyhow::{anyhow, Context};
 use itertools::Itertools;
-use pandoc::OutputKind;
+use pandoc::PandocOption;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
-pub struct PandocGeneratorBuilder;
+/// Paragraph styles pandoc gives to body text and block quotes in DOCX output
+const DEFAULT_BODY_STYLE: &str = "Body Text";
+const DEFAULT_QUOTE_STYLE: &str = "Block Text";
+
+/// Heading levels a DOCX reference document must have styles for
+const REQUIRED_HEADING_LEVELS: usize = 3;
+
+static PARAGRAPH_STYLE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<w:style\b[^>]*\bw:type="paragraph"[^>]*>.*?<w:name w:val="([^"]*)""#).expect("regex")
+});
+
+static ORDERED_LIST_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\d+[.)]\s").expect("regex"));
+
+pub struct PandocGeneratorBuilder {
+    /// If set, source text is kept in the output alongside the translation
+    pub bilingual: Option<BilingualStyle>,
+    /// Pairs up sentences of bilingual output
+    pub aligner: AlignerKind,
+    pub headings: HeadingOptions,
+    pub options: PandocOutputOptions,
+}
+
+/// Options of the final conversion from Markdown, pandoc defaults are used for the ones not set.
+/// Options irrelevant to the output format are ignored by pandoc.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PandocOutputOptions {
+    /// DOCX/ODT/PPTX to take styles from
+    pub reference_doc: Option<PathBuf>,
+    /// Embed images, styles and scripts into HTML output
+    pub self_contained: Option<bool>,
+    pub epub_cover_image: Option<PathBuf>,
+    /// XML file with EPUB metadata in Dublin Core format
+    pub epub_metadata: Option<PathBuf>,
+    /// Program to produce PDF with, e.g. `xelatex`, `weasyprint` or `wkhtmltopdf`
+    pub pdf_engine: Option<String>,
+    /// DOCX paragraph style of the reference document to use for body text instead of "Body Text"
+    pub body_style: Option<String>,
+    /// DOCX paragraph style of the reference document to use for block quotes instead of "Block Text"
+    pub quote_style: Option<String>,
+}
+
+impl PandocOutputOptions {
+    /// Options set in `overrides` take precedence over these
+    pub fn overridden_by(&self, overrides: &PandocOutputOptions) -> PandocOutputOptions {
+        PandocOutputOptions {
+            reference_doc: overrides.reference_doc.clone().or_else(|| self.reference_doc.clone()),
+            self_contained: overrides.self_contained.or(self.self_contained),
+            epub_cover_image: overrides.epub_cover_image.clone().or_else(|| self.epub_cover_image.clone()),
+            epub_metadata: overrides.epub_metadata.clone().or_else(|| self.epub_metadata.clone()),
+            pdf_engine: overrides.pdf_engine.clone().or_else(|| self.pdf_engine.clone()),
+            body_style: overrides.body_style.clone().or_else(|| self.body_style.clone()),
+            quote_style: overrides.quote_style.clone().or_else(|| self.quote_style.clone()),
+        }
+    }
+
+    /// Styles DOCX output is expected to use, pandoc takes its own ones for those missing in the reference document
+    fn required_styles(&self) -> Vec<String> {
+        let mut styles = vec![
+            self.body_style.clone().unwrap_or_else(|| DEFAULT_BODY_STYLE.to_owned()),
+            self.quote_style.clone().unwrap_or_else(|| DEFAULT_QUOTE_STYLE.to_owned()),
+        ];
+        styles.extend((1..=REQUIRED_HEADING_LEVELS).map(|level| format!("Heading {level}")));
+        styles
+    }
+
+    /// Fails if the DOCX reference document lacks any of the styles the output is expected to use
+    fn validate_reference_doc(&self, path: &Path) -> Result<(), TranslationError> {
+        let available = docx_paragraph_styles(path).map_err(TranslationError::OtherError)?;
+        let missing = self
+            .required_styles()
+            .into_iter()
+            .filter(|style| !available.iter().any(|s| s.eq_ignore_ascii_case(style)))
+            .collect_vec();
+        if !missing.is_empty() {
+            return Err(TranslationError::OtherError(anyhow!(
+                "Reference document {} has no {} style(s), define them there or pick other ones",
+                path.display(),
+                missing.iter().map(|s| format!("\"{s}\"")).join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    fn to_pandoc(&self) -> Vec<PandocOption> {
+        let mut options = vec![];
+        if let Some(ref path) = self.reference_doc {
+            options.push(PandocOption::ReferenceDoc(path.clone()));
+        }
+        if self.self_contained == Some(true) {
+            options.push(PandocOption::SelfContained);
+        }
+        if let Some(ref path) = self.epub_cover_image {
+            options.push(PandocOption::EpubCoverImage(path.clone()));
+        }
+        if let Some(ref path) = self.epub_metadata {
+            options.push(PandocOption::EpubMetadata(path.clone()));
+        }
+        if let Some(ref engine) = self.pdf_engine {
+            options.push(PandocOption::PdfEngine(PathBuf::from(engine)));
+        }
+        options
+    }
+}
 
 impl GeneratorBuilder for PandocGeneratorBuilder {
     type Built = PandocGenrator;
 
     async fn build(&self, output_path: &Path) -> Result<Self::Built, TranslationError> {
+        let is_docx = |path: &Path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("docx"));
+        let docx_output = is_docx(output_path);
+        if docx_output
+            && let Some(ref reference_doc) = self.options.reference_doc
+            && is_docx(reference_doc)
+        {
+            self.options.validate_reference_doc(reference_doc)?;
+        }
+
         let translated_md_path = output_path.with_extension("md");
 
         // Created from scratch every time
@@ -22,6 +142,15 @@ impl GeneratorBuilder for PandocGeneratorBuilder {
             .map_err(TranslationError::IoError)?;
 
         Ok(PandocGenrator {
+            bilingual: self.bilingual,
+            aligner: match self.bilingual {
+                Some(_) => self.aligner.build(),
+                None => Box::new(crate::align::HeuristicAligner),
+            },
+            headings: HeadingNumberer::new(self.headings.clone()),
+            body_style: self.options.body_style.clone().filter(|_| docx_output),
+            quote_style: self.options.quote_style.clone().filter(|_| docx_output),
+            options: self.options.to_pandoc(),
             output_path: output_path.to_owned(),
             translated_md_path,
             translated_md_file,
@@ -30,13 +159,26 @@ impl GeneratorBuilder for PandocGeneratorBuilder {
 }
 
 pub struct PandocGenrator {
+    bilingual: Option<BilingualStyle>,
+    aligner: Box<dyn Aligner>,
+    headings: HeadingNumberer,
+    body_style: Option<String>,
+    quote_style: Option<String>,
+    options: Vec<PandocOption>,
     output_path: PathBuf,
     translated_md_path: PathBuf,
     translated_md_file: File,
 }
 
 impl Generator for PandocGenrator {
-    async fn write(&mut self, md: MarkdownSection) -> Result<(), TranslationError> {
+    async fn write(&mut self, src: &MarkdownSection, md: MarkdownSection) -> Result<(), TranslationError> {
+        let md = self.headings.renumber(md);
+        let md = match self.bilingual {
+            Some(style) => interleave(style, self.aligner.as_ref(), src, &md),
+            None => md,
+        };
+        let md = apply_styles(md, self.body_style.as_deref(), self.quote_style.as_deref());
+
         self.translated_md_file
             .write_all(md.0.iter().map(|ss| &ss.0).join("\n").as_bytes())
             .await?;
@@ -49,22 +191,105 @@ impl Generator for PandocGenrator {
     }
 
     async fn finalize(&mut self) -> Result<(), TranslationError> {
+        // Pending writes have to land before pandoc reads the file
+        self.translated_md_file.flush().await?;
+
         let translated_md_path = self.translated_md_path.clone();
         let output_path = self.output_path.clone();
+        let options = self.options.clone();
 
         // If output file itself is Markdown, no need to run pandoc
         if translated_md_path != output_path {
             tokio::task::spawn_blocking(move || {
-                let mut pandoc = pandoc::new();
-                pandoc.add_input(&translated_md_path);
-                pandoc.set_output(OutputKind::File(output_path));
-                pandoc.execute()
+                execute_pandoc(&translated_md_path, Some(&output_path), |pandoc| {
+                    pandoc.add_options(&options);
+                })
             })
             .await
             .map_err(|e| TranslationError::OtherError(e.into()))?
-            .map_err(|e| TranslationError::OtherError(e.into()))?;
+            .map_err(TranslationError::OtherError)?;
         }
 
         Ok(())
     }
 }
+
+/// Names of the paragraph styles defined in a DOCX file
+fn docx_paragraph_styles(path: &Path) -> anyhow::Result<Vec<String>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file).with_context(|| format!("{} is not a DOCX file", path.display()))?;
+    let mut styles = String::new();
+    archive
+        .by_name("word/styles.xml")
+        .with_context(|| format!("{} has no styles", path.display()))?
+        .read_to_string(&mut styles)?;
+    Ok(paragraph_style_names(&styles))
+}
+
+fn paragraph_style_names(styles_xml: &str) -> Vec<String> {
+    PARAGRAPH_STYLE_REGEX.captures_iter(styles_xml).map(|c| c[1].to_owned()).collect()
+}
+
+/// Puts plain paragraphs and block quotes into pandoc divs with the given custom DOCX styles
+fn apply_styles(md: MarkdownSection, body_style: Option<&str>, quote_style: Option<&str>) -> MarkdownSection {
+    if body_style.is_none() && quote_style.is_none() {
+        return md;
+    }
+    let styled = |style: &str, text: &str| format!("::: {{custom-style=\"{style}\"}}\n{text}\n:::");
+    let subsections = md.0.into_iter().map(|ss| {
+        let text = ss.0.trim();
+        let is_quote = text.lines().all(|line| line.starts_with('>'));
+        match (is_quote, quote_style, body_style) {
+            (true, Some(style), _) => {
+                let unquoted = text
+                    .lines()
+                    .map(|line| line.strip_prefix('>').unwrap_or(line))
+                    .map(|line| line.strip_prefix(' ').unwrap_or(line))
+                    .join("\n");
+                MarkdownSubsection(styled(style, &unquoted))
+            }
+            (false, _, Some(style)) if is_plain_paragraph(text) => MarkdownSubsection(styled(style, text)),
+            _ => ss,
+        }
+    });
+    MarkdownSection(subsections.collect())
+}
+
+/// Not a heading, list, table, code, quote, HTML or any other block with a style of its own
+fn is_plain_paragraph(text: &str) -> bool {
+    let starts_block = text.starts_with(['#', '>', '-', '*', '+', '|', '`', '~', '<', ':', '=', ' ', '\t']);
+    !text.is_empty() && !starts_block && !ORDERED_LIST_REGEX.is_match(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_styles() {
+        let styles_xml = r#"<w:styles>
+            <w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/></w:style>
+            <w:style w:type="character" w:styleId="Strong"><w:name w:val="Strong"/></w:style>
+            <w:style w:type="paragraph" w:customStyle="1" w:styleId="ClientQuote"><w:name w:val="Client Quote"/></w:style>
+        </w:styles>"#;
+        assert_eq!(paragraph_style_names(styles_xml), vec!["heading 1", "Client Quote"]);
+
+        let md = MarkdownSection(
+            ["# Title", "Some text.", "> Quoted\n> text", "- item", "1. item"]
+                .into_iter()
+                .map(|s| MarkdownSubsection(s.to_owned()))
+                .collect(),
+        );
+        let styled = apply_styles(md, Some("Client Body"), Some("Client Quote"));
+        assert_eq!(
+            styled.0.iter().map(|ss| ss.0.as_str()).collect_vec(),
+            vec![
+                "# Title",
+                "::: {custom-style=\"Client Body\"}\nSome text.\n:::",
+                "::: {custom-style=\"Client Quote\"}\nQuoted\ntext\n:::",
+                "- item",
+                "1. item",
+            ]
+        );
+    }
+}
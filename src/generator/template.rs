@@ -0,0 +1,133 @@
+use super::headings::HeadingOptions;
+use crate::align::AlignerKind;
+use super::pandoc::{PandocGeneratorBuilder, PandocGenrator};
+use super::{BilingualStyle, Generator, GeneratorBuilder};
+use crate::parser::MarkdownSection;
+use crate::utils::execute_pandoc;
+use crate::TranslationError;
+
+use anyhow::anyhow;
+use pandoc::{OutputFormat, PandocOption, PandocOutput};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Marker in an HTML template to be replaced with the translated content
+pub const TEMPLATE_MARKER: &str = "<!-- rosetta:content -->";
+
+/// Puts translated content into a user-supplied skeleton document instead of a generic one.
+/// Office templates (e.g. letterhead DOCX) are used by pandoc as a reference document for styles,
+/// headers and footers, HTML templates get the content injected at [TEMPLATE_MARKER].
+pub struct TemplateGeneratorBuilder {
+    pub template: PathBuf,
+    pub bilingual: Option<BilingualStyle>,
+    pub aligner: AlignerKind,
+    pub headings: HeadingOptions,
+}
+
+enum TemplateKind {
+    ReferenceDoc,
+    Html,
+}
+
+impl GeneratorBuilder for TemplateGeneratorBuilder {
+    type Built = TemplateGenerator;
+
+    async fn build(&self, output_path: &Path) -> Result<Self::Built, TranslationError> {
+        let extension = |path: &Path| path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+        let kind = match extension(&self.template).as_deref() {
+            Some("docx" | "odt" | "pptx") => TemplateKind::ReferenceDoc,
+            Some("html" | "htm") if matches!(extension(output_path).as_deref(), Some("html" | "htm")) => {
+                TemplateKind::Html
+            }
+            Some("html" | "htm") => {
+                return Err(TranslationError::OtherError(anyhow!("HTML template needs an HTML output file")));
+            }
+            _ => {
+                return Err(TranslationError::OtherError(anyhow!(
+                    "Unsupported template {}, expected DOCX, ODT, PPTX or HTML",
+                    self.template.display()
+                )));
+            }
+        };
+        if matches!(kind, TemplateKind::Html) && !fs::read_to_string(&self.template).await?.contains(TEMPLATE_MARKER) {
+            return Err(TranslationError::OtherError(anyhow!(
+                "HTML template has no {TEMPLATE_MARKER} marker to put the content at"
+            )));
+        }
+
+        // Markdown is written as usual, it's only converted differently
+        let translated_md_path = output_path.with_extension("md");
+        if translated_md_path == output_path {
+            return Err(TranslationError::OtherError(anyhow!("Templates can't be used for Markdown output")));
+        }
+        let md = PandocGeneratorBuilder {
+            bilingual: self.bilingual,
+            aligner: self.aligner,
+            headings: self.headings.clone(),
+            options: Default::default(),
+        }
+        .build(&translated_md_path)
+        .await?;
+
+        Ok(TemplateGenerator {
+            md,
+            kind,
+            template: self.template.clone(),
+            translated_md_path,
+            output_path: output_path.to_owned(),
+        })
+    }
+}
+
+pub struct TemplateGenerator {
+    md: PandocGenrator,
+    kind: TemplateKind,
+    template: PathBuf,
+    translated_md_path: PathBuf,
+    output_path: PathBuf,
+}
+
+impl Generator for TemplateGenerator {
+    async fn write(&mut self, src: &MarkdownSection, md: MarkdownSection) -> Result<(), TranslationError> {
+        self.md.write(src, md).await
+    }
+
+    async fn finalize(&mut self) -> Result<(), TranslationError> {
+        // Markdown output needs no conversion, so this only flushes it
+        self.md.finalize().await?;
+
+        let translated_md_path = self.translated_md_path.clone();
+        let template = self.template.clone();
+        match self.kind {
+            TemplateKind::ReferenceDoc => {
+                let output_path = self.output_path.clone();
+                tokio::task::spawn_blocking(move || {
+                    execute_pandoc(&translated_md_path, Some(&output_path), |pandoc| {
+                        pandoc.add_option(PandocOption::ReferenceDoc(template));
+                    })
+                })
+                .await
+                .map_err(|e| TranslationError::OtherError(e.into()))?
+                .map_err(TranslationError::OtherError)?;
+            }
+            TemplateKind::Html => {
+                let output = tokio::task::spawn_blocking(move || {
+                    execute_pandoc(&translated_md_path, None, |pandoc| {
+                        pandoc.set_output_format(OutputFormat::Html5, vec![]);
+                    })
+                })
+                .await
+                .map_err(|e| TranslationError::OtherError(e.into()))?
+                .map_err(TranslationError::OtherError)?;
+                let PandocOutput::ToBuffer(content) = output else {
+                    return Err(TranslationError::OtherError(anyhow!("Unexpected pandoc output")));
+                };
+
+                let html = fs::read_to_string(&self.template).await?.replacen(TEMPLATE_MARKER, &content, 1);
+                fs::write(&self.output_path, html).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use super::{Generator, GeneratorBuilder};
+use crate::parser::transcript::split_speaker_label;
+use crate::parser::MarkdownSection;
+use crate::TranslationError;
+
+use itertools::Itertools;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// Writes translated utterances as plain text, one per line, restoring the speaker labels from the source.
+pub struct TranscriptGeneratorBuilder;
+
+impl GeneratorBuilder for TranscriptGeneratorBuilder {
+    type Built = TranscriptGenerator;
+
+    async fn build(&self, output_path: &Path) -> Result<Self::Built, TranslationError> {
+        // Created from scratch every time
+        let output_file = File::create(output_path)
+            .await
+            .map_err(TranslationError::IoError)?;
+
+        Ok(TranscriptGenerator { output_file })
+    }
+}
+
+pub struct TranscriptGenerator {
+    output_file: File,
+}
+
+impl Generator for TranscriptGenerator {
+    async fn write(&mut self, src: &MarkdownSection, md: MarkdownSection) -> Result<(), TranslationError> {
+        let label = src.0.first().map_or("", |ss| split_speaker_label(&ss.0).0.trim_end());
+        let text = md.0.iter().map(|ss| &ss.0).join(" ");
+
+        let line = match (label.is_empty(), text.is_empty()) {
+            (true, _) => text,
+            (false, true) => label.to_owned(),
+            (false, false) => format!("{label} {text}"),
+        };
+
+        self.output_file.write_all(line.as_bytes()).await?;
+        self.output_file.write_all("\n".as_bytes()).await?;
+
+        Ok(())
+    }
+
+    async fn finalize(&mut self) -> Result<(), TranslationError> {
+        self.output_file.flush().await?;
+        Ok(())
+    }
+}
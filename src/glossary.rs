@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/// Mandated translation of a term
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub source: String,
+    pub target: String,
+}
+
+/// Parses a glossary of `source = target` or tab-separated lines, skipping blank lines and `#` comments.
+pub fn parse_glossary(text: &str) -> Vec<GlossaryEntry> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('\t').or_else(|| line.split_once('=')))
+        .map(|(source, target)| GlossaryEntry {
+            source: source.trim().to_owned(),
+            target: target.trim().to_owned(),
+        })
+        .filter(|entry| !entry.source.is_empty() && !entry.target.is_empty())
+        .collect()
+}
+
+/// Entries whose source term occurs in the text, ignoring case
+pub fn relevant_entries<'a>(glossary: &'a [GlossaryEntry], text: &str) -> Vec<&'a GlossaryEntry> {
+    let text = text.to_lowercase();
+    glossary.iter().filter(|entry| text.contains(&entry.source.to_lowercase())).collect()
+}
+
+/// Entries whose source term occurs in the source text, but whose target term isn't found in the translation.
+/// Words of the target term only need to match by their beginning, to allow for inflections.
+pub fn missing_terms<'a>(glossary: &'a [GlossaryEntry], src: &str, dst: &str) -> Vec<&'a GlossaryEntry> {
+    let dst = dst.to_lowercase();
+    relevant_entries(glossary, src)
+        .into_iter()
+        .filter(|entry| {
+            !entry.target.to_lowercase().split_whitespace().all(|word| {
+                let chars = word.chars().count();
+                let stem_len = chars.min(3.max(chars * 2 / 3));
+                dst.contains(&word.chars().take(stem_len).collect::<String>())
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inflected_terms_are_not_missing() {
+        let glossary = parse_glossary("# Terms\nrelease train = релизный поезд\nsprint\tспринт\n\nbroken line");
+        assert_eq!(glossary.len(), 2);
+
+        let src = "The release train leaves after each Sprint.";
+        let missing = missing_terms(&glossary, src, "Релизный поезд уходит после каждого спринта.");
+        assert!(missing.is_empty(), "{missing:?}");
+
+        let missing = missing_terms(&glossary, src, "Поезд выпуска уходит после каждого спринта.");
+        assert_eq!(missing, vec![&glossary[0]]);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::parser::MarkdownSection;
+use crate::TranslationError;
+
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// Checks translated text with a LanguageTool server, see https://languagetool.org/http-api/
+pub struct GrammarChecker {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarIssue {
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Display for GrammarIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.suggestion {
+            Some(ref suggestion) => write!(f, "{} (suggested: \"{}\")", self.message, suggestion),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckResponse {
+    matches: Vec<CheckMatch>,
+}
+
+#[derive(Deserialize)]
+struct CheckMatch {
+    message: String,
+    #[serde(default)]
+    replacements: Vec<Replacement>,
+}
+
+#[derive(Deserialize)]
+struct Replacement {
+    value: String,
+}
+
+impl GrammarChecker {
+    pub fn new(base_url: &str) -> Self {
+        GrammarChecker {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Checks all subsections of the section at once, language is detected by the server.
+    pub async fn check_section(&self, section: &MarkdownSection) -> Result<Vec<GrammarIssue>, TranslationError> {
+        let text = section.0.iter().filter(|ss| !ss.is_annotation()).map(|ss| &ss.0).join("\n\n");
+        if text.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let response = self.client
+            .post(format!("{}/v2/check", self.base_url))
+            .form(&[("text", text.as_str()), ("language", "auto")])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| TranslationError::OtherError(anyhow!("Grammar check failed: {e}")))?;
+        let response: CheckResponse = response
+            .json()
+            .await
+            .map_err(|e| TranslationError::OtherError(anyhow!("Malformed grammar check response: {e}")))?;
+
+        Ok(response
+            .matches
+            .into_iter()
+            .map(|m| GrammarIssue {
+                message: m.message,
+                suggestion: m.replacements.into_iter().next().map(|r| r.value),
+            })
+            .collect())
+    }
+}
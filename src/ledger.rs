@@ -0,0 +1,64 @@
+use crate::llm::TokenUsage;
+use crate::utils::data_dir;
+use crate::TranslationError;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Run of the usage ledger, stored as a line of JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub finished_at: String,
+    /// Client the run is billed to, empty if none
+    pub billing_project: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In USD, if known
+    pub cost: Option<f64>,
+}
+
+/// Log of the tokens spent by each run, shared by all runs of the install, so that the costs can be attributed
+/// per client when one install translates for many of them. Entries are only ever appended.
+pub struct UsageLedger {
+    path: PathBuf,
+}
+
+impl UsageLedger {
+    /// Relative path is taken relative to the per-user data directory, wherever the run is started from
+    pub fn new(path: PathBuf) -> Self {
+        let path = match data_dir() {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        UsageLedger { path }
+    }
+
+    pub async fn record(
+        &self,
+        billing_project: &str,
+        input: &Path,
+        output: &Path,
+        usage: &TokenUsage,
+    ) -> Result<(), TranslationError> {
+        let entry = LedgerEntry {
+            finished_at: chrono::Local::now().to_rfc3339(),
+            billing_project: billing_project.trim().to_owned(),
+            input: input.to_owned(),
+            output: output.to_owned(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: usage.cost,
+        };
+        let mut line = serde_json::to_string(&entry).map_err(|e| TranslationError::OtherError(e.into()))?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
@@ -1,65 +1,790 @@
 #![allow(async_fn_in_trait)]
 
+pub mod align;
+pub mod bundle;
 pub mod cache;
+pub mod checkpoint;
+pub mod fiction;
 pub mod generator;
+pub mod glossary;
+pub mod grammar;
+pub mod ledger;
 pub mod llm;
+pub mod manifest;
+pub mod masking;
+pub mod moderation;
 pub mod parser;
+pub mod review;
+pub mod sampling;
+pub mod update;
 pub mod utils;
 
-use crate::generator::{Generator, GeneratorBuilder};
+use crate::generator::{BilingualStyle, Generator, GeneratorBuilder};
 use crate::llm::{LLMBuilder, LLM};
 use crate::parser::{MarkdownSection, MarkdownSubsection, Parser};
 use config::Config;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs;
-use std::path::Path;
-use crate::cache::Cache;
-use crate::utils::substr_up_to_len;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::cache::{Cache, CacheBuilder};
+use crate::manifest::RunManifest;
+use crate::utils::{
+    detect_language, estimate_tokens, first_line, is_echo, is_same_language, log_preview, markdown_mismatch,
+    placeholders, split_sentences,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 pub const MAX_LOG_SRC_LEN: usize = 100;
 
+const DEFAULT_MAX_SECTION_LEN: usize = 4000;
+
 pub async fn translate(
     settings: Config,
     input: &Path,
     output: &Path,
     cfg: TranslationConfig,
-    send_progress: impl SendProgress,
+    send_progress: impl SendProgress + 'static,
+    partial_export: PartialExport,
 ) -> Result<(), TranslationError> {
-    let parser = parser::pandoc::PandocParser {
-        max_section_len: 4000,
-        skip_if_present: true
+    fs::create_dir_all(output.parent().expect("output parent"))
+        .map_err(TranslationError::IoError)?;
+    let settings = with_billing_project(settings, &cfg.billing_project)?;
+    let manifest = RunManifest {
+        input: input.to_owned(),
+        output: output.to_owned(),
+        provider: provider(&settings),
+        model: configured_model(&settings).unwrap_or_default(),
+        prompt_hash: llm::prompt_hash(&cfg),
+        prompt: llm::system_prompt(&cfg),
+        rosetta_version: env!("CARGO_PKG_VERSION").to_owned(),
+        cfg: TranslationConfig {
+            // A seed the provider doesn't send wouldn't make a reproduced run any closer to this one
+            seed: cfg.seed.filter(|_| supports_seed(&settings)),
+            ..cfg.clone()
+        },
+    };
+    record_run(&manifest, &send_progress)?;
+    manifest.save().await?;
+
+    if cfg.transcript {
+        let parser = parser::transcript::TranscriptParser {
+            max_section_len: DEFAULT_MAX_SECTION_LEN,
+        };
+        let generator_builder = generator::transcript::TranscriptGeneratorBuilder;
+        translate_with(settings, parser, generator_builder, input, output, cfg, send_progress, partial_export).await
+    } else if let Some(format) = parser::localization::LocalizationFormat::from_path(input) {
+        let parser = parser::localization::LocalizationParser {
+            max_section_len: DEFAULT_MAX_SECTION_LEN,
+            format,
+        };
+        let generator_builder = generator::localization::LocalizationGeneratorBuilder {
+            input: input.to_owned(),
+            format,
+        };
+        translate_with(settings, parser, generator_builder, input, output, cfg, send_progress, partial_export).await
+    } else if let Some(format) = parser::data::DataFormat::from_path(input, cfg.data_keys.clone()) {
+        let parser = parser::localization::LocalizationParser {
+            max_section_len: DEFAULT_MAX_SECTION_LEN,
+            format: format.clone(),
+        };
+        let generator_builder = generator::localization::LocalizationGeneratorBuilder {
+            input: input.to_owned(),
+            format,
+        };
+        translate_with(settings, parser, generator_builder, input, output, cfg, send_progress, partial_export).await
+    } else if let Some(template) = cfg.template.clone() {
+        let generator_builder = generator::template::TemplateGeneratorBuilder {
+            template,
+            bilingual: cfg.bilingual,
+            aligner: aligner_kind(&settings),
+            headings: cfg.headings.for_language(&cfg.dst_lang),
+        };
+        let parser = default_parser(&settings);
+        translate_with(settings, parser, generator_builder, input, output, cfg, send_progress, partial_export).await
+    } else {
+        let generator_builder = generator::pandoc::PandocGeneratorBuilder {
+            bilingual: cfg.bilingual,
+            aligner: aligner_kind(&settings),
+            headings: cfg.headings.for_language(&cfg.dst_lang),
+            options: pandoc_options(&settings).overridden_by(&cfg.pandoc),
+        };
+        let parser = default_parser(&settings);
+        translate_with(settings, parser, generator_builder, input, output, cfg, send_progress, partial_export).await
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn translate_with<P: Parser, GB: GeneratorBuilder>(
+    settings: Config,
+    parser: P,
+    generator_builder: GB,
+    input: &Path,
+    output: &Path,
+    cfg: TranslationConfig,
+    send_progress: impl SendProgress + 'static,
+    partial_export: PartialExport,
+) -> Result<(), TranslationError> {
+    let llm_builder = translation_llm_builder(&settings)?;
+    let draft_llm_builder = draft_llm_builder(&settings)?;
+
+    let send_progress = Arc::new(send_progress);
+    let events = send_progress.clone();
+    let limits = cache_limits(&settings);
+    let grammar_checker = settings
+        .get_string("grammar.languagetool_url")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| grammar::GrammarChecker::new(&url));
+    let content_screener = content_screener(&settings);
+    let prices = token_prices(&settings);
+    let usage_ledger = settings
+        .get_string("billing.ledger")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .map(|path| ledger::UsageLedger::new(PathBuf::from(path)));
+    let stale_fallback = settings.get_bool("llm.stale_fallback").unwrap_or(false);
+    let batch_subsections = settings.get_int("llm.batch_subsections").map_or(1, |n| n.max(1) as usize);
+
+    let checkpoints = match settings.get_string("checkpoints.socket").ok().filter(|path| !path.trim().is_empty()) {
+        Some(path) => match checkpoint::CheckpointSink::connect(Path::new(&path), input).await {
+            Ok(sink) => Some(Arc::new(sink)),
+            Err(e) => {
+                let warning = format!("{e}, going on without it");
+                log::warn!("{warning}");
+                send_progress.send_warning(Warning::Other(warning));
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(ref checkpoints) = checkpoints {
+        checkpoints.send(checkpoint::Phase::Started).await;
+    }
+    let start = Instant::now();
+
+    let result = match settings.get_string("cache.remote_url").ok().filter(|url| !url.is_empty()) {
+        Some(base_url) => {
+            let cache_builder = cache::RemoteCacheBuilder {
+                base_url,
+                token: settings.get_string("cache.remote_token").ok(),
+                limits,
+                request_timeout: remote_cache_timeout(&settings),
+                proxy: proxy_config(&settings)?,
+            };
+            let translator = LlmTranslationService {
+                parser,
+                llm_builder,
+                generator_builder,
+                cache_builder,
+                draft_llm_builder,
+                grammar_checker,
+                content_screener,
+                prices,
+                stale_fallback,
+                batch_subsections,
+                checkpoints: checkpoints.clone(),
+                usage_ledger,
+                send_progress,
+                partial_export,
+            };
+            translator.translate(input, output, cfg.clone()).await
+        }
+        None => {
+            let translator = LlmTranslationService {
+                parser,
+                llm_builder,
+                generator_builder,
+                cache_builder: cache::SqliteCacheBuilder { limits },
+                draft_llm_builder,
+                grammar_checker,
+                content_screener,
+                prices,
+                stale_fallback,
+                batch_subsections,
+                checkpoints: checkpoints.clone(),
+                usage_ledger,
+                send_progress,
+                partial_export,
+            };
+            translator.translate(input, output, cfg.clone()).await
+        }
+    };
+    let result = match result {
+        Ok(()) if settings.get_bool("delivery.bundle").unwrap_or(false) => {
+            bundle::make_bundle(&settings, input, output, &cfg).await.map(|path| {
+                let info = format!("Deliverable bundle is saved to {}", path.display());
+                log::info!("{info}");
+                events.send_info(info);
+            })
+        }
+        result => result,
     };
 
+    if let Some(checkpoints) = checkpoints {
+        let phase = match result {
+            Ok(()) => checkpoint::Phase::Finished { duration_ms: start.elapsed().as_millis() as u64 },
+            Err(ref e) => checkpoint::Phase::Failed { error: e.to_string() },
+        };
+        checkpoints.send(phase).await;
+    }
+    result
+}
+
+/// Keeps a snapshot of the run setup in the local cache of the output, which outlives the run manifest
+/// being overwritten or lost. Warns if the previous run writing there had another setup,
+/// since the translations it left in the cache will be mixed with new ones.
+fn record_run(manifest: &RunManifest, send_progress: &impl SendProgress) -> Result<(), TranslationError> {
+    let cfg = &manifest.cfg;
+    let run_db = cache::SqliteCache::new(&manifest.output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)?;
+    let previous = run_db
+        .last_run()?
+        .and_then(|snapshot| serde_json::from_str::<RunManifest>(&snapshot).ok())
+        .or_else(|| RunManifest::find_previous(&manifest.input, &manifest.output));
+    if let Some(previous) = previous {
+        let differences = manifest.differences(&previous);
+        if !differences.is_empty() {
+            let warning = format!(
+                "Previous run into this output differs ({}), its cached translations will be mixed with new ones",
+                differences.join("; ")
+            );
+            log::warn!("{warning}");
+            send_progress.send_warning(Warning::Other(warning));
+        }
+    }
+    let snapshot = serde_json::to_string(manifest).map_err(|e| TranslationError::OtherError(e.into()))?;
+    run_db.record_run(&snapshot)
+}
+
+/// Re-executes a recorded run with the same config and model into a separate output, bypassing the cache.
+pub async fn reproduce(
+    settings: Config,
+    manifest_path: &Path,
+    send_progress: impl SendProgress + 'static,
+) -> Result<(), TranslationError> {
+    let manifest = RunManifest::load(manifest_path).await?;
+    if llm::prompt_hash(&manifest.cfg) != manifest.prompt_hash {
+        let warning = "Prompt has changed since the recorded run, results may differ".to_owned();
+        log::warn!("{warning}");
+        send_progress.send_warning(Warning::Other(warning));
+    }
+
+    let settings = Config::builder()
+        .add_source(settings)
+        .set_override("llm.provider", manifest.provider.settings_section())
+        .and_then(|builder| {
+            builder.set_override(format!("{}.model", manifest.provider.settings_section()), manifest.model.clone())
+        })
+        // Failing over or judging would make it a run of other models
+        .and_then(|builder| builder.set_override("llm.fallback_providers", Vec::<String>::new()))
+        .and_then(|builder| builder.set_override("llm.ensemble_providers", Vec::<String>::new()))
+        .and_then(|builder| builder.build())
+        .map_err(|e| TranslationError::OtherError(e.into()))?;
+
+    let output = manifest.reproduction_output();
+    // Cache left by a previous reproduction would turn this one into a no-op
+    let cache_path = output.with_extension("sqlite");
+    if cache_path.exists() {
+        fs::remove_file(&cache_path)?;
+    }
+
+    translate(settings, &manifest.input, &output, manifest.cfg, send_progress, PartialExport::default()).await
+}
+
+/// Prunes the local cache of the given output according to the configured limits, and shrinks its file.
+pub async fn compact_cache(
+    settings: Config,
+    output: &Path,
+    cfg: &TranslationConfig,
+) -> Result<cache::CompactStats, TranslationError> {
+    let mut cache = cache::SqliteCache::new(&output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)?;
+    cache.compact(cache_limits(&settings))
+}
+
+fn pandoc_options(settings: &Config) -> generator::pandoc::PandocOutputOptions {
+    settings
+        .get::<generator::pandoc::PandocOutputOptions>("generator.pandoc")
+        .unwrap_or_else(|e| {
+            if !matches!(e, config::ConfigError::NotFound(_)) {
+                log::warn!("Ignoring malformed generator.pandoc settings: {}", e);
+            }
+            Default::default()
+        })
+}
+
+fn cache_limits(settings: &Config) -> cache::CacheLimits {
+    let get_positive = |key: &str| settings.get_int(key).ok().filter(|&v| v > 0).map(|v| v as u64);
+    cache::CacheLimits {
+        max_size_mb: get_positive("cache.max_size_mb"),
+        max_age_days: get_positive("cache.max_age_days"),
+    }
+}
+
+/// Content pre-screen enabled by `moderation.prescreen`, using `moderation.api_key` or the OpenAI one
+fn content_screener(settings: &Config) -> Option<moderation::ContentScreener> {
+    if !settings.get_bool("moderation.prescreen").unwrap_or(false) {
+        return None;
+    }
     let api_key = settings
-        .get_string("openai.api_key")
+        .get_string("moderation.api_key")
+        .ok()
+        .filter(|key| !key.trim().is_empty())
+        .or_else(|| utils::secrets::api_key(settings, llm::Provider::OpenAi));
+    if api_key.is_none() {
+        log::warn!("Content pre-screen needs an OpenAI API key, skipping it");
+    }
+    api_key.map(moderation::ContentScreener::new)
+}
+
+/// Sends a minimal request through the configured provider, returning its latency.
+pub async fn check_provider(settings: Config) -> Result<Duration, TranslationError> {
+    llm_builder(&settings, provider(&settings))?
+        .health_check()
+        .await
+        .map_err(TranslationError::LLMError)
+}
+
+/// Releases provider resources left over by runs that were interrupted, returning their number.
+pub async fn cleanup_provider(settings: Config) -> Result<usize, TranslationError> {
+    llm_builder(&settings, provider(&settings))?
+        .cleanup()
+        .await
+        .map_err(TranslationError::LLMError)
+}
+
+/// Provider chosen by `llm.provider` setting, OpenAI if not set
+pub fn provider(settings: &Config) -> llm::Provider {
+    settings.get::<llm::Provider>("llm.provider").unwrap_or_default()
+}
+
+/// Model of the chosen provider
+pub fn configured_model(settings: &Config) -> Option<String> {
+    if provider(settings) == llm::Provider::OpenAi
+        && settings.get_string("openai.azure_endpoint").is_ok_and(|e| !e.trim().is_empty())
+    {
+        return settings.get_string("openai.azure_deployment").ok();
+    }
+    settings.get_string(&format!("{}.model", provider(settings).settings_section())).ok()
+}
+
+/// Context size, output limit, capabilities and prices of the model of the chosen provider, if it's a well-known one
+pub fn configured_model_info(settings: &Config) -> Option<llm::ModelInfo> {
+    any_llm_builder(settings, provider(settings)).ok()?.model_info()
+}
+
+/// Machine translation provider making drafts for the main one to post-edit, set by `llm.draft_provider`
+fn draft_llm_builder(settings: &Config) -> Result<Option<ProviderLLMBuilder>, TranslationError> {
+    match settings.get_string("llm.draft_provider") {
+        Ok(draft_provider) if !draft_provider.trim().is_empty() => {
+            let draft_provider = settings
+                .get::<llm::Provider>("llm.draft_provider")
+                .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
+            llm_builder(settings, draft_provider).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Configured provider, joined by the ones set by `llm.ensemble_providers` to judge their translations,
+/// along with the ones to fail over to, set by `llm.fallback_providers`
+fn translation_llm_builder(
+    settings: &Config,
+) -> Result<llm::fallback::FallbackLLMBuilder<llm::ensemble::EnsembleLLMBuilder<ProviderLLMBuilder>>, TranslationError>
+{
+    let primary = provider(settings);
+    let name = primary.settings_section().to_owned();
+    let mut ensemble = llm::ensemble::EnsembleLLMBuilder::new(name.clone(), llm_builder(settings, primary)?);
+    let members = providers_setting(settings, "llm.ensemble_providers")?;
+    let is_machine_translation = matches!(primary, llm::Provider::DeepL | llm::Provider::LibreTranslate);
+    if is_machine_translation && members.iter().any(|&p| p != primary) {
+        return Err(TranslationError::OtherError(anyhow::anyhow!(
+            "{} can't judge ensemble translations, make another provider the main one",
+            primary.name()
+        )));
+    }
+    for member in members.into_iter().filter(|&p| p != primary).unique() {
+        ensemble = ensemble.with_member(member.settings_section().to_owned(), llm_builder(settings, member)?);
+    }
+
+    let max_latency = settings.get_int("llm.max_section_latency_secs").ok().filter(|&secs| secs > 0);
+    let mut builder = llm::fallback::FallbackLLMBuilder::new(name, ensemble)
+        .with_max_latency(max_latency.map(|secs| Duration::from_secs(secs as u64)));
+    let fallbacks = providers_setting(settings, "llm.fallback_providers")?;
+    for fallback in fallbacks.into_iter().filter(|&p| p != primary).unique() {
+        let name = fallback.settings_section().to_owned();
+        let ensemble = llm::ensemble::EnsembleLLMBuilder::new(name.clone(), llm_builder(settings, fallback)?);
+        builder = builder.with_fallback(name, ensemble);
+    }
+    Ok(builder)
+}
+
+fn providers_setting(settings: &Config, key: &str) -> Result<Vec<llm::Provider>, TranslationError> {
+    match settings.get::<Vec<llm::Provider>>(key) {
+        Ok(providers) => Ok(providers),
+        Err(config::ConfigError::NotFound(_)) => Ok(vec![]),
+        Err(e) => Err(TranslationError::OtherError(anyhow::Error::new(e))),
+    }
+}
+
+/// `<provider>.request_timeout_secs`, or `llm.request_timeout_secs` if not set, no timeout if 0
+fn request_timeout(settings: &Config, provider: llm::Provider) -> Option<Duration> {
+    let secs = settings
+        .get_int(&format!("{}.request_timeout_secs", provider.settings_section()))
+        .or_else(|_| settings.get_int("llm.request_timeout_secs"))
+        .unwrap_or(0);
+    (secs > 0).then(|| Duration::from_secs(secs as u64))
+}
+
+/// `cache.remote_timeout_secs`, [cache::DEFAULT_REMOTE_TIMEOUT] if not set, no timeout if 0
+fn remote_cache_timeout(settings: &Config) -> Option<Duration> {
+    let secs = settings.get_int("cache.remote_timeout_secs").ok();
+    match secs {
+        Some(secs) => (secs > 0).then(|| Duration::from_secs(secs as u64)),
+        None => Some(cache::DEFAULT_REMOTE_TIMEOUT),
+    }
+}
+
+type ProviderLLMBuilder = llm::rate_limit::RateLimitedLLMBuilder<llm::AnyLLMBuilder>;
+
+/// `<provider>.requests_per_minute` and `<provider>.tokens_per_minute`, no limit if 0
+fn rate_limits(settings: &Config, provider: llm::Provider) -> llm::rate_limit::RateLimits {
+    let get_positive = |key: &str| {
+        let key = format!("{}.{key}", provider.settings_section());
+        settings.get_int(&key).ok().filter(|&v| v > 0).map(|v| v as usize)
+    };
+    llm::rate_limit::RateLimits {
+        requests_per_minute: get_positive("requests_per_minute"),
+        tokens_per_minute: get_positive("tokens_per_minute"),
+    }
+}
+
+/// Prices of a provider in USD per million tokens
+#[derive(Debug, Clone, Copy)]
+struct TokenPrices {
+    input: f64,
+    output: f64,
+}
+
+impl TokenPrices {
+    fn cost(&self, usage: &llm::TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// Settings with `billing.project` set to the client the run is billed to, for the providers to tag requests with
+fn with_billing_project(settings: Config, billing_project: &str) -> Result<Config, TranslationError> {
+    if billing_project.trim().is_empty() {
+        return Ok(settings);
+    }
+    Config::builder()
+        .add_source(settings)
+        .set_override("billing.project", billing_project.trim())
+        .and_then(|builder| builder.build())
+        .map_err(|e| TranslationError::OtherError(e.into()))
+}
+
+/// OpenAI project of `billing.project` as given in `billing.openai_projects`, e.g. `acme = "proj_abc123"`
+fn openai_project(settings: &Config) -> Option<String> {
+    let billing_project = settings.get_string("billing.project").ok()?;
+    settings
+        .get_table("billing.openai_projects")
+        .ok()?
+        .remove(&billing_project)
+        .and_then(|project| project.into_string().ok())
+        .filter(|project| !project.trim().is_empty())
+}
+
+/// `<provider>.input_price` and `<provider>.output_price` of the providers having them set,
+/// or the prices of their models if they are well-known ones
+fn token_prices(settings: &Config) -> HashMap<llm::Provider, TokenPrices> {
+    use llm::Provider::*;
+    [OpenAi, Anthropic, DeepL, Mistral, OpenRouter, LibreTranslate]
+        .into_iter()
+        .filter_map(|provider| {
+            let get_price = |key: &str| settings.get_float(&format!("{}.{key}", provider.settings_section())).ok();
+            let prices = match (get_price("input_price"), get_price("output_price")) {
+                (Some(input), Some(output)) => TokenPrices { input, output },
+                _ => {
+                    let info = any_llm_builder(settings, provider).ok()?.model_info()?;
+                    TokenPrices { input: info.price_in?, output: info.price_out? }
+                }
+            };
+            Some((provider, prices))
+        })
+        .collect()
+}
+
+fn llm_builder(settings: &Config, provider: llm::Provider) -> Result<ProviderLLMBuilder, TranslationError> {
+    let builder = any_llm_builder(settings, provider)?;
+    Ok(llm::rate_limit::RateLimitedLLMBuilder::new(builder, rate_limits(settings, provider)))
+}
+
+fn any_llm_builder(settings: &Config, provider: llm::Provider) -> Result<llm::AnyLLMBuilder, TranslationError> {
+    let timeout = request_timeout(settings, provider);
+    let proxy = proxy_config(settings)?;
+    match provider {
+        llm::Provider::OpenAi => openai_builder(settings).map(|builder| {
+            llm::AnyLLMBuilder::OpenAi(
+                builder.with_request_timeout(timeout).with_proxy(proxy).with_project(openai_project(settings)),
+            )
+        }),
+        llm::Provider::Anthropic => anthropic_builder(settings).map(|builder| {
+            llm::AnyLLMBuilder::Anthropic(builder.with_request_timeout(timeout).with_proxy(proxy))
+        }),
+        llm::Provider::DeepL => {
+            let api_key = api_key(settings, provider)?;
+            let builder = llm::deepl::DeepLBuilder::new(api_key);
+            Ok(llm::AnyLLMBuilder::DeepL(builder.with_request_timeout(timeout).with_proxy(proxy)))
+        }
+        llm::Provider::Mistral => {
+            let api_key = api_key(settings, provider)?;
+            let model = settings
+                .get_string("mistral.model")
+                .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
+            let builder = llm::mistral::MistralBuilder::new(model, api_key);
+            Ok(llm::AnyLLMBuilder::Mistral(builder.with_request_timeout(timeout).with_proxy(proxy)))
+        }
+        llm::Provider::OpenRouter => {
+            let api_key = api_key(settings, provider)?;
+            let model = settings
+                .get_string("openrouter.model")
+                .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
+            let app_url = settings.get_string("openrouter.app_url").ok().filter(|v| !v.is_empty());
+            let app_title = settings.get_string("openrouter.app_title").ok().filter(|v| !v.is_empty());
+            let prompt_caching = settings.get_bool("openrouter.prompt_caching").unwrap_or(true);
+            let billing_project = settings.get_string("billing.project").ok();
+            let builder = llm::openrouter::OpenRouterBuilder::new(model, api_key, app_url, app_title)
+                .with_prompt_caching(prompt_caching)
+                .with_billing_label(billing_project);
+            Ok(llm::AnyLLMBuilder::OpenRouter(builder.with_request_timeout(timeout).with_proxy(proxy)))
+        }
+        llm::Provider::LibreTranslate => {
+            let url = settings
+                .get_string("libretranslate.url")
+                .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
+            // Self-hosted instances usually don't require keys
+            let api_key = utils::secrets::api_key(settings, provider);
+            let builder = llm::libretranslate::LibreTranslateBuilder::new(url, api_key);
+            Ok(llm::AnyLLMBuilder::LibreTranslate(builder.with_request_timeout(timeout).with_proxy(proxy)))
+        }
+    }
+}
+
+/// `proxy.http_proxy` and `proxy.https_proxy` with optional `proxy.username` and `proxy.password`,
+/// None if neither proxy is set
+fn proxy_config(settings: &Config) -> Result<Option<llm::ProxyConfig>, TranslationError> {
+    let get = |key: &str| settings.get_string(&format!("proxy.{key}")).ok().filter(|v| !v.trim().is_empty());
+    let (http_proxy, https_proxy) = (get("http_proxy"), get("https_proxy"));
+    for (key, url) in [("http_proxy", &http_proxy), ("https_proxy", &https_proxy)] {
+        if let Some(url) = url
+            && let Err(e) = reqwest::Proxy::all(url)
+        {
+            return Err(TranslationError::OtherError(anyhow::anyhow!("proxy.{key} is not a valid URL: {e}")));
+        }
+    }
+    if http_proxy.is_none() && https_proxy.is_none() {
+        return Ok(None);
+    }
+    let auth = get("username").map(|username| (username, get("password").unwrap_or_default()));
+    Ok(Some(llm::ProxyConfig { http_proxy, https_proxy, auth }))
+}
+
+/// API key of the provider, which doesn't have to be in the settings file, see [utils::secrets::api_key]
+fn api_key(settings: &Config, provider: llm::Provider) -> Result<String, TranslationError> {
+    utils::secrets::api_key(settings, provider).ok_or_else(|| {
+        TranslationError::OtherError(anyhow::anyhow!(
+            "No API key, set {}.api_key, the {} environment variable, or store it in the OS keyring \
+            under the \"{}\" service as \"{}\"",
+            provider.settings_section(),
+            utils::secrets::env_var(provider),
+            utils::secrets::KEYRING_SERVICE,
+            provider.settings_section()
+        ))
+    })
+}
+
+fn anthropic_builder(settings: &Config) -> Result<llm::anthropic::AnthropicBuilder, TranslationError> {
+    let api_key = api_key(settings, llm::Provider::Anthropic)?;
+
+    let model = settings
+        .get_string("anthropic.model")
         .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
 
+    let max_tokens = settings
+        .get_int("anthropic.max_tokens")
+        .ok()
+        .and_then(|v| u32::try_from(v).ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(llm::anthropic::DEFAULT_MAX_TOKENS);
+
+    let prompt_caching = settings.get_bool("anthropic.prompt_caching").unwrap_or(true);
+
+    Ok(llm::anthropic::AnthropicBuilder::new(model, api_key, max_tokens).with_prompt_caching(prompt_caching))
+}
+
+fn openai_builder(settings: &Config) -> Result<llm::openai::OpenAiGPTBuilder, TranslationError> {
+    // Self-hosted OpenAI-compatible servers usually don't need a key
+    let base_url = settings.get_string("openai.base_url").ok().filter(|url| !url.trim().is_empty());
+
+    // Several keys can be used in turns to spread the rate limits
+    let api_keys = match settings.get::<Vec<String>>("openai.api_keys") {
+        Ok(api_keys) if !api_keys.is_empty() => api_keys,
+        _ => match api_key(settings, llm::Provider::OpenAi) {
+            Ok(api_key) => vec![api_key],
+            Err(_) if base_url.is_some() => vec!["".to_owned()],
+            Err(e) => return Err(e),
+        },
+    };
+    let key_selection = settings
+        .get::<llm::openai::keys::KeySelection>("openai.key_selection")
+        .unwrap_or_default();
+
+    let stream = settings.get_bool("openai.stream").unwrap_or(false);
+    let json_output = settings.get_bool("openai.json_output").unwrap_or(false);
+    let batch = settings.get_bool("openai.batch").unwrap_or(false);
+    let glossary_tool = settings.get_bool("openai.glossary_tool").unwrap_or(false);
+    let history = settings
+        .get_int("openai.history_exchanges")
+        .map_or(llm::openai::DEFAULT_HISTORY_EXCHANGES, |n| n.max(0) as usize);
+    let (temperature, top_p, max_tokens) = openai_sampling(settings)?;
+    let section_timeout = settings
+        .get_int("openai.section_timeout_secs")
+        .ok()
+        .filter(|&secs| secs > 0)
+        .map(|secs| Duration::from_secs(secs as u64));
+    let reasoning_effort = match settings.get_string("openai.reasoning_effort") {
+        Ok(effort) if !effort.trim().is_empty() => Some(
+            settings
+                .get::<llm::openai::ReasoningEffort>("openai.reasoning_effort")
+                .map_err(|e| TranslationError::OtherError(anyhow::anyhow!("openai.reasoning_effort: {e}")))?,
+        ),
+        _ => None,
+    };
+
+    // Azure deployment has its model fixed, so a model name isn't needed
+    let azure_endpoint = settings.get_string("openai.azure_endpoint").unwrap_or_default();
+    if !azure_endpoint.trim().is_empty() {
+        let deployment = settings
+            .get_string("openai.azure_deployment")
+            .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
+        let api_version = settings
+            .get_string("openai.azure_api_version")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| llm::openai::azure::DEFAULT_API_VERSION.to_owned());
+        let azure = llm::openai::azure::AzureDeployment { endpoint: azure_endpoint, deployment, api_version };
+        return Ok(llm::openai::OpenAiGPTBuilder::new_azure(api_keys, key_selection, azure)
+            .with_streaming(stream)
+            .with_sampling(temperature, top_p, max_tokens)
+            .with_json_output(json_output)
+            .with_batch(batch)
+            .with_glossary_tool(glossary_tool)
+            .with_history(history)
+            .with_reasoning_effort(reasoning_effort)
+            .with_section_timeout(section_timeout));
+    }
+
     let model =
         settings
         .get_string("openai.model")
         .map_err(|e| TranslationError::OtherError(anyhow::Error::new(e)))?;
 
-    let llm_builder = llm::openai::OpenAiGPTBuilder::new(model, api_key);
+    Ok(llm::openai::OpenAiGPTBuilder::new(model, api_keys, key_selection, base_url)
+        .with_streaming(stream)
+        .with_sampling(temperature, top_p, max_tokens)
+        .with_json_output(json_output)
+        .with_batch(batch)
+        .with_glossary_tool(glossary_tool)
+        .with_history(history)
+        .with_reasoning_effort(reasoning_effort)
+        .with_section_timeout(section_timeout))
+}
 
-    let generator_builder = generator::pandoc::PandocGeneratorBuilder;
+/// Sampling parameters from `openai.temperature`, `openai.top_p` and `openai.max_tokens`, API defaults if not set
+fn openai_sampling(settings: &Config) -> Result<(f32, f32, Option<u32>), TranslationError> {
+    let temperature = settings.get_float("openai.temperature").unwrap_or(1.0);
+    if !(0.0..=2.0).contains(&temperature) {
+        return Err(TranslationError::OtherError(anyhow::anyhow!(
+            "openai.temperature must be between 0 and 2, got {temperature}"
+        )));
+    }
+    let top_p = settings.get_float("openai.top_p").unwrap_or(1.0);
+    if !(top_p > 0.0 && top_p <= 1.0) {
+        return Err(TranslationError::OtherError(anyhow::anyhow!(
+            "openai.top_p must be above 0 and at most 1, got {top_p}"
+        )));
+    }
+    let max_tokens = settings.get_int("openai.max_tokens").ok().filter(|&v| v > 0).map(|v| v as u32);
+    Ok((temperature as f32, top_p as f32, max_tokens))
+}
 
-    let translator = LlmTranslationService {
-        parser,
-        llm_builder,
-        generator_builder,
-        send_progress,
-    };
+pub(crate) fn default_parser(settings: &Config) -> parser::pandoc::PandocParser {
+    parser::pandoc::PandocParser {
+        max_section_len: DEFAULT_MAX_SECTION_LEN,
+        max_section_tokens: max_section_tokens(settings),
+        skip_if_present: true
+    }
+}
 
-    translator.translate(input, output, cfg).await
+/// Sentence alignment backend of `alignment.backend`, heuristic by default
+fn aligner_kind(settings: &Config) -> align::AlignerKind {
+    settings.get::<align::AlignerKind>("alignment.backend").unwrap_or_default()
 }
 
-#[derive(Debug, Clone)]
+/// Token budget of a section for the configured providers, see [llm::LLMBuilder::max_section_tokens]
+fn max_section_tokens(settings: &Config) -> Option<usize> {
+    translation_llm_builder(settings).ok().and_then(|builder| builder.max_section_tokens())
+}
+
+/// Whether the configured providers honor seeds, see [llm::LLMBuilder::supports_seed]
+fn supports_seed(settings: &Config) -> bool {
+    translation_llm_builder(settings).is_ok_and(|builder| builder.supports_seed())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TranslationConfig {
     pub src_lang: String,
     pub dst_lang: String,
     pub subject: String,
+    /// Kind of content, adding domain-specific constraints to the prompt
+    pub domain: Domain,
     pub tone: String,
     pub additional_instructions: String,
+    /// Sample of a previously approved translation to match the style of
+    pub style_sample: String,
+    /// Replaces the built-in system prompt, see [llm::DEFAULT_PROMPT_TEMPLATE] for the variables it can use
+    pub prompt_template: Option<String>,
+    /// Terms that have to be translated the given way, translations missing them are retried
+    pub glossary: Vec<glossary::GlossaryEntry>,
+    pub language_policy: LanguagePolicy,
+    pub bilingual: Option<BilingualStyle>,
+    /// Numbering of the output headings, applied after translation so that it's the same on every run
+    pub headings: generator::headings::HeadingOptions,
+    pub headings_first: bool,
+    /// Source is a work of fiction, dialogues need to follow the destination language conventions
+    pub fiction: bool,
+    /// Source is a speaker-labeled transcript, labels and timestamps are kept as is
+    pub transcript: bool,
+    /// Seed for providers supporting deterministic sampling, to make runs reproducible
+    pub seed: Option<u64>,
+    /// Re-translate sections with grammar issues found by the grammar checker, if one is configured
+    pub fix_grammar: bool,
+    /// Smooth the junctions of paragraphs that had to be split to be translated, with an extra request per junction
+    pub stitch_seams: bool,
+    /// Document to put the translated content into, see [generator::template::TemplateGeneratorBuilder]
+    pub template: Option<PathBuf>,
+    /// Keys, paths or column names of JSON/YAML/CSV values to translate, see [parser::data::DataFormat]
+    pub data_keys: Vec<String>,
+    pub no_translate_markers: Vec<masking::NoTranslateMarkers>,
+    /// Overrides of the `generator.pandoc` settings for this run
+    pub pandoc: generator::pandoc::PandocOutputOptions,
+    /// Client the run is billed to, if any. Requests are tagged with it where the provider supports that,
+    /// see `billing` settings, and it's recorded in the usage ledger.
+    pub billing_project: String,
 }
 
 impl Default for TranslationConfig {
@@ -68,8 +793,95 @@ impl Default for TranslationConfig {
             src_lang: "English".to_owned(),
             dst_lang: "Russian".to_owned(),
             subject: "Unknown".to_owned(),
+            domain: Domain::default(),
             tone: "formal".to_owned(),
             additional_instructions: "".to_owned(),
+            style_sample: "".to_owned(),
+            prompt_template: None,
+            glossary: vec![],
+            language_policy: LanguagePolicy::default(),
+            bilingual: None,
+            headings: Default::default(),
+            headings_first: false,
+            fiction: false,
+            transcript: false,
+            seed: None,
+            fix_grammar: false,
+            stitch_seams: false,
+            template: None,
+            data_keys: vec![],
+            no_translate_markers: masking::NoTranslateMarkers::defaults(),
+            pandoc: Default::default(),
+            billing_project: "".to_owned(),
+        }
+    }
+}
+
+/// What to do with sections whose detected language differs from the source one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LanguagePolicy {
+    /// Translate everything regardless of the detected language
+    #[default]
+    TranslateAll,
+    /// Keep sections that are already in the destination language as they are
+    SkipDestination,
+    /// Translate everything, but mark sections detected to be in a third language
+    AnnotateForeign,
+}
+
+impl LanguagePolicy {
+    pub const ALL: [LanguagePolicy; 3] = [
+        LanguagePolicy::TranslateAll,
+        LanguagePolicy::SkipDestination,
+        LanguagePolicy::AnnotateForeign,
+    ];
+}
+
+impl Display for LanguagePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LanguagePolicy::TranslateAll => write!(f, "Translate all"),
+            LanguagePolicy::SkipDestination => write!(f, "Skip already translated"),
+            LanguagePolicy::AnnotateForeign => write!(f, "Annotate foreign passages"),
+        }
+    }
+}
+
+/// Content type of the source text, each having its own translation constraints.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Domain {
+    #[default]
+    General,
+    Legal,
+    Medical,
+    Technical,
+    Marketing,
+    Literary,
+    /// User-supplied constraints
+    Custom(String),
+}
+
+impl Domain {
+    pub const PRESETS: [Domain; 6] = [
+        Domain::General,
+        Domain::Legal,
+        Domain::Medical,
+        Domain::Technical,
+        Domain::Marketing,
+        Domain::Literary,
+    ];
+}
+
+impl Display for Domain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Domain::General => write!(f, "General"),
+            Domain::Legal => write!(f, "Legal"),
+            Domain::Medical => write!(f, "Medical"),
+            Domain::Technical => write!(f, "Technical"),
+            Domain::Marketing => write!(f, "Marketing"),
+            Domain::Literary => write!(f, "Literary"),
+            Domain::Custom(_) => write!(f, "Custom"),
         }
     }
 }
@@ -110,10 +922,30 @@ impl Display for ParseError {
 pub enum LLMError {
     ConnectionError(anyhow::Error),
     ApiError(anyhow::Error),
+    /// Requests kept timing out, or the section took longer than allowed
+    Timeout(anyhow::Error),
+    /// API key is missing, malformed or revoked
+    InvalidApiKey { help_url: Option<&'static str>, source: anyhow::Error },
+    /// Account has run out of credits
+    QuotaExceeded { help_url: Option<&'static str>, source: anyhow::Error },
+    /// Model doesn't exist or isn't available for the API key
+    ModelNotFound(anyhow::Error),
+    /// Provider refused to process the text
+    ContentPolicyViolation(anyhow::Error),
     InteractionError(anyhow::Error),
     OtherError(anyhow::Error),
 }
 
+impl LLMError {
+    /// Provider page where the user can fix the problem
+    pub fn help_url(&self) -> Option<&'static str> {
+        match self {
+            LLMError::InvalidApiKey { help_url, .. } | LLMError::QuotaExceeded { help_url, .. } => *help_url,
+            _ => None,
+        }
+    }
+}
+
 impl Display for LLMError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -123,6 +955,21 @@ impl Display for LLMError {
             LLMError::ApiError(e) => {
                 write!(f, "LLM API error: {:#}", e)
             }
+            LLMError::Timeout(e) => {
+                write!(f, "LLM timed out: {:#}", e)
+            }
+            LLMError::InvalidApiKey { source, .. } => {
+                write!(f, "LLM API key was rejected, check it in the settings file: {:#}", source)
+            }
+            LLMError::QuotaExceeded { source, .. } => {
+                write!(f, "LLM quota is exhausted, add credits to the account to continue: {:#}", source)
+            }
+            LLMError::ModelNotFound(e) => {
+                write!(f, "LLM model is not available, check its name in the settings file: {:#}", e)
+            }
+            LLMError::ContentPolicyViolation(e) => {
+                write!(f, "Text was rejected by the LLM content policy, it needs to be translated manually: {:#}", e)
+            }
             LLMError::InteractionError(e) => {
                 write!(f, "LLM interaction error: {:#}", e)
             }
@@ -138,6 +985,8 @@ pub enum TranslationError {
     ParseError(ParseError),
     IoError(std::io::Error),
     DatabaseError(rusqlite::Error),
+    /// Cache was created by a newer version and can't be migrated back
+    CacheSchemaTooNew { found: u32, supported: u32 },
     LLMError(LLMError),
     OtherError(anyhow::Error),
 }
@@ -166,6 +1015,13 @@ impl Display for TranslationError {
             TranslationError::DatabaseError(e) => {
                 write!(f, "Database error: {}", e)
             }
+            TranslationError::CacheSchemaTooNew { found, supported } => {
+                write!(
+                    f,
+                    "Cache was created by a newer Rosetta version (schema version {found}, up to {supported} is supported), \
+                    update Rosetta or choose another output file"
+                )
+            }
             TranslationError::LLMError(e) => {
                 write!(f, "{}", e)
             }
@@ -180,18 +1036,130 @@ impl Display for TranslationError {
 pub enum TranslationStatus {
     Started,
     Progress(Progress),
+    /// Network went down or came back during the translation
+    Connectivity { online: bool },
+    /// Something noteworthy that doesn't stop the translation
+    Warning(Warning),
+    /// Outcome of an auxiliary action worth telling the user about
+    Info(String),
+    /// Document has no sections needing translation, so the provider wasn't used at all
+    NothingToTranslate(String),
+    /// Text of the subsection being translated, as much as has arrived so far
+    LiveText(String),
+    /// Chapters of the document, sent before its sections are translated
+    Outline { entries: Vec<OutlineEntry>, total_sections: usize },
+    /// Section has started or finished, along with its translation if finished
+    Section { section: usize, status: SectionStatus, translated: Option<String> },
     Success,
     Error(TranslationError),
 }
 
+/// Non-fatal issue, reported as soon as it occurs so that the user doesn't have to dig it out of the logs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Translation doesn't use the glossary translations of the given entries, as `source → target`
+    GlossaryNotFollowed { section: usize, terms: Vec<String>, retrying: bool },
+    /// Section is annotated for the reviewer to handle it
+    SectionFlagged { section: usize, reason: String },
+    /// Provider throttled the requests, which are paused for a while
+    RateLimited { provider: llm::Provider, pause: Duration },
+    Other(String),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::GlossaryNotFollowed { section, terms, retrying: true } => {
+                write!(f, "Section {} doesn't follow the glossary ({}), retrying", section, terms.join(", "))
+            }
+            Warning::GlossaryNotFollowed { section, terms, retrying: false } => {
+                write!(f, "Section {} still doesn't follow the glossary: {}", section, terms.join(", "))
+            }
+            Warning::SectionFlagged { section, reason } => {
+                write!(f, "Section {} {}, flagging it", section, reason)
+            }
+            Warning::RateLimited { provider, pause } => {
+                write!(f, "Rate limited by {}, retrying in {} s", provider.name(), pause.as_secs())
+            }
+            Warning::Other(warning) => {
+                write!(f, "{}", warning)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Progress {
     pub processed_sections: usize,
     pub total_sections: usize,
+    /// Tokens spent by the run so far
+    pub usage: llm::TokenUsage,
+}
+
+/// Heading section starting a chapter, which lasts until the next one
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub section: usize,
+    /// 1 for `#`, 2 for `##` and so on
+    pub level: usize,
+    /// Source text of the heading
+    pub heading: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionStatus {
+    Pending,
+    Translating,
+    Done,
+    /// Done, but has notes added for the reviewer
+    Flagged,
 }
 
 pub trait SendProgress: Send + Sync {
     fn send_progress(&self, progress: Progress);
+
+    fn send_connectivity(&self, _online: bool) {}
+
+    fn send_warning(&self, _warning: Warning) {}
+
+    fn send_info(&self, _info: String) {}
+
+    fn send_nothing_to_translate(&self, _reason: String) {}
+
+    fn send_live_text(&self, _text: String) {}
+
+    fn send_outline(&self, _entries: Vec<OutlineEntry>, _total_sections: usize) {}
+
+    fn send_section(&self, _section: usize, _status: SectionStatus, _translated: Option<String>) {}
+
+    /// Whether the user has stopped the run, checked between sections and while waiting for batch jobs
+    fn is_stopped(&self) -> bool {
+        false
+    }
+}
+
+/// Lets the caller request a snapshot of the output while the translation is still running.
+#[derive(Debug, Clone, Default)]
+pub struct PartialExport(Arc<Mutex<Option<PathBuf>>>);
+
+impl PartialExport {
+    /// Sections translated so far are written to the given path once the current section is done
+    pub fn request(&self, path: PathBuf) {
+        *self.0.lock().expect("lock") = Some(path);
+    }
+
+    fn take(&self) -> Option<PathBuf> {
+        self.0.lock().expect("lock").take()
+    }
+}
+
+/// Where partial result of the given output is exported to, e.g. `book.partial.epub` for `book.epub`
+pub fn partial_output_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    match output.extension() {
+        Some(ext) => output.with_file_name(format!("{stem}.partial.{}", ext.to_string_lossy())),
+        None => output.with_file_name(format!("{stem}.partial")),
+    }
 }
 
 pub struct DummySendProgress;
@@ -200,19 +1168,38 @@ impl SendProgress for DummySendProgress {
     fn send_progress(&self, _progress: Progress) {}
 }
 
-pub struct LlmTranslationService<P, LB, GB, SP> {
+pub struct LlmTranslationService<P, LB, GB, CB, SP> {
     parser: P,
     llm_builder: LB,
     generator_builder: GB,
-    send_progress: SP,
+    cache_builder: CB,
+    /// If set, fresh translations are drafted by this provider for the LLM to post-edit
+    draft_llm_builder: Option<ProviderLLMBuilder>,
+    /// If set, fresh translations are checked for grammar issues
+    grammar_checker: Option<grammar::GrammarChecker>,
+    /// Screens the sources before the run if `moderation.prescreen` is set
+    content_screener: Option<moderation::ContentScreener>,
+    /// Prices to estimate the cost with, for providers not reporting it
+    prices: HashMap<llm::Provider, TokenPrices>,
+    /// Whether sections are filled in with cached drafts when the provider is down, see [Self::stale_translation]
+    stale_fallback: bool,
+    /// Most subsections sent in a single request, see [LLM::translate_batch]
+    batch_subsections: usize,
+    /// Listener of run events set by `checkpoints.socket`
+    checkpoints: Option<Arc<checkpoint::CheckpointSink>>,
+    /// Where the usage of the run is recorded once it's done, set by `billing.ledger`
+    usage_ledger: Option<ledger::UsageLedger>,
+    send_progress: Arc<SP>,
+    partial_export: PartialExport,
 }
 
-impl<P, LB, GB, SP> TranslationService for LlmTranslationService<P, LB, GB, SP>
+impl<P, LB, GB, CB, SP> TranslationService for LlmTranslationService<P, LB, GB, CB, SP>
 where
     P: Parser,
     LB: LLMBuilder,
     GB: GeneratorBuilder,
-    SP: SendProgress,
+    CB: CacheBuilder,
+    SP: SendProgress + 'static,
 {
     async fn translate(
         &self,
@@ -236,51 +1223,296 @@ where
             .map_err(TranslationError::ParseError)?;
         let total_sections = input_sections.len();
 
-        let mut cache = Cache::new(&output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)?;
+        let prepared_sections = input_sections
+            .iter()
+            .map(|section| {
+                // Speaker labels aren't translated, transcript generator restores them from the source
+                let section = if cfg.transcript {
+                    parser::transcript::strip_speaker_label(section)
+                } else {
+                    section.clone()
+                };
+                let (section, spans) = masking::mask(&section, &cfg.no_translate_markers);
+                let detected_lang = match cfg.language_policy {
+                    LanguagePolicy::TranslateAll => None,
+                    _ => detect_language(&section.0.iter().map(|ss| &ss.0).join("\n")),
+                };
+                (section, detected_lang, spans)
+            })
+            .collect_vec();
+        // Markers don't make it to the output, not even to the source side of a bilingual one
+        let sources = input_sections
+            .iter()
+            .map(|section| masking::strip_markers(section, &cfg.no_translate_markers))
+            .collect_vec();
+
+        // No need to set up the cache and the LLM, output is the same as input
+        if prepared_sections.iter().all(|(section, lang, _)| is_passthrough(section, *lang, &cfg)) {
+            let reason = if total_sections == 0 {
+                "Document is empty, nothing to translate".to_owned()
+            } else {
+                "All sections are kept as is, nothing to translate".to_owned()
+            };
+            log::info!("{reason}");
+            self.send_progress.send_nothing_to_translate(reason);
+
+            let mut generator = self.generator_builder.build(output).await?;
+            for (src, (section, _, spans)) in sources.iter().zip(prepared_sections) {
+                generator.write(src, masking::unmask(section, &spans)).await?;
+            }
+            generator.finalize().await?;
+            return Ok(());
+        }
+
+        let mut cache = self.cache_builder
+            .build(&output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)
+            .await?;
 
         let mut generator =
             self.generator_builder.build(output).await?;
 
         {
-            let llm = self
+            let mut llm = self
                 .llm_builder
-                .build(cfg)
+                .build(cfg.clone(), self.send_progress.clone())
                 .await
                 .map_err(TranslationError::LLMError)?;
 
-            for (current, section) in input_sections.into_iter().enumerate() {
-                let cached_subsections = section.0.iter()
-                    .map(|ss| cache.get(ss))
-                    .collect::<Result<Vec<Option<MarkdownSubsection>>, TranslationError>>()?;
-
-                let translated_section =
-                    if cached_subsections.iter().all(|opt| opt.is_some()) {
-                        // Translation is fully cached
-                        let translated = MarkdownSection(cached_subsections.into_iter().map(|opt| opt.unwrap()).collect());
-                        log::info!("Section {} already translated:\n >>> {}\n <<< {}", current,
-                            substr_up_to_len(section.0.first().unwrap().0.lines().next().unwrap(), MAX_LOG_SRC_LEN),
-                            substr_up_to_len(translated.0.first().unwrap().0.lines().next().unwrap(), MAX_LOG_SRC_LEN));
-                        translated
-                    } else {
-                        let translated = llm
-                            .translate(&section)
-                            .await
-                            .map_err(TranslationError::LLMError)?;
-
-                        for (src, dst) in section.0.iter().zip(translated.0.iter()) {
-                            cache.insert(src.clone(), dst.clone())?;
+            let mut draft = match self.draft_llm_builder {
+                Some(ref draft_llm_builder) => Some(Draft {
+                    llm: draft_llm_builder
+                        .build(cfg.clone(), self.send_progress.clone())
+                        .await
+                        .map_err(TranslationError::LLMError)?,
+                    cache: self.cache_builder
+                        .build(&output.with_extension("sqlite"), &cfg.src_lang, &draft_lang(&cfg.dst_lang))
+                        .await?,
+                }),
+                None => None,
+            };
+            let mut stale_cache = match self.stale_fallback {
+                true => Some(self.cache_builder
+                    .build(&output.with_extension("sqlite"), &cfg.src_lang, &draft_lang(&cfg.dst_lang))
+                    .await?),
+                false => None,
+            };
+
+            if self.content_screener.is_some() {
+                let to_translate = prepared_sections
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (section, lang, _))| !is_passthrough(section, *lang, &cfg))
+                    .map(|(idx, (section, ..))| (idx, section));
+                self.prescreen(to_translate).await;
+            }
+
+            // Drafts make the messages differ from the sources, so there's nothing to batch then
+            if self.llm_builder.supports_batch() && draft.is_none() {
+                let mut uncached = vec![];
+                for (section, lang, _) in prepared_sections.iter() {
+                    if is_passthrough(section, *lang, &cfg) {
+                        continue;
+                    }
+                    for ss in section.0.iter() {
+                        if cache.get(ss).await?.is_none() {
+                            uncached.push(ss.clone());
                         }
+                    }
+                }
+                llm.prefetch(&uncached).await.map_err(TranslationError::LLMError)?;
+            }
+
+            // Glossaries and style samples go to the prompt, which is sent along with every message
+            if let Some(info) = self.llm_builder.model_info() {
+                let prompt_tokens = estimate_tokens(&llm::system_prompt(&cfg));
+                if prompt_tokens > info.context_tokens / 2 {
+                    let warning = format!(
+                        "System prompt takes about {} of {} tokens of the model context, \
+                        consider trimming the glossary or style samples",
+                        prompt_tokens, info.context_tokens
+                    );
+                    log::warn!("{warning}");
+                    self.send_progress.send_warning(Warning::Other(warning));
+                }
+            }
+
+            if cfg.seed.is_some() && !self.llm_builder.supports_seed() {
+                let warning = "Provider doesn't support seeds, results may differ between runs".to_owned();
+                log::warn!("{warning}");
+                self.send_progress.send_warning(Warning::Other(warning));
+            }
+
+            // Sections are translated in a block so that the LLM is closed even if one of them fails
+            let result: Result<(), TranslationError> = async {
+                let order = translation_order(&input_sections, cfg.headings_first);
+                let first_occurrence = first_occurrences(prepared_sections.iter().map(|(section, ..)| section));
+                let mut translated_sections: Vec<Option<MarkdownSection>> = vec![None; total_sections];
+                // Kept for partial exports, generator takes ownership of what it writes
+                let mut written_sections = vec![];
+                let mut next_to_write = 0;
+                let mut stale_sections = vec![];
+                self.send_progress.send_outline(outline(&sources), total_sections);
+
+                for (processed, current) in order.into_iter().enumerate() {
+                    if self.send_progress.is_stopped() {
+                        return Err(TranslationError::OtherError(anyhow::anyhow!("Translation was stopped")));
+                    }
+                    let (section, detected_lang, spans) = &prepared_sections[current];
+                    let detected_lang = *detected_lang;
+                    let section_start = Instant::now();
+                    let section_usage = self.total_usage(&llm, draft.as_ref());
+                    let mut cache_hit = false;
+                    self.send_progress.send_section(current, SectionStatus::Translating, None);
+
+                    let translated_section = match detected_lang {
+                        _ if section.0.is_empty() => section.clone(),
+                        Some(lang) if cfg.language_policy == LanguagePolicy::SkipDestination
+                            && is_same_language(&cfg.dst_lang, lang) => {
+                            log::info!("Section {} is already in {}, keeping it as is", current, lang);
+                            section.clone()
+                        }
+                        _ => {
+                            let result = self
+                                .translate_section(&llm, &mut cache, draft.as_mut(), &cfg, current, section)
+                                .await;
+                            let (mut translated, reused) = match (result, stale_cache.as_mut()) {
+                                (
+                                    Err(TranslationError::LLMError(
+                                        e @ (LLMError::ConnectionError(_)
+                                        | LLMError::ApiError(_)
+                                        | LLMError::Timeout(_)),
+                                    )),
+                                    Some(stale_cache),
+                                ) => {
+                                    match Self::stale_translation(stale_cache, section).await? {
+                                        Some(stale) => {
+                                            let warning =
+                                                format!("{e}, section {current} is filled in with a stale translation");
+                                            log::warn!("{warning}");
+                                            self.send_progress.send_warning(Warning::Other(warning));
+                                            stale_sections.push(current);
+                                            (stale, 0)
+                                        }
+                                        None => return Err(TranslationError::LLMError(e)),
+                                    }
+                                }
+                                (result, _) => result?,
+                            };
+                            cache_hit = reused == section.0.len();
+
+                            let repeated_from = section.0.iter()
+                                .filter_map(|ss| first_occurrence.get(ss.0.as_str()).copied())
+                                .filter(|&idx| idx != current)
+                                .unique()
+                                .sorted()
+                                .collect_vec();
+                            if reused > 0 && !repeated_from.is_empty() {
+                                let info = format!(
+                                    "Section {} repeats passages of section(s) {}, reusing their translation",
+                                    current, repeated_from.iter().join(", ")
+                                );
+                                log::info!("{info}");
+                                self.send_progress.send_info(info);
+                            }
+
+                            if cfg.language_policy == LanguagePolicy::AnnotateForeign
+                                && let Some(lang) = detected_lang.filter(|lang| !is_same_language(&cfg.src_lang, lang))
+                            {
+                                let warning = format!("Section {} seems to be in {} rather than {}", current, lang, cfg.src_lang);
+                                log::warn!("{warning}");
+                                self.send_progress.send_warning(Warning::Other(warning));
+                                translated.0.insert(0, MarkdownSubsection(
+                                    format!("<!-- rosetta: source language detected as {lang} -->")
+                                ));
+                            }
+
+                            if cfg.fiction {
+                                for ss in translated.0.iter_mut() {
+                                    ss.0 = fiction::convert_dialogue(&ss.0, &cfg.dst_lang);
+                                }
+                            }
 
-                        translated
+                            translated
+                        }
                     };
 
-                generator.write(translated_section).await?;
+                    let translated_section = masking::unmask(translated_section, spans);
+                    let status = match translated_section.0.iter().any(|ss| ss.is_annotation()) {
+                        true => SectionStatus::Flagged,
+                        false => SectionStatus::Done,
+                    };
+                    let text = translated_section.0.iter().map(|ss| &ss.0).join("\n");
+                    self.send_progress.send_section(current, status, Some(text));
+                    translated_sections[current] = Some(translated_section);
 
-                self.send_progress.send_progress(Progress {
-                    processed_sections: current + 1,
-                    total_sections,
-                });
+                    // Sections might be translated out of order, but are written in order
+                    while next_to_write < total_sections
+                        && let Some(translated_section) = translated_sections[next_to_write].take()
+                    {
+                        written_sections.push(translated_section.clone());
+                        generator.write(&sources[next_to_write], translated_section).await?;
+                        next_to_write += 1;
+                    }
+
+                    if let Some(partial_path) = self.partial_export.take() {
+                        self.export_partial(&partial_path, &sources, &written_sections, total_sections).await;
+                    }
+
+                    let usage = self.total_usage(&llm, draft.as_ref());
+                    if usage != section_usage {
+                        log::info!(
+                            "Section {} used {} prompt and {} completion tokens",
+                            current,
+                            usage.prompt_tokens - section_usage.prompt_tokens,
+                            usage.completion_tokens - section_usage.completion_tokens
+                        );
+                    }
+                    self.send_progress.send_progress(Progress {
+                        processed_sections: processed + 1,
+                        total_sections,
+                        usage,
+                    });
+                    if let Some(ref checkpoints) = self.checkpoints {
+                        checkpoints.send(checkpoint::Phase::Section {
+                            section: current,
+                            processed_sections: processed + 1,
+                            total_sections,
+                            duration_ms: section_start.elapsed().as_millis() as u64,
+                            cache_hit,
+                        }).await;
+                    }
+                }
+
+                if !stale_sections.is_empty() {
+                    let warning = format!(
+                        "Section(s) {} have stale translations, run the translation again once the provider is back",
+                        stale_sections.iter().sorted().join(", ")
+                    );
+                    log::warn!("{warning}");
+                    self.send_progress.send_warning(Warning::Other(warning));
+                }
+                Ok(())
             }
+            .await;
+
+            let usage = self.total_usage(&llm, draft.as_ref());
+            self.report_usage(usage);
+            if let Some(ref ledger) = self.usage_ledger
+                && let Err(e) = ledger.record(&cfg.billing_project, input, output, &usage).await
+            {
+                let warning = format!("Usage couldn't be recorded in the ledger: {e}");
+                log::warn!("{warning}");
+                self.send_progress.send_warning(Warning::Other(warning));
+            }
+            if let Err(e) = llm.close().await {
+                log::warn!("Failed to close the LLM: {}", e);
+            }
+            if let Some(mut draft) = draft
+                && let Err(e) = draft.llm.close().await
+            {
+                log::warn!("Failed to close the draft provider: {}", e);
+            }
+            result?;
         }
 
         generator.finalize().await?;
@@ -288,3 +1520,516 @@ where
         Ok(())
     }
 }
+
+/// Machine translation stage of a hybrid run, which the LLM then post-edits
+struct Draft<C> {
+    llm: llm::rate_limit::RateLimitedLLM<llm::AnyLLM>,
+    /// Drafts are stored next to the final translations, under their own language key
+    cache: C,
+}
+
+impl<C: Cache> Draft<C> {
+    /// Drafts the section, reusing cached drafts of its subsections
+    async fn translate(&mut self, section: &MarkdownSection) -> Result<MarkdownSection, TranslationError> {
+        let mut drafts = Vec::with_capacity(section.0.len());
+        for ss in section.0.iter() {
+            drafts.push(self.cache.get(ss).await?);
+        }
+
+        let missing = section.0.iter()
+            .zip(drafts.iter())
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(ss, _)| ss.clone())
+            .collect_vec();
+        if !missing.is_empty() {
+            let fresh = self.llm
+                .translate(&MarkdownSection(missing.clone()))
+                .await
+                .map_err(TranslationError::LLMError)?;
+            if fresh.0.len() != missing.len() {
+                return Err(TranslationError::LLMError(LLMError::InteractionError(anyhow::anyhow!(
+                    "Incorrect number of drafts: {} instead of {}", fresh.0.len(), missing.len()
+                ))));
+            }
+            let mut fresh = fresh.0.into_iter();
+            for (src, cached) in section.0.iter().zip(drafts.iter_mut()) {
+                if cached.is_none() {
+                    let dst = fresh.next().expect("drafts count checked");
+                    self.cache.insert(src.clone(), dst.clone()).await?;
+                    *cached = Some(dst);
+                }
+            }
+        }
+
+        Ok(MarkdownSection(drafts.into_iter().flatten().collect()))
+    }
+}
+
+/// Language key drafts are cached under
+fn draft_lang(dst_lang: &str) -> String {
+    format!("{dst_lang} (draft)")
+}
+
+/// Whether the section is written out untranslated, without consulting the cache or the LLM.
+fn is_passthrough(section: &MarkdownSection, detected_lang: Option<&str>, cfg: &TranslationConfig) -> bool {
+    section.0.is_empty()
+        || detected_lang.is_some_and(|lang| {
+            cfg.language_policy == LanguagePolicy::SkipDestination && is_same_language(&cfg.dst_lang, lang)
+        })
+}
+
+/// Maps each subsection text to the index of the first section it appears in.
+fn first_occurrences<'a>(sections: impl IntoIterator<Item = &'a MarkdownSection>) -> HashMap<&'a str, usize> {
+    let mut result = HashMap::new();
+    for (idx, section) in sections.into_iter().enumerate() {
+        for ss in section.0.iter() {
+            result.entry(ss.0.as_str()).or_insert(idx);
+        }
+    }
+    result
+}
+
+/// Heading sections of the document
+fn outline(sections: &[MarkdownSection]) -> Vec<OutlineEntry> {
+    sections
+        .iter()
+        .enumerate()
+        .filter(|(_, section)| section.is_heading())
+        .map(|(idx, section)| {
+            let line = first_line(&section.0[0].0);
+            let heading = line.trim_start_matches('#');
+            OutlineEntry {
+                section: idx,
+                level: line.len() - heading.len(),
+                heading: heading.trim().to_owned(),
+            }
+        })
+        .collect()
+}
+
+/// Order in which sections should be translated.
+/// Translating headings first lets them set the terminology for the bodies that follow.
+fn translation_order(sections: &[MarkdownSection], headings_first: bool) -> Vec<usize> {
+    let indices = 0..sections.len();
+    if headings_first {
+        let (headings, bodies): (Vec<usize>, Vec<usize>) =
+            indices.partition(|&idx| sections[idx].is_heading());
+        headings.into_iter().chain(bodies).collect()
+    } else {
+        indices.collect()
+    }
+}
+
+impl<P, LB, GB, CB, SP> LlmTranslationService<P, LB, GB, CB, SP>
+where
+    P: Parser,
+    LB: LLMBuilder,
+    GB: GeneratorBuilder,
+    CB: CacheBuilder,
+    SP: SendProgress + 'static,
+{
+    /// Generates a separate output from the sections written so far, the run itself goes on regardless of the outcome.
+    async fn export_partial(
+        &self,
+        partial_path: &Path,
+        sources: &[MarkdownSection],
+        written_sections: &[MarkdownSection],
+        total_sections: usize,
+    ) {
+        let result: Result<(), TranslationError> = async {
+            let mut generator = self.generator_builder.build(partial_path).await?;
+            for (src, section) in sources.iter().zip(written_sections) {
+                generator.write(src, section.clone()).await?;
+            }
+            generator.finalize().await
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                let info = format!(
+                    "Exported {} of {} sections to {}",
+                    written_sections.len(),
+                    total_sections,
+                    partial_path.display()
+                );
+                log::info!("{info}");
+                self.send_progress.send_info(info);
+            }
+            Err(e) => {
+                let warning = format!("Partial export failed: {e}");
+                log::warn!("{warning}");
+                self.send_progress.send_warning(Warning::Other(warning));
+            }
+        }
+    }
+
+    /// Tokens spent by the LLM and the draft provider so far. Cost is estimated from the configured prices
+    /// for providers not reporting it, and is unknown if there's a provider with neither.
+    fn total_usage(&self, llm: &LB::Built, draft: Option<&Draft<CB::Built>>) -> llm::TokenUsage {
+        let mut total = llm::TokenUsage { cost: Some(0.0), ..Default::default() };
+        for (provider, usage) in llm.usage().into_iter().chain(draft.into_iter().flat_map(|d| d.llm.usage())) {
+            let cost = usage.cost.or_else(|| self.prices.get(&provider).map(|prices| prices.cost(&usage)));
+            total.add_tokens(usage.prompt_tokens, usage.completion_tokens);
+            total.cost = total.cost.zip(cost).map(|(a, b)| a + b);
+        }
+        total
+    }
+
+    fn report_usage(&self, usage: llm::TokenUsage) {
+        if usage.prompt_tokens + usage.completion_tokens == 0 {
+            return;
+        }
+        let mut info = format!(
+            "Used {} prompt and {} completion tokens",
+            usage.prompt_tokens, usage.completion_tokens
+        );
+        if let Some(cost) = usage.cost {
+            info += &format!(", about ${cost:.2}");
+        }
+        log::info!("{info}");
+        self.send_progress.send_info(info);
+    }
+
+    /// Translation of the section made of the drafts cached by earlier runs, if there are drafts of all subsections.
+    /// It's marked as stale and isn't cached, so that the next run translates the section properly.
+    async fn stale_translation(
+        stale_cache: &mut CB::Built,
+        section: &MarkdownSection,
+    ) -> Result<Option<MarkdownSection>, TranslationError> {
+        let mut stale = vec![MarkdownSubsection(
+            "<!-- rosetta: stale translation, made while the provider was unavailable -->".to_owned(),
+        )];
+        for ss in section.0.iter() {
+            match stale_cache.get(ss).await? {
+                Some(draft) => stale.push(draft),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(MarkdownSection(stale)))
+    }
+
+    /// Warns about the sections the provider is likely to refuse, a failed screening is not considered an error.
+    async fn prescreen(&self, sections: impl Iterator<Item = (usize, &MarkdownSection)>) {
+        let Some(ref screener) = self.content_screener else {
+            return;
+        };
+        let mut flagged = vec![];
+        for (idx, section) in sections {
+            match screener.screen_section(section).await {
+                Ok(categories) if categories.is_empty() => {}
+                Ok(categories) => {
+                    let warning = format!("Section {} may be refused by the provider: {}", idx, categories.join(", "));
+                    log::warn!("{warning}");
+                    self.send_progress.send_warning(Warning::Other(warning));
+                    flagged.push(idx);
+                }
+                Err(e) => {
+                    let warning = format!("{e}, going on without it");
+                    log::warn!("{warning}");
+                    self.send_progress.send_warning(Warning::Other(warning));
+                    return;
+                }
+            }
+        }
+        let info = match flagged.len() {
+            0 => "Content pre-screen found nothing likely to be refused".to_owned(),
+            n => format!("Content pre-screen flagged {n} section(s): {}", flagged.iter().join(", ")),
+        };
+        log::info!("{info}");
+        self.send_progress.send_info(info);
+    }
+
+    /// Returns the translation along with the number of subsections taken from the cache.
+    async fn translate_section(
+        &self,
+        llm: &LB::Built,
+        cache: &mut CB::Built,
+        draft: Option<&mut Draft<CB::Built>>,
+        cfg: &TranslationConfig,
+        current: usize,
+        section: &MarkdownSection,
+    ) -> Result<(MarkdownSection, usize), TranslationError> {
+        let mut cached_subsections = Vec::with_capacity(section.0.len());
+        for ss in section.0.iter() {
+            cached_subsections.push(cache.get(ss).await?);
+        }
+
+        if cached_subsections.iter().all(|opt| opt.is_some()) {
+            // Translation is fully cached
+            let translated = MarkdownSection(cached_subsections.into_iter().map(|opt| opt.unwrap()).collect());
+            log::info!("Section {} already translated:\n >>> {}\n <<< {}", current,
+                log_preview(section.0.first().map_or("", |ss| &ss.0)),
+                log_preview(translated.0.first().map_or("", |ss| &ss.0)));
+            let reused = translated.0.len();
+            return Ok((translated, reused));
+        }
+
+        // Passages repeated verbatim reuse their earlier translation, the rest goes to the LLM
+        let missing = MarkdownSection(
+            section.0.iter()
+                .zip(cached_subsections.iter())
+                .filter(|(_, cached)| cached.is_none())
+                .map(|(ss, _)| ss.clone())
+                .collect()
+        );
+        let reused = section.0.len() - missing.0.len();
+        let fresh = self.translate_fresh(llm, cache, draft, cfg, current, &missing).await?;
+        if reused == 0 {
+            return Ok((fresh, 0));
+        }
+
+        let (mut translated, fresh): (Vec<_>, Vec<_>) = fresh.0.into_iter().partition(|ss| ss.is_annotation());
+        let mut fresh = fresh.into_iter();
+        for cached in cached_subsections {
+            translated.extend(cached.or_else(|| fresh.next()));
+        }
+        translated.extend(fresh);
+        Ok((MarkdownSection(translated), reused))
+    }
+
+    /// Translates the section with the LLM, retrying and flagging unsatisfactory results.
+    async fn translate_fresh(
+        &self,
+        llm: &LB::Built,
+        cache: &mut CB::Built,
+        draft: Option<&mut Draft<CB::Built>>,
+        cfg: &TranslationConfig,
+        current: usize,
+        section: &MarkdownSection,
+    ) -> Result<MarkdownSection, TranslationError> {
+        let mut translated = match draft {
+            Some(draft) => {
+                let draft_translation = draft.translate(section).await?;
+                log::info!("Post-editing the draft of section {}", current);
+                llm.post_edit(section, &draft_translation).await
+            }
+            // Batched messages are made of several subsections, so they aren't followed live
+            None if self.batch_subsections > 1 => llm.translate_batch(section, self.batch_subsections).await,
+            None => {
+                let send_progress = self.send_progress.clone();
+                llm.translate_streaming(section, Arc::new(move |text| send_progress.send_live_text(text))).await
+            }
+        }
+        .map_err(TranslationError::LLMError)?;
+
+        // Models sometimes echo the source back instead of translating it
+        let check_echo = !is_same_language(&cfg.src_lang, &cfg.dst_lang);
+        let has_echo = |translated: &MarkdownSection| {
+            check_echo && section.0.iter().zip(translated.0.iter()).any(|(src, dst)| is_echo(&src.0, &dst.0))
+        };
+
+        if has_echo(&translated) {
+            let warning = format!("Section {} came back untranslated, retrying", current);
+            log::warn!("{warning}");
+            self.send_progress.send_warning(Warning::Other(warning));
+            translated = llm
+                .retry_translate(section, &format!("Translate this text to {}, do not repeat it as is!", cfg.dst_lang))
+                .await
+                .map_err(TranslationError::LLMError)?;
+        }
+
+        // Tokens standing for passages that are not to be translated always have to survive
+        let strict_placeholders = self.parser.strict_placeholders();
+        let keeps_placeholders = |src: &MarkdownSubsection, dst: &MarkdownSubsection| {
+            masking::tokens(&src.0) == masking::tokens(&dst.0)
+                && (!strict_placeholders || placeholders(&src.0) == placeholders(&dst.0))
+        };
+
+        if !section.0.iter().zip(translated.0.iter()).all(|(src, dst)| keeps_placeholders(src, dst)) {
+            let warning = format!("Section {} lost some placeholders, retrying", current);
+            log::warn!("{warning}");
+            self.send_progress.send_warning(Warning::Other(warning));
+            translated = llm
+                .retry_translate(section, "Keep all placeholders like %s, %1$d, {name} or ⟦0⟧ exactly as they are!")
+                .await
+                .map_err(TranslationError::LLMError)?;
+        }
+
+        let markdown_issues = |translated: &MarkdownSection| {
+            section
+                .0
+                .iter()
+                .zip(translated.0.iter())
+                .filter_map(|(src, dst)| markdown_mismatch(&src.0, &dst.0))
+                .join("; ")
+        };
+
+        let issues = markdown_issues(&translated);
+        if !issues.is_empty() {
+            let warning = format!("Section {} broke Markdown structure ({}), retrying", current, issues);
+            log::warn!("{warning}");
+            self.send_progress.send_warning(Warning::Other(warning));
+            let reminder = format!(
+                "Your previous translation of this text had {issues}. \
+                Keep the same headings, links and fenced code blocks as in the source!"
+            );
+            translated = llm
+                .retry_translate(section, &reminder)
+                .await
+                .map_err(TranslationError::LLMError)?;
+
+            let issues = markdown_issues(&translated);
+            if !issues.is_empty() {
+                let warning = format!("Section {} still has broken Markdown structure: {}", current, issues);
+                log::warn!("{warning}");
+                self.send_progress.send_warning(Warning::Other(warning));
+            }
+        }
+
+        let missing_terms = |translated: &MarkdownSection| {
+            section
+                .0
+                .iter()
+                .zip(translated.0.iter())
+                .flat_map(|(src, dst)| glossary::missing_terms(&cfg.glossary, &src.0, &dst.0))
+                .unique()
+                .map(|entry| format!("{} → {}", entry.source, entry.target))
+                .collect_vec()
+        };
+
+        let missing = missing_terms(&translated);
+        if !missing.is_empty() {
+            let reminder = format!("Translate these terms exactly as given:\n{}", missing.join("\n"));
+            let warning = Warning::GlossaryNotFollowed { section: current, terms: missing, retrying: true };
+            log::warn!("{warning}");
+            self.send_progress.send_warning(warning);
+            translated = llm
+                .retry_translate(section, &reminder)
+                .await
+                .map_err(TranslationError::LLMError)?;
+
+            let missing = missing_terms(&translated);
+            if !missing.is_empty() {
+                let warning = Warning::GlossaryNotFollowed { section: current, terms: missing, retrying: false };
+                log::warn!("{warning}");
+                self.send_progress.send_warning(warning);
+            }
+        }
+
+        let grammar_issues = self.check_grammar(llm, cfg, current, section, &mut translated).await?;
+
+        let flagged = has_echo(&translated);
+        if cfg.stitch_seams && !flagged {
+            self.stitch_seams(llm, current, &mut translated).await?;
+        }
+        for (src, dst) in section.0.iter().zip(translated.0.iter_mut()) {
+            if !keeps_placeholders(src, dst) {
+                // Not cached, so that it's retried next time
+                let warning = format!("Section {} still lost some placeholders, keeping it untranslated", current);
+                log::warn!("{warning}");
+                self.send_progress.send_warning(Warning::Other(warning));
+                *dst = src.clone();
+            } else if !(check_echo && is_echo(&src.0, &dst.0)) {
+                cache.insert(src.clone(), dst.clone()).await?;
+            }
+        }
+
+        if !grammar_issues.is_empty() {
+            let issues = grammar_issues.iter().join("; ").replace("--", "—");
+            translated.0.insert(0, MarkdownSubsection(
+                format!("<!-- rosetta: grammar issues: {issues} -->")
+            ));
+        }
+
+        if flagged {
+            // Not cached, so that it's retried next time
+            let warning = Warning::SectionFlagged { section: current, reason: "is still untranslated".to_owned() };
+            log::warn!("{warning}");
+            self.send_progress.send_warning(warning);
+            translated.0.insert(0, MarkdownSubsection(
+                "<!-- rosetta: translation seems to be missing -->".to_owned()
+            ));
+        }
+
+        Ok(translated)
+    }
+
+    /// Lets the LLM smooth the junctions between the subsections of a paragraph split to be translated.
+    /// Sentences around a junction are rewritten only if they keep their placeholders and masked passages.
+    async fn stitch_seams(
+        &self,
+        llm: &LB::Built,
+        current: usize,
+        translated: &mut MarkdownSection,
+    ) -> Result<(), TranslationError> {
+        for idx in 1..translated.0.len() {
+            let (left, right) = translated.0.split_at_mut(idx);
+            let (left, right) = (&mut left[idx - 1], &mut right[0]);
+            if left.is_annotation() || right.is_annotation() {
+                continue;
+            }
+            let (Some(&before), Some(&after)) = (split_sentences(&left.0).last(), split_sentences(&right.0).first())
+            else {
+                continue;
+            };
+            // Multi-line sentences can't be told apart in the answer
+            if before.contains('\n') || after.contains('\n') {
+                continue;
+            }
+
+            let Some((new_before, new_after)) = llm.stitch(before, after).await.map_err(TranslationError::LLMError)?
+            else {
+                log::warn!("Section {}: couldn't stitch subsections {} and {}", current, idx - 1, idx);
+                continue;
+            };
+            let joined = |a: &str, b: &str| format!("{a}\n{b}");
+            let (old, new) = (joined(before, after), joined(&new_before, &new_after));
+            if masking::tokens(&old) != masking::tokens(&new) || placeholders(&old) != placeholders(&new) {
+                log::warn!("Section {}: stitching subsections {} and {} lost placeholders, skipping", current, idx - 1, idx);
+                continue;
+            }
+
+            let before_start = left.0.rfind(before).expect("sentence of the subsection");
+            let after_start = right.0.find(after).expect("sentence of the subsection");
+            left.0.replace_range(before_start..before_start + before.len(), &new_before);
+            right.0.replace_range(after_start..after_start + after.len(), &new_after);
+        }
+        Ok(())
+    }
+
+    /// Checks a fresh translation for grammar issues, re-translating it once if asked to.
+    /// Returns the remaining issues, a failed check is not considered an error.
+    async fn check_grammar(
+        &self,
+        llm: &LB::Built,
+        cfg: &TranslationConfig,
+        current: usize,
+        section: &MarkdownSection,
+        translated: &mut MarkdownSection,
+    ) -> Result<Vec<grammar::GrammarIssue>, TranslationError> {
+        let Some(ref checker) = self.grammar_checker else {
+            return Ok(vec![]);
+        };
+        let check = async |translated: &MarkdownSection| match checker.check_section(translated).await {
+            Ok(issues) => issues,
+            Err(e) => {
+                log::warn!("Section {}: {}", current, e);
+                vec![]
+            }
+        };
+
+        let mut issues = check(translated).await;
+        if !issues.is_empty() && cfg.fix_grammar {
+            let warning = format!("Section {} has {} grammar issue(s), retrying", current, issues.len());
+            log::warn!("{warning}");
+            self.send_progress.send_warning(Warning::Other(warning));
+            let reminder = format!(
+                "Your previous translation of this text had these grammar issues, avoid them:\n{}",
+                issues.iter().map(|issue| format!("- {issue}")).join("\n")
+            );
+            *translated = llm
+                .retry_translate(section, &reminder)
+                .await
+                .map_err(TranslationError::LLMError)?;
+            issues = check(translated).await;
+        }
+
+        if !issues.is_empty() {
+            let warning = format!("Section {} has grammar issues: {}", current, issues.iter().join("; "));
+            log::warn!("{warning}");
+            self.send_progress.send_warning(Warning::Other(warning));
+        }
+        Ok(issues)
+    }
+}
@@ -1,17 +1,776 @@
+pub mod anthropic;
+pub mod deepl;
 pub mod dummy;
+pub mod ensemble;
+pub mod fallback;
+pub mod libretranslate;
+pub mod mistral;
+pub mod models;
 pub mod openai;
+pub mod openrouter;
+pub mod rate_limit;
 
-use super::parser::MarkdownSection;
-use super::{LLMError, TranslationConfig};
+use super::glossary::GlossaryEntry;
+use super::parser::{MarkdownSection, MarkdownSubsection};
+use super::utils::substr_up_to_len;
+pub use models::ModelInfo;
+use super::{Domain, LLMError, SendProgress, TranslationConfig, Warning};
+use anyhow::anyhow;
+use backoff::ExponentialBackoff;
+use backoff::backoff::Backoff;
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Receives the text of the subsection being translated, as much of it as arrived so far
+pub type OnText = Arc<dyn Fn(String) + Send + Sync>;
 
 pub trait LLMBuilder {
     type Built: LLM;
 
-    async fn build(&self, cfg: TranslationConfig) -> Result<Self::Built, LLMError>;
+    /// Builds an LLM for a translation run, which can report its state through `events`.
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError>;
+
+    /// Sends a minimal request to the provider to verify the credentials and model availability.
+    /// Returns the round-trip latency of that request.
+    async fn health_check(&self) -> Result<Duration, LLMError>;
+
+    /// Releases server-side resources left over by runs that weren't closed properly, e.g. after a crash.
+    /// Returns the number of released resources.
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        Ok(0)
+    }
+
+    /// Whether [TranslationConfig::seed] is honored, making runs deterministic.
+    fn supports_seed(&self) -> bool {
+        false
+    }
+
+    /// Whether the built LLM translates subsections in bulk in [LLM::prefetch].
+    fn supports_batch(&self) -> bool {
+        false
+    }
+
+    /// Most tokens of source text a section can have for its translation to fit the output limit of the model,
+    /// see [crate::utils::estimate_tokens]. None if the limit isn't known.
+    fn max_section_tokens(&self) -> Option<usize> {
+        None
+    }
+
+    /// Context size, output limit, capabilities and prices of the model, None if it's not a well-known one.
+    fn model_info(&self) -> Option<ModelInfo> {
+        None
+    }
 }
 
+/// Translation may take up to this many times as many tokens as the source, e.g. from English to Russian
+const TRANSLATION_TOKEN_RATIO: usize = 2;
+
 pub trait LLM {
     async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError>;
+
+    /// Same as [LLM::translate], reporting the text of the subsection being translated as it arrives.
+    /// Providers that don't stream report each subsection once it's complete.
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        let translated = self.translate(section).await?;
+        for ss in translated.0.iter() {
+            on_text(ss.0.clone());
+        }
+        Ok(translated)
+    }
+
+    /// Same as [LLM::translate], packing up to `max_per_request` subsections into a single message
+    /// to save round trips. Parts are delimited by numbered marker lines the answer has to keep,
+    /// if it doesn't, subsections of that message are translated one by one instead.
+    async fn translate_batch(
+        &self,
+        section: &MarkdownSection,
+        max_per_request: usize,
+    ) -> Result<MarkdownSection, LLMError> {
+        let mut translated = Vec::with_capacity(section.0.len());
+        for chunk in section.0.chunks(max_per_request.max(1)) {
+            let chunk = MarkdownSection(chunk.to_vec());
+            if chunk.0.len() == 1 {
+                translated.extend(self.translate(&chunk).await?.0);
+                continue;
+            }
+            let reply = self.translate(&MarkdownSection(vec![MarkdownSubsection(pack_parts(&chunk))])).await?;
+            match unpack_parts(&reply.0.into_iter().map(|ss| ss.0).join("\n"), chunk.0.len()) {
+                Some(parts) => translated.extend(parts.into_iter().map(MarkdownSubsection)),
+                None => {
+                    log::warn!("Batched translation came back with mismatched parts, translating them one by one");
+                    translated.extend(self.translate(&chunk).await?.0);
+                }
+            }
+        }
+        Ok(MarkdownSection(translated))
+    }
+
+    /// Translates the section again after an unsatisfactory attempt, reminding the model about the problem.
+    async fn retry_translate(&self, section: &MarkdownSection, _reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.translate(section).await
+    }
+
+    /// Sends instructions that aren't translations of the source, e.g. to post-edit a draft, subsection by subsection.
+    /// Providers keeping previous translations as context leave these exchanges out of it.
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate(section).await
+    }
+
+    /// Corrects a machine translation draft of the section against the source, subsection by subsection.
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let messages = section.0.iter().zip(draft.0.iter()).map(|(src, draft)| {
+            MarkdownSubsection(format!(
+                "Here is a machine translation draft of the source text. Post-edit it: fix mistranslations, \
+                omissions and unnatural phrasing, keep what is already right. Output just the corrected translation.\n\n\
+                Source:\n{}\n\nDraft:\n{}",
+                src.0, draft.0
+            ))
+        });
+        self.instruct(&MarkdownSection(messages.collect())).await
+    }
+
+    /// Smooths the junction of two consecutive pieces of a paragraph translated separately,
+    /// given the translated sentences on either side of it. Returns them rewritten,
+    /// or `None` if the answer doesn't keep the two sentences apart.
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        let message = MarkdownSubsection(format!(
+            "These two consecutive sentences of a paragraph were translated separately and join awkwardly. \
+            Smooth the junction between them: fix repeated subjects, broken agreement and abrupt transitions, \
+            change nothing else. Output just the two sentences, each on its own line.\n\n{before}\n{after}"
+        ));
+        let reply = self.instruct(&MarkdownSection(vec![message])).await?;
+        let reply = reply.0.into_iter().map(|ss| ss.0).join("\n");
+        Ok(reply
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect_tuple()
+            .map(|(before, after)| (before.to_owned(), after.to_owned())))
+    }
+
+    /// Translates the subsections up front in a cheaper bulk job, which might take hours.
+    /// Translating them afterwards takes the bulk translations, the ones that failed are translated as usual.
+    async fn prefetch(&self, _subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        Ok(())
+    }
+
+    /// Releases server-side resources, to be awaited once the translation is done.
+    async fn close(&mut self) -> Result<(), LLMError> {
+        Ok(())
+    }
+
+    /// Tokens spent so far, by provider. Empty for providers not billing by tokens.
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        vec![]
+    }
+}
+
+/// Tokens spent on requests to a provider
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In USD, if known
+    pub cost: Option<f64>,
+}
+
+impl TokenUsage {
+    pub fn add_tokens(&mut self, prompt_tokens: u64, completion_tokens: u64) {
+        self.prompt_tokens += prompt_tokens;
+        self.completion_tokens += completion_tokens;
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        self.add_tokens(other.prompt_tokens, other.completion_tokens);
+        self.cost = match (self.cost, other.cost) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// LLM provider, each configured in its own section of the settings file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    OpenAi,
+    Anthropic,
+    /// Machine translation rather than an LLM
+    DeepL,
+    Mistral,
+    /// Gateway to models of many providers
+    OpenRouter,
+    /// Self-hosted machine translation
+    LibreTranslate,
+}
+
+impl Provider {
+    pub fn settings_section(&self) -> &'static str {
+        match self {
+            Provider::OpenAi => "openai",
+            Provider::Anthropic => "anthropic",
+            Provider::DeepL => "deepl",
+            Provider::Mistral => "mistral",
+            Provider::OpenRouter => "openrouter",
+            Provider::LibreTranslate => "libretranslate",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenAi => "OpenAI",
+            Provider::Anthropic => "Anthropic",
+            Provider::DeepL => "DeepL",
+            Provider::Mistral => "Mistral",
+            Provider::OpenRouter => "OpenRouter",
+            Provider::LibreTranslate => "LibreTranslate",
+        }
+    }
+}
+
+/// Builder for the provider chosen in the settings file
+pub enum AnyLLMBuilder {
+    OpenAi(openai::OpenAiGPTBuilder),
+    Anthropic(anthropic::AnthropicBuilder),
+    DeepL(deepl::DeepLBuilder),
+    Mistral(mistral::MistralBuilder),
+    OpenRouter(openrouter::OpenRouterBuilder),
+    LibreTranslate(libretranslate::LibreTranslateBuilder),
+}
+
+pub enum AnyLLM {
+    OpenAi(Box<openai::OpenAiGPT>),
+    Anthropic(anthropic::Claude),
+    DeepL(deepl::DeepL),
+    Mistral(mistral::Mistral),
+    OpenRouter(openrouter::OpenRouter),
+    LibreTranslate(libretranslate::LibreTranslate),
+}
+
+impl LLMBuilder for AnyLLMBuilder {
+    type Built = AnyLLM;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.build(cfg, events).await.map(|llm| AnyLLM::OpenAi(Box::new(llm))),
+            AnyLLMBuilder::Anthropic(builder) => builder.build(cfg, events).await.map(AnyLLM::Anthropic),
+            AnyLLMBuilder::DeepL(builder) => builder.build(cfg, events).await.map(AnyLLM::DeepL),
+            AnyLLMBuilder::Mistral(builder) => builder.build(cfg, events).await.map(AnyLLM::Mistral),
+            AnyLLMBuilder::OpenRouter(builder) => builder.build(cfg, events).await.map(AnyLLM::OpenRouter),
+            AnyLLMBuilder::LibreTranslate(builder) => builder.build(cfg, events).await.map(AnyLLM::LibreTranslate),
+        }
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.health_check().await,
+            AnyLLMBuilder::Anthropic(builder) => builder.health_check().await,
+            AnyLLMBuilder::DeepL(builder) => builder.health_check().await,
+            AnyLLMBuilder::Mistral(builder) => builder.health_check().await,
+            AnyLLMBuilder::OpenRouter(builder) => builder.health_check().await,
+            AnyLLMBuilder::LibreTranslate(builder) => builder.health_check().await,
+        }
+    }
+
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.cleanup().await,
+            AnyLLMBuilder::Anthropic(builder) => builder.cleanup().await,
+            AnyLLMBuilder::DeepL(builder) => builder.cleanup().await,
+            AnyLLMBuilder::Mistral(builder) => builder.cleanup().await,
+            AnyLLMBuilder::OpenRouter(builder) => builder.cleanup().await,
+            AnyLLMBuilder::LibreTranslate(builder) => builder.cleanup().await,
+        }
+    }
+
+    fn supports_seed(&self) -> bool {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.supports_seed(),
+            AnyLLMBuilder::Anthropic(builder) => builder.supports_seed(),
+            AnyLLMBuilder::DeepL(builder) => builder.supports_seed(),
+            AnyLLMBuilder::Mistral(builder) => builder.supports_seed(),
+            AnyLLMBuilder::OpenRouter(builder) => builder.supports_seed(),
+            AnyLLMBuilder::LibreTranslate(builder) => builder.supports_seed(),
+        }
+    }
+
+    fn supports_batch(&self) -> bool {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.supports_batch(),
+            AnyLLMBuilder::Anthropic(builder) => builder.supports_batch(),
+            AnyLLMBuilder::DeepL(builder) => builder.supports_batch(),
+            AnyLLMBuilder::Mistral(builder) => builder.supports_batch(),
+            AnyLLMBuilder::OpenRouter(builder) => builder.supports_batch(),
+            AnyLLMBuilder::LibreTranslate(builder) => builder.supports_batch(),
+        }
+    }
+
+    fn max_section_tokens(&self) -> Option<usize> {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.max_section_tokens(),
+            AnyLLMBuilder::Anthropic(builder) => builder.max_section_tokens(),
+            AnyLLMBuilder::DeepL(builder) => builder.max_section_tokens(),
+            AnyLLMBuilder::Mistral(builder) => builder.max_section_tokens(),
+            AnyLLMBuilder::OpenRouter(builder) => builder.max_section_tokens(),
+            AnyLLMBuilder::LibreTranslate(builder) => builder.max_section_tokens(),
+        }
+    }
+
+    fn model_info(&self) -> Option<ModelInfo> {
+        match self {
+            AnyLLMBuilder::OpenAi(builder) => builder.model_info(),
+            AnyLLMBuilder::Anthropic(builder) => builder.model_info(),
+            AnyLLMBuilder::DeepL(builder) => builder.model_info(),
+            AnyLLMBuilder::Mistral(builder) => builder.model_info(),
+            AnyLLMBuilder::OpenRouter(builder) => builder.model_info(),
+            AnyLLMBuilder::LibreTranslate(builder) => builder.model_info(),
+        }
+    }
+}
+
+impl LLM for AnyLLM {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.translate(section).await,
+            AnyLLM::Anthropic(llm) => llm.translate(section).await,
+            AnyLLM::DeepL(llm) => llm.translate(section).await,
+            AnyLLM::Mistral(llm) => llm.translate(section).await,
+            AnyLLM::OpenRouter(llm) => llm.translate(section).await,
+            AnyLLM::LibreTranslate(llm) => llm.translate(section).await,
+        }
+    }
+
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.translate_streaming(section, on_text).await,
+            AnyLLM::Anthropic(llm) => llm.translate_streaming(section, on_text).await,
+            AnyLLM::DeepL(llm) => llm.translate_streaming(section, on_text).await,
+            AnyLLM::Mistral(llm) => llm.translate_streaming(section, on_text).await,
+            AnyLLM::OpenRouter(llm) => llm.translate_streaming(section, on_text).await,
+            AnyLLM::LibreTranslate(llm) => llm.translate_streaming(section, on_text).await,
+        }
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.retry_translate(section, reminder).await,
+            AnyLLM::Anthropic(llm) => llm.retry_translate(section, reminder).await,
+            AnyLLM::DeepL(llm) => llm.retry_translate(section, reminder).await,
+            AnyLLM::Mistral(llm) => llm.retry_translate(section, reminder).await,
+            AnyLLM::OpenRouter(llm) => llm.retry_translate(section, reminder).await,
+            AnyLLM::LibreTranslate(llm) => llm.retry_translate(section, reminder).await,
+        }
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.instruct(section).await,
+            AnyLLM::Anthropic(llm) => llm.instruct(section).await,
+            AnyLLM::DeepL(llm) => llm.instruct(section).await,
+            AnyLLM::Mistral(llm) => llm.instruct(section).await,
+            AnyLLM::OpenRouter(llm) => llm.instruct(section).await,
+            AnyLLM::LibreTranslate(llm) => llm.instruct(section).await,
+        }
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.post_edit(section, draft).await,
+            AnyLLM::Anthropic(llm) => llm.post_edit(section, draft).await,
+            AnyLLM::DeepL(llm) => llm.post_edit(section, draft).await,
+            AnyLLM::Mistral(llm) => llm.post_edit(section, draft).await,
+            AnyLLM::OpenRouter(llm) => llm.post_edit(section, draft).await,
+            AnyLLM::LibreTranslate(llm) => llm.post_edit(section, draft).await,
+        }
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.stitch(before, after).await,
+            AnyLLM::Anthropic(llm) => llm.stitch(before, after).await,
+            AnyLLM::DeepL(llm) => llm.stitch(before, after).await,
+            AnyLLM::Mistral(llm) => llm.stitch(before, after).await,
+            AnyLLM::OpenRouter(llm) => llm.stitch(before, after).await,
+            AnyLLM::LibreTranslate(llm) => llm.stitch(before, after).await,
+        }
+    }
+
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.prefetch(subsections).await,
+            AnyLLM::Anthropic(llm) => llm.prefetch(subsections).await,
+            AnyLLM::DeepL(llm) => llm.prefetch(subsections).await,
+            AnyLLM::Mistral(llm) => llm.prefetch(subsections).await,
+            AnyLLM::OpenRouter(llm) => llm.prefetch(subsections).await,
+            AnyLLM::LibreTranslate(llm) => llm.prefetch(subsections).await,
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.close().await,
+            AnyLLM::Anthropic(llm) => llm.close().await,
+            AnyLLM::DeepL(llm) => llm.close().await,
+            AnyLLM::Mistral(llm) => llm.close().await,
+            AnyLLM::OpenRouter(llm) => llm.close().await,
+            AnyLLM::LibreTranslate(llm) => llm.close().await,
+        }
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        match self {
+            AnyLLM::OpenAi(llm) => llm.usage(),
+            AnyLLM::Anthropic(llm) => llm.usage(),
+            AnyLLM::DeepL(llm) => llm.usage(),
+            AnyLLM::Mistral(llm) => llm.usage(),
+            AnyLLM::OpenRouter(llm) => llm.usage(),
+            AnyLLM::LibreTranslate(llm) => llm.usage(),
+        }
+    }
+}
+
+/// Previous source texts and their translations, sent along with new messages to keep the translation consistent.
+/// Translations of a section are only kept once the next section is translated, so that a rejected translation
+/// doesn't get imitated when retrying.
+pub(crate) struct History {
+    max_exchanges: usize,
+    exchanges: Mutex<HistoryExchanges>,
+}
+
+#[derive(Default)]
+struct HistoryExchanges {
+    /// Oldest first
+    accepted: VecDeque<(String, String)>,
+    /// Of the last translated section
+    pending: Vec<(String, String)>,
+}
+
+impl History {
+    pub(crate) fn new(max_exchanges: usize) -> Self {
+        History { max_exchanges, exchanges: Mutex::new(HistoryExchanges::default()) }
+    }
+
+    /// Starts a new attempt at translating a section. A retry means the last translation was rejected,
+    /// anything else means it was accepted.
+    pub(crate) fn start_section(&self, retry: bool) {
+        let mut exchanges = self.exchanges.lock().expect("lock");
+        let pending = std::mem::take(&mut exchanges.pending);
+        if !retry {
+            exchanges.accepted.extend(pending);
+            while exchanges.accepted.len() > self.max_exchanges {
+                exchanges.accepted.pop_front();
+            }
+        }
+    }
+
+    pub(crate) fn push(&self, src: String, translated: String) {
+        self.exchanges.lock().expect("lock").pending.push((src, translated));
+    }
+
+    /// Latest exchanges to send before a new message, oldest first
+    pub(crate) fn exchanges(&self) -> Vec<(String, String)> {
+        let exchanges = self.exchanges.lock().expect("lock");
+        let all = exchanges.accepted.iter().chain(exchanges.pending.iter()).cloned().collect_vec();
+        all[all.len().saturating_sub(self.max_exchanges)..].to_vec()
+    }
+}
+
+/// Proxy that provider requests go through, e.g. a corporate one.
+/// Without one, proxies of the `HTTP_PROXY` and `HTTPS_PROXY` environment variables are used.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// URL of the proxy for plain HTTP requests, e.g. `http://proxy.corp:3128`
+    pub http_proxy: Option<String>,
+    /// URL of the proxy for HTTPS requests, which provider APIs are
+    pub https_proxy: Option<String>,
+    /// Basic auth credentials, used for both proxies
+    pub auth: Option<(String, String)>,
+}
+
+/// HTTP client for provider requests, which fail with a timeout error if not done in time.
+/// Timeouts are retried like transient server errors, and reported as timeout errors once retries run out.
+/// Proxy URLs are expected to be validated by then.
+pub(crate) fn http_client(request_timeout: Option<Duration>, proxy: Option<&ProxyConfig>) -> reqwest::Client {
+    http_client_builder(request_timeout, proxy).build().expect("HTTP client")
+}
+
+/// Builder of [http_client], for providers that need more set up, e.g. default headers
+pub(crate) fn http_client_builder(
+    request_timeout: Option<Duration>,
+    proxy: Option<&ProxyConfig>,
+) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(proxy) = proxy {
+        let with_auth = |p: reqwest::Proxy| match &proxy.auth {
+            Some((username, password)) => p.basic_auth(username, password),
+            None => p,
+        };
+        if let Some(url) = &proxy.http_proxy {
+            builder = builder.proxy(with_auth(reqwest::Proxy::http(url).expect("valid proxy URL")));
+        }
+        if let Some(url) = &proxy.https_proxy {
+            builder = builder.proxy(with_auth(reqwest::Proxy::https(url).expect("valid proxy URL")));
+        }
+    }
+    builder
+}
+
+const MAX_SEQUENTIAL_ERRORS: usize = 5;
+
+const OFFLINE_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Failed attempt of a provider request, as classified by the provider, telling whether and how it's retried
+pub(crate) enum RequestError {
+    /// Not worth retrying, e.g. the API key was rejected
+    Fatal(LLMError),
+    /// Network is unreachable, which is waited out without giving up
+    Offline(anyhow::Error),
+    /// Retried like transient errors, reported as a timeout once retries run out
+    TimedOut(anyhow::Error),
+    /// Server error or the like, retried with a backoff unless it keeps happening
+    Transient { source: anyhow::Error, retry_after: Option<Duration> },
+    /// Rate limit hit, retried like transient errors but reported to the user as a pause
+    RateLimited { source: anyhow::Error, retry_after: Option<Duration> },
+    /// Short-lived rate limit (e.g. per second), waited out without counting as an error
+    Throttled { source: anyhow::Error, retry_after: Option<Duration> },
+    /// Retried right away without counting as an error, e.g. with another API key
+    Immediately,
+}
+
+impl RequestError {
+    pub(crate) fn transient(source: anyhow::Error) -> Self {
+        RequestError::Transient { source, retry_after: None }
+    }
+
+    pub(crate) fn rate_limited(source: anyhow::Error) -> Self {
+        RequestError::RateLimited { source, retry_after: None }
+    }
+
+    pub(crate) fn throttled(source: anyhow::Error) -> Self {
+        RequestError::Throttled { source, retry_after: None }
+    }
+
+    /// Pause the provider asked for, taken instead of the backoff
+    fn with_retry_after(self, pause: Option<Duration>) -> Self {
+        match self {
+            RequestError::Transient { source, retry_after } => {
+                RequestError::Transient { source, retry_after: retry_after.or(pause) }
+            }
+            RequestError::RateLimited { source, retry_after } => {
+                RequestError::RateLimited { source, retry_after: retry_after.or(pause) }
+            }
+            RequestError::Throttled { source, retry_after } => {
+                RequestError::Throttled { source, retry_after: retry_after.or(pause) }
+            }
+            other => other,
+        }
+    }
+}
+
+impl From<LLMError> for RequestError {
+    fn from(e: LLMError) -> Self {
+        RequestError::Fatal(e)
+    }
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(e: reqwest::Error) -> Self {
+        // Connecting can time out too, that's a server not responding rather than the network being down,
+        // so timeouts are never waited out and count towards giving up
+        if e.is_timeout() {
+            RequestError::TimedOut(anyhow!("Request timed out: {e}"))
+        } else if e.is_connect() {
+            RequestError::Offline(e.into())
+        } else {
+            RequestError::transient(anyhow!("Request failed: {e}"))
+        }
+    }
+}
+
+/// Makes attempts of a provider request until one succeeds or fails in a way that isn't worth retrying.
+/// Network outages are waited out without giving up, other failures are retried with a backoff
+/// unless they keep happening.
+pub(crate) async fn retry_request<T, Fut>(
+    provider: Provider,
+    events: &dyn SendProgress,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, LLMError>
+where
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut sequential_errors = 0;
+    let mut offline = false;
+
+    let mut backoff = ExponentialBackoff::default();
+
+    loop {
+        let result = attempt().await;
+
+        let is_connectivity_loss = matches!(result, Err(RequestError::Offline(_)));
+        if offline != is_connectivity_loss {
+            offline = is_connectivity_loss;
+            events.send_connectivity(!offline);
+            if !offline {
+                // Errors before the outage don't tell anything about the server now,
+                // and the backoff would count the time offline towards its maximum elapsed time
+                sequential_errors = 0;
+                backoff.reset();
+            }
+        }
+
+        let (error, give_up, rate_limited, retry_after): (_, fn(anyhow::Error) -> LLMError, _, _) = match result {
+            Ok(v) => return Ok(v),
+            Err(RequestError::Fatal(e)) => return Err(e),
+            Err(RequestError::Offline(e)) => {
+                // Laptop sleep or network switch rather than a server error, wait patiently without giving up
+                log::warn!("Network unreachable, retrying in {} s: {:#}", OFFLINE_RETRY_INTERVAL.as_secs(), e);
+                tokio::time::sleep(OFFLINE_RETRY_INTERVAL).await;
+                continue;
+            }
+            Err(RequestError::Immediately) => continue,
+            Err(RequestError::Throttled { source, retry_after }) => {
+                let Some(duration) = retry_after.or_else(|| backoff.next_backoff()) else {
+                    return Err(LLMError::InteractionError(source.context("Backoff exhausted")));
+                };
+                log::warn!("Rate limited, retrying in {} ms: {:#}", duration.as_millis(), source);
+                events.send_warning(Warning::RateLimited { provider, pause: duration });
+                tokio::time::sleep(duration).await;
+                continue;
+            }
+            // Timeouts that persist are an outage, which fallback providers take over
+            Err(RequestError::TimedOut(e)) => (e, LLMError::Timeout, false, None),
+            Err(RequestError::Transient { source, retry_after }) => {
+                (source, LLMError::InteractionError, false, retry_after)
+            }
+            Err(RequestError::RateLimited { source, retry_after }) => {
+                (source, LLMError::InteractionError, true, retry_after)
+            }
+        };
+
+        if sequential_errors >= MAX_SEQUENTIAL_ERRORS {
+            return Err(give_up(error));
+        }
+        sequential_errors += 1;
+        let Some(duration) = retry_after.or_else(|| backoff.next_backoff()) else {
+            return Err(give_up(error.context("Backoff exhausted")));
+        };
+        log::warn!("{:#}, retrying in {} ms", error, duration.as_millis());
+        events.send_warning(match rate_limited {
+            true => Warning::RateLimited { provider, pause: duration },
+            false => Warning::Other(format!("{:#}, retrying", error)),
+        });
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Error response of a provider API that isn't worth retrying, `message` being what its body says.
+/// Rejected API keys and exhausted quotas point to `api_keys_url` and `billing_url` to fix them at.
+pub(crate) fn api_error(
+    status: reqwest::StatusCode,
+    message: String,
+    api_keys_url: Option<&'static str>,
+    billing_url: Option<&'static str>,
+) -> LLMError {
+    let source = anyhow!("{status}: {message}");
+    match status.as_u16() {
+        401 | 403 => LLMError::InvalidApiKey { help_url: api_keys_url, source },
+        // 456 is what DeepL responds with
+        402 | 456 => LLMError::QuotaExceeded { help_url: billing_url, source },
+        _ => LLMError::ApiError(source),
+    }
+}
+
+/// Sends a JSON API request with [retry_request], deserializing the successful response.
+/// Unsuccessful responses are classified by `classify` from their status and body,
+/// a pause asked for by the `Retry-After` header is taken instead of the backoff.
+pub(crate) async fn send_json<T, Fut>(
+    provider: Provider,
+    events: &dyn SendProgress,
+    send: impl Fn() -> Fut,
+    classify: impl Fn(reqwest::StatusCode, String) -> RequestError,
+) -> Result<T, LLMError>
+where
+    T: serde::de::DeserializeOwned,
+    Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let (send, classify) = (&send, &classify);
+    retry_request(provider, events, move || async move {
+        let response = send().await?;
+        let status = response.status();
+        if status.is_success() {
+            let malformed = |e| LLMError::InteractionError(anyhow!("Malformed response: {e}"));
+            return response.json::<T>().await.map_err(|e| malformed(e).into());
+        }
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await.unwrap_or_default();
+        Err(classify(status, body).with_retry_after(retry_after))
+    })
+    .await
+}
+
+/// Style sample is embedded into every prompt, so it's capped to keep token costs sane
+const MAX_STYLE_SAMPLE_LEN: usize = 3000;
+
+/// Identifies the prompt a config produces, to tell whether a recorded run can be reproduced exactly.
+pub fn prompt_hash(cfg: &TranslationConfig) -> String {
+    format!("{:x}", Sha256::digest(cfg_to_prompt(cfg)))
+}
+
+/// System prompt the config makes, as providers get it before their own additions
+pub fn system_prompt(cfg: &TranslationConfig) -> String {
+    cfg_to_prompt(cfg)
+}
+
+fn domain_prompt(domain: &Domain) -> String {
+    let constraints = match domain {
+        Domain::General => return "".to_owned(),
+        Domain::Legal => "This is a legal text. Keep the structure of clauses, numbering and defined terms intact, \
+            translate terms of art consistently and don't paraphrase or simplify the legalese.",
+        Domain::Medical => "This is a medical text. Never simplify or round dosages, units, frequencies and warnings, \
+            use established medical terminology and keep drug names as is unless there is an official local name.",
+        Domain::Technical => "This is a technical text. Keep code, commands, identifiers, UI string placeholders \
+            (like {0}, %s, {{name}}) and units unchanged, keep terminology consistent throughout.",
+        Domain::Marketing => "This is a marketing text. Adapt idioms, puns and calls to action \
+            so that they sound natural and persuasive to the target audience rather than translating them literally.",
+        Domain::Literary => "This is a literary text. Preserve the author's voice, imagery and rhythm, \
+            prefer a natural literary rendering over a literal one.",
+        Domain::Custom(constraints) if constraints.trim().is_empty() => return "".to_owned(),
+        Domain::Custom(constraints) => constraints.trim(),
+    };
+    format!("\n{constraints}")
+}
+
+fn glossary_prompt(glossary: &[GlossaryEntry]) -> String {
+    if glossary.is_empty() {
+        return "".to_owned();
+    }
+    let terms = glossary.iter().map(|entry| format!("- {} → {}", entry.source, entry.target)).join("\n");
+    format!("\nAlways translate these terms as given:\n{terms}")
 }
 
 fn cfg_to_prompt(cfg: &TranslationConfig) -> String {
@@ -25,17 +784,143 @@ fn cfg_to_prompt(cfg: &TranslationConfig) -> String {
             format!("\n{}.", instructions)
         }
     };
-    format!(
-        r#"
-You are a professional translator from {} language to {}.
+    let style_prompt = if cfg.style_sample.trim().is_empty() {
+        "".to_owned()
+    } else {
+        let sample = cfg.style_sample.trim();
+        let truncated = substr_up_to_len(sample, MAX_STYLE_SAMPLE_LEN);
+        // Limit is in graphemes, same as the truncation
+        if truncated.graphemes(true).count() < sample.graphemes(true).count() {
+            log::info!("Style sample is too long, truncated to {} characters", MAX_STYLE_SAMPLE_LEN);
+        }
+        format!("\nMatch the style of this previously approved translation:\n\"\"\"\n{}\n\"\"\"", truncated.trim())
+    };
+    let fiction_prompt = if cfg.fiction {
+        format!("\nThis is a work of fiction, format dialogues and quotations according to {} conventions.", cfg.dst_lang)
+    } else {
+        "".to_owned()
+    };
+    let domain_prompt = domain_prompt(&cfg.domain);
+    let glossary_prompt = glossary_prompt(&cfg.glossary);
+    let template = match cfg.prompt_template {
+        Some(ref template) if !template.trim().is_empty() => template.as_str(),
+        _ => DEFAULT_PROMPT_TEMPLATE,
+    };
+    render_prompt(template, |name| match name {
+        "src_lang" => Some(cfg.src_lang.clone()),
+        "dst_lang" => Some(cfg.dst_lang.clone()),
+        "subject" => Some(cfg.subject.clone()),
+        "tone" => Some(cfg.tone.clone()),
+        "domain" => Some(domain_prompt.clone()),
+        "glossary" => Some(glossary_prompt.clone()),
+        "fiction" => Some(fiction_prompt.clone()),
+        "style_sample" => Some(style_prompt.clone()),
+        "additional_instructions" => Some(additional_prompt.clone()),
+        _ => None,
+    })
+    .trim()
+    .to_owned()
+}
+
+/// Template of the system prompt, see [TranslationConfig::prompt_template].
+/// Optional parts start with a line break, and are empty if not used.
+pub const DEFAULT_PROMPT_TEMPLATE: &str = r#"You are a professional translator from {{src_lang}} language to {{dst_lang}}.
 Translate each of my messages, keeping in mind that they are pieces of the same text.
-The subject of the source text is "{}"
+The subject of the source text is "{{subject}}"
 Make sure this translation is accurate and natural, preserve Markdown syntax and HTML markup.
-Translation tone needs to be matching the source, use {} tone when in doubt.{additional_prompt}
-Output just the translation and nothing else.
-"#,
-        cfg.src_lang, cfg.dst_lang, cfg.subject, cfg.tone
+Translation tone needs to be matching the source, use {{tone}} tone when in doubt.{{domain}}{{glossary}}{{fiction}}{{style_sample}}{{additional_instructions}}
+Output just the translation and nothing else."#;
+
+static TEMPLATE_VARIABLE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*(\w+)\s*\}\}").expect("valid regex"));
+
+/// Replaces `{{name}}` variables of the template, unknown ones are kept as is
+fn render_prompt(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    TEMPLATE_VARIABLE_REGEX
+        .replace_all(template, |caps: &regex::Captures| {
+            value(&caps[1]).unwrap_or_else(|| {
+                log::warn!("Unknown prompt template variable {}, keeping it as is", &caps[0]);
+                caps[0].to_owned()
+            })
+        })
+        .into_owned()
+}
+
+/// Marker line starting each part of a batched message, e.g. `=== 1 ===`
+static PART_MARKER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^=== (\d+) ===[ \t]*$").expect("valid regex"));
+
+/// Single message asking to translate the subsections as separate parts, see [LLM::translate_batch]
+fn pack_parts(section: &MarkdownSection) -> String {
+    let parts = section.0.iter().enumerate().map(|(idx, ss)| format!("=== {} ===\n{}", idx + 1, ss.0)).join("\n");
+    format!(
+        "Translate each part of this message separately. Keep the lines like \"=== 1 ===\" starting the parts \
+        exactly as they are, and don't merge, split or reorder the parts.\n\n{parts}"
     )
-    .trim()
-    .to_owned()
+}
+
+/// Translated parts of a batched message, None unless all parts are there in order
+fn unpack_parts(reply: &str, count: usize) -> Option<Vec<String>> {
+    let markers = PART_MARKER_REGEX.captures_iter(reply).collect_vec();
+    if markers.len() != count || markers.iter().enumerate().any(|(idx, caps)| caps[1] != (idx + 1).to_string()) {
+        return None;
+    }
+    let bounds = markers.iter().map(|caps| caps.get(0).expect("whole match")).collect_vec();
+    let parts = bounds
+        .iter()
+        .enumerate()
+        .map(|(idx, marker)| {
+            let end = bounds.get(idx + 1).map_or(reply.len(), |next| next.start());
+            reply[marker.end()..end].trim().to_owned()
+        })
+        .collect();
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_prompt_template() {
+        let cfg = TranslationConfig {
+            additional_instructions: "Keep it short".to_owned(),
+            prompt_template: Some("Translate {{ src_lang }} to {{dst_lang}}.{{additional_instructions}} {{speaker}}".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(cfg_to_prompt(&cfg), "Translate English to Russian.\nKeep it short. {{speaker}}");
+
+        let default = cfg_to_prompt(&TranslationConfig::default());
+        assert!(default.starts_with("You are a professional translator from English language to Russian."));
+        assert!(default.ends_with("use formal tone when in doubt.\nOutput just the translation and nothing else."));
+    }
+
+    #[test]
+    fn unpacks_batched_parts() {
+        let section = MarkdownSection(vec![MarkdownSubsection("One".to_owned()), MarkdownSubsection("Two".to_owned())]);
+        assert!(pack_parts(&section).ends_with("=== 1 ===\nOne\n=== 2 ===\nTwo"));
+
+        let parts = unpack_parts("=== 1 ===\nОдин\n\n=== 2 ===\nДва\n", 2);
+        assert_eq!(parts, Some(vec!["Один".to_owned(), "Два".to_owned()]));
+        assert_eq!(unpack_parts("=== 1 ===\nОдин, два", 2), None);
+        assert_eq!(unpack_parts("=== 2 ===\nДва\n=== 1 ===\nОдин", 2), None);
+    }
+
+    #[test]
+    fn history_drops_rejected_translations() {
+        let exchange = |src: &str, translated: &str| (src.to_owned(), translated.to_owned());
+        let history = History::new(2);
+        history.start_section(false);
+        history.push("One".to_owned(), "Один".to_owned());
+        history.start_section(false);
+        history.push("Two".to_owned(), "Two".to_owned());
+        assert_eq!(history.exchanges(), vec![exchange("One", "Один"), exchange("Two", "Two")]);
+
+        history.start_section(true);
+        assert_eq!(history.exchanges(), vec![exchange("One", "Один")]);
+        history.push("Two".to_owned(), "Два".to_owned());
+        history.start_section(false);
+        history.push("Three".to_owned(), "Три".to_owned());
+        assert_eq!(history.exchanges(), vec![exchange("Two", "Два"), exchange("Three", "Три")]);
+    }
 }
@@ -0,0 +1,322 @@
+use super::{History, LLM, LLMBuilder, ModelInfo, Provider, ProxyConfig, RequestError, TokenUsage};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::log_preview;
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://api.anthropic.com/v1/messages";
+const API_VERSION: &str = "2023-06-01";
+
+const API_KEYS_URL: &str = "https://console.anthropic.com/settings/keys";
+const BILLING_URL: &str = "https://console.anthropic.com/settings/billing";
+
+pub const DEFAULT_MAX_TOKENS: u32 = 8192;
+
+/// Messages API is stateless, so previous exchanges are re-sent to keep the terminology consistent.
+/// Only the most recent ones are kept to bound the cost.
+const MAX_HISTORY_EXCHANGES: usize = 4;
+
+/// Builder for Anthropic Claude models, see https://docs.anthropic.com/en/api/messages
+pub struct AnthropicBuilder {
+    model: String,
+    api_key: String,
+    max_tokens: u32,
+    temperature: f32,
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+    prompt_caching: bool,
+}
+
+impl AnthropicBuilder {
+    pub fn new(model: String, api_key: String, max_tokens: u32) -> Self {
+        AnthropicBuilder {
+            model,
+            api_key,
+            max_tokens,
+            temperature: 1.0,
+            request_timeout: None,
+            proxy: None,
+            prompt_caching: false,
+        }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        AnthropicBuilder { request_timeout, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        AnthropicBuilder { proxy, ..self }
+    }
+
+    /// System prompt is cached between requests, which makes re-reading it much cheaper
+    /// at the cost of writing it to the cache once. Prompts shorter than the model minimum are not cached,
+    /// see https://docs.anthropic.com/en/docs/build-with-claude/prompt-caching
+    pub fn with_prompt_caching(self, prompt_caching: bool) -> Self {
+        AnthropicBuilder { prompt_caching, ..self }
+    }
+
+    fn client(&self, system: String, events: Arc<dyn SendProgress>) -> Claude {
+        Claude {
+            client: super::http_client(self.request_timeout, self.proxy.as_ref()),
+            api_key: self.api_key.clone(),
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            system,
+            prompt_caching: self.prompt_caching,
+            history: History::new(MAX_HISTORY_EXCHANGES),
+            usage: Mutex::new(TokenUsage::default()),
+            events,
+        }
+    }
+}
+
+impl LLMBuilder for AnthropicBuilder {
+    type Built = Claude;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        Ok(self.client(super::cfg_to_prompt(&cfg), events))
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let claude = self.client("Reply with OK".to_owned(), Arc::new(crate::DummySendProgress));
+        let req = MessagesRequest {
+            model: &claude.model,
+            max_tokens: 5,
+            temperature: claude.temperature,
+            system: vec![SystemBlock::text(&claude.system, false)],
+            messages: vec![Message { role: "user", content: "OK?".to_owned() }],
+        };
+
+        let start = Instant::now();
+        claude.send(&req).await?;
+        Ok(start.elapsed())
+    }
+
+    fn max_section_tokens(&self) -> Option<usize> {
+        Some(self.max_tokens as usize / super::TRANSLATION_TOKEN_RATIO)
+    }
+
+    fn model_info(&self) -> Option<ModelInfo> {
+        super::models::model_info(&self.model)
+    }
+}
+
+pub struct Claude {
+    client: reqwest::Client,
+    api_key: String,
+    model: String,
+    max_tokens: u32,
+    temperature: f32,
+    system: String,
+    prompt_caching: bool,
+    history: History,
+    usage: Mutex<TokenUsage>,
+    events: Arc<dyn SendProgress>,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    temperature: f32,
+    system: Vec<SystemBlock<'a>>,
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct SystemBlock<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+impl<'a> SystemBlock<'a> {
+    fn text(text: &'a str, cached: bool) -> Self {
+        SystemBlock {
+            kind: "text",
+            text,
+            cache_control: cached.then_some(CacheControl { kind: "ephemeral" }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct Message {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl LLM for Claude {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.history.start_section(false);
+        self.translate_with_reminder(section, None, true).await
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.history.start_section(true);
+        self.translate_with_reminder(section, Some(reminder), true).await
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, None, false).await
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        vec![(Provider::Anthropic, *self.usage.lock().expect("lock"))]
+    }
+}
+
+impl Claude {
+    async fn translate_with_reminder(
+        &self,
+        section: &MarkdownSection,
+        reminder: Option<&str>,
+        keep_in_history: bool,
+    ) -> Result<MarkdownSection, LLMError> {
+        let mut subsections = vec![];
+        for s in section.0.iter() {
+            log::info!("Sending message {}", log_preview(&s.0));
+            let content = match reminder {
+                Some(reminder) => format!("{reminder}\n\n{}", s.0),
+                None => s.0.clone(),
+            };
+
+            let mut messages = vec![];
+            for (src, translated) in self.history.exchanges() {
+                messages.push(Message { role: "user", content: src });
+                messages.push(Message { role: "assistant", content: translated });
+            }
+            messages.push(Message { role: "user", content });
+
+            let req = MessagesRequest {
+                model: &self.model,
+                max_tokens: self.max_tokens,
+                temperature: self.temperature,
+                system: vec![SystemBlock::text(&self.system, self.prompt_caching)],
+                messages,
+            };
+            let response = self.send(&req).await?;
+
+            if let Some(ref usage) = response.usage {
+                // Input tokens don't include the cached ones
+                let prompt_tokens =
+                    usage.input_tokens + usage.cache_creation_input_tokens + usage.cache_read_input_tokens;
+                self.usage.lock().expect("lock").add_tokens(prompt_tokens, usage.output_tokens);
+                if self.prompt_caching {
+                    log::debug!(
+                        "Prompt cache: {} tokens written, {} tokens read",
+                        usage.cache_creation_input_tokens,
+                        usage.cache_read_input_tokens
+                    );
+                }
+            }
+
+            if response.stop_reason.as_deref() == Some("max_tokens") {
+                return Err(LLMError::InteractionError(anyhow!(
+                    "Translation was cut off at {} tokens, increase anthropic.max_tokens",
+                    self.max_tokens
+                )));
+            }
+            let translated = response
+                .content
+                .into_iter()
+                .filter(|block| block.kind == "text")
+                .map(|block| block.text)
+                .collect::<String>();
+            if translated.is_empty() {
+                return Err(LLMError::InteractionError(anyhow!("Response has no text")));
+            }
+            log::info!("Got translated message");
+
+            if keep_in_history {
+                self.history.push(s.0.clone(), translated.clone());
+            }
+
+            subsections.push(MarkdownSubsection(translated));
+        }
+        Ok(MarkdownSection(subsections))
+    }
+
+    /// Sends the request, waiting out network outages and retrying transient failures with a backoff
+    async fn send(&self, req: &MessagesRequest<'_>) -> Result<MessagesResponse, LLMError> {
+        let send = || {
+            self.client
+                .post(API_URL)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", API_VERSION)
+                .json(req)
+                .send()
+        };
+        super::send_json(Provider::Anthropic, &*self.events, send, classify_error).await
+    }
+}
+
+fn classify_error(status: reqwest::StatusCode, body: String) -> RequestError {
+    let Ok(ErrorResponse { error }) = serde_json::from_str::<ErrorResponse>(&body) else {
+        return LLMError::ApiError(anyhow!("{status}: {body}")).into();
+    };
+    let source = anyhow!("{}", error.message);
+    match error.kind.as_str() {
+        "authentication_error" | "permission_error" => {
+            LLMError::InvalidApiKey { help_url: Some(API_KEYS_URL), source }.into()
+        }
+        "billing_error" => LLMError::QuotaExceeded { help_url: Some(BILLING_URL), source }.into(),
+        "not_found_error" => LLMError::ModelNotFound(source).into(),
+        "rate_limit_error" => RequestError::rate_limited(source.context(format!("{} ({status})", error.kind))),
+        "overloaded_error" | "api_error" => {
+            RequestError::transient(source.context(format!("{} ({status})", error.kind)))
+        }
+        _ => LLMError::ApiError(source.context(error.kind)).into(),
+    }
+}
@@ -0,0 +1,263 @@
+use super::{LLM, LLMBuilder, Provider, ProxyConfig, RequestError};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://api.deepl.com/v2";
+/// Free plan keys have a `:fx` suffix and their own endpoint
+const FREE_API_URL: &str = "https://api-free.deepl.com/v2";
+
+const ACCOUNT_URL: &str = "https://www.deepl.com/your-account/keys";
+const USAGE_URL: &str = "https://www.deepl.com/your-account/usage";
+
+/// Language names and DeepL codes for them, as a source and as a target
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("Arabic", "AR", "AR"),
+    ("Bulgarian", "BG", "BG"),
+    ("Chinese", "ZH", "ZH-HANS"),
+    ("Czech", "CS", "CS"),
+    ("Danish", "DA", "DA"),
+    ("Dutch", "NL", "NL"),
+    ("English", "EN", "EN-US"),
+    ("Estonian", "ET", "ET"),
+    ("Finnish", "FI", "FI"),
+    ("French", "FR", "FR"),
+    ("German", "DE", "DE"),
+    ("Greek", "EL", "EL"),
+    ("Hungarian", "HU", "HU"),
+    ("Indonesian", "ID", "ID"),
+    ("Italian", "IT", "IT"),
+    ("Japanese", "JA", "JA"),
+    ("Korean", "KO", "KO"),
+    ("Latvian", "LV", "LV"),
+    ("Lithuanian", "LT", "LT"),
+    ("Norwegian", "NB", "NB"),
+    ("Polish", "PL", "PL"),
+    ("Portuguese", "PT", "PT-PT"),
+    ("Romanian", "RO", "RO"),
+    ("Russian", "RU", "RU"),
+    ("Slovak", "SK", "SK"),
+    ("Slovenian", "SL", "SL"),
+    ("Spanish", "ES", "ES"),
+    ("Swedish", "SV", "SV"),
+    ("Turkish", "TR", "TR"),
+    ("Ukrainian", "UK", "UK"),
+];
+
+/// Regional variants only valid as a target
+const TARGET_VARIANTS: &[&str] = &["EN-GB", "EN-US", "PT-BR", "PT-PT", "ZH-HANS", "ZH-HANT"];
+
+/// DeepL machine translation, see https://developers.deepl.com/docs/api-reference/translate.
+/// It's not an LLM, so only the languages and the tone are taken from the config.
+pub struct DeepLBuilder {
+    api_key: String,
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl DeepLBuilder {
+    pub fn new(api_key: String) -> Self {
+        DeepLBuilder { api_key, request_timeout: None, proxy: None }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        DeepLBuilder { request_timeout, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        DeepLBuilder { proxy, ..self }
+    }
+
+    fn client(&self, events: Arc<dyn SendProgress>) -> DeepL {
+        let base_url = if self.api_key.ends_with(":fx") { FREE_API_URL } else { API_URL };
+        DeepL {
+            client: super::http_client(self.request_timeout, self.proxy.as_ref()),
+            base_url,
+            api_key: self.api_key.clone(),
+            source_lang: None,
+            target_lang: String::new(),
+            formality: None,
+            events,
+        }
+    }
+}
+
+impl LLMBuilder for DeepLBuilder {
+    type Built = DeepL;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let source_lang = language_code(&cfg.src_lang, false)
+            .ok_or_else(|| LLMError::OtherError(anyhow!("DeepL can't translate from {}", cfg.src_lang)))?;
+        let target_lang = language_code(&cfg.dst_lang, true)
+            .ok_or_else(|| LLMError::OtherError(anyhow!("DeepL can't translate to {}", cfg.dst_lang)))?;
+        if !cfg.additional_instructions.trim().is_empty() || !cfg.style_sample.trim().is_empty() {
+            log::info!("DeepL doesn't follow instructions or style samples, ignoring them");
+        }
+
+        let mut deepl = self.client(events);
+        deepl.source_lang = Some(source_lang);
+        deepl.target_lang = target_lang;
+        deepl.formality = match cfg.tone.trim().to_lowercase().as_str() {
+            "formal" => Some("prefer_more"),
+            "informal" | "casual" | "friendly" => Some("prefer_less"),
+            _ => None,
+        };
+        Ok(deepl)
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let deepl = self.client(Arc::new(crate::DummySendProgress));
+        let start = Instant::now();
+        let response = deepl
+            .client
+            .get(format!("{}/usage", deepl.base_url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", deepl.api_key))
+            .send()
+            .await
+            .map_err(|e| LLMError::ConnectionError(e.into()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(api_error(status, body));
+        }
+        Ok(start.elapsed())
+    }
+}
+
+/// Maps a language name (e.g. `German`) or a DeepL code (e.g. `de`, `PT-BR`) to a code DeepL accepts
+fn language_code(lang: &str, as_target: bool) -> Option<String> {
+    let lang = lang.trim();
+    if as_target
+        && let Some(variant) = TARGET_VARIANTS.iter().find(|v| v.eq_ignore_ascii_case(lang))
+    {
+        return Some(variant.to_string());
+    }
+    LANGUAGES
+        .iter()
+        .find(|(name, code, _)| name.eq_ignore_ascii_case(lang) || code.eq_ignore_ascii_case(lang))
+        .map(|(_, source, target)| if as_target { target.to_string() } else { source.to_string() })
+}
+
+pub struct DeepL {
+    client: reqwest::Client,
+    base_url: &'static str,
+    api_key: String,
+    source_lang: Option<String>,
+    target_lang: String,
+    formality: Option<&'static str>,
+    events: Arc<dyn SendProgress>,
+}
+
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    text: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_lang: Option<&'a str>,
+    target_lang: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formality: Option<&'a str>,
+    preserve_formatting: bool,
+}
+
+#[derive(Deserialize)]
+struct TranslateResponse {
+    translations: Vec<Translation>,
+}
+
+#[derive(Deserialize)]
+struct Translation {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+impl LLM for DeepL {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let mut req = TranslateRequest {
+            text: section.0.iter().map(|ss| ss.0.as_str()).collect(),
+            source_lang: self.source_lang.as_deref(),
+            target_lang: &self.target_lang,
+            formality: self.formality,
+            preserve_formatting: true,
+        };
+
+        let response = match self.send(&req).await {
+            // Formality is only supported for some target languages
+            Err(LLMError::ApiError(e)) if req.formality.is_some() && format!("{e}").contains("formality") => {
+                log::info!("DeepL doesn't support formality for {}, ignoring the tone", self.target_lang);
+                req.formality = None;
+                self.send(&req).await?
+            }
+            result => result?,
+        };
+
+        if response.translations.len() != section.0.len() {
+            return Err(LLMError::InteractionError(anyhow!(
+                "Incorrect number of translations: {} instead of {}",
+                response.translations.len(),
+                section.0.len()
+            )));
+        }
+        Ok(MarkdownSection(
+            response.translations.into_iter().map(|t| MarkdownSubsection(t.text)).collect(),
+        ))
+    }
+
+    /// Can't follow post-editing instructions, so translates the source anew
+    async fn post_edit(&self, section: &MarkdownSection, _draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate(section).await
+    }
+
+    /// Can't follow stitching instructions either, so leaves the junction as is
+    async fn stitch(&self, _before: &str, _after: &str) -> Result<Option<(String, String)>, LLMError> {
+        Ok(None)
+    }
+}
+
+impl DeepL {
+    /// Sends the request, waiting out network outages and retrying transient failures with a backoff
+    async fn send(&self, req: &TranslateRequest<'_>) -> Result<TranslateResponse, LLMError> {
+        let send = || {
+            self.client
+                .post(format!("{}/translate", self.base_url))
+                .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+                .json(req)
+                .send()
+        };
+        super::send_json(Provider::DeepL, &*self.events, send, classify_error).await
+    }
+}
+
+fn classify_error(status: reqwest::StatusCode, body: String) -> RequestError {
+    match status.as_u16() {
+        429 => RequestError::rate_limited(anyhow!("{status}")),
+        _ if status.is_server_error() => RequestError::transient(anyhow!("{status}")),
+        _ => api_error(status, body).into(),
+    }
+}
+
+fn api_error(status: reqwest::StatusCode, body: String) -> LLMError {
+    let message = serde_json::from_str::<ErrorResponse>(&body).map_or(body, |e| e.message);
+    super::api_error(status, message, Some(ACCOUNT_URL), Some(USAGE_URL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(language_code("English", false).as_deref(), Some("EN"));
+        assert_eq!(language_code("english", true).as_deref(), Some("EN-US"));
+        assert_eq!(language_code("pt-br", true).as_deref(), Some("PT-BR"));
+        assert_eq!(language_code("pt-br", false), None);
+        assert_eq!(language_code("de", true).as_deref(), Some("DE"));
+        assert_eq!(language_code("Klingon", true), None);
+    }
+}
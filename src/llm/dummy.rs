@@ -1,15 +1,29 @@
-use super::{LLMBuilder, LLM};
+use super::{LLMBuilder, OnText, LLM};
 use crate::parser::{MarkdownSection, MarkdownSubsection};
-use crate::{LLMError, TranslationConfig};
+use crate::utils::log_preview;
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub struct DummyLLMBuilder;
 
 impl LLMBuilder for DummyLLMBuilder {
     type Built = DummyLLM;
 
-    async fn build(&self, _cfg: TranslationConfig) -> Result<Self::Built, LLMError> {
+    async fn build(&self, _cfg: TranslationConfig, _events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
         Ok(DummyLLM)
     }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        Ok(Duration::ZERO)
+    }
 }
 
 pub struct DummyLLM;
@@ -19,3 +33,236 @@ impl LLM for DummyLLM {
         Ok(MarkdownSection(vec![MarkdownSubsection("Dummy output".to_owned())]))
     }
 }
+
+/// Records exchanges of the wrapped LLM to a JSONL fixture, appending to it, for [ReplayLLM] to play back.
+pub struct RecordingLLMBuilder<B: LLMBuilder> {
+    inner: B,
+    fixture: PathBuf,
+}
+
+impl<B: LLMBuilder> RecordingLLMBuilder<B> {
+    pub fn new(inner: B, fixture: PathBuf) -> Self {
+        RecordingLLMBuilder { inner, fixture }
+    }
+}
+
+impl<B: LLMBuilder> LLMBuilder for RecordingLLMBuilder<B> {
+    type Built = RecordingLLM<B::Built>;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.fixture)
+            .map_err(|e| LLMError::OtherError(anyhow!("Couldn't open fixture {}: {e}", self.fixture.display())))?;
+        Ok(RecordingLLM { inner: self.inner.build(cfg, events).await?, file: Mutex::new(file) })
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        self.inner.health_check().await
+    }
+}
+
+/// Method of [LLM] an exchange was made by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Method {
+    Translate,
+    RetryTranslate,
+    Instruct,
+    PostEdit,
+    Stitch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Request {
+    method: Method,
+    source: Vec<String>,
+    /// Reminder of a retry, draft of a post-edit
+    #[serde(default)]
+    extra: Vec<String>,
+}
+
+/// Line of a fixture
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    #[serde(flatten)]
+    request: Request,
+    /// Empty if stitching gave no result
+    response: Vec<String>,
+}
+
+fn texts(section: &MarkdownSection) -> Vec<String> {
+    section.0.iter().map(|ss| ss.0.clone()).collect()
+}
+
+fn section(texts: Vec<String>) -> MarkdownSection {
+    MarkdownSection(texts.into_iter().map(MarkdownSubsection).collect())
+}
+
+pub struct RecordingLLM<L: LLM> {
+    inner: L,
+    file: Mutex<File>,
+}
+
+impl<L: LLM> RecordingLLM<L> {
+    fn record(
+        &self,
+        method: Method,
+        source: Vec<String>,
+        extra: Vec<String>,
+        response: Vec<String>,
+    ) -> Result<(), LLMError> {
+        let exchange = Exchange { request: Request { method, source, extra }, response };
+        let line = serde_json::to_string(&exchange).map_err(|e| LLMError::OtherError(e.into()))?;
+        writeln!(self.file.lock().expect("lock"), "{line}")
+            .map_err(|e| LLMError::OtherError(anyhow!("Couldn't record an exchange: {e}")))
+    }
+}
+
+impl<L: LLM> LLM for RecordingLLM<L> {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let translated = self.inner.translate(section).await?;
+        self.record(Method::Translate, texts(section), vec![], texts(&translated))?;
+        Ok(translated)
+    }
+
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        let translated = self.inner.translate_streaming(section, on_text).await?;
+        self.record(Method::Translate, texts(section), vec![], texts(&translated))?;
+        Ok(translated)
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        let translated = self.inner.retry_translate(section, reminder).await?;
+        self.record(Method::RetryTranslate, texts(section), vec![reminder.to_owned()], texts(&translated))?;
+        Ok(translated)
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let answered = self.inner.instruct(section).await?;
+        self.record(Method::Instruct, texts(section), vec![], texts(&answered))?;
+        Ok(answered)
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let edited = self.inner.post_edit(section, draft).await?;
+        self.record(Method::PostEdit, texts(section), texts(draft), texts(&edited))?;
+        Ok(edited)
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        let stitched = self.inner.stitch(before, after).await?;
+        let response = stitched.iter().flat_map(|(before, after)| [before.clone(), after.clone()]).collect();
+        self.record(Method::Stitch, vec![before.to_owned(), after.to_owned()], vec![], response)?;
+        Ok(stitched)
+    }
+
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        self.inner.prefetch(subsections).await
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        self.inner.close().await
+    }
+}
+
+/// Plays back exchanges recorded by [RecordingLLM] without any network access, so that runs can be tested
+/// deterministically. Requests that weren't recorded fail.
+pub struct ReplayLLMBuilder {
+    fixture: PathBuf,
+}
+
+impl ReplayLLMBuilder {
+    pub fn new(fixture: PathBuf) -> Self {
+        ReplayLLMBuilder { fixture }
+    }
+}
+
+impl LLMBuilder for ReplayLLMBuilder {
+    type Built = ReplayLLM;
+
+    async fn build(&self, _cfg: TranslationConfig, _events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let content = std::fs::read_to_string(&self.fixture)
+            .map_err(|e| LLMError::OtherError(anyhow!("Couldn't read fixture {}: {e}", self.fixture.display())))?;
+        let mut responses = HashMap::new();
+        for (idx, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let exchange = serde_json::from_str::<Exchange>(line)
+                .map_err(|e| LLMError::OtherError(anyhow!("Malformed fixture line {}: {e}", idx + 1)))?;
+            // Latest recording wins
+            responses.insert(exchange.request, exchange.response);
+        }
+        Ok(ReplayLLM { responses })
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        Ok(Duration::ZERO)
+    }
+}
+
+pub struct ReplayLLM {
+    responses: HashMap<Request, Vec<String>>,
+}
+
+impl ReplayLLM {
+    fn replay(&self, method: Method, source: Vec<String>, extra: Vec<String>) -> Result<Vec<String>, LLMError> {
+        let request = Request { method, source, extra };
+        self.responses.get(&request).cloned().ok_or_else(|| {
+            let source = request.source.first().map_or("", String::as_str);
+            LLMError::InteractionError(anyhow!("No recorded {:?} response for {}", method, log_preview(source)))
+        })
+    }
+}
+
+impl LLM for ReplayLLM {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.replay(Method::Translate, texts(section), vec![]).map(self::section)
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.replay(Method::RetryTranslate, texts(section), vec![reminder.to_owned()]).map(self::section)
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.replay(Method::Instruct, texts(section), vec![]).map(self::section)
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.replay(Method::PostEdit, texts(section), texts(draft)).map(self::section)
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        let response = self.replay(Method::Stitch, vec![before.to_owned(), after.to_owned()], vec![])?;
+        Ok(response.into_iter().collect_tuple())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DummySendProgress;
+
+    #[tokio::test]
+    async fn replays_recorded_exchanges() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = dir.path().join("fixture.jsonl");
+        let section = MarkdownSection(vec![MarkdownSubsection("Hello".to_owned())]);
+        let other = MarkdownSection(vec![MarkdownSubsection("Bye".to_owned())]);
+
+        let recording = RecordingLLMBuilder::new(DummyLLMBuilder, fixture.clone());
+        let llm = recording.build(TranslationConfig::default(), Arc::new(DummySendProgress)).await.unwrap();
+        let recorded = llm.translate(&section).await.unwrap();
+        assert_eq!(llm.stitch("One.", "Two.").await.unwrap(), None);
+
+        let replay = ReplayLLMBuilder::new(fixture);
+        let llm = replay.build(TranslationConfig::default(), Arc::new(DummySendProgress)).await.unwrap();
+        assert_eq!(llm.translate(&section).await.unwrap(), recorded);
+        assert_eq!(llm.stitch("One.", "Two.").await.unwrap(), None);
+        assert!(matches!(llm.translate(&other).await, Err(LLMError::InteractionError(_))));
+        assert!(matches!(llm.retry_translate(&section, "Again").await, Err(LLMError::InteractionError(_))));
+    }
+}
@@ -0,0 +1,255 @@
+use super::{LLMBuilder, ModelInfo, Provider, TokenUsage, LLM};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::{LLMError, SendProgress, TranslationConfig, Warning};
+use futures::future::join_all;
+use itertools::Itertools;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Several LLMs translating the same text, the first one of which judges their translations.
+pub struct EnsembleLLMBuilder<B: LLMBuilder> {
+    members: Vec<(String, B)>,
+}
+
+impl<B: LLMBuilder> EnsembleLLMBuilder<B> {
+    pub fn new(name: String, judge: B) -> Self {
+        EnsembleLLMBuilder { members: vec![(name, judge)] }
+    }
+
+    pub fn with_member(mut self, name: String, member: B) -> Self {
+        self.members.push((name, member));
+        self
+    }
+}
+
+impl<B: LLMBuilder> LLMBuilder for EnsembleLLMBuilder<B> {
+    type Built = EnsembleLLM<B::Built>;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let mut members = vec![];
+        for (name, builder) in self.members.iter() {
+            members.push((name.clone(), builder.build(cfg.clone(), events.clone()).await?));
+        }
+        Ok(EnsembleLLM { members, events })
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let mut latency = Duration::ZERO;
+        for (_, builder) in self.members.iter() {
+            latency = latency.max(builder.health_check().await?);
+        }
+        Ok(latency)
+    }
+
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        let mut released = 0;
+        for (_, builder) in self.members.iter() {
+            released += builder.cleanup().await?;
+        }
+        Ok(released)
+    }
+
+    fn supports_seed(&self) -> bool {
+        self.members.iter().all(|(_, builder)| builder.supports_seed())
+    }
+
+    fn supports_batch(&self) -> bool {
+        self.members.iter().any(|(_, builder)| builder.supports_batch())
+    }
+
+    fn max_section_tokens(&self) -> Option<usize> {
+        self.members.iter().filter_map(|(_, builder)| builder.max_section_tokens()).min()
+    }
+
+    /// Of the judge
+    fn model_info(&self) -> Option<ModelInfo> {
+        self.members[0].1.model_info()
+    }
+}
+
+/// Has every member translate each section and the judge pick or merge the best translation of each subsection.
+/// Members failing to translate are left out, as long as at least one of them succeeds.
+/// With a single member, it's just that member.
+pub struct EnsembleLLM<L: LLM> {
+    /// Judge goes first
+    members: Vec<(String, L)>,
+    events: Arc<dyn SendProgress>,
+}
+
+impl<L: LLM> EnsembleLLM<L> {
+    fn judge(&self) -> &L {
+        &self.members[0].1
+    }
+
+    /// Combines translations of the members into one
+    async fn select(
+        &self,
+        section: &MarkdownSection,
+        results: Vec<Result<MarkdownSection, LLMError>>,
+    ) -> Result<MarkdownSection, LLMError> {
+        let mut candidates = vec![];
+        let mut first_error = None;
+        for ((name, _), result) in self.members.iter().zip(results) {
+            match result {
+                Ok(translated) if translated.0.len() == section.0.len() => candidates.push(translated),
+                Ok(_) => log::warn!("Ensemble member {name} returned a mismatched translation, leaving it out"),
+                Err(e) => {
+                    let warning = format!("Ensemble member {name} failed, leaving it out: {e}");
+                    log::warn!("{warning}");
+                    self.events.send_warning(Warning::Other(warning));
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if candidates.len() <= 1 {
+            return match candidates.pop() {
+                Some(translated) => Ok(translated),
+                None => Err(first_error.expect("ensemble member errors")),
+            };
+        }
+
+        let mut selected = candidates[0].clone();
+        let mut disputed = vec![];
+        for (idx, src) in section.0.iter().enumerate() {
+            let options = candidates.iter().map(|c| c.0[idx].0.as_str()).unique().collect_vec();
+            if options.len() > 1 && !src.is_annotation() {
+                disputed.push((idx, judge_prompt(&src.0, &options)));
+            }
+        }
+        if disputed.is_empty() {
+            return Ok(selected);
+        }
+
+        let prompts = disputed.iter().map(|(_, prompt)| MarkdownSubsection(prompt.clone())).collect();
+        let verdicts = self.judge().instruct(&MarkdownSection(prompts)).await?;
+        for ((idx, _), verdict) in disputed.iter().zip(verdicts.0) {
+            if !verdict.0.trim().is_empty() {
+                selected.0[*idx] = verdict;
+            }
+        }
+        Ok(selected)
+    }
+}
+
+fn judge_prompt(src: &str, options: &[&str]) -> String {
+    let candidates = options
+        .iter()
+        .enumerate()
+        .map(|(idx, option)| format!("Translation {}:\n{}", idx + 1, option))
+        .join("\n\n");
+    format!(
+        "Here are several translations of the source text. Pick the most accurate and natural one, \
+        or merge their best parts. Output just the final translation.\n\n\
+        Source:\n{src}\n\n{candidates}"
+    )
+}
+
+impl<L: LLM> LLM for EnsembleLLM<L> {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        if self.members.len() == 1 {
+            return self.judge().translate(section).await;
+        }
+        let results = join_all(self.members.iter().map(|(_, llm)| llm.translate(section))).await;
+        self.select(section, results).await
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        if self.members.len() == 1 {
+            return self.judge().retry_translate(section, reminder).await;
+        }
+        let results = join_all(self.members.iter().map(|(_, llm)| llm.retry_translate(section, reminder))).await;
+        self.select(section, results).await
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.judge().instruct(section).await
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        if self.members.len() == 1 {
+            return self.judge().post_edit(section, draft).await;
+        }
+        let results = join_all(self.members.iter().map(|(_, llm)| llm.post_edit(section, draft))).await;
+        self.select(section, results).await
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        self.judge().stitch(before, after).await
+    }
+
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        for (_, llm) in self.members.iter() {
+            llm.prefetch(subsections).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        let mut result = Ok(());
+        for (_, llm) in self.members.iter_mut() {
+            let closed = llm.close().await;
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        self.members.iter().flat_map(|(_, llm)| llm.usage()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DummySendProgress;
+    use anyhow::anyhow;
+
+    /// Prefixes the source with the given text, answers judge prompts with a fixed verdict, fails if there's none
+    struct TestLLM(Option<&'static str>);
+
+    impl LLM for TestLLM {
+        async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+            let prefix = self.0.ok_or_else(|| LLMError::ConnectionError(anyhow!("down")))?;
+            Ok(MarkdownSection(
+                section
+                    .0
+                    .iter()
+                    .map(|ss| match ss.0.starts_with("Here are several translations") {
+                        true => MarkdownSubsection("Judged".to_owned()),
+                        false => MarkdownSubsection(format!("{prefix}{}", ss.0)),
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    fn ensemble(members: Vec<TestLLM>) -> EnsembleLLM<TestLLM> {
+        EnsembleLLM {
+            members: members.into_iter().enumerate().map(|(idx, llm)| (format!("llm{idx}"), llm)).collect(),
+            events: Arc::new(DummySendProgress),
+        }
+    }
+
+    #[tokio::test]
+    async fn judges_disagreements_only() {
+        let section = MarkdownSection(vec![
+            MarkdownSubsection("Hello".to_owned()),
+            MarkdownSubsection("<!-- rosetta: note -->".to_owned()),
+        ]);
+
+        let translated = ensemble(vec![TestLLM(Some("a")), TestLLM(Some("a"))]).translate(&section).await.unwrap();
+        assert_eq!(translated.0[0].0, "aHello");
+
+        let translated = ensemble(vec![TestLLM(Some("a")), TestLLM(Some("b"))]).translate(&section).await.unwrap();
+        assert_eq!(translated.0[0].0, "Judged");
+        assert_eq!(translated.0[1].0, "a<!-- rosetta: note -->");
+
+        let translated = ensemble(vec![TestLLM(Some("a")), TestLLM(None)]).translate(&section).await.unwrap();
+        assert_eq!(translated.0[0].0, "aHello");
+
+        let result = ensemble(vec![TestLLM(None), TestLLM(None)]).translate(&section).await;
+        assert!(matches!(result, Err(LLMError::ConnectionError(_))));
+    }
+}
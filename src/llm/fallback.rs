@@ -0,0 +1,325 @@
+use super::{LLMBuilder, ModelInfo, OnText, Provider, TokenUsage, LLM};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::{LLMError, SendProgress, TranslationConfig, Warning};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of the latest requests whose latency is averaged
+const LATENCY_WINDOW: usize = 5;
+
+/// Chain of LLMs to fail over to when the one in use has an outage, in the order of preference.
+pub struct FallbackLLMBuilder<B: LLMBuilder> {
+    chain: Vec<(String, B)>,
+    max_latency: Option<Duration>,
+}
+
+impl<B: LLMBuilder> FallbackLLMBuilder<B> {
+    pub fn new(name: String, primary: B) -> Self {
+        FallbackLLMBuilder { chain: vec![(name, primary)], max_latency: None }
+    }
+
+    pub fn with_fallback(mut self, name: String, fallback: B) -> Self {
+        self.chain.push((name, fallback));
+        self
+    }
+
+    /// Switches to the next LLM when the average latency of the latest sections exceeds the limit,
+    /// so that slow providers don't hold deadline-sensitive runs up
+    pub fn with_max_latency(self, max_latency: Option<Duration>) -> Self {
+        FallbackLLMBuilder { max_latency, ..self }
+    }
+}
+
+impl<B: LLMBuilder> LLMBuilder for FallbackLLMBuilder<B> {
+    type Built = FallbackLLM<B::Built>;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let mut chain = vec![];
+        for (idx, (name, builder)) in self.chain.iter().enumerate() {
+            match builder.build(cfg.clone(), events.clone()).await {
+                Ok(llm) => chain.push((name.clone(), llm)),
+                Err(e) if idx == 0 => return Err(e),
+                // Run goes on as long as the primary one is fine
+                Err(e) => {
+                    let warning = format!("Fallback provider {name} is unavailable: {e}");
+                    log::warn!("{warning}");
+                    events.send_warning(Warning::Other(warning));
+                }
+            }
+        }
+        Ok(FallbackLLM {
+            chain,
+            active: AtomicUsize::new(0),
+            max_latency: self.max_latency,
+            latencies: Mutex::new(VecDeque::new()),
+            events,
+        })
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        self.chain[0].1.health_check().await
+    }
+
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        let mut released = 0;
+        for (_, builder) in self.chain.iter() {
+            released += builder.cleanup().await?;
+        }
+        Ok(released)
+    }
+
+    /// Only if the run stays deterministic whichever provider it ends up with
+    fn supports_seed(&self) -> bool {
+        self.chain.iter().all(|(_, builder)| builder.supports_seed())
+    }
+
+    /// Bulk translation is done by the primary one
+    fn supports_batch(&self) -> bool {
+        self.chain[0].1.supports_batch()
+    }
+
+    /// Sections have to fit whichever provider it ends up with
+    fn max_section_tokens(&self) -> Option<usize> {
+        self.chain.iter().filter_map(|(_, builder)| builder.max_section_tokens()).min()
+    }
+
+    /// Of the primary one
+    fn model_info(&self) -> Option<ModelInfo> {
+        self.chain[0].1.model_info()
+    }
+}
+
+/// Switches to the next LLM of the chain when the current one fails with a connection or API error or times out,
+/// retrying the failed request with it, or when it gets too slow.
+/// Once switched, it stays with the next one for the rest of the run.
+pub struct FallbackLLM<L: LLM> {
+    chain: Vec<(String, L)>,
+    active: AtomicUsize,
+    max_latency: Option<Duration>,
+    /// Latencies of the latest successful requests of the active LLM, oldest first
+    latencies: Mutex<VecDeque<Duration>>,
+    events: Arc<dyn SendProgress>,
+}
+
+impl<L: LLM> FallbackLLM<L> {
+    /// Whether the request failed with the given error should be retried with the next LLM
+    fn fail_over(&self, idx: usize, e: &LLMError) -> bool {
+        let is_outage = matches!(e, LLMError::ConnectionError(_) | LLMError::ApiError(_) | LLMError::Timeout(_));
+        if !is_outage || idx + 1 >= self.chain.len() {
+            return false;
+        }
+        self.active.store(idx + 1, Ordering::SeqCst);
+        let warning = format!("{}, switching from {} to {}", e, self.chain[idx].0, self.chain[idx + 1].0);
+        log::warn!("{warning}");
+        self.events.send_warning(Warning::Other(warning));
+        true
+    }
+
+    /// Switches to the next LLM if the average latency of the latest requests exceeds the limit
+    fn record_latency(&self, idx: usize, latency: Duration) {
+        let Some(max_latency) = self.max_latency.filter(|_| idx + 1 < self.chain.len()) else {
+            return;
+        };
+        let mut latencies = self.latencies.lock().expect("lock");
+        latencies.push_back(latency);
+        if latencies.len() > LATENCY_WINDOW {
+            latencies.pop_front();
+        }
+        if latencies.len() < LATENCY_WINDOW {
+            return;
+        }
+        let average = latencies.iter().sum::<Duration>() / LATENCY_WINDOW as u32;
+        // Requests running concurrently might have switched already
+        if average <= max_latency
+            || self.active.compare_exchange(idx, idx + 1, Ordering::SeqCst, Ordering::SeqCst).is_err()
+        {
+            return;
+        }
+        latencies.clear();
+        let warning = format!(
+            "Average latency of {} s exceeds {} s, switching from {} to {}",
+            average.as_secs(),
+            max_latency.as_secs(),
+            self.chain[idx].0,
+            self.chain[idx + 1].0
+        );
+        log::warn!("{warning}");
+        self.events.send_warning(Warning::Other(warning));
+    }
+}
+
+impl<L: LLM> LLM for FallbackLLM<L> {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            let start = Instant::now();
+            match self.chain[idx].1.translate(section).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                Ok(translated) => {
+                    self.record_latency(idx, start.elapsed());
+                    return Ok(translated);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            let start = Instant::now();
+            match self.chain[idx].1.translate_streaming(section, on_text.clone()).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                Ok(translated) => {
+                    self.record_latency(idx, start.elapsed());
+                    return Ok(translated);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            let start = Instant::now();
+            match self.chain[idx].1.retry_translate(section, reminder).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                Ok(translated) => {
+                    self.record_latency(idx, start.elapsed());
+                    return Ok(translated);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            match self.chain[idx].1.instruct(section).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            let start = Instant::now();
+            match self.chain[idx].1.post_edit(section, draft).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                Ok(translated) => {
+                    self.record_latency(idx, start.elapsed());
+                    return Ok(translated);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        loop {
+            let idx = self.active.load(Ordering::SeqCst);
+            match self.chain[idx].1.stitch(before, after).await {
+                Err(e) if self.fail_over(idx, &e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        self.chain[self.active.load(Ordering::SeqCst)].1.prefetch(subsections).await
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        let mut result = Ok(());
+        for (_, llm) in self.chain.iter_mut() {
+            let closed = llm.close().await;
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        self.chain.iter().flat_map(|(_, llm)| llm.usage()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DummySendProgress;
+    use anyhow::anyhow;
+
+    /// Fails with the given error, echoes the source if there's none
+    struct TestLLM(Option<fn() -> LLMError>);
+
+    impl LLM for TestLLM {
+        async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+            match self.0 {
+                Some(error) => Err(error()),
+                None => Ok(section.clone()),
+            }
+        }
+    }
+
+    fn fallback(chain: Vec<TestLLM>) -> FallbackLLM<TestLLM> {
+        FallbackLLM {
+            chain: chain.into_iter().enumerate().map(|(idx, llm)| (format!("llm{idx}"), llm)).collect(),
+            active: AtomicUsize::new(0),
+            max_latency: Some(Duration::from_secs(10)),
+            latencies: Mutex::new(VecDeque::new()),
+            events: Arc::new(DummySendProgress),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_outage_only() {
+        let section = MarkdownSection(vec![MarkdownSubsection("Hello".to_owned())]);
+
+        let llm = fallback(vec![
+            TestLLM(Some(|| LLMError::ConnectionError(anyhow!("down")))),
+            TestLLM(Some(|| LLMError::ApiError(anyhow!("overloaded")))),
+            TestLLM(None),
+        ]);
+        assert_eq!(llm.translate(&section).await.unwrap(), section);
+        assert_eq!(llm.active.load(Ordering::SeqCst), 2);
+
+        let llm = fallback(vec![
+            TestLLM(Some(|| LLMError::ContentPolicyViolation(anyhow!("refused")))),
+            TestLLM(None),
+        ]);
+        assert!(matches!(llm.translate(&section).await, Err(LLMError::ContentPolicyViolation(_))));
+        assert_eq!(llm.active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn switches_when_too_slow_on_average() {
+        let llm = fallback(vec![TestLLM(None), TestLLM(None)]);
+        for secs in [30, 1, 1, 1, 1, 1] {
+            llm.record_latency(0, Duration::from_secs(secs));
+        }
+        assert_eq!(llm.active.load(Ordering::SeqCst), 0);
+
+        for _ in 0..LATENCY_WINDOW {
+            llm.record_latency(0, Duration::from_secs(11));
+        }
+        assert_eq!(llm.active.load(Ordering::SeqCst), 1);
+
+        // Nowhere to switch from the last one
+        for _ in 0..LATENCY_WINDOW {
+            llm.record_latency(1, Duration::from_secs(60));
+        }
+        assert_eq!(llm.active.load(Ordering::SeqCst), 1);
+    }
+}
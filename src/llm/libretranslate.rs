@@ -0,0 +1,238 @@
+use super::{LLM, LLMBuilder, Provider, ProxyConfig, RequestError};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Language names and LibreTranslate codes for them
+const LANGUAGES: &[(&str, &str)] = &[
+    ("Arabic", "ar"),
+    ("Bulgarian", "bg"),
+    ("Catalan", "ca"),
+    ("Chinese", "zh"),
+    ("Czech", "cs"),
+    ("Danish", "da"),
+    ("Dutch", "nl"),
+    ("English", "en"),
+    ("Estonian", "et"),
+    ("Finnish", "fi"),
+    ("French", "fr"),
+    ("German", "de"),
+    ("Greek", "el"),
+    ("Hebrew", "he"),
+    ("Hindi", "hi"),
+    ("Hungarian", "hu"),
+    ("Indonesian", "id"),
+    ("Irish", "ga"),
+    ("Italian", "it"),
+    ("Japanese", "ja"),
+    ("Korean", "ko"),
+    ("Latvian", "lv"),
+    ("Lithuanian", "lt"),
+    ("Norwegian", "nb"),
+    ("Persian", "fa"),
+    ("Polish", "pl"),
+    ("Portuguese", "pt"),
+    ("Romanian", "ro"),
+    ("Russian", "ru"),
+    ("Slovak", "sk"),
+    ("Slovenian", "sl"),
+    ("Spanish", "es"),
+    ("Swedish", "sv"),
+    ("Thai", "th"),
+    ("Turkish", "tr"),
+    ("Ukrainian", "uk"),
+    ("Vietnamese", "vi"),
+];
+
+/// Machine translation by a self-hosted LibreTranslate instance, see https://docs.libretranslate.com,
+/// free and keeping the documents private, which makes it good for drafts.
+/// It's not an LLM, so only the languages are taken from the config.
+pub struct LibreTranslateBuilder {
+    /// Instance URL, e.g. `http://localhost:5000`
+    url: String,
+    /// Only needed by instances requiring API keys
+    api_key: Option<String>,
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl LibreTranslateBuilder {
+    pub fn new(url: String, api_key: Option<String>) -> Self {
+        LibreTranslateBuilder {
+            url: url.trim_end_matches('/').to_owned(),
+            api_key,
+            request_timeout: None,
+            proxy: None,
+        }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        LibreTranslateBuilder { request_timeout, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        LibreTranslateBuilder { proxy, ..self }
+    }
+
+    fn client(&self, events: Arc<dyn SendProgress>) -> LibreTranslate {
+        LibreTranslate {
+            client: super::http_client(self.request_timeout, self.proxy.as_ref()),
+            url: self.url.clone(),
+            api_key: self.api_key.clone(),
+            source: String::new(),
+            target: String::new(),
+            events,
+        }
+    }
+}
+
+impl LLMBuilder for LibreTranslateBuilder {
+    type Built = LibreTranslate;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let source = language_code(&cfg.src_lang)
+            .ok_or_else(|| LLMError::OtherError(anyhow!("LibreTranslate can't translate from {}", cfg.src_lang)))?;
+        let target = language_code(&cfg.dst_lang)
+            .ok_or_else(|| LLMError::OtherError(anyhow!("LibreTranslate can't translate to {}", cfg.dst_lang)))?;
+        if !cfg.additional_instructions.trim().is_empty() || !cfg.style_sample.trim().is_empty() {
+            log::info!("LibreTranslate doesn't follow instructions or style samples, ignoring them");
+        }
+
+        let mut libre = self.client(events);
+        libre.source = source;
+        libre.target = target;
+        Ok(libre)
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let libre = self.client(Arc::new(crate::DummySendProgress));
+        let start = Instant::now();
+        let response = libre
+            .client
+            .get(format!("{}/languages", libre.url))
+            .send()
+            .await
+            .map_err(|e| LLMError::ConnectionError(e.into()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(api_error(status, body));
+        }
+        Ok(start.elapsed())
+    }
+}
+
+/// Maps a language name (e.g. `German`) or a code (e.g. `de`, `zh-Hant`) to a code LibreTranslate accepts.
+/// Codes not known here are passed as they are, since instances can have other languages installed.
+fn language_code(lang: &str) -> Option<String> {
+    let lang = lang.trim();
+    if let Some((_, code)) = LANGUAGES.iter().find(|(name, code)| name.eq_ignore_ascii_case(lang) || *code == lang) {
+        return Some(code.to_string());
+    }
+    let is_code = (2..=7).contains(&lang.len())
+        && lang.starts_with(|c: char| c.is_ascii_lowercase())
+        && lang.chars().all(|c| c.is_ascii_alphabetic() || c == '-');
+    is_code.then(|| lang.to_owned())
+}
+
+pub struct LibreTranslate {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    source: String,
+    target: String,
+    events: Arc<dyn SendProgress>,
+}
+
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    q: Vec<&'a str>,
+    source: &'a str,
+    target: &'a str,
+    format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct TranslateResponse {
+    #[serde(rename = "translatedText")]
+    translated_text: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+impl LLM for LibreTranslate {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let req = TranslateRequest {
+            q: section.0.iter().map(|ss| ss.0.as_str()).collect(),
+            source: &self.source,
+            target: &self.target,
+            format: "text",
+            api_key: self.api_key.as_deref(),
+        };
+        let response = self.send(&req).await?;
+
+        if response.translated_text.len() != section.0.len() {
+            return Err(LLMError::InteractionError(anyhow!(
+                "Incorrect number of translations: {} instead of {}",
+                response.translated_text.len(),
+                section.0.len()
+            )));
+        }
+        Ok(MarkdownSection(response.translated_text.into_iter().map(MarkdownSubsection).collect()))
+    }
+
+    /// Can't follow post-editing instructions, so translates the source anew
+    async fn post_edit(&self, section: &MarkdownSection, _draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate(section).await
+    }
+
+    /// Can't follow stitching instructions either, so leaves the junction as is
+    async fn stitch(&self, _before: &str, _after: &str) -> Result<Option<(String, String)>, LLMError> {
+        Ok(None)
+    }
+}
+
+impl LibreTranslate {
+    /// Sends the request, waiting out network outages and retrying transient failures with a backoff
+    async fn send(&self, req: &TranslateRequest<'_>) -> Result<TranslateResponse, LLMError> {
+        let send = || self.client.post(format!("{}/translate", self.url)).json(req).send();
+        super::send_json(Provider::LibreTranslate, &*self.events, send, classify_error).await
+    }
+}
+
+fn classify_error(status: reqwest::StatusCode, body: String) -> RequestError {
+    match status.as_u16() {
+        429 => RequestError::rate_limited(anyhow!("{status}")),
+        // Instance being restarted or overloaded
+        _ if status.is_server_error() => RequestError::transient(anyhow!("{status}")),
+        _ => api_error(status, body).into(),
+    }
+}
+
+/// Instances requiring API keys respond with 403 if the one given, if any, isn't valid
+fn api_error(status: reqwest::StatusCode, body: String) -> LLMError {
+    let message = serde_json::from_str::<ErrorResponse>(&body).map_or(body, |e| e.error);
+    super::api_error(status, message, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(language_code("English").as_deref(), Some("en"));
+        assert_eq!(language_code(" russian ").as_deref(), Some("ru"));
+        assert_eq!(language_code("de").as_deref(), Some("de"));
+        assert_eq!(language_code("zh-Hant").as_deref(), Some("zh-Hant"));
+        assert_eq!(language_code("Klingon"), None);
+    }
+}
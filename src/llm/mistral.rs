@@ -0,0 +1,256 @@
+use super::{LLM, LLMBuilder, ModelInfo, Provider, ProxyConfig, RequestError, TokenUsage};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::log_preview;
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://api.mistral.ai/v1/chat/completions";
+
+const API_KEYS_URL: &str = "https://console.mistral.ai/api-keys";
+const BILLING_URL: &str = "https://console.mistral.ai/billing";
+
+/// Chat API is stateless, so previous exchanges are re-sent to keep the terminology consistent.
+/// Only the most recent ones are kept to bound the cost.
+const MAX_HISTORY_EXCHANGES: usize = 4;
+
+/// Builder for Mistral models, see https://docs.mistral.ai/api/#tag/chat
+pub struct MistralBuilder {
+    model: String,
+    api_key: String,
+    temperature: f32,
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl MistralBuilder {
+    pub fn new(model: String, api_key: String) -> Self {
+        MistralBuilder {
+            model,
+            api_key,
+            temperature: 0.7,
+            request_timeout: None,
+            proxy: None,
+        }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        MistralBuilder { request_timeout, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        MistralBuilder { proxy, ..self }
+    }
+
+    fn client(&self, system: String, seed: Option<u64>, events: Arc<dyn SendProgress>) -> Mistral {
+        Mistral {
+            client: super::http_client(self.request_timeout, self.proxy.as_ref()),
+            api_key: self.api_key.clone(),
+            model: self.model.clone(),
+            temperature: self.temperature,
+            seed,
+            system,
+            history: Mutex::new(VecDeque::new()),
+            usage: Mutex::new(TokenUsage::default()),
+            events,
+        }
+    }
+}
+
+impl LLMBuilder for MistralBuilder {
+    type Built = Mistral;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        Ok(self.client(super::cfg_to_prompt(&cfg), cfg.seed, events))
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let mistral = self.client("Reply with OK".to_owned(), None, Arc::new(crate::DummySendProgress));
+        let req = ChatRequest {
+            model: &mistral.model,
+            temperature: mistral.temperature,
+            max_tokens: Some(5),
+            random_seed: None,
+            messages: vec![
+                Message { role: "system", content: mistral.system.clone() },
+                Message { role: "user", content: "OK?".to_owned() },
+            ],
+        };
+
+        let start = Instant::now();
+        mistral.send(&req).await?;
+        Ok(start.elapsed())
+    }
+
+    fn supports_seed(&self) -> bool {
+        true
+    }
+
+    fn model_info(&self) -> Option<ModelInfo> {
+        super::models::model_info(&self.model)
+    }
+}
+
+pub struct Mistral {
+    client: reqwest::Client,
+    api_key: String,
+    model: String,
+    temperature: f32,
+    seed: Option<u64>,
+    system: String,
+    /// Previous source texts and their translations, oldest first
+    history: Mutex<VecDeque<(String, String)>>,
+    usage: Mutex<TokenUsage>,
+    events: Arc<dyn SendProgress>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    random_seed: Option<u64>,
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: String,
+}
+
+/// Mistral reports errors in several shapes, only the message is common to them
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(alias = "detail")]
+    message: serde_json::Value,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+}
+
+impl LLM for Mistral {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, None).await
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, Some(reminder)).await
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        vec![(Provider::Mistral, *self.usage.lock().expect("lock"))]
+    }
+}
+
+impl Mistral {
+    async fn translate_with_reminder(&self, section: &MarkdownSection, reminder: Option<&str>) -> Result<MarkdownSection, LLMError> {
+        let mut subsections = vec![];
+        for s in section.0.iter() {
+            log::info!("Sending message {}", log_preview(&s.0));
+            let content = match reminder {
+                Some(reminder) => format!("{reminder}\n\n{}", s.0),
+                None => s.0.clone(),
+            };
+
+            let mut messages = vec![Message { role: "system", content: self.system.clone() }];
+            for (src, translated) in self.history.lock().expect("lock").iter() {
+                messages.push(Message { role: "user", content: src.clone() });
+                messages.push(Message { role: "assistant", content: translated.clone() });
+            }
+            messages.push(Message { role: "user", content });
+
+            let req = ChatRequest {
+                model: &self.model,
+                temperature: self.temperature,
+                max_tokens: None,
+                random_seed: self.seed,
+                messages,
+            };
+            let response = self.send(&req).await?;
+            if let Some(ref usage) = response.usage {
+                self.usage.lock().expect("lock").add_tokens(usage.prompt_tokens, usage.completion_tokens);
+            }
+
+            let Some(choice) = response.choices.into_iter().next() else {
+                return Err(LLMError::InteractionError(anyhow!("Response has no choices")));
+            };
+            if choice.finish_reason.as_deref() == Some("length") {
+                return Err(LLMError::InteractionError(anyhow!("Translation was cut off by the model context length")));
+            }
+            let translated = choice.message.content;
+            if translated.is_empty() {
+                return Err(LLMError::InteractionError(anyhow!("Response has no text")));
+            }
+            log::info!("Got translated message");
+
+            let mut history = self.history.lock().expect("lock");
+            history.push_back((s.0.clone(), translated.clone()));
+            if history.len() > MAX_HISTORY_EXCHANGES {
+                history.pop_front();
+            }
+            drop(history);
+
+            subsections.push(MarkdownSubsection(translated));
+        }
+        Ok(MarkdownSection(subsections))
+    }
+
+    /// Sends the request, waiting out network outages and retrying transient failures with a backoff
+    async fn send(&self, req: &ChatRequest<'_>) -> Result<ChatResponse, LLMError> {
+        let send = || self.client.post(API_URL).bearer_auth(&self.api_key).json(req).send();
+        super::send_json(Provider::Mistral, &*self.events, send, classify_error).await
+    }
+}
+
+fn classify_error(status: reqwest::StatusCode, body: String) -> RequestError {
+    let (message, kind) = match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(ErrorResponse { message: serde_json::Value::String(message), kind }) => (message, kind),
+        Ok(ErrorResponse { message, kind }) => (message.to_string(), kind),
+        Err(_) => (body, None),
+    };
+    let source = anyhow!("{status}: {message}");
+    match status.as_u16() {
+        401 | 403 => LLMError::InvalidApiKey { help_url: Some(API_KEYS_URL), source }.into(),
+        402 => LLMError::QuotaExceeded { help_url: Some(BILLING_URL), source }.into(),
+        // Monthly token limit of the workspace, which waiting won't help with
+        429 if message.to_lowercase().contains("monthly") => {
+            LLMError::QuotaExceeded { help_url: Some(BILLING_URL), source }.into()
+        }
+        // Rate limits are per second and per minute, so they are waited out without counting as errors
+        429 => RequestError::throttled(source),
+        _ if kind.as_deref() == Some("invalid_model") || status.as_u16() == 404 => {
+            LLMError::ModelNotFound(source).into()
+        }
+        _ if status.is_server_error() => RequestError::transient(source),
+        _ => LLMError::ApiError(source).into(),
+    }
+}
@@ -0,0 +1,78 @@
+/// What's known of a model, so that features don't need tables of their own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelInfo {
+    /// Tokens of the prompt and the output together
+    pub context_tokens: usize,
+    pub max_output_tokens: Option<usize>,
+    pub supports_streaming: bool,
+    /// Reasoning models only take the default temperature and top_p
+    pub supports_temperature: bool,
+    /// USD per million prompt tokens, without caching discounts
+    pub price_in: Option<f64>,
+    /// USD per million completion tokens
+    pub price_out: Option<f64>,
+}
+
+impl ModelInfo {
+    const fn chat(context_tokens: usize, max_output_tokens: usize, price_in: f64, price_out: f64) -> Self {
+        ModelInfo {
+            context_tokens,
+            max_output_tokens: Some(max_output_tokens),
+            supports_streaming: true,
+            supports_temperature: true,
+            price_in: Some(price_in),
+            price_out: Some(price_out),
+        }
+    }
+
+    const fn reasoning(context_tokens: usize, max_output_tokens: usize, price_in: f64, price_out: f64) -> Self {
+        ModelInfo { supports_temperature: false, ..Self::chat(context_tokens, max_output_tokens, price_in, price_out) }
+    }
+}
+
+/// Well-known models by name prefix, more specific prefixes go first
+const MODELS: &[(&str, ModelInfo)] = &[
+    ("gpt-5-nano", ModelInfo::reasoning(400_000, 128_000, 0.05, 0.4)),
+    ("gpt-5-mini", ModelInfo::reasoning(400_000, 128_000, 0.25, 2.0)),
+    ("gpt-5", ModelInfo::reasoning(400_000, 128_000, 1.25, 10.0)),
+    ("gpt-4.1-nano", ModelInfo::chat(1_047_576, 32_768, 0.1, 0.4)),
+    ("gpt-4.1-mini", ModelInfo::chat(1_047_576, 32_768, 0.4, 1.6)),
+    ("gpt-4.1", ModelInfo::chat(1_047_576, 32_768, 2.0, 8.0)),
+    ("gpt-4o-mini", ModelInfo::chat(128_000, 16_384, 0.15, 0.6)),
+    ("gpt-4o", ModelInfo::chat(128_000, 16_384, 2.5, 10.0)),
+    ("o4-mini", ModelInfo::reasoning(200_000, 100_000, 1.1, 4.4)),
+    ("o3-mini", ModelInfo::reasoning(200_000, 100_000, 1.1, 4.4)),
+    ("o3", ModelInfo::reasoning(200_000, 100_000, 2.0, 8.0)),
+    ("o1", ModelInfo::reasoning(200_000, 100_000, 15.0, 60.0)),
+    ("claude-opus-4", ModelInfo::chat(200_000, 32_000, 15.0, 75.0)),
+    ("claude-sonnet-4", ModelInfo::chat(200_000, 64_000, 3.0, 15.0)),
+    ("claude-haiku-4", ModelInfo::chat(200_000, 64_000, 1.0, 5.0)),
+    ("claude-3-5-haiku", ModelInfo::chat(200_000, 8_192, 0.8, 4.0)),
+    // Output is only limited by the context
+    ("mistral-large", ModelInfo { max_output_tokens: None, ..ModelInfo::chat(131_072, 0, 2.0, 6.0) }),
+    ("mistral-small", ModelInfo { max_output_tokens: None, ..ModelInfo::chat(131_072, 0, 0.1, 0.3) }),
+];
+
+/// Info of a well-known model, None for others, e.g. local ones or Azure deployments.
+/// OpenRouter names like `anthropic/claude-sonnet-4.5` are looked up without the vendor.
+pub fn model_info(model: &str) -> Option<ModelInfo> {
+    let model = model.rsplit('/').next().unwrap_or_default().replace('.', "-").to_lowercase();
+    MODELS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(&prefix.replace('.', "-")))
+        .map(|(_, info)| *info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_most_specific_model() {
+        assert_eq!(model_info("gpt-4o-mini-2024-07-18").and_then(|info| info.price_in), Some(0.15));
+        assert_eq!(model_info("gpt-4o").and_then(|info| info.price_in), Some(2.5));
+        assert_eq!(model_info("anthropic/claude-sonnet-4.5").map(|info| info.max_output_tokens), Some(Some(64_000)));
+        assert!(!model_info("o3-mini").unwrap().supports_temperature);
+        assert_eq!(model_info("llama-3.1-8b"), None);
+    }
+}
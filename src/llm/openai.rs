@@ -1,429 +1,803 @@
-use super::{LLM, LLMBuilder};
+pub mod azure;
+mod batch;
+pub mod keys;
+
+use super::{History, LLM, LLMBuilder, ModelInfo, OnText, Provider, ProxyConfig, RequestError, TokenUsage};
+use crate::glossary::GlossaryEntry;
 use crate::parser::{MarkdownSection, MarkdownSubsection};
-use crate::utils::substr_up_to_len;
-use crate::{LLMError, MAX_LOG_SRC_LEN, TranslationConfig};
-use anyhow::{Context, anyhow, bail};
+use crate::utils::{data_dir, log_preview, write_atomically};
+use crate::{LLMError, SendProgress, TranslationConfig, Warning};
+use anyhow::anyhow;
 use async_openai::Client;
-use async_openai::config::OpenAIConfig;
 use async_openai::error::OpenAIError;
 use async_openai::types::{
-    AssistantObject, AssistantsApiResponseFormatOption, CreateAssistantRequest,
-    CreateMessageRequest, CreateMessageRequestContent, CreateRunRequest, CreateThreadRequest,
-    LastError, LastErrorCode, MessageContent, MessageRole, ModifyAssistantRequest, ResponseFormat,
-    RunObject, RunStatus, ThreadObject,
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionStreamOptions,
+    ChatCompletionToolArgs, CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs, FinishReason,
+    FunctionObjectArgs, ResponseFormat, ResponseFormatJsonSchema,
 };
-use backoff::ExponentialBackoff;
-use backoff::backoff::Backoff;
-use serde::{Deserialize, Serialize};
+use azure::{AzureDeployment, Endpoint, EndpointConfig};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+pub use async_openai::types::ReasoningEffort;
+use futures::StreamExt;
+use keys::{ApiKeyPool, KeySelection};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Structured responses that don't match the schema are requested again this many times
+const MAX_MALFORMED_RETRIES: usize = 2;
+
+/// Model looking the glossary up more times than that for a single message is considered stuck
+const MAX_GLOSSARY_LOOKUPS: usize = 3;
+
+const GLOSSARY_TOOL_NAME: &str = "look_up_glossary";
+
+/// Chat Completions API is stateless, so previous exchanges are re-sent to keep the terminology consistent.
+/// Only the most recent ones are kept to bound the cost.
+pub const DEFAULT_HISTORY_EXCHANGES: usize = 4;
+
+const OPENAI_PROJECT: HeaderName = HeaderName::from_static("openai-project");
 
-const ASSISTANT_NAME: &str = "rosetta-translator";
-const ASSISTANT_DESC: &str = "A Rosetta translation assistant";
+const API_KEYS_URL: &str = "https://platform.openai.com/api-keys";
+const BILLING_URL: &str = "https://platform.openai.com/settings/organization/billing";
 
-const MAX_SEQUENTIAL_ERRORS: usize = 5;
+/// IDs of Assistants API threads left over by older versions, since OpenAI API provides no way to list them.
+/// It's kept in the per-user data directory, older versions kept it in the working directory.
+const THREAD_REGISTRY_FILE: &str = "rosetta-openai-threads.txt";
+
+/// Held while the thread registry is read and written back
+static THREAD_REGISTRY_LOCK: Mutex<()> = Mutex::new(());
 
 pub struct OpenAiGPTBuilder {
+    /// Deployment name for Azure
     model: String,
-    api_key: String,
+    keys: Arc<ApiKeyPool>,
+    /// Only the public API is sure to list models the same way
+    is_public_api: bool,
+    stream: bool,
     temperature: f32,
     top_p: f32,
+    max_tokens: Option<u32>,
+    json_output: bool,
+    batch: bool,
+    glossary_tool: bool,
+    history_exchanges: usize,
+    reasoning_effort: Option<ReasoningEffort>,
+    section_timeout: Option<Duration>,
+    /// HTTP client of the keys is rebuilt with both whenever either of them changes
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+    /// OpenAI project requests are billed to, sent as a header of the HTTP client
+    project: Option<String>,
 }
 
 /// Builder for OpenAI-compatible LLM APIs
 impl OpenAiGPTBuilder {
-    pub fn new(model: String, api_key: String) -> Self {
+    /// Several API keys can be given to spread requests across them.
+    /// Requests go to the public API, or to an OpenAI-compatible server at `base_url` if given.
+    pub fn new(model: String, api_keys: Vec<String>, key_selection: KeySelection, base_url: Option<String>) -> Self {
+        let endpoint = match base_url {
+            Some(base_url) => Endpoint::Compatible(base_url),
+            None => Endpoint::OpenAi,
+        };
         OpenAiGPTBuilder {
             model,
-            api_key,
+            keys: Arc::new(ApiKeyPool::new(&api_keys, key_selection, &endpoint)),
+            is_public_api: endpoint == Endpoint::OpenAi,
+            stream: false,
+            temperature: 1.0,
+            top_p: 1.0,
+            max_tokens: None,
+            json_output: false,
+            batch: false,
+            glossary_tool: false,
+            history_exchanges: DEFAULT_HISTORY_EXCHANGES,
+            reasoning_effort: None,
+            section_timeout: None,
+            request_timeout: None,
+            proxy: None,
+            project: None,
+        }
+    }
+
+    /// Uses an Azure OpenAI deployment instead of the public API, keys need to be the resource's ones
+    pub fn new_azure(api_keys: Vec<String>, key_selection: KeySelection, azure: AzureDeployment) -> Self {
+        OpenAiGPTBuilder {
+            model: azure.deployment.clone(),
+            keys: Arc::new(ApiKeyPool::new(&api_keys, key_selection, &Endpoint::Azure(azure))),
+            is_public_api: false,
+            stream: false,
             temperature: 1.0,
             top_p: 1.0,
+            max_tokens: None,
+            json_output: false,
+            batch: false,
+            glossary_tool: false,
+            history_exchanges: DEFAULT_HISTORY_EXCHANGES,
+            reasoning_effort: None,
+            section_timeout: None,
+            request_timeout: None,
+            proxy: None,
+            project: None,
+        }
+    }
+
+    /// Responses are received as they are generated, so that the translation can be followed live
+    pub fn with_streaming(self, stream: bool) -> Self {
+        OpenAiGPTBuilder { stream, ..self }
+    }
+
+    /// Lower temperature and top_p make translations more literal and repeatable, higher ones more free.
+    /// Without `max_tokens` the output is only limited by the model.
+    pub fn with_sampling(self, temperature: f32, top_p: f32, max_tokens: Option<u32>) -> Self {
+        OpenAiGPTBuilder { temperature, top_p, max_tokens, ..self }
+    }
+
+    /// Translations are requested as `{"translation": "..."}` JSON, so that models can't add remarks around them.
+    /// Such responses can't be followed live, so streaming is not used then.
+    pub fn with_json_output(self, json_output: bool) -> Self {
+        OpenAiGPTBuilder { json_output, ..self }
+    }
+
+    /// Instead of listing the whole glossary in the system prompt, the model is given a tool to look terms up,
+    /// which keeps prompts short with large glossaries. Responses using tools can't be followed live.
+    pub fn with_glossary_tool(self, glossary_tool: bool) -> Self {
+        OpenAiGPTBuilder { glossary_tool, ..self }
+    }
+
+    /// Requests taking longer than that are retried, see [super::http_client].
+    /// Streamed responses need to be received within it as well.
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        OpenAiGPTBuilder { request_timeout, ..self }.with_http_client()
+    }
+
+    /// Requests go through the proxy, e.g. a corporate one, see [super::http_client]
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        OpenAiGPTBuilder { proxy, ..self }.with_http_client()
+    }
+
+    /// Requests are billed to the given OpenAI project, e.g. `proj_abc123`, rather than the default one of the keys,
+    /// so that costs can be told apart per client on OpenAI's usage dashboard. Only the public API has projects.
+    pub fn with_project(self, project: Option<String>) -> Self {
+        OpenAiGPTBuilder { project, ..self }.with_http_client()
+    }
+
+    fn with_http_client(mut self) -> Self {
+        let mut builder = super::http_client_builder(self.request_timeout, self.proxy.as_ref());
+        if let Some(project) = self.project.as_deref().filter(|_| self.is_public_api) {
+            match HeaderValue::from_str(project) {
+                Ok(project) => builder = builder.default_headers(HeaderMap::from_iter([(OPENAI_PROJECT, project)])),
+                Err(_) => log::warn!("OpenAI project ID {project:?} isn't valid, requests go to the default project"),
+            }
         }
+        let http_client = builder.build().expect("HTTP client");
+        Arc::get_mut(&mut self.keys).expect("key pool is shared only once built").set_http_client(http_client);
+        self
+    }
+
+    /// Uncached subsections are translated up front by a Batch API job, which costs half as much
+    /// and has separate rate limits, but takes up to 24 hours.
+    /// Batched requests are independent, so they don't see previous exchanges.
+    pub fn with_batch(self, batch: bool) -> Self {
+        OpenAiGPTBuilder { batch, ..self }
+    }
+
+    /// Number of previous exchanges re-sent with each message as context, 0 to send messages on their own.
+    /// More context keeps the translation more consistent, at the cost of more prompt tokens per message.
+    pub fn with_history(self, history_exchanges: usize) -> Self {
+        OpenAiGPTBuilder { history_exchanges, ..self }
+    }
+
+    /// How much reasoning models think before answering, the API default if not given.
+    /// Setting it marks the model as a reasoning one, so that sampling parameters it rejects aren't sent
+    /// even if it's not a well-known one, e.g. an Azure deployment.
+    pub fn with_reasoning_effort(self, reasoning_effort: Option<ReasoningEffort>) -> Self {
+        OpenAiGPTBuilder { reasoning_effort, ..self }
+    }
+
+    /// Sections taking longer than that to translate, retries and glossary lookups included,
+    /// fail with [LLMError::Timeout], so that a hanging request doesn't hold the run up indefinitely
+    pub fn with_section_timeout(self, section_timeout: Option<Duration>) -> Self {
+        OpenAiGPTBuilder { section_timeout, ..self }
     }
 }
 
 impl LLMBuilder for OpenAiGPTBuilder {
     type Built = OpenAiGPT;
 
-    async fn build(&self, cfg: TranslationConfig) -> Result<Self::Built, LLMError> {
-        let prompt = super::cfg_to_prompt(&cfg);
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        let tool_glossary = match self.glossary_tool {
+            true => cfg.glossary.clone(),
+            false => vec![],
+        };
+        let system = match tool_glossary.is_empty() {
+            true => super::cfg_to_prompt(&cfg),
+            false => format!(
+                "{}\n{}",
+                super::cfg_to_prompt(&TranslationConfig { glossary: vec![], ..cfg.clone() }),
+                GLOSSARY_TOOL_PROMPT
+            ),
+        };
+        let info = self.model_info();
+        Ok(OpenAiGPT {
+            keys: self.keys.clone(),
+            model: self.model.clone(),
+            stream: self.stream && info.is_none_or(|info| info.supports_streaming),
+            supports_temperature: self.reasoning_effort.is_none() && info.is_none_or(|info| info.supports_temperature),
+            reasoning_effort: self.reasoning_effort.clone(),
+            section_timeout: self.section_timeout,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            json_output: self.json_output,
+            seed: cfg.seed,
+            system: match self.json_output {
+                true => format!("{}\n\n{}", system, JSON_OUTPUT_PROMPT),
+                false => system,
+            },
+            tool_glossary,
+            history: History::new(self.history_exchanges),
+            prefetched: Mutex::new(HashMap::new()),
+            usage: Mutex::new(TokenUsage::default()),
+            events,
+        })
+    }
 
-        let config = OpenAIConfig::new()
-            .with_api_key(&self.api_key);
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let (_, client) = self.keys.pick()?;
 
-        let client = Client::with_config(config);
+        // Fails early with a clear error if the model isn't available for this key,
+        // Azure lists base models rather than deployments though, and compatible servers vary
+        if self.is_public_api {
+            client.models().retrieve(&self.model).await?;
+        }
 
-        let asistants = {
-            let client = client.clone();
-            run_openai_request(async move || {
-                client
-                    .assistants()
-                    .list(&HashMap::<String, String>::new())
-                    .await
-            }).await?
-        };
-        assert!(!asistants.has_more);
+        let req = CreateChatCompletionRequestArgs::default()
+            .model(&self.model)
+            .messages([ChatCompletionRequestUserMessageArgs::default()
+                .content("Reply with OK")
+                .build()?
+                .into()])
+            .max_completion_tokens(5u32)
+            .build()?;
+
+        let start = Instant::now();
+        client.chat().create(req).await?;
+        Ok(start.elapsed())
+    }
 
-        let assistant = asistants
-            .data
-            .into_iter()
-            .find(|assistant| assistant.name.as_deref() == Some(ASSISTANT_NAME));
-
-        let assistant = if let Some(assistant) = assistant {
-            let req = ModifyAssistantRequest {
-                model: Some(self.model.clone()),
-                name: Some(ASSISTANT_NAME.to_owned()),
-                description: Some(ASSISTANT_DESC.to_owned()),
-                instructions: Some(prompt),
-                tools: None,
-                tool_resources: None,
-                metadata: None,
-                temperature: Some(self.temperature),
-                top_p: Some(self.top_p),
-                response_format: Some(AssistantsApiResponseFormatOption::Format(
-                    ResponseFormat::Text,
-                )),
-            };
-            let client = client.clone();
-            let assistant_id = assistant.id.clone();
-            run_openai_request(async move || {
-                client.assistants().update(&assistant_id, req.clone()).await
-            }).await?
-        } else {
-            let req = CreateAssistantRequest {
-                model: self.model.clone(),
-                name: Some(ASSISTANT_NAME.to_owned()),
-                description: Some(ASSISTANT_DESC.to_owned()),
-                instructions: Some(prompt),
-                tools: None,
-                tool_resources: None,
-                metadata: None,
-                temperature: Some(self.temperature),
-                top_p: Some(self.top_p),
-                response_format: Some(AssistantsApiResponseFormatOption::Format(
-                    ResponseFormat::Text,
-                )),
-            };
-            let client = client.clone();
-            run_openai_request(async move || {
-                client.assistants().create(req.clone()).await
-            }).await?
-        };
+    /// Deletes Assistants API threads left by versions that used it, current version doesn't create any
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        let (_, client) = self.keys.pick()?;
+        let mut deleted = 0;
+        for thread_id in registered_threads() {
+            match client.threads().delete(&thread_id).await {
+                Ok(_) => deleted += 1,
+                // Server doesn't know it, nothing to clean up
+                Err(OpenAIError::ApiError(e)) if e.message.starts_with("No thread found") => {
+                    log::warn!("Couldn't delete thread {}: {}", thread_id, e.message)
+                }
+                // Kept registered to be deleted by the next cleanup
+                Err(e) => {
+                    log::error!("Failed to delete thread {}: {}", thread_id, e);
+                    continue;
+                }
+            }
+            unregister_thread(&thread_id);
+        }
+        Ok(deleted)
+    }
 
-        let thread = {
-            let client = client.clone();
-            run_openai_request(async move || {
-                client
-                    .threads()
-                    .create(CreateThreadRequest {
-                        messages: None,
-                        tool_resources: None,
-                        metadata: None,
-                    }).await
-            }).await?
-        };
+    fn supports_seed(&self) -> bool {
+        true
+    }
 
-        Ok(OpenAiGPT {
-            client,
-            assistant,
-            thread,
-        })
+    fn supports_batch(&self) -> bool {
+        self.batch
+    }
+
+    /// Model output limit is used if there's no limit of our own
+    fn max_section_tokens(&self) -> Option<usize> {
+        let max_tokens = self.max_tokens.map(|max_tokens| max_tokens as usize);
+        max_tokens
+            .or_else(|| self.model_info().and_then(|info| info.max_output_tokens))
+            .map(|max_tokens| max_tokens / super::TRANSLATION_TOKEN_RATIO)
+    }
+
+    /// Unknown for Azure deployments and models of compatible servers, unless they are named after a well-known model
+    fn model_info(&self) -> Option<ModelInfo> {
+        super::models::model_info(&self.model)
     }
 }
 
-pub struct OpenAiGPT {
-    client: Client<OpenAIConfig>,
-    assistant: AssistantObject,
-    thread: ThreadObject,
+/// Thread registry path, the one in the working directory is moved to the data directory first
+fn thread_registry_path() -> PathBuf {
+    let legacy_path = Path::new(THREAD_REGISTRY_FILE);
+    let Some(path) = data_dir().map(|dir| dir.join(THREAD_REGISTRY_FILE)) else {
+        return legacy_path.to_owned();
+    };
+    if legacy_path.exists() && !path.exists() {
+        let moved = std::fs::read(legacy_path)
+            .and_then(|content| write_atomically(&path, &content))
+            .and_then(|()| std::fs::remove_file(legacy_path));
+        if let Err(e) = moved {
+            log::warn!("Failed to move thread registry to {}: {}", path.display(), e);
+            return legacy_path.to_owned();
+        }
+    }
+    path
 }
 
-impl Drop for OpenAiGPT {
-    fn drop(&mut self) {
-        let client = self.client.clone();
-        let thread_id = self.thread.id.clone();
-        tokio::spawn(async move {
-            let client = client.clone();
-            let cleanup_result = run_openai_request(async move || {
-                client.threads().delete(&thread_id).await
-            }).await;
-
-            if let Err(e) = cleanup_result {
-                log::error!("Failed to clean up thread: {}", e);
-            }
-        });
+fn registered_threads() -> Vec<String> {
+    let _lock = THREAD_REGISTRY_LOCK.lock().expect("lock");
+    std::fs::read_to_string(thread_registry_path())
+        .map(|content| content.lines().filter(|l| !l.is_empty()).map(|l| l.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+fn unregister_thread(thread_id: &str) {
+    let _lock = THREAD_REGISTRY_LOCK.lock().expect("lock");
+    let path = thread_registry_path();
+    let threads = std::fs::read_to_string(&path).unwrap_or_default();
+    let threads = threads.lines().filter(|id| !id.is_empty() && *id != thread_id).collect::<Vec<_>>();
+    if let Err(e) = write_atomically(&path, threads.join("\n").as_bytes()) {
+        log::warn!("Failed to unregister thread {}: {}", thread_id, e);
     }
 }
 
+pub struct OpenAiGPT {
+    keys: Arc<ApiKeyPool>,
+    model: String,
+    stream: bool,
+    /// Temperature and top_p aren't sent to models not taking them
+    supports_temperature: bool,
+    temperature: f32,
+    top_p: f32,
+    max_tokens: Option<u32>,
+    reasoning_effort: Option<ReasoningEffort>,
+    section_timeout: Option<Duration>,
+    json_output: bool,
+    seed: Option<u64>,
+    system: String,
+    history: History,
+    /// Translations made by a batch job, by source text
+    prefetched: Mutex<HashMap<String, String>>,
+    usage: Mutex<TokenUsage>,
+    /// Glossary the model looks terms up in with a tool, empty if it's in the system prompt
+    tool_glossary: Vec<GlossaryEntry>,
+    events: Arc<dyn SendProgress>,
+}
+
 impl LLM for OpenAiGPT {
     async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
-        let mut subsections = vec![];
-        for s in section.0.iter() {
-            log::info!(r#"Sending message "{}...""#, substr_up_to_len(s.0.lines().next().unwrap(), MAX_LOG_SRC_LEN));
-            let my_message = {
-                let client = self.client.clone();
-                let s = s.clone();
-                let thread_id = self.thread.id.clone();
-                run_openai_request(async move || {
-                    client
-                        .threads()
-                        .messages(&thread_id)
-                        .create(CreateMessageRequest {
-                            role: MessageRole::User,
-                            content: CreateMessageRequestContent::Content(s.0.clone()),
-                            attachments: None,
-                            metadata: None,
-                        })
-                        .await
-                }).await?
-            };
-            log::info!("Message sent");
+        self.history.start_section(false);
+        self.translate_with_reminder(section, None, None, true).await
+    }
 
-            let run_req = CreateRunRequest {
-                assistant_id: self.assistant.id.clone(),
-                ..Default::default()
-            };
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        self.history.start_section(false);
+        self.translate_with_reminder(section, None, Some(&on_text), true).await
+    }
 
-            log::info!("Getting translated message...");
-            let run = self
-                .run_with_backoff(run_req)
-                .await?;
-
-            let msgs = {
-                let req = ListMessagesRequest {
-                    run_id: Some(run.id.clone()),
-                    limit: None,
-                    order: Some("asc".to_owned()),
-                    after: Some(my_message.id.clone()),
-                    before: None,
-                };
-
-                let client = self.client.clone();
-                let thread_id = self.thread.id.clone();
-                run_openai_request(async move || {
-                    client
-                        .threads()
-                        .messages(&thread_id)
-                        .list(&req)
-                        .await
-                }).await?
-            };
-            assert!(!msgs.has_more);
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.history.start_section(true);
+        self.translate_with_reminder(section, Some(reminder), None, true).await
+    }
 
-            if msgs.data.len() != 1 {
-                return Err(LLMError::InteractionError(anyhow!(
-                    "Incorrect number of response messages: {}",
-                    msgs.data.len()
-                )));
-            }
-            let msg = &msgs.data[0];
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, None, None, false).await
+    }
 
-            if msg.content.len() != 1 {
-                return Err(LLMError::InteractionError(anyhow!(
-                    "Incorrect number of response message sections: {}",
-                    msgs.data.len()
-                )));
-            }
-            let mc = &msg.content[0];
-            let translated = match mc {
-                MessageContent::Text(obj) => obj.text.value.clone(),
-                _ => {
-                    return Err(LLMError::InteractionError(anyhow!(
-                        "Incorrect response type: {:?}",
-                        mc
-                    )));
-                }
-            };
-            subsections.push(MarkdownSubsection(translated));
-        }
-        Ok(MarkdownSection(subsections))
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        self.run_batch(subsections).await
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        self.report_key_usage();
+        Ok(())
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        vec![(Provider::OpenAi, *self.usage.lock().expect("lock"))]
     }
 }
 
 impl OpenAiGPT {
-    async fn run_with_backoff(&self, req: CreateRunRequest) -> Result<RunObject, LLMError> {
-        let thread_id = self.thread.id.clone();
+    /// Per-key statistics are only interesting if there are several keys
+    fn report_key_usage(&self) {
+        let usage = self.keys.usage();
+        if usage.len() < 2 {
+            return;
+        }
+        for (idx, key) in usage.iter().enumerate() {
+            let info = format!(
+                "API key {}: {} requests, rate limited {} times{}",
+                self.keys.name(idx),
+                key.requests,
+                key.throttled,
+                if key.disabled { ", disabled" } else { "" }
+            );
+            log::info!("{info}");
+            self.events.send_info(info);
+        }
+    }
 
-        let mut sequential_errors = 0;
-        let mut backoff = ExponentialBackoff::default();
+    async fn translate_with_reminder(
+        &self,
+        section: &MarkdownSection,
+        reminder: Option<&str>,
+        on_text: Option<&OnText>,
+        keep_in_history: bool,
+    ) -> Result<MarkdownSection, LLMError> {
+        let translation = self.translate_subsections(section, reminder, on_text, keep_in_history);
+        match self.section_timeout {
+            Some(section_timeout) => tokio::time::timeout(section_timeout, translation).await.map_err(|_| {
+                LLMError::Timeout(anyhow!("Section took longer than {} s", section_timeout.as_secs()))
+            })?,
+            None => translation.await,
+        }
+    }
 
-        'outer: loop {
-            // Shadow anyhow's bail macro with this one
-            macro_rules! bail {
-                ($($t:tt)*) => { return Err(LLMError::InteractionError(anyhow!($($t)*))) };
+    async fn translate_subsections(
+        &self,
+        section: &MarkdownSection,
+        reminder: Option<&str>,
+        on_text: Option<&OnText>,
+        keep_in_history: bool,
+    ) -> Result<MarkdownSection, LLMError> {
+        // Structured responses only make sense once complete, and tool calls need a response to be complete
+        let stream = self.stream && !self.json_output && self.tool_glossary.is_empty();
+        let mut subsections = vec![];
+        for s in section.0.iter() {
+            // Batch job translations are only good for the first attempt
+            let prefetched = match reminder {
+                Some(_) => None,
+                None => self.prefetched.lock().expect("lock").remove(&s.0),
+            };
+            let (translated, streamed) = match prefetched {
+                Some(translated) => {
+                    log::info!("Using batch translation of {}", log_preview(&s.0));
+                    (translated, false)
+                }
+                None => {
+                    log::info!("Sending message {}", log_preview(&s.0));
+                    let content = match reminder {
+                        Some(reminder) => format!("{reminder}\n\n{}", s.0),
+                        None => s.0.clone(),
+                    };
+                    (self.request_translation(content, on_text.filter(|_| stream)).await?, stream)
+                }
+            };
+            if let Some(on_text) = on_text
+                && !streamed
+            {
+                on_text(translated.clone());
             }
-
-            macro_rules! sleep {
-                () => {
-                    if let Some(duration) = backoff.next_backoff() {
-                        if duration > backoff.initial_interval {
-                            log::warn!("Sleeping for {} ms", duration.as_millis());
-                        }
-                        tokio::time::sleep(duration).await;
-                    } else {
-                        bail!("Rate limit exceeded and backoff exhausted");
-                    }
-                };
+            if translated.is_empty() {
+                return Err(LLMError::InteractionError(anyhow!("Response has no text")));
             }
+            log::info!("Got translated message");
 
-            // Retry run creation and polling, or bail out if we've hit the max number of sequential errors
-            macro_rules! retry_run_or_bail {
-                ($($t:tt)*) => {
-                    if sequential_errors >= MAX_SEQUENTIAL_ERRORS {
-                        bail!($($t)*);
-                    } else {
-                        log::warn!($($t)*);
-                        sequential_errors += 1;
-                        sleep!();
-                        continue 'outer;
-                    }
-                };
+            if keep_in_history {
+                self.history.push(s.0.clone(), translated.clone());
             }
 
-            let run_id = {
-                let client = self.client.clone();
-                let thread_id = thread_id.clone();
-                let req = req.clone();
-                run_openai_request(async move || {
-                    let runs_api = client.threads();
-                    let runs_api = runs_api.runs(&thread_id);
-                    runs_api.create(req.clone()).await
-                }).await?.id
-            };
-
-            loop {
-                // Repeatedly poll the run until it's complete
-                let run = {
-                    let client = self.client.clone();
-                    let run_id = run_id.clone();
-                    let thread_id = thread_id.clone();
-                    run_openai_request(async move || {
-                        let runs_api = client.threads();
-                        let runs_api = runs_api.runs(&thread_id);
-                        runs_api.retrieve(&run_id).await
-                    }).await?
-                };
-
-                match run.status {
-                    RunStatus::Completed => {
-                        log::info!("Run complete");
-                        return Ok(run);
-                    }
-                    RunStatus::Queued | RunStatus::InProgress => {
-                        sleep!();
-                        // Fallthrough to the next iteration
-                    }
-                    RunStatus::Cancelling | RunStatus::Cancelled => {
-                        bail!("Run is cancelled!")
-                    }
-                    RunStatus::Failed => match run.last_error {
-                        Some(LastError {
-                            code: LastErrorCode::RateLimitExceeded,
-                            message,
-                        }) => {
-                            log::warn!("Hit the rate limit: {message}");
-
-                            // Message looks like this:
-                            // Rate limit reached for gpt-4o in organization org-Z0EwW949tS7WYT6MNWC6YBii on tokens per min (TPM): Limit 30000, Used 4308, Requested 26233. Please try again in 1.082s. Visit https://platform.openai.com/account/rate-limits to learn more.
-                            let limit = message
-                                .split("Limit ")
-                                .nth(1)
-                                .and_then(|s| s.split(',').next())
-                                .and_then(|s| s.parse::<u32>().ok());
-                            let requested = message
-                                .split("Requested ")
-                                .nth(1)
-                                .and_then(|s| s.split('.').next())
-                                .and_then(|s| s.parse::<u32>().ok());
-                            if let (Some(limit), Some(requested)) = (limit, requested) {
-                                if requested > limit {
-                                    retry_run_or_bail!("Requested more tokens than the rate limit allows");
-                                }
-                            }
-
-                            sleep!();
-                            continue 'outer;
-                        }
-                        Some(LastError {
-                            code: LastErrorCode::InvalidPrompt,
-                            message,
-                        }) => {
-                            bail!("Invalid prompt: {message}")
-                        }
+            subsections.push(MarkdownSubsection(translated));
+        }
+        Ok(MarkdownSection(subsections))
+    }
 
-                        Some(LastError {
-                            code: LastErrorCode::ServerError,
-                            message,
-                        }) => {
-                            retry_run_or_bail!("Server error: {message}")
+    /// Sends the message, requesting it again if a structured response is malformed
+    async fn request_translation(&self, content: String, on_text: Option<&OnText>) -> Result<String, LLMError> {
+        let mut malformed = 0;
+        // Glossary lookups of the model along with their results, sent after the message
+        let mut lookups: Vec<ChatCompletionRequestMessage> = vec![];
+        loop {
+            let req = self.chat_request(content.clone(), &lookups)?;
+            let (translated, finish_reason) = match on_text {
+                Some(on_text) => self.stream_chat(req, on_text).await?,
+                None => {
+                    let response = run_openai_request(&*self.events, &self.keys, async move |client| {
+                        client.chat().create(req.clone()).await
+                    }).await?;
+                    self.add_usage(response.usage.as_ref());
+
+                    let Some(choice) = response.choices.into_iter().next() else {
+                        return Err(LLMError::InteractionError(anyhow!("Response has no choices")));
+                    };
+                    if let Some(tool_calls) = choice.message.tool_calls.filter(|calls| !calls.is_empty()) {
+                        if lookups.iter().filter(|m| matches!(m, ChatCompletionRequestMessage::Assistant(_))).count()
+                            >= MAX_GLOSSARY_LOOKUPS
+                        {
+                            return Err(LLMError::InteractionError(anyhow!("Model keeps looking up the glossary")));
                         }
-
-                        None => {
-                            retry_run_or_bail!("Run failed with no error")
+                        lookups.push(
+                            ChatCompletionRequestAssistantMessageArgs::default()
+                                .tool_calls(tool_calls.clone())
+                                .build()?
+                                .into(),
+                        );
+                        for call in tool_calls {
+                            log::info!("Looking up glossary: {}", call.function.arguments);
+                            lookups.push(
+                                ChatCompletionRequestToolMessageArgs::default()
+                                    .tool_call_id(call.id)
+                                    .content(self.look_up_glossary(&call.function.arguments))
+                                    .build()?
+                                    .into(),
+                            );
                         }
-                    },
-                    RunStatus::Incomplete => {
-                        retry_run_or_bail!(
-                            "Run is incomplete: {:?}",
-                            run.incomplete_details.unwrap().reason
-                        )
-                    }
-                    RunStatus::Expired => {
-                        bail!("Run expired!")
-                    }
-                    RunStatus::RequiresAction => {
-                        unreachable!("No tools should be needed")
+                        continue;
                     }
+                    (choice.message.content.unwrap_or_default(), choice.finish_reason)
+                }
+            };
+
+            match finish_reason {
+                // Reasoning tokens count towards the limit, so they can take all of it without any output
+                Some(FinishReason::Length) if translated.is_empty() => {
+                    return Err(LLMError::InteractionError(anyhow!(
+                        "Model spent the whole output limit on reasoning, \
+                        raise openai.max_tokens or lower openai.reasoning_effort"
+                    )));
+                }
+                Some(FinishReason::Length) => {
+                    return Err(LLMError::InteractionError(anyhow!("Translation was cut off by the model output limit")));
+                }
+                Some(FinishReason::ContentFilter) => {
+                    return Err(LLMError::ContentPolicyViolation(anyhow!("Translation was blocked by the content filter")));
+                }
+                _ => {}
+            }
+            if !self.json_output {
+                return Ok(translated);
+            }
+            match serde_json::from_str::<StructuredTranslation>(&translated) {
+                Ok(structured) => return Ok(structured.translation),
+                Err(e) if malformed < MAX_MALFORMED_RETRIES => {
+                    malformed += 1;
+                    log::warn!("Malformed structured response, retrying: {}", e);
+                    self.events.send_warning(Warning::Other(format!("Malformed structured response, retrying: {e}")));
+                }
+                Err(e) => {
+                    return Err(LLMError::InteractionError(anyhow!("Malformed structured response: {e}")));
                 }
             }
         }
     }
+
+    /// Receives the response chunk by chunk, reporting the text so far.
+    /// Only opening the stream is retried, a failure in the middle of it fails the translation.
+    async fn stream_chat(
+        &self,
+        mut req: CreateChatCompletionRequest,
+        on_text: &OnText,
+    ) -> Result<(String, Option<FinishReason>), LLMError> {
+        req.stream_options = Some(ChatCompletionStreamOptions { include_usage: true });
+        let mut stream = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client.chat().create_stream(req.clone()).await
+        }).await?;
+
+        let mut text = String::new();
+        let mut finish_reason = None;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| LLMError::InteractionError(anyhow!("Response stream broke: {e}")))?;
+            // Comes with the last chunk, which has no choices
+            self.add_usage(chunk.usage.as_ref());
+            let Some(choice) = chunk.choices.into_iter().next() else {
+                continue;
+            };
+            if let Some(content) = choice.delta.content {
+                text += &content;
+                on_text(text.clone());
+            }
+            finish_reason = choice.finish_reason.or(finish_reason);
+        }
+        Ok((text, finish_reason))
+    }
+
+    fn add_usage(&self, usage: Option<&CompletionUsage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        self.usage.lock().expect("lock").add_tokens(usage.prompt_tokens as u64, usage.completion_tokens as u64);
+        // Long enough prompts are cached by OpenAI on its own, as long as they start the same
+        if let Some(cached_tokens) = usage.prompt_tokens_details.as_ref().and_then(|details| details.cached_tokens) {
+            log::debug!("Prompt cache: {cached_tokens} tokens read");
+        }
+        // Billed as completion tokens, though not part of the output
+        if let Some(reasoning_tokens) =
+            usage.completion_tokens_details.as_ref().and_then(|details| details.reasoning_tokens)
+        {
+            log::debug!("Reasoning: {reasoning_tokens} tokens");
+        }
+    }
+
+    /// Translations of the requested terms as a JSON object, null for the ones not in the glossary
+    fn look_up_glossary(&self, arguments: &str) -> String {
+        let Ok(lookup) = serde_json::from_str::<GlossaryLookup>(arguments) else {
+            return r#"Malformed arguments, expected {"terms": ["..."]}"#.to_owned();
+        };
+        let found = lookup
+            .terms
+            .into_iter()
+            .map(|term| {
+                let entry = self.tool_glossary.iter().find(|e| e.source.to_lowercase() == term.trim().to_lowercase());
+                (term, entry.map_or(serde_json::Value::Null, |e| e.target.clone().into()))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::Value::Object(found).to_string()
+    }
+
+    /// System prompt, then previous exchanges, then the new message and glossary lookups made for it
+    fn chat_request(
+        &self,
+        content: String,
+        lookups: &[ChatCompletionRequestMessage],
+    ) -> Result<CreateChatCompletionRequest, LLMError> {
+        let mut messages: Vec<ChatCompletionRequestMessage> = vec![
+            ChatCompletionRequestSystemMessageArgs::default().content(self.system.clone()).build()?.into(),
+        ];
+        for (src, translated) in self.history.exchanges() {
+            messages.push(ChatCompletionRequestUserMessageArgs::default().content(src).build()?.into());
+            messages.push(ChatCompletionRequestAssistantMessageArgs::default().content(translated).build()?.into());
+        }
+        messages.push(ChatCompletionRequestUserMessageArgs::default().content(content).build()?.into());
+        messages.extend(lookups.iter().cloned());
+        self.request_with(messages)
+    }
+
+    fn request_with(&self, messages: Vec<ChatCompletionRequestMessage>) -> Result<CreateChatCompletionRequest, LLMError> {
+        let mut req = CreateChatCompletionRequestArgs::default();
+        req.model(&self.model).messages(messages);
+        if self.supports_temperature {
+            req.temperature(self.temperature).top_p(self.top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            req.max_completion_tokens(max_tokens);
+        }
+        if let Some(reasoning_effort) = &self.reasoning_effort {
+            req.reasoning_effort(reasoning_effort.clone());
+        }
+        if !self.tool_glossary.is_empty() {
+            req.tools(vec![ChatCompletionToolArgs::default()
+                .function(
+                    FunctionObjectArgs::default()
+                        .name(GLOSSARY_TOOL_NAME)
+                        .description("Looks up mandated translations of terms, null for the ones not in the glossary")
+                        .parameters(serde_json::json!({
+                            "type": "object",
+                            "properties": { "terms": { "type": "array", "items": { "type": "string" } } },
+                            "required": ["terms"],
+                            "additionalProperties": false,
+                        }))
+                        .build()?,
+                )
+                .build()?]);
+        }
+        if self.json_output {
+            req.response_format(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: Some("Translation of the user message".to_owned()),
+                    name: "translation".to_owned(),
+                    schema: Some(serde_json::json!({
+                        "type": "object",
+                        "properties": { "translation": { "type": "string" } },
+                        "required": ["translation"],
+                        "additionalProperties": false,
+                    })),
+                    strict: Some(true),
+                },
+            });
+        }
+        if let Some(seed) = self.seed {
+            req.seed(seed as i64);
+        }
+        Ok(req.build()?)
+    }
+}
+
+const JSON_OUTPUT_PROMPT: &str =
+    r#"Reply with a JSON object having the translation as its only "translation" field, e.g. {"translation": "..."}."#;
+
+const GLOSSARY_TOOL_PROMPT: &str = "Some terms have mandated translations, \
+    look up the terms of each message you're not sure about in the glossary before translating it.";
+
+#[derive(Deserialize)]
+struct GlossaryLookup {
+    terms: Vec<String>,
+}
+
+/// Response of the JSON output mode
+#[derive(Deserialize)]
+struct StructuredTranslation {
+    translation: String,
 }
 
 /// This is needed because OpenAI's wrapper library is awful at times
-async fn run_openai_request<R, F>(req: F) -> Result<R, LLMError>
+async fn run_openai_request<R, F>(events: &dyn SendProgress, keys: &ApiKeyPool, req: F) -> Result<R, LLMError>
 where
-    R: Send + Sync + 'static,
-    F: AsyncFn() -> Result<R, OpenAIError> + 'static,
+    R: Send + 'static,
+    F: AsyncFn(&Client<EndpointConfig>) -> Result<R, OpenAIError> + 'static,
 {
-    let mut sequential_errors = 0;
-
-    let mut backoff = ExponentialBackoff::default();
-
-    loop {
-        // Retry request, or bail out if we've hit the max number of sequential errors
-        macro_rules! retry_or_bail {
-            ($err:expr, $cxt:literal) => {
-                let err = $err;
-                if sequential_errors >= MAX_SEQUENTIAL_ERRORS {
-                    return Err(err).context($cxt).map_err(LLMError::InteractionError);
-                } else {
-                    log::warn!("{}: {}", $cxt, err);
-                    sequential_errors += 1;
-                    if let Some(duration) = backoff.next_backoff() {
-                        log::info!("Sleeping for {} ms", duration.as_millis());
-                        tokio::time::sleep(duration).await;
-                        continue;
-                    }
-                    return Err(err)
-                        .context($cxt)
-                        .context("Rate limit exceeded and backoff exhausted")
-                        .map_err(LLMError::InteractionError);
-                }
-            };
-        }
+    run_openai_request_with_key(events, keys, req).await.map(|(result, _)| result)
+}
 
-        let result = req().await;
-        match result {
-            Ok(v) => return Ok(v),
-            Err(OpenAIError::Reqwest(e)) => {
-                retry_or_bail!(e, "Reqwest error");
+/// Same as [run_openai_request], but also returns the index of the API key that succeeded
+async fn run_openai_request_with_key<R, F>(
+    events: &dyn SendProgress,
+    keys: &ApiKeyPool,
+    req: F,
+) -> Result<(R, usize), LLMError>
+where
+    R: Send + 'static,
+    F: AsyncFn(&Client<EndpointConfig>) -> Result<R, OpenAIError> + 'static,
+{
+    // Keys that hit their rate limit in a row, once all of them did it's time to back off
+    let throttled_keys = AtomicUsize::new(0);
+    // Every attempt is a future of its own, borrowing these
+    let (req, throttled_keys) = (&req, &throttled_keys);
+    super::retry_request(Provider::OpenAi, events, move || async move {
+        let (key_idx, client) = keys.pick()?;
+        match req(&client).await {
+            Ok(v) => Ok((v, key_idx)),
+            Err(OpenAIError::ApiError(e)) if e.code.as_deref() == Some("rate_limit_exceeded") => {
+                if exceeds_rate_limit(&e.message) {
+                    let source = anyhow!("Request is too large for the rate limit: {}", e.message);
+                    return Err(LLMError::InteractionError(source).into());
+                }
+                keys.report_throttled(key_idx);
+                if throttled_keys.fetch_add(1, Ordering::Relaxed) + 1 < keys.enabled_count() {
+                    log::warn!("API key {} hit the rate limit, switching to another one", keys.name(key_idx));
+                    return Err(RequestError::Immediately);
+                }
+                throttled_keys.store(0, Ordering::Relaxed);
+                Err(RequestError::rate_limited(anyhow!(OpenAIError::ApiError(e)).context("Rate limit exceeded")))
+            }
+            Err(OpenAIError::ApiError(e))
+                if e.code.as_deref() == Some("invalid_api_key") && keys.enabled_count() > 1 =>
+            {
+                keys.disable(key_idx);
+                let warning = format!("API key {} was rejected, not using it anymore", keys.name(key_idx));
+                log::warn!("{warning}: {}", e.message);
+                events.send_warning(Warning::Other(warning));
+                Err(RequestError::Immediately)
             }
+            Err(OpenAIError::Reqwest(e)) => Err(e.into()),
             Err(OpenAIError::JSONDeserialize(e)) => {
-                retry_or_bail!(e, "Deserialization error");
+                Err(RequestError::transient(anyhow!(e).context("Deserialization error")))
             }
-            Err(e) => return Err(LLMError::InteractionError(e.into())),
+            Err(e @ OpenAIError::ApiError(_)) => Err(LLMError::from(e).into()),
+            Err(e) => Err(LLMError::InteractionError(e.into()).into()),
         }
-    }
+    })
+    .await
+}
+
+/// Whether the request alone takes more tokens than the rate limit allows, so retrying is pointless.
+/// Message looks like this:
+/// Request too large for gpt-4o in organization org-Z0EwW949tS7WYT6MNWC6YBii on tokens per min (TPM): Limit 30000, Requested 36233. The input or output tokens must be reduced in order to run successfully.
+fn exceeds_rate_limit(message: &str) -> bool {
+    let limit = message
+        .split("Limit ")
+        .nth(1)
+        .and_then(|s| s.split(',').next())
+        .and_then(|s| s.parse::<u32>().ok());
+    let requested = message
+        .split("Requested ")
+        .nth(1)
+        .and_then(|s| s.split('.').next())
+        .and_then(|s| s.parse::<u32>().ok());
+    matches!((limit, requested), (Some(limit), Some(requested)) if requested > limit)
 }
 
 impl From<OpenAIError> for LLMError {
@@ -434,7 +808,22 @@ impl From<OpenAIError> for LLMError {
             } else {
                 e.into()
             }),
-            OpenAIError::ApiError(e) => LLMError::ApiError(anyhow!("{e}")),
+            OpenAIError::ApiError(e) => {
+                // Some errors only have the code in the type field
+                let code = e.code.as_deref().or(e.r#type.as_deref()).unwrap_or_default();
+                let source = anyhow!("{}", e.message);
+                match code {
+                    "invalid_api_key" | "invalid_authentication" => {
+                        LLMError::InvalidApiKey { help_url: Some(API_KEYS_URL), source }
+                    }
+                    "insufficient_quota" | "billing_hard_limit_reached" => {
+                        LLMError::QuotaExceeded { help_url: Some(BILLING_URL), source }
+                    }
+                    "model_not_found" => LLMError::ModelNotFound(source),
+                    "content_policy_violation" | "content_filter" => LLMError::ContentPolicyViolation(source),
+                    _ => LLMError::ApiError(anyhow!("{e}")),
+                }
+            }
             OpenAIError::JSONDeserialize(e) => LLMError::OtherError(e.into()),
             OpenAIError::FileSaveError(e) => LLMError::OtherError(anyhow!("{e}")),
             OpenAIError::FileReadError(e) => LLMError::OtherError(anyhow!("{e}")),
@@ -443,30 +832,3 @@ impl From<OpenAIError> for LLMError {
         }
     }
 }
-
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
-pub struct ListMessagesRequest {
-    /// Filter messages by the run ID that generated them.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub run_id: Option<String>,
-
-    /// A limit on the number of objects to be returned. Limit can range between 1 and 100, and the default is 20.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub limit: Option<usize>,
-
-    /// Sort order by the created_at timestamp of the objects. asc for ascending order and desc for descending order.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub order: Option<String>,
-
-    /// A cursor for use in pagination. after is an object ID that defines your place in the list.
-    /// For instance, if you make a list request and receive 100 objects, ending with obj_foo,
-    /// your subsequent call can include after=obj_foo in order to fetch the next page of the list.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub after: Option<String>,
-
-    /// A cursor for use in pagination. before is an object ID that defines your place in the list.
-    /// For instance, if you make a list request and receive 100 objects, starting with obj_foo,
-    /// your subsequent call can include before=obj_foo in order to fetch the previous page of the list.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub before: Option<String>,
-}
@@ -0,0 +1,92 @@
+use async_openai::config::{AzureConfig, Config, OpenAIConfig};
+use reqwest::header::HeaderMap;
+use secrecy::SecretString;
+
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Model deployed to an Azure OpenAI resource, used instead of the public OpenAI API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureDeployment {
+    /// Resource URL, e.g. `https://my-resource.openai.azure.com`
+    pub endpoint: String,
+    /// Deployment name, which Azure uses in place of the model name
+    pub deployment: String,
+    /// Azure OpenAI REST API version, e.g. `2024-10-21`
+    pub api_version: String,
+}
+
+/// Where OpenAI requests are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    OpenAi,
+    /// Any server implementing OpenAI API, e.g. LM Studio, vLLM or llama.cpp server.
+    /// Base URL includes the version, e.g. `http://localhost:1234/v1`
+    Compatible(String),
+    Azure(AzureDeployment),
+}
+
+/// Either the public OpenAI API (or a compatible one) or an Azure deployment.
+/// Unlike [AzureConfig] assumes, Azure serves models and threads outside of deployment URLs.
+#[derive(Debug, Clone)]
+pub enum EndpointConfig {
+    OpenAi(OpenAIConfig),
+    Azure(AzureConfig),
+}
+
+impl EndpointConfig {
+    pub fn new(api_key: &str, endpoint: &Endpoint) -> Self {
+        match endpoint {
+            Endpoint::OpenAi => EndpointConfig::OpenAi(OpenAIConfig::new().with_api_key(api_key)),
+            Endpoint::Compatible(base_url) => EndpointConfig::OpenAi(
+                OpenAIConfig::new()
+                    .with_api_base(base_url.trim_end_matches('/'))
+                    .with_api_key(api_key),
+            ),
+            Endpoint::Azure(azure) => EndpointConfig::Azure(
+                AzureConfig::new()
+                    .with_api_base(azure.endpoint.trim_end_matches('/'))
+                    .with_deployment_id(&azure.deployment)
+                    .with_api_version(&azure.api_version)
+                    .with_api_key(api_key),
+            ),
+        }
+    }
+}
+
+impl Config for EndpointConfig {
+    fn headers(&self) -> HeaderMap {
+        match self {
+            EndpointConfig::OpenAi(config) => config.headers(),
+            EndpointConfig::Azure(config) => config.headers(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        match self {
+            EndpointConfig::OpenAi(config) => config.url(path),
+            EndpointConfig::Azure(config) if path.starts_with("/chat/") => config.url(path),
+            EndpointConfig::Azure(config) => format!("{}/openai{}", config.api_base(), path),
+        }
+    }
+
+    fn query(&self) -> Vec<(&str, &str)> {
+        match self {
+            EndpointConfig::OpenAi(config) => config.query(),
+            EndpointConfig::Azure(config) => config.query(),
+        }
+    }
+
+    fn api_base(&self) -> &str {
+        match self {
+            EndpointConfig::OpenAi(config) => config.api_base(),
+            EndpointConfig::Azure(config) => config.api_base(),
+        }
+    }
+
+    fn api_key(&self) -> &SecretString {
+        match self {
+            EndpointConfig::OpenAi(config) => config.api_key(),
+            EndpointConfig::Azure(config) => config.api_key(),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use super::{OpenAiGPT, StructuredTranslation, run_openai_request};
+use crate::LLMError;
+use crate::parser::MarkdownSubsection;
+
+use anyhow::anyhow;
+use async_openai::types::{
+    Batch, BatchCompletionWindow, BatchEndpoint, BatchRequest, BatchRequestInput, BatchRequestInputMethod,
+    BatchRequestOutput, BatchStatus, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionResponse, CreateFileRequest, FileInput, FilePurpose, FinishReason,
+};
+use itertools::Itertools;
+use std::time::{Duration, Instant};
+
+/// Batch API limit of requests in a single job
+const MAX_BATCH_REQUESTS: usize = 50_000;
+
+/// Batch API limit of the input file size of a single job
+const MAX_BATCH_FILE_SIZE: usize = 200 * 1024 * 1024;
+
+/// Jobs take minutes to hours, there's no point in checking more often
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Run being stopped is checked this often while waiting for a job
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl OpenAiGPT {
+    /// Translates the subsections with Batch API jobs, keeping the results for [OpenAiGPT::translate_with_reminder]
+    pub(super) async fn run_batch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        let texts = subsections.iter().map(|ss| ss.0.as_str()).unique().collect_vec();
+        if texts.is_empty() {
+            return Ok(());
+        }
+        // Jobs are split to fit both the request count and the input file size limits
+        let mut translated = 0;
+        let mut input = Vec::new();
+        let mut requests = 0;
+        for (idx, text) in texts.iter().enumerate() {
+            let line = self.batch_line(idx, text)?;
+            if requests > 0 && (requests == MAX_BATCH_REQUESTS || input.len() + line.len() > MAX_BATCH_FILE_SIZE) {
+                translated += self.run_batch_job(&texts, std::mem::take(&mut input), requests).await?;
+                requests = 0;
+            }
+            input.extend(line);
+            requests += 1;
+        }
+        translated += self.run_batch_job(&texts, input, requests).await?;
+
+        let info = match texts.len() - translated {
+            0 => format!("Batch job translated all {} subsections", texts.len()),
+            failed => format!(
+                "Batch job translated {} of {} subsections, {} will be translated one by one",
+                translated,
+                texts.len(),
+                failed
+            ),
+        };
+        log::info!("{info}");
+        self.events.send_info(info);
+        Ok(())
+    }
+
+    /// Line of a job input file requesting the translation of the text, `idx` is its index among all texts
+    fn batch_line(&self, idx: usize, text: &str) -> Result<Vec<u8>, LLMError> {
+        let req = self.request_with(vec![
+            ChatCompletionRequestSystemMessageArgs::default().content(self.system.clone()).build()?.into(),
+            ChatCompletionRequestUserMessageArgs::default().content(text).build()?.into(),
+        ])?;
+        let line = BatchRequestInput {
+            custom_id: idx.to_string(),
+            method: BatchRequestInputMethod::POST,
+            url: BatchEndpoint::V1ChatCompletions,
+            body: Some(serde_json::to_value(req).map_err(|e| LLMError::InteractionError(e.into()))?),
+        };
+        let mut line = serde_json::to_vec(&line).map_err(|e| LLMError::InteractionError(e.into()))?;
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    /// Runs a job of `requests` lines made by [Self::batch_line] for some of the `texts`,
+    /// returns the number of translations received
+    async fn run_batch_job(&self, texts: &[&str], input: Vec<u8>, requests: usize) -> Result<usize, LLMError> {
+        let file = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client
+                .files()
+                .create(CreateFileRequest {
+                    file: FileInput::from_vec_u8("rosetta-batch.jsonl".to_owned(), input.clone()),
+                    purpose: FilePurpose::Batch,
+                })
+                .await
+        }).await?;
+        let result = self.await_batch_job(&file.id, requests).await;
+
+        // Input file isn't needed anymore whatever the outcome, failing to delete it only leaves it in storage
+        let _ = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client.files().delete(&file.id).await
+        }).await;
+        let batch = result?;
+
+        // Expired and cancelled jobs still have the results of the requests done by then
+        let Some(output_file_id) = batch.output_file_id else {
+            log::warn!("Batch job {} ended as {:?} without results", batch.id, batch.status);
+            return Ok(0);
+        };
+        let content_file_id = output_file_id.clone();
+        let content = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client.files().content(&content_file_id).await
+        }).await?;
+        let _ = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client.files().delete(&output_file_id).await
+        }).await;
+
+        let mut prefetched = self.prefetched.lock().expect("lock");
+        let mut translated = 0;
+        for line in String::from_utf8_lossy(&content).lines().filter(|l| !l.trim().is_empty()) {
+            let translation = serde_json::from_str::<BatchRequestOutput>(line)
+                .ok()
+                .and_then(|output| Some((output.custom_id.parse::<usize>().ok()?, output.response?)))
+                .filter(|(_, response)| response.status_code == 200)
+                .and_then(|(idx, response)| {
+                    let response = serde_json::from_value::<CreateChatCompletionResponse>(response.body).ok()?;
+                    self.add_usage(response.usage.as_ref());
+                    let choice = response.choices.into_iter().next()?;
+                    // Cut off or filtered ones are left to be translated as usual, to fail with a proper error
+                    if choice.finish_reason != Some(FinishReason::Stop) {
+                        return None;
+                    }
+                    let content = choice.message.content?;
+                    let content = match self.json_output {
+                        true => serde_json::from_str::<StructuredTranslation>(&content).ok()?.translation,
+                        false => content,
+                    };
+                    Some((texts.get(idx)?, content))
+                });
+            if let Some((src, content)) = translation
+                && !content.is_empty()
+            {
+                prefetched.insert(src.to_string(), content);
+                translated += 1;
+            }
+        }
+        Ok(translated)
+    }
+
+    /// Submits a job for the uploaded input file and waits until it's done.
+    /// Job is cancelled if the run is stopped in the meantime.
+    async fn await_batch_job(&self, input_file_id: &str, requests: usize) -> Result<Batch, LLMError> {
+        let input_file_id = input_file_id.to_owned();
+        let batch = run_openai_request(&*self.events, &self.keys, async move |client| {
+            client
+                .batches()
+                .create(BatchRequest {
+                    input_file_id: input_file_id.clone(),
+                    endpoint: BatchEndpoint::V1ChatCompletions,
+                    completion_window: BatchCompletionWindow::W24H,
+                    metadata: None,
+                })
+                .await
+        }).await?;
+        let info = format!("Submitted batch job {} of {} requests, it may take up to 24 hours", batch.id, requests);
+        log::info!("{info}");
+        self.events.send_info(info);
+
+        loop {
+            let poll_at = Instant::now() + POLL_INTERVAL;
+            while Instant::now() < poll_at && !self.events.is_stopped() {
+                tokio::time::sleep(STOP_CHECK_INTERVAL).await;
+            }
+            if self.events.is_stopped() {
+                let batch_id = batch.id.clone();
+                if let Err(e) = run_openai_request(&*self.events, &self.keys, async move |client| {
+                    client.batches().cancel(&batch_id).await
+                }).await {
+                    log::warn!("Failed to cancel batch job {}: {}", batch.id, e);
+                }
+                return Err(LLMError::OtherError(anyhow!("Batch job {} was cancelled, run is stopped", batch.id)));
+            }
+
+            let batch_id = batch.id.clone();
+            let batch = run_openai_request(&*self.events, &self.keys, async move |client| {
+                client.batches().retrieve(&batch_id).await
+            }).await?;
+            match batch.status {
+                BatchStatus::Completed | BatchStatus::Expired | BatchStatus::Cancelled => return Ok(batch),
+                BatchStatus::Failed => {
+                    let errors = batch.errors.map(|e| e.data).unwrap_or_default();
+                    return Err(LLMError::InteractionError(anyhow!(
+                        "Batch job {} failed: {}",
+                        batch.id,
+                        errors.iter().map(|e| &e.message).join("; ")
+                    )));
+                }
+                _ => {
+                    if let Some(counts) = batch.request_counts {
+                        log::info!("Batch job {}: {} of {} requests done", batch.id, counts.completed, counts.total);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::LLMError;
+
+use anyhow::anyhow;
+use super::azure::{Endpoint, EndpointConfig};
+use async_openai::Client;
+use backoff::ExponentialBackoff;
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// With several keys, rate limited requests are retried by the client only this long before switching keys
+const MAX_RETRY_PER_KEY: Duration = Duration::from_secs(10);
+
+/// How the next API key is chosen when several are configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeySelection {
+    /// Keys are used in turns
+    #[default]
+    RoundRobin,
+    /// Keys that weren't rate limited for the longest time are preferred, then ones used least recently
+    LeastRecentlyThrottled,
+}
+
+/// Set of API keys requests are spread across, so that a single key's rate limit isn't a bottleneck.
+/// Keys rejected by the API are disabled for the rest of the pool's life.
+pub struct ApiKeyPool {
+    selection: KeySelection,
+    clients: Vec<Client<EndpointConfig>>,
+    names: Vec<String>,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    picks: u64,
+    keys: Vec<KeyState>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyState {
+    pub requests: usize,
+    pub throttled: usize,
+    pub disabled: bool,
+    last_picked: u64,
+    last_throttled: Option<Instant>,
+}
+
+impl ApiKeyPool {
+    pub fn new(api_keys: &[String], selection: KeySelection, endpoint: &Endpoint) -> Self {
+        let clients = api_keys
+            .iter()
+            .map(|key| {
+                let client = Client::with_config(EndpointConfig::new(key, endpoint));
+                if api_keys.len() > 1 {
+                    client.with_backoff(ExponentialBackoff {
+                        max_elapsed_time: Some(MAX_RETRY_PER_KEY),
+                        ..Default::default()
+                    })
+                } else {
+                    client
+                }
+            })
+            .collect();
+        ApiKeyPool {
+            selection,
+            clients,
+            names: api_keys.iter().map(|key| masked(key)).collect(),
+            state: Mutex::new(PoolState {
+                picks: 0,
+                keys: vec![KeyState::default(); api_keys.len()],
+            }),
+        }
+    }
+
+    /// Makes all clients send their requests with the given HTTP client, e.g. one with a timeout
+    pub fn set_http_client(&mut self, http_client: reqwest::Client) {
+        for client in self.clients.iter_mut() {
+            *client = client.clone().with_http_client(http_client.clone());
+        }
+    }
+
+    /// Chooses the key for the next request, returning its index and a client using it
+    pub fn pick(&self) -> Result<(usize, Client<EndpointConfig>), LLMError> {
+        let mut state = self.state.lock().expect("lock");
+        let idx = state
+            .keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !key.disabled)
+            .min_by_key(|(_, key)| match self.selection {
+                KeySelection::RoundRobin => (None, key.last_picked),
+                KeySelection::LeastRecentlyThrottled => (key.last_throttled, key.last_picked),
+            })
+            .map(|(idx, _)| idx)
+            .ok_or_else(|| LLMError::InvalidApiKey {
+                help_url: Some(super::API_KEYS_URL),
+                source: anyhow!("All configured API keys were rejected"),
+            })?;
+        state.picks += 1;
+        let picks = state.picks;
+        let key = &mut state.keys[idx];
+        key.last_picked = picks;
+        key.requests += 1;
+        Ok((idx, self.clients[idx].clone()))
+    }
+
+    pub fn report_throttled(&self, idx: usize) {
+        let mut state = self.state.lock().expect("lock");
+        let key = &mut state.keys[idx];
+        key.throttled += 1;
+        key.last_throttled = Some(Instant::now());
+    }
+
+    pub fn disable(&self, idx: usize) {
+        self.state.lock().expect("lock").keys[idx].disabled = true;
+    }
+
+    pub fn enabled_count(&self) -> usize {
+        self.state.lock().expect("lock").keys.iter().filter(|key| !key.disabled).count()
+    }
+
+    /// Key with all but the last few characters hidden, to be shown to the user
+    pub fn name(&self, idx: usize) -> &str {
+        &self.names[idx]
+    }
+
+    /// Usage of each key so far, in the order they were configured
+    pub fn usage(&self) -> Vec<KeyState> {
+        self.state.lock().expect("lock").keys.clone()
+    }
+}
+
+fn masked(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+    let visible = chars.len().saturating_sub(4).max(chars.len() / 2);
+    format!("...{}", chars[visible..].iter().collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection() {
+        let keys = ["sk-aaaa1111", "sk-bbbb2222", "sk-cccc3333"].map(|k| k.to_owned());
+
+        let pool = ApiKeyPool::new(&keys, KeySelection::RoundRobin, &Endpoint::OpenAi);
+        let pick = || pool.pick().map(|(idx, _)| idx).ok();
+        assert_eq!([pick(), pick(), pick(), pick()], [Some(0), Some(1), Some(2), Some(0)]);
+
+        let pool = ApiKeyPool::new(&keys, KeySelection::LeastRecentlyThrottled, &Endpoint::OpenAi);
+        pool.report_throttled(0);
+        pool.report_throttled(1);
+        pool.disable(2);
+        let pick = || pool.pick().map(|(idx, _)| idx).ok();
+        assert_eq!([pick(), pick()], [Some(0), Some(0)]);
+        pool.disable(0);
+        pool.disable(1);
+        assert_eq!(pick(), None);
+
+        assert_eq!(pool.name(1), "...2222");
+        assert_eq!(pool.usage()[0].requests, 2);
+    }
+}
@@ -0,0 +1,359 @@
+use super::{LLM, LLMBuilder, ModelInfo, Provider, ProxyConfig, RequestError, TokenUsage};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::log_preview;
+use crate::{LLMError, SendProgress, TranslationConfig};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+
+const API_KEYS_URL: &str = "https://openrouter.ai/settings/keys";
+const CREDITS_URL: &str = "https://openrouter.ai/settings/credits";
+
+/// Chat API is stateless, so previous exchanges are re-sent to keep the terminology consistent.
+/// Only the most recent ones are kept to bound the cost.
+const MAX_HISTORY_EXCHANGES: usize = 4;
+
+/// Builder for any model routed through OpenRouter, see https://openrouter.ai/docs/api-reference/overview.
+/// Model is named as on OpenRouter, e.g. `anthropic/claude-sonnet-4.5` or `meta-llama/llama-3.3-70b-instruct`.
+pub struct OpenRouterBuilder {
+    model: String,
+    api_key: String,
+    /// Sent as `HTTP-Referer`, identifies the app on OpenRouter
+    app_url: Option<String>,
+    /// Sent as `X-Title`, app name shown on OpenRouter
+    app_title: Option<String>,
+    temperature: f32,
+    request_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+    prompt_caching: bool,
+    /// Sent as `user`, see [Self::with_billing_label]
+    billing_label: Option<String>,
+}
+
+impl OpenRouterBuilder {
+    pub fn new(model: String, api_key: String, app_url: Option<String>, app_title: Option<String>) -> Self {
+        OpenRouterBuilder {
+            model,
+            api_key,
+            app_url,
+            app_title,
+            temperature: 1.0,
+            request_timeout: None,
+            proxy: None,
+            prompt_caching: false,
+            billing_label: None,
+        }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Option<Duration>) -> Self {
+        OpenRouterBuilder { request_timeout, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<ProxyConfig>) -> Self {
+        OpenRouterBuilder { proxy, ..self }
+    }
+
+    /// Marks the system prompt as cacheable for the models that need it marked, e.g. Anthropic and Gemini ones,
+    /// see https://openrouter.ai/docs/features/prompt-caching. Other models either cache it on their own or don't.
+    pub fn with_prompt_caching(self, prompt_caching: bool) -> Self {
+        OpenRouterBuilder { prompt_caching, ..self }
+    }
+
+    /// Requests are tagged with the label as their user, so that OpenRouter activity can be broken down by it,
+    /// e.g. by the client a run is billed to
+    pub fn with_billing_label(self, billing_label: Option<String>) -> Self {
+        OpenRouterBuilder { billing_label, ..self }
+    }
+
+    fn client(&self, system: String, events: Arc<dyn SendProgress>) -> OpenRouter {
+        OpenRouter {
+            client: super::http_client(self.request_timeout, self.proxy.as_ref()),
+            api_key: self.api_key.clone(),
+            app_url: self.app_url.clone(),
+            app_title: self.app_title.clone(),
+            model: self.model.clone(),
+            temperature: self.temperature,
+            system,
+            prompt_caching: self.prompt_caching,
+            billing_label: self.billing_label.clone(),
+            history: Mutex::new(VecDeque::new()),
+            usage: Mutex::new(TokenUsage::default()),
+            events,
+        }
+    }
+}
+
+impl LLMBuilder for OpenRouterBuilder {
+    type Built = OpenRouter;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        Ok(self.client(super::cfg_to_prompt(&cfg), events))
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        let router = self.client("Reply with OK".to_owned(), Arc::new(crate::DummySendProgress));
+        let req = ChatRequest {
+            model: &router.model,
+            temperature: router.temperature,
+            max_tokens: Some(5),
+            usage: UsageRequest { include: false },
+            messages: vec![
+                Message { role: "system", content: router.system.clone().into() },
+                Message { role: "user", content: "OK?".to_owned().into() },
+            ],
+            user: None,
+        };
+
+        let start = Instant::now();
+        router.send(&req).await?;
+        Ok(start.elapsed())
+    }
+
+    /// Prices are left out, since OpenRouter reports the cost on its own
+    fn model_info(&self) -> Option<ModelInfo> {
+        super::models::model_info(&self.model).map(|info| ModelInfo { price_in: None, price_out: None, ..info })
+    }
+}
+
+pub struct OpenRouter {
+    client: reqwest::Client,
+    api_key: String,
+    app_url: Option<String>,
+    app_title: Option<String>,
+    model: String,
+    temperature: f32,
+    system: String,
+    prompt_caching: bool,
+    billing_label: Option<String>,
+    /// Previous source texts and their translations, oldest first
+    history: Mutex<VecDeque<(String, String)>>,
+    /// Tokens and credits spent by this run so far, as reported by OpenRouter
+    usage: Mutex<TokenUsage>,
+    events: Arc<dyn SendProgress>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    usage: UsageRequest,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct UsageRequest {
+    /// Makes the response carry token counts and the cost of the request
+    include: bool,
+}
+
+#[derive(Serialize)]
+struct Message {
+    role: &'static str,
+    content: MessageContent,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    /// Needed to mark content as cacheable
+    Parts(Vec<ContentPart>),
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+#[derive(Serialize)]
+struct ContentPart {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+#[derive(Serialize)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    /// Model actually used, which may differ from the requested one when OpenRouter falls back
+    #[serde(default)]
+    model: String,
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    /// In credits, which are USD
+    cost: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+impl LLM for OpenRouter {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, None).await
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.translate_with_reminder(section, Some(reminder)).await
+    }
+
+    /// Cost is as reported by OpenRouter
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        vec![(Provider::OpenRouter, *self.usage.lock().expect("lock"))]
+    }
+}
+
+impl OpenRouter {
+    async fn translate_with_reminder(&self, section: &MarkdownSection, reminder: Option<&str>) -> Result<MarkdownSection, LLMError> {
+        let mut subsections = vec![];
+        for s in section.0.iter() {
+            log::info!("Sending message {}", log_preview(&s.0));
+            let content = match reminder {
+                Some(reminder) => format!("{reminder}\n\n{}", s.0),
+                None => s.0.clone(),
+            };
+
+            let system = match self.prompt_caching {
+                true => MessageContent::Parts(vec![ContentPart {
+                    kind: "text",
+                    text: self.system.clone(),
+                    cache_control: Some(CacheControl { kind: "ephemeral" }),
+                }]),
+                false => self.system.clone().into(),
+            };
+            let mut messages = vec![Message { role: "system", content: system }];
+            for (src, translated) in self.history.lock().expect("lock").iter() {
+                messages.push(Message { role: "user", content: src.clone().into() });
+                messages.push(Message { role: "assistant", content: translated.clone().into() });
+            }
+            messages.push(Message { role: "user", content: content.into() });
+
+            let req = ChatRequest {
+                model: &self.model,
+                temperature: self.temperature,
+                max_tokens: None,
+                usage: UsageRequest { include: true },
+                messages,
+                user: self.billing_label.as_deref(),
+            };
+            let response = self.send(&req).await?;
+
+            if let Some(usage) = response.usage {
+                let cost = usage.cost.unwrap_or_default();
+                let cached_tokens = usage.prompt_tokens_details.map_or(0, |details| details.cached_tokens);
+                log::info!(
+                    "{}: {} prompt ({} cached) and {} completion tokens, ${:.6}",
+                    response.model,
+                    usage.prompt_tokens,
+                    cached_tokens,
+                    usage.completion_tokens,
+                    cost
+                );
+                let mut total = self.usage.lock().expect("lock");
+                total.add_tokens(usage.prompt_tokens, usage.completion_tokens);
+                total.cost = Some(total.cost.unwrap_or_default() + cost);
+            }
+
+            let Some(choice) = response.choices.into_iter().next() else {
+                return Err(LLMError::InteractionError(anyhow!("Response has no choices")));
+            };
+            if choice.finish_reason.as_deref() == Some("length") {
+                return Err(LLMError::InteractionError(anyhow!("Translation was cut off by the model output limit")));
+            }
+            let translated = choice.message.content;
+            if translated.is_empty() {
+                return Err(LLMError::InteractionError(anyhow!("Response has no text")));
+            }
+            log::info!("Got translated message");
+
+            let mut history = self.history.lock().expect("lock");
+            history.push_back((s.0.clone(), translated.clone()));
+            if history.len() > MAX_HISTORY_EXCHANGES {
+                history.pop_front();
+            }
+            drop(history);
+
+            subsections.push(MarkdownSubsection(translated));
+        }
+        Ok(MarkdownSection(subsections))
+    }
+
+    /// Sends the request, waiting out network outages and retrying transient failures with a backoff
+    async fn send(&self, req: &ChatRequest<'_>) -> Result<ChatResponse, LLMError> {
+        let send = || {
+            let mut request = self.client.post(API_URL).bearer_auth(&self.api_key);
+            if let Some(ref app_url) = self.app_url {
+                request = request.header("HTTP-Referer", app_url);
+            }
+            if let Some(ref app_title) = self.app_title {
+                request = request.header("X-Title", app_title);
+            }
+            request.json(req).send()
+        };
+        super::send_json(Provider::OpenRouter, &*self.events, send, classify_error).await
+    }
+}
+
+fn classify_error(status: reqwest::StatusCode, body: String) -> RequestError {
+    let message = serde_json::from_str::<ErrorResponse>(&body).map_or(body, |e| e.error.message);
+    let source = anyhow!("{status}: {message}");
+    match status.as_u16() {
+        401 | 403 => LLMError::InvalidApiKey { help_url: Some(API_KEYS_URL), source }.into(),
+        402 => LLMError::QuotaExceeded { help_url: Some(CREDITS_URL), source }.into(),
+        400 | 404 if message.contains("not a valid model") || message.contains("No endpoints found") => {
+            LLMError::ModelNotFound(source).into()
+        }
+        429 => RequestError::rate_limited(source),
+        // Upstream provider being down or timing out
+        408 | 502 | 503 => RequestError::transient(source),
+        _ if status.is_server_error() => RequestError::transient(source),
+        _ => LLMError::ApiError(source).into(),
+    }
+}
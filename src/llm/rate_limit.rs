@@ -0,0 +1,216 @@
+use super::{LLMBuilder, ModelInfo, OnText, Provider, TokenUsage, LLM, TRANSLATION_TOKEN_RATIO};
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+use crate::utils::estimate_tokens;
+use crate::{LLMError, SendProgress, TranslationConfig};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Requests per minute and tokens per minute a provider is allowed, no limit if not set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<usize>,
+    pub tokens_per_minute: Option<usize>,
+}
+
+/// Keeps requests within the limits of the API key on the client side, so that long runs on low-tier keys
+/// wait for their turn instead of running into rate limit errors and backing off.
+/// Everything built by the same builder shares the limits.
+pub struct RateLimitedLLMBuilder<B: LLMBuilder> {
+    inner: B,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<B: LLMBuilder> RateLimitedLLMBuilder<B> {
+    pub fn new(inner: B, limits: RateLimits) -> Self {
+        RateLimitedLLMBuilder {
+            inner,
+            limiter: Arc::new(RateLimiter { limits, window: Mutex::new(VecDeque::new()) }),
+        }
+    }
+}
+
+impl<B: LLMBuilder> LLMBuilder for RateLimitedLLMBuilder<B> {
+    type Built = RateLimitedLLM<B::Built>;
+
+    async fn build(&self, cfg: TranslationConfig, events: Arc<dyn SendProgress>) -> Result<Self::Built, LLMError> {
+        Ok(RateLimitedLLM {
+            prompt_tokens: estimate_tokens(&super::cfg_to_prompt(&cfg)),
+            inner: self.inner.build(cfg, events).await?,
+            limiter: self.limiter.clone(),
+        })
+    }
+
+    async fn health_check(&self) -> Result<Duration, LLMError> {
+        self.inner.health_check().await
+    }
+
+    async fn cleanup(&self) -> Result<usize, LLMError> {
+        self.inner.cleanup().await
+    }
+
+    fn supports_seed(&self) -> bool {
+        self.inner.supports_seed()
+    }
+
+    fn supports_batch(&self) -> bool {
+        self.inner.supports_batch()
+    }
+
+    fn max_section_tokens(&self) -> Option<usize> {
+        self.inner.max_section_tokens()
+    }
+
+    fn model_info(&self) -> Option<ModelInfo> {
+        self.inner.model_info()
+    }
+}
+
+/// Waits for the limits to allow a request before passing it on.
+/// Tokens are estimated from the system prompt, the source text and its expected translation,
+/// which leaves out the previous exchanges some providers re-send.
+/// Batch jobs of [LLM::prefetch] are passed on as is, since they have limits of their own.
+pub struct RateLimitedLLM<L: LLM> {
+    inner: L,
+    limiter: Arc<RateLimiter>,
+    /// Estimated tokens of the system prompt, sent with every request
+    prompt_tokens: usize,
+}
+
+impl<L: LLM> RateLimitedLLM<L> {
+    /// Providers send a request per subsection
+    async fn acquire(&self, section: &MarkdownSection, extra_text: &[&str]) {
+        let requests = section.0.len().max(1);
+        let src_tokens = section.0.iter().map(|ss| estimate_tokens(&ss.0)).sum::<usize>();
+        let extra_tokens = extra_text.iter().map(|text| estimate_tokens(text)).sum::<usize>();
+        let tokens = requests * self.prompt_tokens + src_tokens * (1 + TRANSLATION_TOKEN_RATIO) + extra_tokens;
+        self.limiter.acquire(requests, tokens).await;
+    }
+}
+
+impl<L: LLM> LLM for RateLimitedLLM<L> {
+    async fn translate(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.acquire(section, &[]).await;
+        self.inner.translate(section).await
+    }
+
+    async fn translate_streaming(
+        &self,
+        section: &MarkdownSection,
+        on_text: OnText,
+    ) -> Result<MarkdownSection, LLMError> {
+        self.acquire(section, &[]).await;
+        self.inner.translate_streaming(section, on_text).await
+    }
+
+    async fn retry_translate(&self, section: &MarkdownSection, reminder: &str) -> Result<MarkdownSection, LLMError> {
+        self.acquire(section, &[reminder]).await;
+        self.inner.retry_translate(section, reminder).await
+    }
+
+    async fn instruct(&self, section: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        self.acquire(section, &[]).await;
+        self.inner.instruct(section).await
+    }
+
+    async fn post_edit(&self, section: &MarkdownSection, draft: &MarkdownSection) -> Result<MarkdownSection, LLMError> {
+        let draft_text = draft.0.iter().map(|ss| ss.0.as_str()).collect::<Vec<_>>();
+        self.acquire(section, &draft_text).await;
+        self.inner.post_edit(section, draft).await
+    }
+
+    async fn stitch(&self, before: &str, after: &str) -> Result<Option<(String, String)>, LLMError> {
+        let tokens = self.prompt_tokens + (estimate_tokens(before) + estimate_tokens(after)) * 2;
+        self.limiter.acquire(1, tokens).await;
+        self.inner.stitch(before, after).await
+    }
+
+    async fn prefetch(&self, subsections: &[MarkdownSubsection]) -> Result<(), LLMError> {
+        self.inner.prefetch(subsections).await
+    }
+
+    async fn close(&mut self) -> Result<(), LLMError> {
+        self.inner.close().await
+    }
+
+    fn usage(&self) -> Vec<(Provider, TokenUsage)> {
+        self.inner.usage()
+    }
+}
+
+struct RateLimiter {
+    limits: RateLimits,
+    /// Requests of the last minute along with their number and estimated tokens, oldest first
+    window: Mutex<VecDeque<(Instant, usize, usize)>>,
+}
+
+impl RateLimiter {
+    async fn acquire(&self, requests: usize, tokens: usize) {
+        if self.limits == RateLimits::default() {
+            return;
+        }
+        loop {
+            let mut window = self.window.lock().await;
+            let now = Instant::now();
+            match wait_time(&mut window, self.limits, now, requests, tokens) {
+                None => {
+                    window.push_back((now, requests, tokens));
+                    return;
+                }
+                Some(wait) => {
+                    drop(window);
+                    log::info!("Waiting {} s for the rate limit", wait.as_secs().max(1));
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+/// How long to wait for the requests to fit into the limits, None if they fit now.
+/// Requests exceeding a limit on their own are let through once the window is empty, as they would never fit.
+fn wait_time(
+    window: &mut VecDeque<(Instant, usize, usize)>,
+    limits: RateLimits,
+    now: Instant,
+    requests: usize,
+    tokens: usize,
+) -> Option<Duration> {
+    while window.front().is_some_and(|(at, _, _)| now.duration_since(*at) >= WINDOW) {
+        window.pop_front();
+    }
+    let &(oldest, _, _) = window.front()?;
+    let used_requests = window.iter().map(|(_, requests, _)| requests).sum::<usize>();
+    let used_tokens = window.iter().map(|(_, _, tokens)| tokens).sum::<usize>();
+    let fits = |limit: Option<usize>, used: usize, more: usize| limit.is_none_or(|limit| used + more <= limit);
+    if fits(limits.requests_per_minute, used_requests, requests)
+        && fits(limits.tokens_per_minute, used_tokens, tokens)
+    {
+        return None;
+    }
+    Some(WINDOW - now.duration_since(oldest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_the_oldest_request_to_expire() {
+        let limits = RateLimits { requests_per_minute: Some(3), tokens_per_minute: Some(1000) };
+        let start = Instant::now();
+        let mut window = VecDeque::from([(start, 2, 100)]);
+        let later = start + Duration::from_secs(20);
+
+        assert_eq!(wait_time(&mut window, limits, later, 1, 100), None);
+        assert_eq!(wait_time(&mut window, limits, later, 2, 100), Some(Duration::from_secs(40)));
+        assert_eq!(wait_time(&mut window, limits, later, 1, 950), Some(Duration::from_secs(40)));
+
+        // Too much for any window goes once it's empty
+        assert_eq!(wait_time(&mut window, limits, start + WINDOW, 5, 5000), None);
+        assert!(window.is_empty());
+    }
+}
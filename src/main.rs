@@ -1,4 +1,12 @@
 use rosetta::*;
+use rosetta::cache::{inspect, CacheInspection};
+use rosetta::generator::BilingualStyle;
+use rosetta::generator::headings::HeadingNumbering;
+use rosetta::manifest::RunManifest;
+use rosetta::review::{export_review, import_review, ReviewFormat};
+use rosetta::sampling::{sample_size, translate_sample, SampleSection};
+use rosetta::update::{check_for_update, Release};
+use rosetta::utils::first_line;
 
 use anyhow::anyhow;
 use config::Config;
@@ -6,9 +14,13 @@ use eframe::egui::{Button, Color32, TextBuffer, TextEdit};
 use eframe::{egui, Frame};
 use log::LevelFilter;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
-use chrono::Local;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Local};
 use tokio::task::JoinHandle;
+use unicode_segmentation::UnicodeSegmentation;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -57,6 +69,21 @@ async fn main() {
         .build();
 
     let (tx, rx) = std::sync::mpsc::channel();
+    let (health_tx, health_rx) = std::sync::mpsc::channel();
+    let (update_tx, update_rx) = std::sync::mpsc::channel();
+    let (sample_tx, sample_rx) = std::sync::mpsc::channel();
+    if settings.as_ref().is_ok_and(|settings| settings.get_bool("updates.check").unwrap_or(false)) {
+        tokio::spawn(async move {
+            match check_for_update(VERSION).await {
+                Ok(Some(release)) => {
+                    log::info!("Rosetta v{} is available at {}", release.version, release.url);
+                    let _ = update_tx.send(release);
+                }
+                Ok(None) => log::info!("Rosetta is up to date"),
+                Err(e) => log::warn!("{e}"),
+            }
+        });
+    }
     eframe::run_native(
         &format!("Rosetta v{VERSION}"),
         options,
@@ -68,10 +95,33 @@ async fn main() {
                 input_path: None,
                 output_path: "".to_owned(),
                 cfg: TranslationConfig::default(),
+                data_keys: "".to_owned(),
+                replaced_cfg: None,
+                window_title: format!("Rosetta v{VERSION}"),
                 tx,
                 rx,
                 status: None,
                 translation_thread: None,
+                partial_export: None,
+                stop_requested: Arc::new(AtomicBool::new(false)),
+                live_text: "".to_owned(),
+                offline: false,
+                history: vec![],
+                health_tx,
+                health_rx,
+                provider_health: None,
+                model_info: None,
+                sample_first: false,
+                sample_tx,
+                sample_rx,
+                sample: None,
+                update_rx,
+                available_update: None,
+                inspection: None,
+                inspection_filter: "".to_owned(),
+                outline: vec![],
+                sections: vec![],
+                preview_chapter: None,
             }))
         }),
     )
@@ -84,17 +134,119 @@ struct TranslationGui {
     input_path: Option<String>,
     output_path: String,
     cfg: TranslationConfig,
+    /// Comma-separated [TranslationConfig::data_keys] as typed
+    data_keys: String,
+    /// Config in effect before the one of the previous run of the same document was loaded, to go back to on reset
+    replaced_cfg: Option<TranslationConfig>,
+    window_title: String,
     tx: Sender<TranslationStatus>,
     rx: Receiver<TranslationStatus>,
     status: Option<TranslationStatus>,
     translation_thread: Option<JoinHandle<()>>,
+    /// Set while a translation is running
+    partial_export: Option<PartialExport>,
+    /// Set by the Stop button, the run stops at the next section
+    stop_requested: Arc<AtomicBool>,
+    /// Translation of the current subsection as it is being received
+    live_text: String,
+    offline: bool,
+    history: Vec<HistoryEntry>,
+    health_tx: Sender<ProviderHealth>,
+    health_rx: Receiver<ProviderHealth>,
+    provider_health: Option<ProviderHealth>,
+    /// Model of the tested provider, if it's a well-known one
+    model_info: Option<llm::ModelInfo>,
+    /// Translate button translates a sample to approve first, the full run only starts once it's approved
+    sample_first: bool,
+    sample_tx: Sender<Result<Vec<SampleSection>, TranslationError>>,
+    sample_rx: Receiver<Result<Vec<SampleSection>, TranslationError>>,
+    sample: Option<SampleReview>,
+    update_rx: Receiver<Release>,
+    /// Newer release found by the update check, until dismissed
+    available_update: Option<Release>,
+    /// Cache opened for inspection, along with its path
+    inspection: Option<(String, CacheInspection)>,
+    inspection_filter: String,
+    /// Chapters of the document of the last run
+    outline: Vec<OutlineEntry>,
+    /// Status of each section of the last run, along with its translation once done
+    sections: Vec<(SectionStatus, Option<String>)>,
+    /// Chapter shown in the preview, by its index in the outline
+    preview_chapter: Option<usize>,
+}
+
+#[derive(Debug)]
+struct HistoryEntry {
+    time: DateTime<Local>,
+    severity: Severity,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Severity {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn icon(&self) -> &'static str {
+        match self {
+            Severity::Info => "ℹ",
+            Severity::Success => "✔",
+            Severity::Warning => "⚠",
+            Severity::Error => "❌",
+        }
+    }
+
+    /// Color of the theme in use, so that labels stay readable in both dark and light mode
+    fn color(&self, visuals: &egui::Visuals) -> Color32 {
+        match self {
+            Severity::Info => visuals.weak_text_color(),
+            Severity::Success if visuals.dark_mode => Color32::LIGHT_GREEN,
+            Severity::Success => Color32::DARK_GREEN,
+            Severity::Warning => visuals.warn_fg_color,
+            Severity::Error => visuals.error_fg_color,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SampleReview {
+    Translating,
+    /// Sample sections along with thumbs up or down given to each, None until given
+    Ready(Vec<(SampleSection, Option<bool>)>),
+}
+
+#[derive(Debug)]
+enum ProviderHealth {
+    Checking,
+    Checked(Result<Duration, TranslationError>),
 }
 
 impl eframe::App for TranslationGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        self.show_outline(ctx);
+        self.show_sample(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading(format!("Rosetta v{VERSION}"));
 
+            while let Ok(release) = self.update_rx.try_recv() {
+                self.available_update = Some(release);
+            }
+            if let Some(release) = self.available_update.clone() {
+                ui.horizontal(|ui| {
+                    let color = Severity::Success.color(ui.visuals());
+                    ui.colored_label(color, format!("Rosetta v{} is available", release.version));
+                    ui.hyperlink_to("Download", &release.url);
+                    if ui.button("Dismiss").clicked() {
+                        self.available_update = None;
+                    }
+                });
+            }
+
             if let Err(ref err) = self.settings {
                 self.status = Some(TranslationStatus::Error(TranslationError::OtherError(
                     anyhow!("{err}"),
@@ -103,14 +255,92 @@ impl eframe::App for TranslationGui {
 
             while let Ok(status) = self.rx.try_recv() {
                 match status {
-                    TranslationStatus::Success | TranslationStatus::Error(_) => {
+                    TranslationStatus::Started => {
+                        self.push_history(Severity::Info, "Started".to_owned());
+                        self.outline.clear();
+                        self.sections.clear();
+                        self.preview_chapter = None;
+                    }
+                    TranslationStatus::Progress(_) => {}
+                    TranslationStatus::Success => {
+                        self.push_history(Severity::Success, "Done!".to_owned());
                         self.translation_thread = None;
+                        self.partial_export = None;
+                        self.live_text.clear();
+                        self.offline = false;
+                        // Explains the outcome better than a plain "Done!"
+                        if matches!(self.status, Some(TranslationStatus::NothingToTranslate(_))) {
+                            continue;
+                        }
+                    }
+                    TranslationStatus::NothingToTranslate(ref reason) => {
+                        self.push_history(Severity::Info, reason.clone());
+                    }
+                    TranslationStatus::Error(ref error) => {
+                        self.push_history(Severity::Error, format!("{}", error));
+                        self.translation_thread = None;
+                        self.partial_export = None;
+                        self.live_text.clear();
+                        self.offline = false;
+                    }
+                    TranslationStatus::Connectivity { online } => {
+                        // Doesn't replace the progress, only shadows it
+                        self.offline = !online;
+                        if online {
+                            self.push_history(Severity::Info, "Back online".to_owned());
+                        } else {
+                            self.push_history(Severity::Warning, "Offline, waiting for network".to_owned());
+                        }
+                        continue;
+                    }
+                    TranslationStatus::Warning(warning) => {
+                        self.push_history(Severity::Warning, warning.to_string());
+                        continue;
+                    }
+                    TranslationStatus::Info(info) => {
+                        self.push_history(Severity::Info, info);
+                        continue;
+                    }
+                    TranslationStatus::LiveText(text) => {
+                        self.live_text = text;
+                        continue;
+                    }
+                    TranslationStatus::Outline { entries, total_sections } => {
+                        self.outline = entries;
+                        self.sections = vec![(SectionStatus::Pending, None); total_sections];
+                        continue;
+                    }
+                    TranslationStatus::Section { section, status, translated } => {
+                        if let Some(entry) = self.sections.get_mut(section) {
+                            *entry = (status, translated);
+                        }
+                        continue;
                     }
-                    _ => {}
                 }
                 self.status = Some(status);
             }
 
+            // Window title is shown on the taskbar, so progress can be followed without the window in focus
+            let title = match self.status {
+                Some(TranslationStatus::Progress(ref progress)) if progress.total_sections > 0 => format!(
+                    "{}% - Rosetta v{VERSION}",
+                    progress.processed_sections * 100 / progress.total_sections
+                ),
+                _ => format!("Rosetta v{VERSION}"),
+            };
+            if title != self.window_title {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+                self.window_title = title;
+            }
+            if self.translation_thread.is_some() {
+                // Progress arrives through the channel, which doesn't wake up an unfocused window
+                ctx.request_repaint_after(Duration::from_secs(1));
+            }
+
+            while let Ok(health) = self.health_rx.try_recv() {
+                self.provider_health = Some(health);
+            }
+
             ui.horizontal(|ui| {
                 let btn = ui
                     .button("Select input file")
@@ -146,7 +376,7 @@ impl eframe::App for TranslationGui {
 
                     if let Some(path) = fd.pick_file() {
                         self.input_path = Some(path.display().to_string());
-                        let model = self.settings.as_ref().map(|s| s.get_string("openai.model")).into_iter().flatten().next();
+                        let model = self.settings.as_ref().ok().and_then(configured_model);
                         let new_file_name = "".to_owned()
                             + path.file_stem().unwrap().to_string_lossy().as_str()
                             + model.map_or("_translated.".to_owned(), |m| format!("_translated_{m}.")).as_str()
@@ -158,6 +388,38 @@ impl eframe::App for TranslationGui {
                             .display()
                             .to_string();
 
+                        // Cache is keyed by languages, so different settings would silently miss it
+                        self.replaced_cfg = None;
+                        if let Some(manifest) = RunManifest::find_previous(&path, Path::new(&self.output_path)) {
+                            let cfg = std::mem::replace(&mut self.cfg, manifest.cfg);
+                            self.replaced_cfg = Some(cfg);
+                            self.data_keys = self.cfg.data_keys.join(", ");
+                        }
+
+                        // Settings living with the document take precedence
+                        if matches!(path.extension().and_then(|ext| ext.to_str()), Some("md" | "markdown"))
+                            && let Ok(markdown) = std::fs::read_to_string(&path)
+                        {
+                            match parser::frontmatter::read_config(&markdown, &self.cfg) {
+                                Ok(Some(front_matter)) => {
+                                    self.cfg = front_matter.cfg;
+                                    self.data_keys = self.cfg.data_keys.join(", ");
+                                    self.push_history(
+                                        Severity::Info,
+                                        format!("Settings from the front matter: {}", front_matter.applied.join(", ")),
+                                    );
+                                    if !front_matter.ignored.is_empty() {
+                                        self.push_history(
+                                            Severity::Warning,
+                                            format!("Unknown front matter settings ignored: {}", front_matter.ignored.join(", ")),
+                                        );
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(error) => self.push_history(Severity::Warning, format!("{}", error)),
+                            }
+                        }
+
                         // if let Ok(settings) = &mut self.settings {
                         //     settings
                         //         .set("last_input_file", path.display().to_string())
@@ -177,6 +439,22 @@ impl eframe::App for TranslationGui {
                 ui.add(text_edit).labelled_by(label.id);
             });
 
+            if self.replaced_cfg.is_some() {
+                ui.horizontal(|ui| {
+                    let color = Severity::Info.color(ui.visuals());
+                    ui.colored_label(color, "ℹ Using settings of the previous run of this document");
+                    if ui.button("Reuse").on_hover_text("Keep the previous run settings").clicked() {
+                        self.replaced_cfg = None;
+                    }
+                    if ui.button("Reset").on_hover_text("Go back to the settings used before").clicked()
+                        && let Some(cfg) = self.replaced_cfg.take()
+                    {
+                        self.cfg = cfg;
+                        self.data_keys = self.cfg.data_keys.join(", ");
+                    }
+                });
+            }
+
             ui.horizontal(|ui| {
                 let label = ui.label("Source language");
                 ui.text_edit_singleline(&mut self.cfg.src_lang)
@@ -196,12 +474,328 @@ impl eframe::App for TranslationGui {
                 ui.add(text_edit).labelled_by(label.id);
             });
 
+            ui.horizontal(|ui| {
+                let label = ui.label("Domain");
+                egui::ComboBox::from_id_salt("domain")
+                    .selected_text(self.cfg.domain.to_string())
+                    .show_ui(ui, |ui| {
+                        for domain in Domain::PRESETS {
+                            let name = domain.to_string();
+                            ui.selectable_value(&mut self.cfg.domain, domain, name);
+                        }
+                        if !matches!(self.cfg.domain, Domain::Custom(_)) && ui.selectable_label(false, "Custom").clicked() {
+                            self.cfg.domain = Domain::Custom("".to_owned());
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+                if let Domain::Custom(constraints) = &mut self.cfg.domain {
+                    ui.add(
+                        TextEdit::singleline(constraints)
+                            .hint_text("Domain-specific constraints")
+                            .desired_width(f32::INFINITY),
+                    );
+                }
+            });
+
             ui.horizontal(|ui| {
                 let label = ui.label("Tone");
                 ui.text_edit_singleline(&mut self.cfg.tone)
                     .labelled_by(label.id);
             });
 
+            ui.horizontal(|ui| {
+                let label = ui.label("Billing project")
+                    .on_hover_text("Client the run is billed to, recorded in the usage ledger and sent to providers supporting it");
+                ui.text_edit_singleline(&mut self.cfg.billing_project)
+                    .labelled_by(label.id);
+            });
+
+            ui.horizontal(|ui| {
+                let label = ui.label("Language policy");
+                egui::ComboBox::from_id_salt("language_policy")
+                    .selected_text(self.cfg.language_policy.to_string())
+                    .show_ui(ui, |ui| {
+                        for policy in LanguagePolicy::ALL {
+                            ui.selectable_value(&mut self.cfg.language_policy, policy, policy.to_string());
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+            });
+
+            ui.horizontal(|ui| {
+                let label = ui.label("Output");
+                let style_name = |style: Option<BilingualStyle>| {
+                    style.map_or("Translation only".to_owned(), |s| s.to_string())
+                };
+                egui::ComboBox::from_id_salt("bilingual")
+                    .selected_text(style_name(self.cfg.bilingual))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.cfg.bilingual, None, style_name(None));
+                        for style in BilingualStyle::ALL {
+                            ui.selectable_value(&mut self.cfg.bilingual, Some(style), style_name(Some(style)));
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+            });
+
+            ui.horizontal(|ui| {
+                let label = ui.label("Heading numbers")
+                    .on_hover_text("Renumber output headings the same way on every run, whatever the provider did to the numbers");
+                egui::ComboBox::from_id_salt("heading_numbering")
+                    .selected_text(self.cfg.headings.numbering.to_string())
+                    .show_ui(ui, |ui| {
+                        for numbering in HeadingNumbering::ALL {
+                            ui.selectable_value(&mut self.cfg.headings.numbering, numbering, numbering.to_string());
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+                if self.cfg.headings.numbering != HeadingNumbering::Preserve {
+                    ui.checkbox(&mut self.cfg.headings.roman, "Roman")
+                        .on_hover_text("Number top-level headings with Roman numerals");
+                }
+            });
+
+            ui.checkbox(&mut self.cfg.fiction, "Fiction")
+                .on_hover_text("Convert dialogues and quotations to the destination language conventions");
+
+            ui.checkbox(&mut self.cfg.transcript, "Transcript")
+                .on_hover_text("Plain text transcript, keep speaker labels and timestamps as is and translate only the utterances");
+
+            ui.horizontal(|ui| {
+                let label = ui.label("Data keys");
+                let response = ui
+                    .add(
+                        TextEdit::singleline(&mut self.data_keys)
+                            .hint_text("All string values")
+                            .desired_width(f32::INFINITY),
+                    )
+                    .labelled_by(label.id)
+                    .on_hover_text("For JSON, YAML and CSV input: comma-separated keys, paths (e.g. products.*.title) \
+                        or column names of the values to translate");
+                if response.changed() {
+                    self.cfg.data_keys = self
+                        .data_keys
+                        .split(',')
+                        .map(|key| key.trim().to_owned())
+                        .filter(|key| !key.is_empty())
+                        .collect();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let mut fixed_seed = self.cfg.seed.is_some();
+                let checkbox = ui
+                    .checkbox(&mut fixed_seed, "Fixed seed")
+                    .on_hover_text("Use the same seed for every run, for providers supporting deterministic sampling");
+                if checkbox.changed() {
+                    self.cfg.seed = fixed_seed.then_some(0);
+                }
+                if let Some(seed) = &mut self.cfg.seed {
+                    ui.add(egui::DragValue::new(seed));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Keep untranslated between")
+                    .on_hover_text("Passages between these markers are kept as is, markers are removed from the output");
+                for (idx, markers) in self.cfg.no_translate_markers.iter_mut().enumerate() {
+                    if idx > 0 {
+                        ui.label("or");
+                    }
+                    ui.add(TextEdit::singleline(&mut markers.open).desired_width(120.0));
+                    ui.label("…");
+                    ui.add(TextEdit::singleline(&mut markers.close).desired_width(120.0));
+                }
+            });
+
+            ui.checkbox(&mut self.cfg.fix_grammar, "Fix grammar issues")
+                .on_hover_text("Re-translate sections where the grammar checker finds issues, needs grammar.languagetool_url in settings");
+
+            ui.checkbox(&mut self.cfg.stitch_seams, "Stitch split paragraphs")
+                .on_hover_text("Smooth the junctions of long paragraphs translated piece by piece, with an extra request per junction");
+
+            ui.checkbox(&mut self.cfg.headings_first, "Translate headings first")
+                .on_hover_text("Translate all headings before the text bodies to settle the terminology early");
+
+            ui.horizontal(|ui| {
+                let btn = ui
+                    .button("Style sample")
+                    .on_hover_text("Load a previously approved translation whose style should be matched");
+
+                if self.cfg.style_sample.is_empty() {
+                    ui.label("None");
+                } else {
+                    ui.label(format!("{} characters", self.cfg.style_sample.trim().graphemes(true).count()));
+                    if ui.button("Clear").clicked() {
+                        self.cfg.style_sample.clear();
+                    }
+                }
+
+                if btn.clicked()
+                    && let Some(path) = rfd::FileDialog::new().add_filter("Text", &["txt", "md"]).pick_file()
+                {
+                    match std::fs::read_to_string(&path) {
+                        Ok(sample) => self.cfg.style_sample = sample,
+                        Err(e) => {
+                            self.status = Some(TranslationStatus::Error(TranslationError::IoError(e)))
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let btn = ui
+                    .button("Glossary")
+                    .on_hover_text("Load mandated translations of terms, one \"source = target\" or tab-separated pair per line");
+
+                if self.cfg.glossary.is_empty() {
+                    ui.label("None");
+                } else {
+                    ui.label(format!("{} terms", self.cfg.glossary.len()));
+                    if ui.button("Clear").clicked() {
+                        self.cfg.glossary.clear();
+                    }
+                }
+
+                if btn.clicked()
+                    && let Some(path) = rfd::FileDialog::new().add_filter("Glossary", &["txt", "tsv"]).pick_file()
+                {
+                    match std::fs::read_to_string(&path) {
+                        Ok(glossary) => self.cfg.glossary = glossary::parse_glossary(&glossary),
+                        Err(e) => {
+                            self.status = Some(TranslationStatus::Error(TranslationError::IoError(e)))
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let btn = ui
+                    .button("Prompt template")
+                    .on_hover_text("Load translation instructions replacing the built-in ones, \
+                        with {{src_lang}}, {{dst_lang}}, {{subject}}, {{tone}}, {{domain}}, {{glossary}}, {{fiction}}, \
+                        {{style_sample}} and {{additional_instructions}} standing for the settings above");
+
+                match self.cfg.prompt_template {
+                    None => {
+                        ui.label("Built-in");
+                    }
+                    Some(ref template) => {
+                        ui.label(format!("{} characters", template.chars().count()));
+                        if ui.button("Clear").clicked() {
+                            self.cfg.prompt_template = None;
+                        }
+                    }
+                }
+
+                if btn.clicked()
+                    && let Some(path) = rfd::FileDialog::new().add_filter("Text", &["txt", "md"]).pick_file()
+                {
+                    match std::fs::read_to_string(&path) {
+                        Ok(template) => self.cfg.prompt_template = Some(template),
+                        Err(e) => {
+                            self.status = Some(TranslationStatus::Error(TranslationError::IoError(e)))
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let btn = ui
+                    .button("Template")
+                    .on_hover_text("Document to put the translation into: a DOCX/ODT to take styles and letterhead from, \
+                        or an HTML layout with a <!-- rosetta:content --> marker");
+
+                match self.cfg.template {
+                    None => {
+                        ui.label("None");
+                    }
+                    Some(ref template) => {
+                        ui.label(template.file_name().unwrap_or_default().to_string_lossy());
+                        if ui.button("Clear").clicked() {
+                            self.cfg.template = None;
+                        }
+                    }
+                }
+
+                if btn.clicked()
+                    && let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Template", &["docx", "odt", "pptx", "html", "htm"])
+                        .pick_file()
+                {
+                    self.cfg.template = Some(path);
+                }
+            });
+
+            egui::CollapsingHeader::new("Output options")
+                .show(ui, |ui| {
+                    ui.label("Override generator.pandoc settings for this run");
+
+                    ui.horizontal(|ui| {
+                        let btn = ui
+                            .button("Reference document")
+                            .on_hover_text("DOCX/ODT/PPTX to take styles from");
+                        match self.cfg.pandoc.reference_doc {
+                            None => {
+                                ui.label("From settings");
+                            }
+                            Some(ref path) => {
+                                ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                                if ui.button("Clear").clicked() {
+                                    self.cfg.pandoc.reference_doc = None;
+                                }
+                            }
+                        }
+                        if btn.clicked()
+                            && let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Reference document", &["docx", "odt", "pptx"])
+                                .pick_file()
+                        {
+                            self.cfg.pandoc.reference_doc = Some(path);
+                        }
+                    });
+
+                    let mut self_contained = self.cfg.pandoc.self_contained.unwrap_or(false);
+                    if ui.checkbox(&mut self_contained, "Self-contained HTML")
+                        .on_hover_text("Embed images, styles and scripts into the HTML file")
+                        .changed()
+                    {
+                        self.cfg.pandoc.self_contained = Some(self_contained);
+                    }
+
+                    ui.horizontal(|ui| {
+                        let label = ui.label("PDF engine");
+                        let mut pdf_engine = self.cfg.pandoc.pdf_engine.clone().unwrap_or_default();
+                        let response = ui
+                            .add(TextEdit::singleline(&mut pdf_engine).hint_text("From settings"))
+                            .labelled_by(label.id);
+                        if response.changed() {
+                            self.cfg.pandoc.pdf_engine = Some(pdf_engine).filter(|engine| !engine.trim().is_empty());
+                        }
+                    });
+
+                    for (name, style) in [
+                        ("Body style", &mut self.cfg.pandoc.body_style),
+                        ("Quote style", &mut self.cfg.pandoc.quote_style),
+                    ] {
+                        ui.horizontal(|ui| {
+                            let label = ui.label(name);
+                            let mut text = style.clone().unwrap_or_default();
+                            let response = ui
+                                .add(TextEdit::singleline(&mut text).hint_text("From settings"))
+                                .labelled_by(label.id)
+                                .on_hover_text("DOCX paragraph style of the reference document");
+                            if response.changed() {
+                                *style = Some(text).filter(|text| !text.trim().is_empty());
+                            }
+                        });
+                    }
+                });
+
             ui.horizontal(|ui| {
                 let text_edit = TextEdit::multiline(&mut self.cfg.additional_instructions)
                     .desired_width(f32::INFINITY)
@@ -214,31 +808,89 @@ impl eframe::App for TranslationGui {
                     .add_enabled(
                         self.input_path.is_some()
                             && self.translation_thread.is_none()
+                            && self.sample.is_none()
                             && self.settings.is_ok(),
                         Button::new("Translate"),
                     )
                     .on_hover_text("Translate the input file");
 
+                ui.checkbox(&mut self.sample_first, "Sample first")
+                    .on_hover_text("Translate a few random sections to approve before the full run");
+
+                let partial_btn = ui
+                    .add_enabled(
+                        self.partial_export.is_some()
+                            && matches!(self.status, Some(TranslationStatus::Progress(_))),
+                        Button::new("Export partial result now"),
+                    )
+                    .on_hover_text("Generate a separate file from the sections translated so far, translation goes on");
+
+                if partial_btn.clicked()
+                    && let Some(ref partial_export) = self.partial_export
+                {
+                    partial_export.request(partial_output_path(Path::new(&self.output_path)));
+                }
+
+                let stop_btn = ui
+                    .add_enabled(
+                        (self.translation_thread.is_some() || matches!(self.sample, Some(SampleReview::Translating)))
+                            && !self.stop_requested.load(Ordering::Relaxed),
+                        Button::new("Stop"),
+                    )
+                    .on_hover_text("Stop the run after the current section, cancelling a pending batch job");
+
+                if stop_btn.clicked() {
+                    self.stop_requested.store(true, Ordering::Relaxed);
+                    self.push_history(Severity::Info, "Stopping...".to_owned());
+                }
+
                 let (status_text, status_text_color) = match self.status.as_ref() {
+                    _ if self.offline => {
+                        ("Offline, waiting for network...".to_owned(), Some(Severity::Warning.color(ui.visuals())))
+                    }
                     Some(TranslationStatus::Started) => {
                         ("Starting translation...".to_owned(), None)
                     }
-                    Some(TranslationStatus::Progress(progress)) => (
-                        format!(
+                    Some(TranslationStatus::Progress(progress)) => {
+                        let mut text = format!(
                             "{}/{} sections translated",
                             progress.processed_sections, progress.total_sections
-                        ),
-                        None,
-                    ),
+                        );
+                        let usage = progress.usage;
+                        if usage.prompt_tokens + usage.completion_tokens > 0 {
+                            text += &format!(", {} tokens", usage.prompt_tokens + usage.completion_tokens);
+                            if let Some(cost) = usage.cost {
+                                text += &format!(" (about ${cost:.2})");
+                            }
+                        }
+                        (text, None)
+                    }
+                    Some(
+                        TranslationStatus::Connectivity { .. }
+                        | TranslationStatus::Warning(_)
+                        | TranslationStatus::Info(_)
+                        | TranslationStatus::LiveText(_)
+                        | TranslationStatus::Outline { .. }
+                        | TranslationStatus::Section { .. },
+                    ) => unreachable!(),
                     Some(TranslationStatus::Success) => {
-                        ("Done!".to_owned(), Some(Color32::DARK_GREEN))
+                        ("Done!".to_owned(), Some(Severity::Success.color(ui.visuals())))
+                    }
+                    Some(TranslationStatus::NothingToTranslate(reason)) => {
+                        (reason.clone(), Some(Severity::Success.color(ui.visuals())))
                     }
                     Some(TranslationStatus::Error(error)) => {
-                        (format!("{}", error), Some(Color32::RED))
+                        (format!("{}", error), Some(Severity::Error.color(ui.visuals())))
                     }
                     None => ("".to_owned(), None),
                 };
 
+                if let Some(TranslationStatus::Error(TranslationError::LLMError(error))) = self.status.as_ref()
+                    && let Some(help_url) = error.help_url()
+                {
+                    ui.hyperlink_to("Provider account", help_url);
+                }
+
                 let mut status_text = status_text.as_str();
                 ui.add(
                     TextEdit::singleline(&mut status_text)
@@ -247,52 +899,554 @@ impl eframe::App for TranslationGui {
                 );
 
                 if btn.clicked() {
-                    self.status = None;
+                    if self.sample_first {
+                        self.start_sample();
+                    } else {
+                        self.start_translation();
+                    }
+                };
+            });
+
+            ui.horizontal(|ui| {
+                let btn = ui
+                    .add_enabled(
+                        self.settings.is_ok()
+                            && !matches!(self.provider_health, Some(ProviderHealth::Checking)),
+                        Button::new("Test provider"),
+                    )
+                    .on_hover_text("Send a tiny request to verify the API key and model, and measure latency");
+
+                match &self.provider_health {
+                    Some(ProviderHealth::Checking) => {
+                        ui.label("Checking...");
+                    }
+                    Some(ProviderHealth::Checked(Ok(latency))) => {
+                        let color = Severity::Success.color(ui.visuals());
+                        ui.colored_label(color, format!("OK, {} ms", latency.as_millis()));
+                        if let Some(info) = self.model_info {
+                            ui.label(model_summary(&info));
+                        }
+                    }
+                    Some(ProviderHealth::Checked(Err(error))) => {
+                        ui.colored_label(Severity::Error.color(ui.visuals()), format!("{}", error));
+                    }
+                    None => {}
+                }
 
+                if btn.clicked() {
                     let settings = self.settings.as_ref().unwrap().clone();
-                    let input_path = self.input_path.as_ref().unwrap().clone();
-                    let output_path = self.output_path.clone();
-                    let cfg = self.cfg.clone();
-                    let tx = self.tx.clone();
+                    let health_tx = self.health_tx.clone();
+                    self.provider_health = Some(ProviderHealth::Checking);
+                    self.model_info = configured_model_info(&settings);
 
-                    self.translation_thread = Some(tokio::spawn(async move {
-                        tx.send(TranslationStatus::Started).unwrap();
+                    tokio::spawn(async move {
+                        let result = check_provider(settings).await;
+                        health_tx.send(ProviderHealth::Checked(result)).unwrap();
+                    });
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let enabled = self.input_path.is_some() && self.translation_thread.is_none();
 
-                        let send_progress = SendProgressThroughChannel { tx: tx.clone() };
-                        let translation_res = tokio::spawn(async move {
-                            translate(
+                let export_btn = ui
+                    .add_enabled(enabled, Button::new("Export for review"))
+                    .on_hover_text("Export source and translated sections to review them in an editor");
+
+                let import_btn = ui
+                    .add_enabled(enabled, Button::new("Import review"))
+                    .on_hover_text("Apply translations marked as reviewed back to the cache and update the output");
+
+                let compact_btn = ui
+                    .add_enabled(enabled, Button::new("Compact cache"))
+                    .on_hover_text("Prune the translation cache according to the limits in settings and shrink its file");
+
+                if export_btn.clicked() {
+                    let fd = rfd::FileDialog::new()
+                        .add_filter("JSON", &["json"])
+                        .add_filter("Markdown", &["md"])
+                        .set_directory(Path::new(&self.output_path).parent().expect("parent"));
+                    if let Some(review_path) = fd.save_file() {
+                        let format = match review_path.extension() {
+                            Some(ext) if ext == "md" => ReviewFormat::Markdown,
+                            _ => ReviewFormat::Json,
+                        };
+                        let settings = self.settings.as_ref().unwrap().clone();
+                        let input_path = self.input_path.as_ref().unwrap().clone();
+                        let output_path = self.output_path.clone();
+                        let cfg = self.cfg.clone();
+
+                        self.spawn_task(async move {
+                            export_review(
+                                &settings,
+                                Path::new(&input_path),
+                                Path::new(&output_path),
+                                &cfg,
+                                &review_path,
+                                format,
+                            )
+                            .await
+                        });
+                    }
+                }
+
+                if import_btn.clicked() {
+                    let fd = rfd::FileDialog::new()
+                        .add_filter("JSON", &["json"])
+                        .set_directory(Path::new(&self.output_path).parent().expect("parent"));
+                    if let Some(review_path) = fd.pick_file() {
+                        let settings = self.settings.as_ref().unwrap().clone();
+                        let input_path = self.input_path.as_ref().unwrap().clone();
+                        let output_path = self.output_path.clone();
+                        let cfg = self.cfg.clone();
+                        let send_progress = self.send_progress();
+
+                        self.spawn_task(async move {
+                            let updated = import_review(
                                 settings,
                                 Path::new(&input_path),
                                 Path::new(&output_path),
-                                cfg,
+                                &cfg,
+                                &review_path,
                                 send_progress,
                             )
-                            .await
-                        })
-                        .await;
-                        match translation_res {
-                            Ok(Ok(())) => {
-                                tx.send(TranslationStatus::Success).unwrap();
-                            }
-                            Ok(Err(failure)) => {
-                                tx.send(TranslationStatus::Error(failure)).unwrap();
+                            .await?;
+                            log::info!("Imported {updated} reviewed translations");
+                            Ok(())
+                        });
+                    }
+                }
+
+                let reproduce_btn = ui
+                    .add_enabled(self.settings.is_ok() && self.translation_thread.is_none(), Button::new("Reproduce run"))
+                    .on_hover_text("Re-execute a recorded run with the exact same config and model, bypassing the cache");
+
+                if reproduce_btn.clicked() {
+                    let fd = rfd::FileDialog::new().add_filter("Run manifest", &["json"]);
+                    let fd = match Path::new(&self.output_path).parent() {
+                        Some(dir) if !self.output_path.is_empty() => fd.set_directory(dir),
+                        _ => fd,
+                    };
+                    if let Some(manifest_path) = fd.pick_file() {
+                        let settings = self.settings.as_ref().unwrap().clone();
+                        let send_progress = self.send_progress();
+
+                        self.spawn_task(async move { reproduce(settings, &manifest_path, send_progress).await });
+                    }
+                }
+
+                let cleanup_btn = ui
+                    .add_enabled(self.settings.is_ok() && self.translation_thread.is_none(), Button::new("Clean up provider"))
+                    .on_hover_text("Delete server-side conversation threads left over by runs of older versions");
+
+                if cleanup_btn.clicked() {
+                    let settings = self.settings.as_ref().unwrap().clone();
+                    let tx = self.tx.clone();
+
+                    self.spawn_task(async move {
+                        let deleted = cleanup_provider(settings).await?;
+                        tx.send(TranslationStatus::Info(format!("Deleted {deleted} leftover threads"))).unwrap();
+                        Ok(())
+                    });
+                }
+
+                let inspect_btn = ui
+                    .button("Inspect cache")
+                    .on_hover_text("Browse cached translations of any cache file, read-only");
+
+                if inspect_btn.clicked() {
+                    let fd = rfd::FileDialog::new().add_filter("Translation cache", &["sqlite"]);
+                    let fd = match Path::new(&self.output_path).parent() {
+                        Some(dir) if !self.output_path.is_empty() => fd.set_directory(dir),
+                        _ => fd,
+                    };
+                    if let Some(db_path) = fd.pick_file() {
+                        match inspect(&db_path) {
+                            Ok(inspection) => {
+                                self.inspection = Some((db_path.to_string_lossy().to_string(), inspection));
+                                self.inspection_filter.clear();
                             }
-                            Err(_) => {
-                                tx.send(TranslationStatus::Error(TranslationError::OtherError(
-                                    anyhow!("Crash!"),
-                                )))
-                                .unwrap();
+                            Err(e) => self.push_history(Severity::Error, format!("Couldn't inspect cache: {e}")),
+                        }
+                    }
+                }
+
+                if compact_btn.clicked() {
+                    let settings = self.settings.as_ref().unwrap().clone();
+                    let output_path = self.output_path.clone();
+                    let cfg = self.cfg.clone();
+                    let tx = self.tx.clone();
+
+                    self.spawn_task(async move {
+                        let stats = compact_cache(settings, Path::new(&output_path), &cfg).await?;
+                        tx.send(TranslationStatus::Info(format!(
+                            "Cache compacted: {} entries pruned, {:.1} MB reclaimed",
+                            stats.pruned_entries,
+                            stats.reclaimed_bytes as f64 / (1024.0 * 1024.0)
+                        )))
+                        .unwrap();
+                        Ok(())
+                    });
+                }
+            });
+
+            if self.translation_thread.is_some() && !self.live_text.is_empty() {
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .id_salt("live_text")
+                    .max_height(160.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        let mut live_text = self.live_text.as_str();
+                        ui.add(TextEdit::multiline(&mut live_text).desired_width(f32::INFINITY));
+                    });
+            }
+
+            if let Some(chapter) = self.preview_chapter {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Preview: {}", self.chapter_heading(chapter)));
+                    if ui.button("Close").clicked() {
+                        self.preview_chapter = None;
+                    }
+                });
+                let text = self.chapter_sections(chapter)
+                    .map(|idx| self.sections[idx].1.as_deref().unwrap_or("…"))
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                egui::ScrollArea::vertical()
+                    .id_salt("preview")
+                    .max_height(240.0)
+                    .show(ui, |ui| {
+                        let mut text = text.as_str();
+                        ui.add(TextEdit::multiline(&mut text).desired_width(f32::INFINITY));
+                    });
+            }
+
+            if !self.history.is_empty() {
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(120.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for entry in self.history.iter() {
+                            ui.horizontal(|ui| {
+                                ui.colored_label(entry.severity.color(ui.visuals()), entry.severity.icon());
+                                ui.label(entry.time.format("%H:%M:%S").to_string());
+                                ui.label(&entry.text);
+                            });
+                        }
+                    });
+            }
+        });
+
+        self.show_inspection(ctx);
+    }
+}
+
+impl TranslationGui {
+    /// Chapters of the document with their status, clicking one shows its translation in the preview
+    fn show_outline(&mut self, ctx: &egui::Context) {
+        if self.outline.is_empty() {
+            return;
+        }
+        let mut clicked = None;
+        egui::SidePanel::left("outline")
+            .resizable(true)
+            .default_width(300.0)
+            .show(ctx, |ui| {
+                ui.heading("Outline");
+                egui::ScrollArea::vertical().id_salt("outline").show(ui, |ui| {
+                    for (idx, entry) in self.outline.iter().enumerate() {
+                        let status = self.chapter_status(idx);
+                        let (icon, color) = match status {
+                            SectionStatus::Pending => ("○", Severity::Info.color(ui.visuals())),
+                            SectionStatus::Translating => ("⟳", ui.visuals().hyperlink_color),
+                            SectionStatus::Done => ("✔", Severity::Success.color(ui.visuals())),
+                            SectionStatus::Flagged => ("⚠", Severity::Warning.color(ui.visuals())),
+                        };
+                        ui.horizontal(|ui| {
+                            ui.add_space(entry.level.saturating_sub(1) as f32 * 12.0);
+                            ui.colored_label(color, icon).on_hover_text(format!("{status:?}"));
+                            let selected = self.preview_chapter == Some(idx);
+                            if ui.selectable_label(selected, self.chapter_heading(idx)).clicked() {
+                                clicked = Some(idx);
                             }
+                        });
+                    }
+                });
+            });
+        if clicked.is_some() {
+            self.preview_chapter = clicked;
+        }
+    }
+
+    /// Sections of the chapter, from its heading up to the next one
+    fn chapter_sections(&self, chapter: usize) -> std::ops::Range<usize> {
+        let end = self.outline.get(chapter + 1).map_or(self.sections.len(), |next| next.section);
+        self.outline[chapter].section..end.min(self.sections.len())
+    }
+
+    /// Translated heading once it's there, source one until then
+    fn chapter_heading(&self, chapter: usize) -> String {
+        let entry = &self.outline[chapter];
+        match self.sections.get(entry.section).and_then(|(_, translated)| translated.as_deref()) {
+            Some(translated) => first_line(translated).trim_start_matches('#').trim().to_owned(),
+            None => entry.heading.clone(),
+        }
+    }
+
+    fn chapter_status(&self, chapter: usize) -> SectionStatus {
+        let statuses = self.chapter_sections(chapter).map(|idx| self.sections[idx].0).collect::<Vec<_>>();
+        let done = |status: &SectionStatus| matches!(status, SectionStatus::Done | SectionStatus::Flagged);
+        if statuses.contains(&SectionStatus::Translating) {
+            SectionStatus::Translating
+        } else if !statuses.iter().all(done) {
+            SectionStatus::Pending
+        } else if statuses.contains(&SectionStatus::Flagged) {
+            SectionStatus::Flagged
+        } else {
+            SectionStatus::Done
+        }
+    }
+
+    fn show_inspection(&mut self, ctx: &egui::Context) {
+        let Some((ref db_path, ref inspection)) = self.inspection else {
+            return;
+        };
+        let mut open = true;
+        egui::Window::new(format!("Cache {db_path}"))
+            .open(&mut open)
+            .default_size([1000.0, 400.0])
+            .show(ctx, |ui| {
+                let pairs = inspection
+                    .language_pairs()
+                    .iter()
+                    .map(|(src, dst, count)| format!("{src} → {dst}: {count}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                ui.label(format!(
+                    "Schema version {}, {} entries ({})",
+                    inspection.schema_version,
+                    inspection.entries.len(),
+                    pairs
+                ));
+                ui.horizontal(|ui| {
+                    ui.label("Filter:");
+                    ui.text_edit_singleline(&mut self.inspection_filter);
+                });
+                ui.separator();
+
+                let filter = self.inspection_filter.to_lowercase();
+                let entries = inspection
+                    .entries
+                    .iter()
+                    .filter(|e| {
+                        filter.is_empty()
+                            || e.src.to_lowercase().contains(&filter)
+                            || e.dst.to_lowercase().contains(&filter)
+                    })
+                    .collect::<Vec<_>>();
+                let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                egui::ScrollArea::both()
+                    .id_salt("inspection")
+                    .auto_shrink(false)
+                    .show_rows(ui, row_height, entries.len(), |ui, rows| {
+                        for entry in &entries[rows] {
+                            let last_used = DateTime::from_timestamp(entry.last_used, 0)
+                                .filter(|_| entry.last_used > 0)
+                                .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                                .unwrap_or_else(|| "?".to_owned());
+                            ui.horizontal(|ui| {
+                                ui.label(last_used);
+                                ui.label(format!("{}→{}", entry.src_lang, entry.dst_lang));
+                                ui.label(one_line(&entry.src))
+                                    .on_hover_text(&entry.src);
+                                ui.label("→");
+                                ui.label(one_line(&entry.dst))
+                                    .on_hover_text(&entry.dst);
+                            });
                         }
-                    }));
+                    });
+            });
+        if !open {
+            self.inspection = None;
+        }
+    }
+
+    fn start_translation(&mut self) {
+        self.live_text.clear();
+        let settings = self.settings.as_ref().unwrap().clone();
+        let input_path = self.input_path.as_ref().unwrap().clone();
+        let output_path = self.output_path.clone();
+        let cfg = self.cfg.clone();
+        let send_progress = self.send_progress();
+        let partial_export = PartialExport::default();
+
+        self.spawn_task({
+            let partial_export = partial_export.clone();
+            async move {
+                translate(
+                    settings,
+                    Path::new(&input_path),
+                    Path::new(&output_path),
+                    cfg,
+                    send_progress,
+                    partial_export,
+                )
+                .await
+            }
+        });
+        self.partial_export = Some(partial_export);
+    }
+
+    /// Translates a sample in background, it's shown for review once done, see [Self::show_sample]
+    fn start_sample(&mut self) {
+        let settings = self.settings.as_ref().unwrap().clone();
+        let input_path = self.input_path.as_ref().unwrap().clone();
+        let cfg = self.cfg.clone();
+        self.stop_requested.store(false, Ordering::Relaxed);
+        let send_progress = self.send_progress();
+        let sample_tx = self.sample_tx.clone();
+        self.sample = Some(SampleReview::Translating);
+        self.push_history(Severity::Info, "Translating a sample".to_owned());
+
+        tokio::spawn(async move {
+            let size = sample_size(&settings);
+            let result = translate_sample(settings, Path::new(&input_path), cfg, size, send_progress).await;
+            sample_tx.send(result).unwrap();
+        });
+    }
+
+    /// Sample waiting for a thumbs up or down on each section, the full run starts once all of them are approved.
+    /// Rejecting it gets back to the settings, to tweak them and try another sample.
+    fn show_sample(&mut self, ctx: &egui::Context) {
+        while let Ok(result) = self.sample_rx.try_recv() {
+            match result {
+                Ok(sections) => {
+                    self.sample = Some(SampleReview::Ready(sections.into_iter().map(|s| (s, None)).collect()));
+                }
+                Err(error) => {
+                    self.push_history(Severity::Error, format!("Sample failed: {error}"));
+                    self.sample = None;
+                }
+            }
+        }
+        let Some(ref mut review) = self.sample else {
+            return;
+        };
+        let mut approved = false;
+        let mut rejected = false;
+        egui::Window::new("Sample translation")
+            .collapsible(false)
+            .default_size([1000.0, 400.0])
+            .show(ctx, |ui| {
+                let SampleReview::Ready(sections) = review else {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Translating a sample...");
+                    });
+                    return;
                 };
+                egui::ScrollArea::vertical().id_salt("sample").max_height(500.0).show(ui, |ui| {
+                    for (sample, verdict) in sections.iter_mut() {
+                        ui.horizontal(|ui| {
+                            ui.strong(format!("Section {}", sample.section + 1));
+                            ui.selectable_value(verdict, Some(true), "👍");
+                            ui.selectable_value(verdict, Some(false), "👎");
+                        });
+                        ui.columns(2, |columns| {
+                            columns[0].label(&sample.source);
+                            columns[1].label(&sample.translation);
+                        });
+                        ui.separator();
+                    }
+                });
+                let all_approved = sections.iter().all(|(_, verdict)| *verdict == Some(true));
+                ui.horizontal(|ui| {
+                    approved = ui
+                        .add_enabled(all_approved, Button::new("Approve and translate"))
+                        .on_hover_text("Start the full run once every section got a thumbs up")
+                        .clicked();
+                    rejected = ui
+                        .button("Reject")
+                        .on_hover_text("Get back to the settings, to tweak the instructions and try another sample")
+                        .clicked();
+                });
             });
+
+        if approved {
+            self.sample = None;
+            self.push_history(Severity::Success, "Sample approved".to_owned());
+            self.start_translation();
+        } else if rejected {
+            let disliked = match review {
+                SampleReview::Ready(sections) => sections
+                    .iter()
+                    .filter(|(_, verdict)| *verdict == Some(false))
+                    .map(|(sample, _)| (sample.section + 1).to_string())
+                    .collect::<Vec<_>>(),
+                SampleReview::Translating => vec![],
+            };
+            let text = match disliked.is_empty() {
+                true => "Sample rejected, tweak the instructions and try again".to_owned(),
+                false => format!(
+                    "Sample rejected, section(s) {} were off, tweak the instructions and try again",
+                    disliked.join(", ")
+                ),
+            };
+            self.sample = None;
+            self.push_history(Severity::Warning, text);
+        }
+    }
+
+    fn push_history(&mut self, severity: Severity, text: String) {
+        self.history.push(HistoryEntry {
+            time: Local::now(),
+            severity,
+            text,
         });
     }
+
+    fn send_progress(&self) -> SendProgressThroughChannel {
+        SendProgressThroughChannel { tx: self.tx.clone(), stop_requested: self.stop_requested.clone() }
+    }
+
+    /// Runs a long task in background, reporting its status through the channel
+    fn spawn_task<F>(&mut self, task: F)
+    where
+        F: Future<Output = Result<(), TranslationError>> + Send + 'static,
+    {
+        self.status = None;
+        // Shared by every task's progress reporting, so a stop of the previous one mustn't carry over
+        self.stop_requested.store(false, Ordering::Relaxed);
+
+        let tx = self.tx.clone();
+
+        self.translation_thread = Some(tokio::spawn(async move {
+            tx.send(TranslationStatus::Started).unwrap();
+
+            match tokio::spawn(task).await {
+                Ok(Ok(())) => {
+                    tx.send(TranslationStatus::Success).unwrap();
+                }
+                Ok(Err(failure)) => {
+                    tx.send(TranslationStatus::Error(failure)).unwrap();
+                }
+                Err(_) => {
+                    tx.send(TranslationStatus::Error(TranslationError::OtherError(
+                        anyhow!("Crash!"),
+                    )))
+                    .unwrap();
+                }
+            }
+        }));
+    }
 }
 
 struct SendProgressThroughChannel {
     tx: Sender<TranslationStatus>,
+    stop_requested: Arc<AtomicBool>,
 }
 
 impl SendProgress for SendProgressThroughChannel {
@@ -301,4 +1455,70 @@ impl SendProgress for SendProgressThroughChannel {
             .send(TranslationStatus::Progress(progress))
             .expect("send");
     }
+
+    fn send_connectivity(&self, online: bool) {
+        self.tx
+            .send(TranslationStatus::Connectivity { online })
+            .expect("send");
+    }
+
+    fn send_warning(&self, warning: Warning) {
+        self.tx
+            .send(TranslationStatus::Warning(warning))
+            .expect("send");
+    }
+
+    fn send_info(&self, info: String) {
+        self.tx
+            .send(TranslationStatus::Info(info))
+            .expect("send");
+    }
+
+    fn send_nothing_to_translate(&self, reason: String) {
+        self.tx
+            .send(TranslationStatus::NothingToTranslate(reason))
+            .expect("send");
+    }
+
+    fn send_live_text(&self, text: String) {
+        self.tx
+            .send(TranslationStatus::LiveText(text))
+            .expect("send");
+    }
+
+    fn send_outline(&self, entries: Vec<OutlineEntry>, total_sections: usize) {
+        self.tx
+            .send(TranslationStatus::Outline { entries, total_sections })
+            .expect("send");
+    }
+
+    fn send_section(&self, section: usize, status: SectionStatus, translated: Option<String>) {
+        self.tx
+            .send(TranslationStatus::Section { section, status, translated })
+            .expect("send");
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop_requested.load(Ordering::Relaxed)
+    }
+}
+
+/// Context size and prices of the model, e.g. "128k tokens context, $2.50 / $10.00 per million tokens"
+fn model_summary(info: &llm::ModelInfo) -> String {
+    let mut summary = format!("{}k tokens context", info.context_tokens / 1000);
+    if let (Some(price_in), Some(price_out)) = (info.price_in, info.price_out) {
+        summary += &format!(", ${price_in:.2} / ${price_out:.2} per million tokens");
+    }
+    summary
+}
+
+/// First line of a text, shortened to fit in a table row
+fn one_line(text: &str) -> String {
+    const MAX_CHARS: usize = 60;
+    let line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or_default().trim();
+    if line.chars().count() > MAX_CHARS || text.trim() != line {
+        format!("{}…", line.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        line.to_owned()
+    }
 }
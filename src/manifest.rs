@@ -0,0 +1,94 @@
+use crate::llm::Provider;
+use crate::{TranslationConfig, TranslationError};
+
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Everything needed to re-execute a translation run, saved next to its output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Manifests recorded before other providers were supported are OpenAI ones
+    #[serde(default)]
+    pub provider: Provider,
+    pub model: String,
+    /// SHA-256 of the system prompt, changes whenever the prompt template or config does
+    pub prompt_hash: String,
+    /// System prompt itself, empty in manifests recorded before it was kept
+    #[serde(default)]
+    pub prompt: String,
+    /// Version of rosetta that made the run, empty in manifests recorded before it was kept
+    #[serde(default)]
+    pub rosetta_version: String,
+    pub cfg: TranslationConfig,
+}
+
+impl RunManifest {
+    pub fn path_for(output: &Path) -> PathBuf {
+        output.with_extension("run.json")
+    }
+
+    pub async fn save(&self) -> Result<(), TranslationError> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| TranslationError::OtherError(e.into()))?;
+        fs::write(Self::path_for(&self.output), content).await?;
+        Ok(())
+    }
+
+    pub async fn load(path: &Path) -> Result<Self, TranslationError> {
+        let content = fs::read_to_string(path).await?;
+        serde_json::from_str(&content)
+            .map_err(|e| TranslationError::OtherError(anyhow!("Malformed run manifest: {e}")))
+    }
+
+    /// Manifest of the last run translating `input` into `output`, if any.
+    /// Synchronous since it's small and is read from the UI thread.
+    pub fn find_previous(input: &Path, output: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(Self::path_for(output)).ok()?;
+        let manifest = serde_json::from_str::<Self>(&content).ok()?;
+        (manifest.input == input).then_some(manifest)
+    }
+
+    /// What in this run's setup differs from the `previous` run, empty if nothing does
+    pub fn differences(&self, previous: &RunManifest) -> Vec<String> {
+        let mut differences = vec![];
+        let mut changed = |what: &str, before: &str, after: &str| {
+            if before != after {
+                differences.push(format!("{what} {before} → {after}"));
+            }
+        };
+        changed("input", &previous.input.display().to_string(), &self.input.display().to_string());
+        changed("rosetta version", &previous.rosetta_version, &self.rosetta_version);
+        changed("provider", previous.provider.settings_section(), self.provider.settings_section());
+        changed("model", &previous.model, &self.model);
+
+        let cfg_keys = match (serde_json::to_value(&previous.cfg), serde_json::to_value(&self.cfg)) {
+            (Ok(Value::Object(before)), Ok(Value::Object(after))) => {
+                after.iter().filter(|(key, value)| before.get(*key) != Some(*value)).map(|(key, _)| key.clone()).collect_vec()
+            }
+            _ => vec![],
+        };
+        match cfg_keys.is_empty() {
+            false => differences.push(format!("settings {}", cfg_keys.join(", "))),
+            // Same settings can still make another prompt, e.g. with an updated default template
+            true if previous.prompt_hash != self.prompt_hash => differences.push("prompt".to_owned()),
+            true => {}
+        }
+        differences
+    }
+
+    /// Output path for a reproduction, so that the original output and its cache stay intact
+    pub fn reproduction_output(&self) -> PathBuf {
+        let stem = self.output.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match self.output.extension() {
+            Some(ext) => format!("{stem}_reproduced.{}", ext.to_string_lossy()),
+            None => format!("{stem}_reproduced"),
+        };
+        self.output.with_file_name(file_name)
+    }
+}
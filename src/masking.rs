@@ -0,0 +1,123 @@
+use crate::parser::{MarkdownSection, MarkdownSubsection};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// Stands in for a protected passage while the text is being translated
+static TOKEN_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"⟦(\d+)⟧").expect("valid regex"));
+
+/// Markers around source passages to keep untranslated, e.g. trademarks, code identifiers or quoted foreign phrases.
+/// Markers themselves don't make it to the output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoTranslateMarkers {
+    pub open: String,
+    pub close: String,
+}
+
+impl NoTranslateMarkers {
+    pub fn defaults() -> Vec<NoTranslateMarkers> {
+        vec![
+            NoTranslateMarkers { open: "<!--notranslate-->".to_owned(), close: "<!--/notranslate-->".to_owned() },
+            NoTranslateMarkers { open: "⟦".to_owned(), close: "⟧".to_owned() },
+        ]
+    }
+}
+
+/// Protected passages of each subsection of a masked section, in the order of their tokens
+pub type MaskedSpans = Vec<Vec<String>>;
+
+/// Replaces marked passages with numbered tokens for the LLM to keep as is.
+pub fn mask(section: &MarkdownSection, markers: &[NoTranslateMarkers]) -> (MarkdownSection, MaskedSpans) {
+    let markers = markers.iter().filter(|m| !m.open.is_empty() && !m.close.is_empty()).collect::<Vec<_>>();
+    let mut all_spans = Vec::with_capacity(section.0.len());
+    let masked = section.0.iter().map(|ss| {
+        let mut spans = vec![];
+        let mut result = String::with_capacity(ss.0.len());
+        let mut rest = ss.0.as_str();
+        loop {
+            // Earliest marked passage, unclosed ones are left as is
+            let next = markers
+                .iter()
+                .filter_map(|m| {
+                    let start = rest.find(&m.open)?;
+                    let content_start = start + m.open.len();
+                    let content_len = rest[content_start..].find(&m.close)?;
+                    Some((start, content_start, content_len, m.close.len()))
+                })
+                .min_by_key(|(start, ..)| *start);
+            let Some((start, content_start, content_len, close_len)) = next else {
+                break;
+            };
+            result += &rest[..start];
+            result += &format!("⟦{}⟧", spans.len());
+            spans.push(rest[content_start..content_start + content_len].to_owned());
+            rest = &rest[content_start + content_len + close_len..];
+        }
+        result += rest;
+        all_spans.push(spans);
+        MarkdownSubsection(result)
+    }).collect();
+    (MarkdownSection(masked), all_spans)
+}
+
+/// Puts protected passages back in place of their tokens, annotations are left as they are.
+pub fn unmask(section: MarkdownSection, spans: &MaskedSpans) -> MarkdownSection {
+    let mut spans = spans.iter();
+    MarkdownSection(section.0.into_iter().map(|ss| {
+        if ss.is_annotation() {
+            return ss;
+        }
+        match spans.next() {
+            Some(spans) if !spans.is_empty() => {
+                MarkdownSubsection(TOKEN_REGEX.replace_all(&ss.0, |caps: &regex::Captures| {
+                    caps[1].parse::<usize>().ok()
+                        .and_then(|idx| spans.get(idx))
+                        .cloned()
+                        .unwrap_or_else(|| caps[0].to_owned())
+                }).into_owned())
+            }
+            _ => ss,
+        }
+    }).collect())
+}
+
+/// Removes the markers, keeping the passages they protect.
+pub fn strip_markers(section: &MarkdownSection, markers: &[NoTranslateMarkers]) -> MarkdownSection {
+    let (masked, spans) = mask(section, markers);
+    unmask(masked, &spans)
+}
+
+/// Lists mask tokens in the text, sorted, since translation may legitimately reorder them.
+pub fn tokens(s: &str) -> Vec<&str> {
+    let mut result = TOKEN_REGEX.find_iter(s).map(|m| m.as_str()).collect::<Vec<_>>();
+    result.sort_unstable();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_and_unmask() {
+        let section = MarkdownSection(vec![
+            MarkdownSubsection("Use <!--notranslate-->Rosetta Pro<!--/notranslate--> with ⟦--dry-run⟧ flag.".to_owned()),
+            MarkdownSubsection("Nothing here, <!--notranslate-->unclosed.".to_owned()),
+        ]);
+        let (masked, spans) = mask(&section, &NoTranslateMarkers::defaults());
+        assert_eq!(masked.0[0].0, "Use ⟦0⟧ with ⟦1⟧ flag.");
+        assert_eq!(masked.0[1].0, "Nothing here, <!--notranslate-->unclosed.");
+        assert_eq!(tokens(&masked.0[0].0), vec!["⟦0⟧", "⟦1⟧"]);
+
+        let translated = MarkdownSection(vec![
+            MarkdownSubsection("<!-- rosetta: note -->".to_owned()),
+            MarkdownSubsection("Флаг ⟦1⟧ для ⟦0⟧.".to_owned()),
+            MarkdownSubsection("Здесь ничего, <!--notranslate-->незакрыто.".to_owned()),
+        ]);
+        let unmasked = unmask(translated, &spans);
+        assert_eq!(unmasked.0[0].0, "<!-- rosetta: note -->");
+        assert_eq!(unmasked.0[1].0, "Флаг --dry-run для Rosetta Pro.");
+        assert_eq!(unmasked.0[2].0, "Здесь ничего, <!--notranslate-->незакрыто.");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::parser::MarkdownSection;
+use crate::TranslationError;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+const API_URL: &str = "https://api.openai.com/v1/moderations";
+
+const MODEL: &str = "omni-moderation-latest";
+
+/// Screens source text with OpenAI moderation endpoint, which is free to use,
+/// to find the sections providers are likely to refuse before a run is under way.
+/// See https://platform.openai.com/docs/guides/moderation
+pub struct ContentScreener {
+    client: reqwest::Client,
+    api_key: String,
+}
+
+#[derive(Serialize)]
+struct ModerationRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    categories: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ContentScreener {
+    pub fn new(api_key: String) -> Self {
+        ContentScreener {
+            client: reqwest::Client::new(),
+            api_key,
+        }
+    }
+
+    /// Categories the section is flagged for, e.g. `violence` or `self-harm/instructions`, none if it's fine.
+    pub async fn screen_section(&self, section: &MarkdownSection) -> Result<Vec<String>, TranslationError> {
+        let input = section.0.iter().filter(|ss| !ss.is_annotation()).map(|ss| ss.0.as_str()).collect::<Vec<_>>();
+        if input.iter().all(|text| text.trim().is_empty()) {
+            return Ok(vec![]);
+        }
+
+        let response = self.client
+            .post(API_URL)
+            .bearer_auth(&self.api_key)
+            .json(&ModerationRequest { model: MODEL, input })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| TranslationError::OtherError(anyhow!("Content pre-screen failed: {e}")))?;
+        let response: ModerationResponse = response
+            .json()
+            .await
+            .map_err(|e| TranslationError::OtherError(anyhow!("Malformed content pre-screen response: {e}")))?;
+
+        let mut categories = vec![];
+        for result in response.results.into_iter().filter(|r| r.flagged) {
+            for (category, flagged) in result.categories {
+                if flagged.as_bool() == Some(true) && !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        Ok(categories)
+    }
+}
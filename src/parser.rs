@@ -1,16 +1,156 @@
+pub mod data;
+pub mod frontmatter;
+pub mod localization;
 pub mod pandoc;
+pub mod transcript;
 
+use crate::utils::{estimate_tokens, SENTENCE_BREAK_REGEX};
 use std::path::Path;
 use super::ParseError;
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct MarkdownSection(pub Vec<MarkdownSubsection>);
 
+impl MarkdownSection {
+    pub fn is_heading(&self) -> bool {
+        self.0.first().is_some_and(|ss| ss.0.starts_with('#'))
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct MarkdownSubsection(pub String);
 
+impl MarkdownSubsection {
+    /// Whether this is a note added by Rosetta rather than a part of the text
+    pub fn is_annotation(&self) -> bool {
+        self.0.starts_with("<!-- rosetta:")
+    }
+}
+
 pub trait Parser {
     fn max_section_len(&self) -> usize;
 
+    /// Whether placeholders like `%s` or `{name}` have to survive translation intact
+    fn strict_placeholders(&self) -> bool {
+        false
+    }
+
     async fn parse(&self, input: &Path) -> Result<Vec<MarkdownSection>, ParseError>;
 }
+
+/// Splits a paragraph into subsections of about `max_len`, breaking between sentences where possible.
+fn split_paragraph(s: &str, max_len: usize) -> MarkdownSection {
+    let mut section = MarkdownSection::default();
+    let mut s = s.trim();
+    while s.len() > max_len {
+        let break_point = find_break_point(s, max_len);
+        section
+            .0
+            .push(MarkdownSubsection(s[..break_point].trim().to_owned()));
+        s = s[break_point..].trim();
+    }
+    if !s.is_empty() {
+        section.0.push(MarkdownSubsection(s.to_owned()));
+    }
+    section
+}
+
+/// Same as [split_paragraph], also keeping subsections within about `max_tokens`, see [estimate_tokens].
+/// Paragraph is assumed to be equally dense throughout, so the token budget is turned into a length.
+fn split_paragraph_by_tokens(s: &str, max_len: usize, max_tokens: Option<usize>) -> MarkdownSection {
+    let max_len = match max_tokens {
+        Some(max_tokens) => {
+            let tokens = estimate_tokens(s);
+            match tokens > max_tokens {
+                true => max_len.min(s.len() * max_tokens / tokens).max(1),
+                false => max_len,
+            }
+        }
+        None => max_len,
+    };
+    split_paragraph(s, max_len)
+}
+
+/// Prefers a sentence break past the half of `max_len`, then a whitespace,
+/// and cuts enormous single tokens between graphemes as a last resort.
+/// Always returns a char boundary within a non-empty trimmed `s`, past its start.
+fn find_break_point(s: &str, max_len: usize) -> usize {
+    let mut min_break_point = max_len / 2;
+    while !s.is_char_boundary(min_break_point) {
+        min_break_point -= 1;
+    }
+
+    if let Some(m) = SENTENCE_BREAK_REGEX.find_at(s, min_break_point) {
+        return m.start() + 1; // Skip past the punctuation
+    }
+    if let Some((idx, _)) = s[min_break_point..].char_indices().find(|(_, c)| c.is_whitespace()) {
+        return min_break_point + idx;
+    }
+    s.grapheme_indices(true)
+        .map(|(idx, g)| idx + g.len())
+        .take_while(|&end| end <= max_len)
+        .last()
+        .unwrap_or_else(|| s.graphemes(true).next().map_or(s.len(), |g| g.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn non_whitespace(s: &str) -> String {
+        s.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    fn check_split(s: &str, max_len: usize) {
+        let section = split_paragraph(s, max_len);
+        assert!(section.0.iter().all(|ss| !ss.0.is_empty()), "empty subsection for {s:?}");
+        let joined = section.0.iter().map(|ss| ss.0.as_str()).collect::<String>();
+        assert_eq!(non_whitespace(&joined), non_whitespace(s), "content lost for {s:?}");
+    }
+
+    #[test]
+    fn split_pathological_inputs() {
+        for max_len in [0, 1, 2, 3, 10] {
+            check_split("", max_len);
+            check_split("\n\n  \n", max_len);
+            check_split("Thisisaverylongwordwithoutbreakpoints.", max_len);
+            check_split("Привет. Как дела? Всё хорошо!", max_len);
+            check_split("👨‍👩‍👧‍👦👨‍👩‍👧‍👦 e\u{301}e\u{301} 漢字漢字。", max_len);
+        }
+        let section = split_paragraph("Thisisaverylongwordwithoutbreakpoints.", 10);
+        assert!(section.0.iter().all(|ss| ss.0.len() <= 10));
+    }
+
+    #[test]
+    fn split_by_tokens() {
+        let english = "This is a sentence. ".repeat(50);
+        let chinese = "这是一个句子。".repeat(50);
+        for s in [english.as_str(), chinese.as_str()] {
+            let section = split_paragraph_by_tokens(s, 4000, Some(100));
+            assert!(section.0.len() > 1);
+            assert!(section.0.iter().all(|ss| estimate_tokens(&ss.0) <= 100), "{section:?}");
+            assert_eq!(split_paragraph_by_tokens(s, 4000, None).0.len(), 1);
+        }
+    }
+
+    #[test]
+    fn split_random_inputs() {
+        const ALPHABET: [&str; 14] = [
+            "a", "B", " ", "\n", ".", "!", "?", "ж", "Ж", "é", "e\u{301}", "漢", "👍🏽", "\t",
+        ];
+        // Simple xorshift, to keep the test deterministic without extra dependencies
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..500 {
+            let len = (next() % 200) as usize;
+            let s = (0..len).map(|_| ALPHABET[(next() % ALPHABET.len() as u64) as usize]).collect::<String>();
+            check_split(&s, (next() % 50) as usize);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use super::localization::{Message, MessageFormat};
+
+use std::ops::Range;
+use std::path::Path;
+
+/// Path segment standing for any array/sequence item
+pub(super) const ITEM_SEGMENT: &str = "[]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    Json,
+    Yaml,
+    /// Comma-separated values with a header row naming the columns
+    Csv,
+}
+
+/// Structured data file where only the string values at selected keys are translated,
+/// everything else is kept byte-for-byte.
+#[derive(Debug, Clone)]
+pub struct DataFormat {
+    pub kind: DataKind,
+    /// Keys/column names (e.g. `description`) or paths (e.g. `products.*.title`, `$.items[*].name`)
+    /// of values to translate, all string values are translated if empty.
+    /// Array items are not distinguished by their index.
+    pub keys: Vec<String>,
+}
+
+impl DataKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "json" => Some(DataKind::Json),
+            "yaml" | "yml" => Some(DataKind::Yaml),
+            "csv" => Some(DataKind::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl DataFormat {
+    pub fn from_path(path: &Path, keys: Vec<String>) -> Option<Self> {
+        DataKind::from_path(path).map(|kind| DataFormat { kind, keys })
+    }
+
+    fn is_translatable(&self, path: &[&str]) -> bool {
+        if self.keys.is_empty() {
+            return true;
+        }
+        let last_key = path.iter().rev().find(|s| **s != ITEM_SEGMENT);
+        self.keys.iter().any(|pattern| {
+            let pattern = normalize_path_pattern(pattern);
+            match pattern.as_slice() {
+                [key] => last_key.is_some_and(|last_key| last_key == key),
+                _ => {
+                    pattern.len() == path.len()
+                        && pattern.iter().zip(path.iter()).all(|(p, s)| p == "*" || p == s)
+                }
+            }
+        })
+    }
+}
+
+impl MessageFormat for DataFormat {
+    fn messages(&self, content: &str) -> Vec<Message> {
+        let values = match self.kind {
+            DataKind::Json => json_values(content),
+            DataKind::Yaml => yaml_values(content),
+            DataKind::Csv => csv_values(content),
+        };
+        values
+            .into_iter()
+            .filter(|value| !value.text.trim().is_empty() && self.is_translatable(&value.path))
+            .map(|value| Message { text: value.text, targets: vec![value.range] })
+            .collect()
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self.kind {
+            DataKind::Json => json_string(text),
+            DataKind::Yaml => {
+                let needs_quotes = text.is_empty()
+                    || text.trim() != text
+                    || text.starts_with(['-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`'])
+                    || text.contains(": ")
+                    || text.contains(" #")
+                    || text.ends_with(':')
+                    || text.contains(['\n', '\t']);
+                // Double-quoted YAML scalars accept JSON string escapes
+                if needs_quotes { json_string(text) } else { text.to_owned() }
+            }
+            DataKind::Csv => {
+                if text.contains([',', '"', '\n', '\r']) || text.trim() != text {
+                    format!("\"{}\"", text.replace('"', "\"\""))
+                } else {
+                    text.to_owned()
+                }
+            }
+        }
+    }
+}
+
+/// `$.items[*].name` -> `["items", "[]", "name"]`
+fn normalize_path_pattern(pattern: &str) -> Vec<String> {
+    let pattern = pattern.trim();
+    let pattern = pattern.strip_prefix('$').unwrap_or(pattern);
+    let mut normalized = String::new();
+    let mut in_brackets = false;
+    for c in pattern.chars() {
+        match c {
+            '[' => {
+                in_brackets = true;
+                normalized += ".[]";
+            }
+            ']' => in_brackets = false,
+            _ if in_brackets => {}
+            _ => normalized.push(c),
+        }
+    }
+    normalized
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+fn json_string(text: &str) -> String {
+    serde_json::to_string(text).expect("string serialization")
+}
+
+/// String value with its location in the file
+pub(super) struct Value<'a> {
+    pub(super) path: Vec<&'a str>,
+    pub(super) text: String,
+    /// Range of the value including its quotes, if any
+    range: Range<usize>,
+}
+
+//
+// JSON
+//
+
+fn json_values(content: &str) -> Vec<Value<'_>> {
+    struct Scanner<'a> {
+        content: &'a str,
+        pos: usize,
+        path: Vec<&'a str>,
+        values: Vec<Value<'a>>,
+    }
+
+    impl<'a> Scanner<'a> {
+        fn skip_whitespace(&mut self) {
+            let rest = &self.content[self.pos..];
+            self.pos += rest.len() - rest.trim_start().len();
+        }
+
+        fn peek(&self) -> Option<char> {
+            self.content[self.pos..].chars().next()
+        }
+
+        /// Returns the range of a string literal starting at the current position, including quotes
+        fn string(&mut self) -> Option<Range<usize>> {
+            let start = self.pos;
+            let mut escaped = false;
+            for (idx, c) in self.content[start + 1..].char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        self.pos = start + 1 + idx + 1;
+                        return Some(start..self.pos);
+                    }
+                    _ => {}
+                }
+            }
+            None
+        }
+
+        fn value(&mut self) -> Option<()> {
+            self.skip_whitespace();
+            match self.peek()? {
+                '{' => {
+                    self.pos += 1;
+                    loop {
+                        self.skip_whitespace();
+                        match self.peek()? {
+                            '}' => {
+                                self.pos += 1;
+                                return Some(());
+                            }
+                            ',' => self.pos += 1,
+                            '"' => {
+                                let key = self.string()?;
+                                self.skip_whitespace();
+                                if self.peek()? != ':' {
+                                    return None;
+                                }
+                                self.pos += 1;
+                                self.path.push(&self.content[key.start + 1..key.end - 1]);
+                                self.value()?;
+                                self.path.pop();
+                            }
+                            _ => return None,
+                        }
+                    }
+                }
+                '[' => {
+                    self.pos += 1;
+                    self.path.push(ITEM_SEGMENT);
+                    loop {
+                        self.skip_whitespace();
+                        match self.peek()? {
+                            ']' => {
+                                self.pos += 1;
+                                self.path.pop();
+                                return Some(());
+                            }
+                            ',' => self.pos += 1,
+                            _ => self.value()?,
+                        }
+                    }
+                }
+                '"' => {
+                    let range = self.string()?;
+                    let text = serde_json::from_str::<String>(&self.content[range.clone()]).ok()?;
+                    self.values.push(Value { path: self.path.clone(), text, range });
+                    Some(())
+                }
+                _ => {
+                    // Numbers, booleans and nulls
+                    let rest = &self.content[self.pos..];
+                    let len = rest.find([',', '}', ']', ' ', '\t', '\r', '\n']).unwrap_or(rest.len());
+                    if len == 0 {
+                        return None;
+                    }
+                    self.pos += len;
+                    Some(())
+                }
+            }
+        }
+    }
+
+    let mut scanner = Scanner { content, pos: 0, path: vec![], values: vec![] };
+    if scanner.value().is_none() {
+        log::warn!("Malformed JSON at byte {}, values past it are left as is", scanner.pos);
+    }
+    scanner.values
+}
+
+//
+// YAML, block style with single-line scalars only
+//
+
+pub(super) fn yaml_values(content: &str) -> Vec<Value<'_>> {
+    let mut values = vec![];
+    // Indentation and path segment of each enclosing mapping key or sequence item
+    let mut stack = Vec::<(usize, &str)>::new();
+    // Lines indented deeper than this belong to a block scalar or a multi-line flow value
+    let mut skip_deeper_than = None;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end();
+        let trimmed = line.trim_start();
+        let mut indent = line.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("---") || trimmed.starts_with("...") {
+            continue;
+        }
+        match skip_deeper_than {
+            Some(block_indent) if indent > block_indent => continue,
+            _ => skip_deeper_than = None,
+        }
+
+        let mut rest = trimmed;
+        while let Some(item) = rest.strip_prefix("- ").or_else(|| (rest == "-").then_some("")) {
+            stack.retain(|(i, _)| *i < indent);
+            stack.push((indent, ITEM_SEGMENT));
+            let item_trimmed = item.trim_start();
+            indent += rest.len() - item_trimmed.len();
+            rest = item_trimmed;
+        }
+        stack.retain(|(i, _)| *i < indent);
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match yaml_key(rest) {
+            Some((key, value)) => (Some(key), value),
+            None => (None, rest),
+        };
+        let value_start = line_start + (line.len() - value.len());
+
+        let mut path = stack.iter().map(|(_, s)| *s).collect::<Vec<_>>();
+        if let Some(key) = key {
+            if value.is_empty() || value.starts_with('#') {
+                // Nested mapping or sequence follows
+                stack.push((indent, key));
+                continue;
+            }
+            path.push(key);
+        }
+
+        let scalar = match value.chars().next() {
+            Some('"') => value
+                .rfind('"')
+                .filter(|&end| end > 0)
+                .and_then(|end| serde_json::from_str::<String>(&value[..=end]).ok().map(|text| (text, end + 1))),
+            Some('\'') => value
+                .rfind('\'')
+                .filter(|&end| end > 0)
+                .map(|end| (value[1..end].replace("''", "'"), end + 1)),
+            Some('|' | '>' | '{' | '[' | '&' | '*' | '!') => {
+                skip_deeper_than = Some(indent);
+                None
+            }
+            _ => {
+                let end = value.find(" #").unwrap_or(value.len());
+                let text = value[..end].trim_end();
+                Some((text.to_owned(), text.len()))
+            }
+        };
+        if let Some((text, len)) = scalar {
+            values.push(Value { path, text, range: value_start..value_start + len });
+        }
+    }
+    values
+}
+
+/// Splits `key: value` into the key (unquoted) and the value, which may be empty
+fn yaml_key(s: &str) -> Option<(&str, &str)> {
+    let (key, rest) = match s.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = s[1..].find(quote)? + 1;
+            (&s[1..end], &s[end + 1..])
+        }
+        _ => {
+            let end = s.find(": ").or_else(|| s.ends_with(':').then(|| s.len() - 1))?;
+            (&s[..end], &s[end..])
+        }
+    };
+    let value = rest.strip_prefix(':')?;
+    if !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+    Some((key.trim(), value.trim_start()))
+}
+
+//
+// CSV
+//
+
+fn csv_values(content: &str) -> Vec<Value<'_>> {
+    let mut rows = Vec::<Vec<(Range<usize>, String)>>::new();
+    let mut row = vec![];
+    let mut pos = 0;
+    let bytes = content.as_bytes();
+    while pos <= content.len() {
+        let start = pos;
+        let text = if bytes.get(pos) == Some(&b'"') {
+            let mut text = String::new();
+            pos += 1;
+            loop {
+                let Some(end) = content[pos..].find('"').map(|idx| pos + idx) else {
+                    log::warn!("Unterminated quoted CSV field at byte {start}, values past it are left as is");
+                    pos = content.len();
+                    break;
+                };
+                text += &content[pos..end];
+                pos = end + 1;
+                if bytes.get(pos) == Some(&b'"') {
+                    text.push('"');
+                    pos += 1;
+                } else {
+                    break;
+                }
+            }
+            text
+        } else {
+            let end = content[pos..].find([',', '\n', '\r']).map_or(content.len(), |idx| pos + idx);
+            let text = content[pos..end].to_owned();
+            pos = end;
+            text
+        };
+        row.push((start..pos, text));
+
+        match bytes.get(pos) {
+            Some(b',') => pos += 1,
+            Some(b'\r') if bytes.get(pos + 1) == Some(&b'\n') => {
+                rows.push(std::mem::take(&mut row));
+                pos += 2;
+            }
+            Some(b'\r' | b'\n') => {
+                rows.push(std::mem::take(&mut row));
+                pos += 1;
+            }
+            _ => {
+                rows.push(std::mem::take(&mut row));
+                break;
+            }
+        }
+    }
+
+    let mut rows = rows.into_iter();
+    let Some(header) = rows.next() else { return vec![] };
+    let header = header.into_iter().map(|(range, _)| &content[range]).map(|h| h.trim_matches('"')).collect::<Vec<_>>();
+    rows.flat_map(|row| {
+        row.into_iter()
+            .zip(header.iter())
+            .map(|((range, text), column)| Value { path: vec![*column], text, range })
+            .collect::<Vec<_>>()
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate_all(format: &DataFormat, content: &str, f: impl Fn(&str) -> String) -> String {
+        let mut result = content.to_owned();
+        let mut messages = format.messages(content);
+        messages.reverse();
+        for msg in messages {
+            for range in msg.targets {
+                result.replace_range(range, &format.escape(&f(&msg.text)));
+            }
+        }
+        result
+    }
+
+    fn format(kind: DataKind, keys: &[&str]) -> DataFormat {
+        DataFormat { kind, keys: keys.iter().map(|k| k.to_string()).collect() }
+    }
+
+    #[test]
+    fn json_keeps_structure() {
+        let content = r#"{
+  "id": "p-1",
+  "products": [ {"title": "Red \"chair\"", "price": 10.5, "tags": ["wood"]} ],
+  "description":"Comfy"
+}"#;
+        let format = format(DataKind::Json, &["$.products[*].title", "description"]);
+        assert_eq!(
+            translate_all(&format, content, |s| s.to_uppercase()),
+            content.replace(r#"Red \"chair\""#, r#"RED \"CHAIR\""#).replace("Comfy", "COMFY")
+        );
+    }
+
+    #[test]
+    fn yaml_keeps_structure() {
+        let content = "# Catalog\nproducts:\n  - title: Red chair # best seller\n    sku: RC-1\n  - title: 'Blue: table'\nnote: |\n  title: not a key\n";
+        let format = format(DataKind::Yaml, &["products.*.title"]);
+        assert_eq!(
+            translate_all(&format, content, |s| s.to_uppercase()),
+            "# Catalog\nproducts:\n  - title: RED CHAIR # best seller\n    sku: RC-1\n  - title: \"BLUE: TABLE\"\nnote: |\n  title: not a key\n"
+        );
+    }
+
+    #[test]
+    fn csv_translates_selected_columns() {
+        let content = "sku,name,description\nRC-1,Chair,\"Red, comfy\"\r\nBT-2,Table,Blue\n";
+        let format = format(DataKind::Csv, &["description"]);
+        assert_eq!(
+            translate_all(&format, content, |s| s.to_uppercase()),
+            "sku,name,description\nRC-1,Chair,\"RED, COMFY\"\r\nBT-2,Table,BLUE\n"
+        );
+    }
+}
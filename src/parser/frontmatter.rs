@@ -0,0 +1,158 @@
+use super::data::{yaml_values, ITEM_SEGMENT};
+use crate::{TranslationConfig, TranslationError};
+
+use anyhow::anyhow;
+use serde_json::{Map, Value};
+
+/// Top-level front matter key holding the settings, e.g.
+/// ```yaml
+/// ---
+/// title: Guide
+/// rosetta:
+///   dst_lang: German
+///   subject: Database administration
+///   headings_first: true
+/// ---
+/// ```
+const ROSETTA_KEY: &str = "rosetta";
+
+/// Config pre-set by the document itself.
+#[derive(Debug, Clone)]
+pub struct FrontMatterConfig {
+    pub cfg: TranslationConfig,
+    /// Keys that were set
+    pub applied: Vec<String>,
+    /// Keys not matching any setting
+    pub ignored: Vec<String>,
+}
+
+/// Applies the settings in the `rosetta` block of a Markdown document's YAML front matter over `base`.
+/// Returns `None` if there is no such block.
+pub fn read_config(markdown: &str, base: &TranslationConfig) -> Result<Option<FrontMatterConfig>, TranslationError> {
+    let Some(front_matter) = front_matter(markdown) else {
+        return Ok(None);
+    };
+
+    let mut settings = Map::new();
+    for value in yaml_values(front_matter) {
+        if let [ROSETTA_KEY, path @ ..] = value.path.as_slice()
+            && !path.is_empty()
+        {
+            insert(&mut settings, path, value.text);
+        }
+    }
+    if settings.is_empty() {
+        return Ok(None);
+    }
+
+    let base = serde_json::to_value(base).map_err(|e| TranslationError::OtherError(e.into()))?;
+    let Value::Object(mut cfg) = base else {
+        unreachable!("config is a struct");
+    };
+    let mut applied = vec![];
+    let mut ignored = vec![];
+    for (key, value) in settings {
+        match cfg.get_mut(&key) {
+            Some(current) => {
+                *current = coerce(value, current);
+                applied.push(key);
+            }
+            None => ignored.push(key),
+        }
+    }
+    let cfg = serde_json::from_value(Value::Object(cfg))
+        .map_err(|e| TranslationError::OtherError(anyhow!("Invalid {ROSETTA_KEY} settings in front matter: {e}")))?;
+
+    Ok(Some(FrontMatterConfig { cfg, applied, ignored }))
+}
+
+/// Text between the `---` line opening the document and the `---` or `...` line closing the block
+fn front_matter(markdown: &str) -> Option<&str> {
+    let markdown = markdown.strip_prefix('\u{feff}').unwrap_or(markdown);
+    let rest = markdown.strip_prefix("---")?;
+    let rest = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n'))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some(&rest[..offset]);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn insert(map: &mut Map<String, Value>, path: &[&str], text: String) {
+    let [key, rest @ ..] = path else {
+        return;
+    };
+    match rest {
+        [] => {
+            map.insert(key.to_string(), Value::String(text));
+        }
+        [ITEM_SEGMENT] => {
+            let value = map.entry(key.to_string()).or_insert_with(|| Value::Array(vec![]));
+            if let Value::Array(items) = value {
+                items.push(Value::String(text));
+            }
+        }
+        _ => {
+            let value = map.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(nested) = value {
+                insert(nested, rest, text);
+            }
+        }
+    }
+}
+
+/// YAML scalars come as text, typed according to the value they replace
+fn coerce(value: Value, current: &Value) -> Value {
+    match (value, current) {
+        (Value::String(text), Value::Bool(_)) => text.parse().map_or(Value::String(text), Value::Bool),
+        (Value::String(text), Value::Number(_) | Value::Null) => match text.as_str() {
+            "~" | "null" => Value::Null,
+            _ => text.parse::<u64>().map_or(Value::String(text), Value::from),
+        },
+        (Value::Object(map), Value::Object(current)) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = match current.get(&key) {
+                        Some(current) => coerce(value, current),
+                        None => value,
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        (value, _) => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_from_front_matter() {
+        let markdown = "---\n\
+            title: Guide\n\
+            rosetta:\n  \
+              dst_lang: German\n  \
+              subject: \"Database administration\"\n  \
+              headings_first: true\n  \
+              seed: 42\n  \
+              model: gpt-4o\n\
+            ---\n\
+            \n\
+            # Guide\n";
+        let result = read_config(markdown, &TranslationConfig::default()).unwrap().unwrap();
+        assert_eq!(result.cfg.dst_lang, "German");
+        assert_eq!(result.cfg.subject, "Database administration");
+        assert!(result.cfg.headings_first);
+        assert_eq!(result.cfg.seed, Some(42));
+        assert_eq!(result.cfg.src_lang, TranslationConfig::default().src_lang);
+        assert_eq!(result.ignored, vec!["model"]);
+
+        assert!(read_config("# Guide\n", &TranslationConfig::default()).unwrap().is_none());
+        assert!(read_config("---\ntitle: Guide\n---\n", &TranslationConfig::default()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,511 @@
+use super::{split_paragraph, MarkdownSection, Parser};
+use crate::utils::read_to_string_lossy;
+use crate::ParseError;
+
+use regex::Regex;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+static ANDROID_STRING_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<(?:string|item)(\s[^>]*)?>(.*?)</(?:string|item)>").expect("valid regex")
+});
+
+/// Software localization resource formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalizationFormat {
+    /// GNU gettext `.po`/`.pot`, only entries with an empty `msgstr` are translated
+    Po,
+    /// Java `.properties`
+    Properties,
+    /// Android `res/values*/strings.xml`
+    AndroidXml,
+    /// iOS/macOS `.strings`
+    IosStrings,
+}
+
+/// Translatable text found in a file, with the ranges its translation should be written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub text: String,
+    pub targets: Vec<Range<usize>>,
+}
+
+/// File format where only some of the text is translatable and the rest has to be kept byte-for-byte.
+pub trait MessageFormat: Clone {
+    fn messages(&self, content: &str) -> Vec<Message>;
+
+    /// Encodes the translated text to be written to the message target
+    fn escape(&self, text: &str) -> String;
+}
+
+impl LocalizationFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_string_lossy().to_lowercase();
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "po" | "pot" => Some(LocalizationFormat::Po),
+            "properties" => Some(LocalizationFormat::Properties),
+            "xml" if file_name.starts_with("strings") => Some(LocalizationFormat::AndroidXml),
+            "strings" => Some(LocalizationFormat::IosStrings),
+            _ => None,
+        }
+    }
+}
+
+impl MessageFormat for LocalizationFormat {
+    fn messages(&self, content: &str) -> Vec<Message> {
+        match self {
+            LocalizationFormat::Po => po_messages(content),
+            LocalizationFormat::Properties => properties_messages(content),
+            LocalizationFormat::AndroidXml => android_messages(content),
+            LocalizationFormat::IosStrings => ios_messages(content),
+        }
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self {
+            LocalizationFormat::Po => format!("\"{}\"", escape_c_like(text)),
+            LocalizationFormat::Properties => {
+                let escaped = escape_c_like(text).replace("\\\"", "\"");
+                // Leading whitespace would be stripped otherwise
+                match escaped.strip_prefix(' ') {
+                    Some(rest) => format!("\\ {rest}"),
+                    None => escaped,
+                }
+            }
+            LocalizationFormat::AndroidXml => escape_android(text),
+            LocalizationFormat::IosStrings => escape_c_like(text),
+        }
+    }
+}
+
+/// Parses software localization and data files, one message per section.
+/// Keys, comments and the file structure are left to [crate::generator::localization].
+pub struct LocalizationParser<F> {
+    pub max_section_len: usize,
+    pub format: F,
+}
+
+impl<F: MessageFormat> Parser for LocalizationParser<F> {
+    fn max_section_len(&self) -> usize {
+        self.max_section_len
+    }
+
+    fn strict_placeholders(&self) -> bool {
+        true
+    }
+
+    async fn parse(&self, input: &Path) -> Result<Vec<MarkdownSection>, ParseError> {
+        let content = read_to_string_lossy(input)
+            .await
+            .map_err(|e| ParseError::OtherError(e.into()))?;
+
+        Ok(self
+            .format
+            .messages(&content)
+            .into_iter()
+            .map(|msg| split_paragraph(&msg.text, self.max_section_len))
+            .collect())
+    }
+}
+
+fn escape_c_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Unescapes `\n`, `\t`, `\r`, `\uXXXX` and `\X` for any other X
+fn unescape_c_like(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('u' | 'U') => {
+                let hex = chars.by_ref().take(4).collect::<String>();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => result.push_str(&hex),
+                }
+            }
+            Some(c) => result.push(c),
+            None => {}
+        }
+    }
+    result
+}
+
+//
+// gettext
+//
+
+fn po_messages(content: &str) -> Vec<Message> {
+    #[derive(Default)]
+    struct Entry {
+        msgid: String,
+        msgid_plural: Option<String>,
+        /// Plural index (0 for a singular) and range of each `msgstr` value
+        msgstrs: Vec<(usize, Range<usize>, String)>,
+    }
+
+    /// Value being read, possibly continued on the following lines
+    enum Field {
+        Msgid,
+        MsgidPlural,
+        Msgstr(usize),
+        Other,
+    }
+
+    let mut entries = Vec::<Entry>::new();
+    let mut field = Field::Other;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end();
+        let trimmed = line.trim_start();
+        let (keyword, value) = if trimmed.starts_with('"') {
+            (None, trimmed)
+        } else if let Some((keyword, value)) = trimmed.split_once(char::is_whitespace) {
+            (Some(keyword), value.trim_start())
+        } else {
+            field = Field::Other;
+            continue;
+        };
+        let Some(quoted) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+            field = Field::Other;
+            continue;
+        };
+        let value_start = line_start + (line.len() - value.len());
+        let value_end = line_start + line.len();
+        let text = unescape_c_like(quoted);
+
+        if let Some(keyword) = keyword {
+            field = match keyword {
+                "msgid" => {
+                    entries.push(Entry::default());
+                    Field::Msgid
+                }
+                "msgid_plural" => Field::MsgidPlural,
+                "msgstr" => Field::Msgstr(0),
+                _ => match keyword.strip_prefix("msgstr[").and_then(|k| k.strip_suffix(']')) {
+                    Some(idx) => Field::Msgstr(idx.parse().unwrap_or(0)),
+                    None => Field::Other,
+                },
+            };
+            if let (Field::Msgstr(idx), Some(entry)) = (&field, entries.last_mut()) {
+                entry.msgstrs.push((*idx, value_start..value_end, "".to_owned()));
+            }
+        }
+
+        let Some(entry) = entries.last_mut() else { continue };
+        match field {
+            Field::Msgid => entry.msgid += &text,
+            Field::MsgidPlural => *entry.msgid_plural.get_or_insert_default() += &text,
+            Field::Msgstr(_) => {
+                let (_, range, msgstr) = entry.msgstrs.last_mut().expect("msgstr");
+                range.end = value_end;
+                *msgstr += &text;
+            }
+            Field::Other => {}
+        }
+    }
+
+    let mut messages = vec![];
+    for entry in entries {
+        // Header entry has an empty msgid, already translated entries are left as they are
+        if entry.msgid.is_empty() || entry.msgstrs.iter().any(|(_, _, msgstr)| !msgstr.is_empty()) {
+            continue;
+        }
+        let (singular, plural): (Vec<_>, Vec<_>) = entry.msgstrs.into_iter().partition(|(idx, _, _)| *idx == 0);
+        messages.push(Message {
+            text: entry.msgid,
+            targets: singular.into_iter().map(|(_, range, _)| range).collect(),
+        });
+        if let Some(msgid_plural) = entry.msgid_plural
+            && !plural.is_empty()
+        {
+            messages.push(Message {
+                text: msgid_plural,
+                targets: plural.into_iter().map(|(_, range, _)| range).collect(),
+            });
+        }
+    }
+    messages
+}
+
+//
+// Java properties
+//
+
+fn properties_messages(content: &str) -> Vec<Message> {
+    let mut messages = vec![];
+    let mut offset = 0;
+    let mut lines = content.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim_start();
+        if trimmed.trim_end().is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+            continue;
+        }
+
+        // Logical line continues while the physical one ends with an odd number of backslashes
+        let mut logical_end = line_start + line.trim_end().len();
+        let mut current = line;
+        while current.trim_end().chars().rev().take_while(|&c| c == '\\').count() % 2 == 1 {
+            let Some(next) = lines.next() else { break };
+            logical_end = offset + next.trim_end().len();
+            offset += next.len();
+            current = next;
+        }
+        let logical = &content[line_start..logical_end];
+
+        let key_start = logical.len() - logical.trim_start().len();
+        let mut key_end = logical.len();
+        let mut escaped = false;
+        for (idx, c) in logical.char_indices().skip_while(|(idx, _)| *idx < key_start) {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '=' | ':' | ' ' | '\t' => {
+                    key_end = idx;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let rest = logical[key_end..].trim_start_matches([' ', '\t']);
+        let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest).trim_start_matches([' ', '\t']);
+        let value_start = logical.len() - rest.len();
+
+        let text = unescape_c_like(&join_continuations(rest));
+        if !text.trim().is_empty() {
+            let value = line_start + value_start..logical_end;
+            messages.push(Message { text, targets: vec![value] });
+        }
+    }
+    messages
+}
+
+/// Removes backslash-newline continuations along with the leading whitespace of the next line
+fn join_continuations(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut physical_lines = value.split('\n').peekable();
+    while let Some(line) = physical_lines.next() {
+        let line = line.trim_end_matches('\r');
+        if physical_lines.peek().is_some() {
+            result += line.strip_suffix('\\').unwrap_or(line);
+        } else {
+            result += line;
+        }
+        if let Some(next) = physical_lines.peek_mut() {
+            *next = next.trim_start();
+        }
+    }
+    result
+}
+
+//
+// Android
+//
+
+fn android_messages(content: &str) -> Vec<Message> {
+    let mut messages = vec![];
+    for caps in ANDROID_STRING_REGEX.captures_iter(content) {
+        let attrs = caps.get(1).map_or("", |m| m.as_str());
+        let inner = caps.get(2).expect("inner text");
+        if attrs.contains(r#"translatable="false""#) {
+            continue;
+        }
+        let raw = inner.as_str();
+        // Resource references and non-text items, e.g. in integer arrays or styles
+        if raw.trim().starts_with('@') || !raw.chars().any(char::is_alphabetic) {
+            continue;
+        }
+        let unquoted = raw
+            .trim()
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap_or(raw);
+        messages.push(Message {
+            text: unescape_c_like(unquoted),
+            targets: vec![inner.range()],
+        });
+    }
+    messages
+}
+
+fn escape_android(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let escaped = if escaped.starts_with(['@', '?']) { format!("\\{escaped}") } else { escaped };
+
+    // Markup and entities are kept, but a stray ampersand would make the file invalid
+    let mut result = String::with_capacity(escaped.len());
+    for (idx, c) in escaped.char_indices() {
+        if c == '&' && !starts_with_entity(&escaped[idx..]) {
+            result += "&amp;";
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn starts_with_entity(s: &str) -> bool {
+    let Some(end) = s.find(';') else { return false };
+    let name = &s[1..end];
+    !name.is_empty()
+        && (name.chars().all(|c| c.is_ascii_alphanumeric())
+            || name.strip_prefix('#').is_some_and(|n| n.chars().all(|c| c.is_ascii_hexdigit() || c == 'x')))
+}
+
+//
+// iOS
+//
+
+fn ios_messages(content: &str) -> Vec<Message> {
+    /// Reads a quoted string starting at `pos`, returning the range of its content
+    fn read_string(content: &str, pos: usize) -> Option<Range<usize>> {
+        let mut escaped = false;
+        for (idx, c) in content[pos + 1..].char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Some(pos + 1..pos + 1 + idx),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn skip_whitespace_and_comments(content: &str, mut pos: usize) -> usize {
+        loop {
+            let rest = &content[pos..];
+            let trimmed = rest.trim_start();
+            pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("/*") {
+                pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else if trimmed.starts_with("//") {
+                pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                return pos;
+            }
+        }
+    }
+
+    let mut messages = vec![];
+    let mut pos = skip_whitespace_and_comments(content, 0);
+    while content[pos..].starts_with('"') {
+        let Some(key) = read_string(content, pos) else { break };
+        pos = skip_whitespace_and_comments(content, key.end + 1);
+        let Some(rest) = content[pos..].strip_prefix('=') else { break };
+        pos = skip_whitespace_and_comments(content, content.len() - rest.len());
+        if !content[pos..].starts_with('"') {
+            break;
+        }
+        let Some(value) = read_string(content, pos) else { break };
+        pos = skip_whitespace_and_comments(content, value.end + 1);
+        pos = content[pos..].strip_prefix(';').map_or(pos, |rest| content.len() - rest.len());
+        pos = skip_whitespace_and_comments(content, pos);
+
+        let text = unescape_c_like(&content[value.clone()]);
+        if !text.trim().is_empty() {
+            messages.push(Message { text, targets: vec![value] });
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(format: LocalizationFormat, content: &str) -> Vec<(String, Vec<&str>)> {
+        format
+            .messages(content)
+            .into_iter()
+            .map(|msg| (msg.text, msg.targets.into_iter().map(|r| &content[r]).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn po() {
+        let content = r#"msgid ""
+msgstr "Content-Type: text/plain; charset=UTF-8\n"
+
+#: main.c:10
+msgid "Hello, %s!"
+msgstr ""
+
+msgid "Done"
+msgstr "Fertig"
+
+msgid "One file"
+msgid_plural ""
+"%d files"
+msgstr[0] ""
+msgstr[1] ""
+"#;
+        assert_eq!(
+            texts(LocalizationFormat::Po, content),
+            vec![
+                ("Hello, %s!".to_owned(), vec![r#""""#]),
+                ("One file".to_owned(), vec![r#""""#]),
+                ("%d files".to_owned(), vec![r#""""#]),
+            ]
+        );
+    }
+
+    #[test]
+    fn properties() {
+        let content = "# Comment\ngreeting = Hello, {0}!\nmultiline: First \\\n    second\nempty=\n";
+        assert_eq!(
+            texts(LocalizationFormat::Properties, content),
+            vec![
+                ("Hello, {0}!".to_owned(), vec!["Hello, {0}!"]),
+                ("First second".to_owned(), vec!["First \\\n    second"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn android() {
+        let content = r#"<resources>
+    <string name="app_name" translatable="false">Rosetta</string>
+    <string name="greeting">Don\'t panic, %1$s</string>
+    <string name="ref">@string/greeting</string>
+</resources>"#;
+        assert_eq!(
+            texts(LocalizationFormat::AndroidXml, content),
+            vec![("Don't panic, %1$s".to_owned(), vec![r"Don\'t panic, %1$s"])]
+        );
+        assert_eq!(LocalizationFormat::AndroidXml.escape("Rock & \"roll\""), r#"Rock &amp; \"roll\""#);
+    }
+
+    #[test]
+    fn ios() {
+        let content = "/* Greeting */\n\"greeting\" = \"Hello, %@!\";\n// Comment\n\"quote\" = \"Say \\\"hi\\\"\";\n";
+        assert_eq!(
+            texts(LocalizationFormat::IosStrings, content),
+            vec![
+                ("Hello, %@!".to_owned(), vec!["Hello, %@!"]),
+                ("Say \"hi\"".to_owned(), vec!["Say \\\"hi\\\""]),
+            ]
+        );
+    }
+}
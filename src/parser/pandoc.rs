@@ -1,14 +1,13 @@
-use super::{MarkdownSection, MarkdownSubsection, Parser};
+use super::{split_paragraph_by_tokens, MarkdownSection, Parser};
+use crate::utils::{execute_pandoc, read_to_string_lossy};
 use crate::ParseError;
 
-use anyhow::anyhow;
-use pandoc::OutputKind;
-use regex::Regex;
 use std::path::Path;
-use tokio::fs;
 
 pub struct PandocParser {
     pub max_section_len: usize,
+    /// Token budget of the model, if it has one, see [crate::llm::LLMBuilder::max_section_tokens]
+    pub max_section_tokens: Option<usize>,
     pub skip_if_present: bool,
 }
 
@@ -24,48 +23,21 @@ impl Parser for PandocParser {
             if !output_path.exists() || !self.skip_if_present {
                 let output_path_clone = output_path.clone();
                 tokio::task::spawn_blocking(move || {
-                    let mut pandoc = pandoc::new();
-                    pandoc.add_input(&input);
-                    pandoc.set_output(OutputKind::File(output_path_clone));
-                    pandoc
-                        .execute()
-                        .map_err(|e| ParseError::OtherError(e.into()))
+                    execute_pandoc(&input, Some(&output_path_clone), |_| {}).map_err(ParseError::OtherError)
                 })
                 .await
                 .map_err(|e| ParseError::OtherError(e.into()))??;
             }
 
-            fs::read_to_string(output_path)
+            read_to_string_lossy(&output_path)
                 .await
                 .map_err(|e| ParseError::OtherError(e.into()))?
         };
 
-        let sentence_break_regex =
-            Regex::new(r"[.!?]\p{White_Space}+\p{Uppercase}").expect("valid regex");
-
         let mut sections = Vec::<MarkdownSection>::new();
 
         for s in markdown.split("\n\n") {
-            let mut s = s.trim();
-            let mut section = MarkdownSection::default();
-            while s.len() > self.max_section_len {
-                let min_break_point = self.max_section_len / 2;
-
-                let Some(m) = sentence_break_regex.find_at(s, min_break_point) else {
-                    return Err(ParseError::OtherError(anyhow!(
-                        "Could not find a suitable break point to split a section!"
-                    )));
-                };
-
-                let match_start = m.start() + 1; // Skip past the punctuation
-                section
-                    .0
-                    .push(MarkdownSubsection(s[..match_start].trim().to_owned()));
-                s = s[match_start..].trim();
-            }
-            if !s.is_empty() {
-                section.0.push(MarkdownSubsection(s.to_owned()));
-            }
+            let section = split_paragraph_by_tokens(s, self.max_section_len, self.max_section_tokens);
             if !section.0.is_empty() {
                 sections.push(section);
             }
@@ -78,21 +50,23 @@ impl Parser for PandocParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::MarkdownSubsection;
     use std::path::PathBuf;
     use tempfile::{tempdir, TempDir};
 
     fn create_temp_file_with_content(dir: &TempDir, content: &str) -> PathBuf {
         let file_path = dir.path().join("test.md");
-        fs::write(&file_path, content).unwrap();
+        std::fs::write(&file_path, content).unwrap();
         file_path
     }
 
-    #[test]
-    fn parse_valid_docx_file() {
+    #[tokio::test]
+    async fn parse_valid_docx_file() {
         let dir = tempdir().unwrap();
 
         let parser = PandocParser {
             max_section_len: 100,
+            max_section_tokens: None,
             skip_if_present: false,
         };
         let input_path = create_temp_file_with_content(
@@ -100,7 +74,7 @@ mod tests {
             "This is a test document.\nIt has multiple sentences.",
         );
 
-        let sections = parser.parse(&input_path).unwrap();
+        let sections = parser.parse(&input_path).await.unwrap();
 
         assert_eq!(sections.len(), 1);
         assert_eq!(
@@ -111,12 +85,13 @@ mod tests {
         );
     }
 
-    #[test]
-    fn parse_docx_file_with_long_section() {
+    #[tokio::test]
+    async fn parse_docx_file_with_long_section() {
         let dir = tempdir().unwrap();
 
         let parser = PandocParser {
             max_section_len: 60,
+            max_section_tokens: None,
             skip_if_present: false,
         };
         let input_path = create_temp_file_with_content(
@@ -124,7 +99,7 @@ mod tests {
             "This is a test document, just like that. It has multiple sentences.",
         );
 
-        let sections = parser.parse(&input_path).unwrap();
+        let sections = parser.parse(&input_path).await.unwrap();
 
         assert_eq!(sections.len(), 1);
         assert_eq!(sections[0].0.len(), 2);
@@ -135,18 +110,19 @@ mod tests {
         assert_eq!(sections[0].0[1].0, "It has multiple sentences.");
     }
 
-    #[test]
-    fn parse_docx_file_with_multiple_sections() {
+    #[tokio::test]
+    async fn parse_docx_file_with_multiple_sections() {
         let dir = tempdir().unwrap();
 
         let parser = PandocParser {
             max_section_len: 60,
+            max_section_tokens: None,
             skip_if_present: false,
         };
         let input_path =
             create_temp_file_with_content(&dir, "This is a test document.\n\nIt has two sections.");
 
-        let sections = parser.parse(&input_path).unwrap();
+        let sections = parser.parse(&input_path).await.unwrap();
 
         assert_eq!(sections.len(), 2);
         assert_eq!(sections[0].0.len(), 1);
@@ -155,33 +131,60 @@ mod tests {
         assert_eq!(sections[1].0[0].0, "It has two sections.");
     }
 
-    #[test]
-    fn parse_docx_file_with_no_break_point() {
+    #[tokio::test]
+    async fn parse_docx_file_with_no_break_point() {
         let dir = tempdir().unwrap();
 
         let parser = PandocParser {
             max_section_len: 10,
+            max_section_tokens: None,
             skip_if_present: false,
         };
         let input_path =
             create_temp_file_with_content(&dir, "Thisisaverylongwordwithoutbreakpoints.");
 
-        let result = parser.parse(&input_path);
+        let sections = parser.parse(&input_path).await.unwrap();
 
-        assert!(result.is_err());
+        assert_eq!(sections.len(), 1);
+        assert!(sections[0].0.iter().all(|ss| ss.0.len() <= 10));
+        assert_eq!(
+            sections[0].0.iter().map(|ss| ss.0.as_str()).collect::<String>(),
+            "Thisisaverylongwordwithoutbreakpoints."
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_file_with_non_ascii_path() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("Документы 文件");
+        std::fs::create_dir(&nested).unwrap();
+        let input_path = nested.join("Отчёт 报告.md");
+        std::fs::write(&input_path, "Это тестовый документ.").unwrap();
+
+        let parser = PandocParser {
+            max_section_len: 100,
+            max_section_tokens: None,
+            skip_if_present: false,
+        };
+
+        let sections = parser.parse(&input_path).await.unwrap();
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].0[0].0, "Это тестовый документ.");
     }
 
-    #[test]
-    fn parse_empty_docx_file() {
+    #[tokio::test]
+    async fn parse_empty_docx_file() {
         let dir = tempdir().unwrap();
 
         let parser = PandocParser {
             max_section_len: 100,
+            max_section_tokens: None,
             skip_if_present: false,
         };
         let input_path = create_temp_file_with_content(&dir, "");
 
-        let sections = parser.parse(&input_path).unwrap();
+        let sections = parser.parse(&input_path).await.unwrap();
 
         assert_eq!(sections.len(), 0);
     }
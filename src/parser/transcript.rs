@@ -0,0 +1,110 @@
+use super::{split_paragraph, MarkdownSection, MarkdownSubsection, Parser};
+use crate::utils::read_to_string_lossy;
+use crate::ParseError;
+
+use regex::Regex;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Optional timestamp followed by an optional speaker name, e.g. `[00:12:03] Maria: ` or `IVAN: `
+static SPEAKER_LABEL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:\[(?:\d{1,2}:)?\d{1,2}:\d{2}(?:[.,]\d{1,3})?\]\p{White_Space}*)?(?:\p{Uppercase}[\p{L}\p{N} .'-]{0,40}?:(?:\p{White_Space}+|$))?")
+        .expect("valid regex")
+});
+
+/// Parses plain text transcripts of meetings and interviews, one utterance per section.
+/// Speaker labels and timestamps are kept in the source text, see [split_speaker_label].
+pub struct TranscriptParser {
+    pub max_section_len: usize,
+}
+
+impl Parser for TranscriptParser {
+    fn max_section_len(&self) -> usize {
+        self.max_section_len
+    }
+
+    async fn parse(&self, input: &Path) -> Result<Vec<MarkdownSection>, ParseError> {
+        let text = read_to_string_lossy(input)
+            .await
+            .map_err(|e| ParseError::OtherError(e.into()))?;
+        Ok(parse_transcript(&text, self.max_section_len))
+    }
+}
+
+/// Splits a line into a speaker label (possibly empty, including trailing whitespace) and the utterance.
+pub fn split_speaker_label(line: &str) -> (&str, &str) {
+    let label_len = SPEAKER_LABEL_REGEX.find(line).map_or(0, |m| m.end());
+    line.split_at(label_len)
+}
+
+/// Removes the speaker label from an utterance section, leaving only the text to be translated.
+pub fn strip_speaker_label(section: &MarkdownSection) -> MarkdownSection {
+    let mut section = section.clone();
+    if let Some(first) = section.0.first_mut() {
+        first.0 = split_speaker_label(&first.0).1.to_owned();
+    }
+    section.0.retain(|ss| !ss.0.is_empty());
+    section
+}
+
+/// Utterance starts with a labeled line and continues until the next labeled or empty line.
+fn parse_transcript(text: &str, max_section_len: usize) -> Vec<MarkdownSection> {
+    let mut utterances = Vec::<(String, String)>::new();
+    let mut in_utterance = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            in_utterance = false;
+            continue;
+        }
+        let (label, text) = split_speaker_label(line);
+        match utterances.last_mut() {
+            Some((_, current)) if in_utterance && label.is_empty() => {
+                current.push(' ');
+                current.push_str(text);
+            }
+            _ => utterances.push((label.to_owned(), text.to_owned())),
+        }
+        in_utterance = true;
+    }
+
+    let mut sections = vec![];
+    for (label, text) in utterances {
+        let mut section = split_paragraph(&text, max_section_len);
+        match section.0.first_mut() {
+            Some(first) => first.0.insert_str(0, &label),
+            None => section.0.push(MarkdownSubsection(label.trim_end().to_owned())),
+        }
+        sections.push(section);
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speaker_labels() {
+        assert_eq!(split_speaker_label("IVAN: Hello."), ("IVAN: ", "Hello."));
+        assert_eq!(split_speaker_label("[00:12:03] Maria: Hi."), ("[00:12:03] Maria: ", "Hi."));
+        assert_eq!(split_speaker_label("[12:03.5] Where?"), ("[12:03.5] ", "Where?"));
+        assert_eq!(split_speaker_label("just text: really"), ("", "just text: really"));
+    }
+
+    #[test]
+    fn utterances_keep_labels() {
+        let text = "[00:00:01] IVAN: Good morning.\nLet's start.\n\nMaria: Sure.\nIVAN:";
+        let sections = parse_transcript(text, 100);
+        assert_eq!(
+            sections,
+            vec![
+                MarkdownSection(vec![MarkdownSubsection("[00:00:01] IVAN: Good morning. Let's start.".to_owned())]),
+                MarkdownSection(vec![MarkdownSubsection("Maria: Sure.".to_owned())]),
+                MarkdownSection(vec![MarkdownSubsection("IVAN:".to_owned())]),
+            ]
+        );
+        assert_eq!(strip_speaker_label(&sections[0]).0[0].0, "Good morning. Let's start.");
+        assert!(strip_speaker_label(&sections[2]).0.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::cache::{Cache, SqliteCache};
+use crate::parser::{MarkdownSection, MarkdownSubsection, Parser};
+use crate::utils::log_preview;
+use crate::{PartialExport, SendProgress, TranslationConfig, TranslationError};
+
+use anyhow::anyhow;
+use config::Config;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+
+/// Exchange format for reviewing translations outside of Rosetta, e.g. side-by-side in an editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewFormat {
+    /// Array of [ReviewEntry], can be edited and imported back once marked as reviewed
+    Json,
+    /// Two-column Markdown table, export only
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    /// Not translated yet
+    Pending,
+    /// Translated, as found in cache
+    Translated,
+    /// Checked by a reviewer, only these are imported back
+    Reviewed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    pub section: usize,
+    pub subsection: usize,
+    pub source: String,
+    pub target: Option<String>,
+    pub status: ReviewStatus,
+}
+
+/// Exports all subsections of the input alongside their cached translations.
+pub async fn export_review(
+    settings: &Config,
+    input: &Path,
+    output: &Path,
+    cfg: &TranslationConfig,
+    review_path: &Path,
+    format: ReviewFormat,
+) -> Result<(), TranslationError> {
+    let sections = crate::default_parser(settings)
+        .parse(input)
+        .await
+        .map_err(TranslationError::ParseError)?;
+
+    let mut cache = SqliteCache::new(&output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)?;
+    let entries = review_entries(sections, &mut cache).await?;
+
+    let content = match format {
+        ReviewFormat::Json => serde_json::to_string_pretty(&entries)
+            .map_err(|e| TranslationError::OtherError(e.into()))?,
+        ReviewFormat::Markdown => to_markdown_table(cfg, &entries),
+    };
+    fs::write(review_path, content).await?;
+
+    Ok(())
+}
+
+/// Subsections of the document alongside their cached translations
+async fn review_entries(
+    sections: Vec<MarkdownSection>,
+    cache: &mut impl Cache,
+) -> Result<Vec<ReviewEntry>, TranslationError> {
+    let mut entries = vec![];
+    for (section_idx, section) in sections.into_iter().enumerate() {
+        for (subsection_idx, subsection) in section.0.into_iter().enumerate() {
+            let target = cache.get(&subsection).await?.map(|ss| ss.0);
+            let status = if target.is_some() { ReviewStatus::Translated } else { ReviewStatus::Pending };
+            entries.push(ReviewEntry {
+                section: section_idx,
+                subsection: subsection_idx,
+                source: subsection.0,
+                target,
+                status,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Applies translations marked as reviewed in a JSON review file back into the cache,
+/// then re-generates the output from it if any of them changed.
+///
+/// Returns the number of changed translations.
+pub async fn import_review(
+    settings: Config,
+    input: &Path,
+    output: &Path,
+    cfg: &TranslationConfig,
+    review_path: &Path,
+    send_progress: impl SendProgress + 'static,
+) -> Result<usize, TranslationError> {
+    let content = fs::read_to_string(review_path).await?;
+    let entries: Vec<ReviewEntry> = serde_json::from_str(&content)
+        .map_err(|e| TranslationError::OtherError(anyhow!("Malformed review file: {e}")))?;
+
+    let mut cache = SqliteCache::new(&output.with_extension("sqlite"), &cfg.src_lang, &cfg.dst_lang)?;
+    let updated = apply_reviewed(entries, &mut cache).await?;
+    drop(cache);
+
+    if updated > 0 {
+        // Translations are all in the cache by now, except for the sections that weren't translated yet
+        crate::translate(settings, input, output, cfg.clone(), send_progress, PartialExport::default()).await?;
+    }
+    Ok(updated)
+}
+
+/// Puts translations of the reviewed entries into the cache, returns the number of those that changed
+async fn apply_reviewed(entries: Vec<ReviewEntry>, cache: &mut impl Cache) -> Result<usize, TranslationError> {
+    let mut updated = 0;
+    for entry in entries {
+        let Some(target) = entry.target.filter(|t| !t.trim().is_empty()) else {
+            continue;
+        };
+        let src = MarkdownSubsection(entry.source);
+        let cached = cache.get(&src).await?;
+        if cached.as_ref().is_some_and(|cached| cached.0 == target) {
+            continue;
+        }
+        if entry.status != ReviewStatus::Reviewed {
+            log::warn!("Translation of {} was edited but not marked as reviewed, skipping it", log_preview(&src.0));
+            continue;
+        }
+        cache.upsert(src, MarkdownSubsection(target)).await?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+fn to_markdown_table(cfg: &TranslationConfig, entries: &[ReviewEntry]) -> String {
+    fn escape_cell(s: &str) -> String {
+        s.replace('|', "\\|").replace('\n', "<br>")
+    }
+
+    let mut result = format!("| # | {} | {} | Status |\n|---|---|---|---|\n", cfg.src_lang, cfg.dst_lang);
+    for entry in entries {
+        result += &format!(
+            "| {}.{} | {} | {} | {:?} |\n",
+            entry.section,
+            entry.subsection,
+            escape_cell(&entry.source),
+            escape_cell(entry.target.as_deref().unwrap_or_default()),
+            entry.status
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn review_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = SqliteCache::new(&dir.path().join("book.sqlite"), "English", "Russian").unwrap();
+        let subsection = |s: &str| MarkdownSubsection(s.to_owned());
+        cache.insert(subsection("Hello"), subsection("Привет")).await.unwrap();
+        cache.insert(subsection("Bye"), subsection("Пока")).await.unwrap();
+        let sections = vec![
+            MarkdownSection(vec![subsection("Hello"), subsection("Bye")]),
+            MarkdownSection(vec![subsection("Thanks")]),
+        ];
+
+        let exported = review_entries(sections, &mut cache).await.unwrap();
+        let statuses = exported.iter().map(|e| e.status).collect::<Vec<_>>();
+        assert_eq!(statuses, [ReviewStatus::Translated, ReviewStatus::Translated, ReviewStatus::Pending]);
+        let json = serde_json::to_string_pretty(&exported).unwrap();
+
+        let mut edited: Vec<ReviewEntry> = serde_json::from_str(&json).unwrap();
+        // Fixed and marked as reviewed
+        edited[0].target = Some("Здравствуйте".to_owned());
+        edited[0].status = ReviewStatus::Reviewed;
+        // Fixed, but not marked as reviewed
+        edited[1].target = Some("До свидания".to_owned());
+        // Marked as reviewed as it is
+        edited[2].target = None;
+        edited[2].status = ReviewStatus::Reviewed;
+        let json = serde_json::to_string_pretty(&edited).unwrap();
+
+        let imported: Vec<ReviewEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(apply_reviewed(imported.clone(), &mut cache).await.unwrap(), 1);
+        assert_eq!(cache.get(&subsection("Hello")).await.unwrap(), Some(subsection("Здравствуйте")));
+        assert_eq!(cache.get(&subsection("Bye")).await.unwrap(), Some(subsection("Пока")));
+        assert_eq!(cache.get(&subsection("Thanks")).await.unwrap(), None);
+
+        // Nothing changes once the review is applied
+        assert_eq!(apply_reviewed(imported, &mut cache).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::llm::{LLMBuilder, LLM};
+use crate::parser::{self, MarkdownSection, Parser};
+use crate::utils::detect_language;
+use crate::{masking, LanguagePolicy, SendProgress, TranslationConfig, TranslationError, DEFAULT_MAX_SECTION_LEN};
+
+use config::Config;
+use itertools::Itertools;
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Sections in a sample if `sampling.sections` isn't set
+pub const DEFAULT_SAMPLE_SIZE: usize = 3;
+
+/// Section translated as a part of a sample, along with its source
+#[derive(Debug, Clone)]
+pub struct SampleSection {
+    /// Index of the section in the document
+    pub section: usize,
+    pub source: String,
+    pub translation: String,
+}
+
+/// Number of sections in a sample, set by `sampling.sections`
+pub fn sample_size(settings: &Config) -> usize {
+    settings.get_int("sampling.sections").ok().filter(|&n| n > 0).map_or(DEFAULT_SAMPLE_SIZE, |n| n as usize)
+}
+
+/// Translates `sample_size` random sections of the document, to have the translation approved before the full run.
+/// Nothing is cached or written, so that a rejected sample can be tried again once the instructions
+/// or settings are tweaked. Sections are returned in document order.
+pub async fn translate_sample(
+    settings: Config,
+    input: &Path,
+    cfg: TranslationConfig,
+    sample_size: usize,
+    send_progress: impl SendProgress + 'static,
+) -> Result<Vec<SampleSection>, TranslationError> {
+    let sections = parse(&settings, input, &cfg).await?;
+
+    // Hashes of a fresh random state shuffle the sections differently every time
+    let random = RandomState::new();
+    let sample = sections
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, section)| {
+            // Speaker labels aren't translated, same as in the full run
+            let prepared = match cfg.transcript {
+                true => parser::transcript::strip_speaker_label(section),
+                false => section.clone(),
+            };
+            let (masked, spans) = masking::mask(&prepared, &cfg.no_translate_markers);
+            let detected_lang = match cfg.language_policy {
+                LanguagePolicy::TranslateAll => None,
+                _ => detect_language(&masked.0.iter().map(|ss| &ss.0).join("\n")),
+            };
+            let kept_as_is = crate::is_passthrough(&masked, detected_lang, &cfg)
+                || masked.0.iter().all(|ss| ss.is_annotation());
+            (!kept_as_is).then_some((idx, masked, spans))
+        })
+        .sorted_by_key(|(idx, _, _)| random.hash_one(idx))
+        .take(sample_size)
+        .sorted_by_key(|(idx, _, _)| *idx)
+        .collect_vec();
+    if sample.is_empty() {
+        return Err(TranslationError::OtherError(anyhow::anyhow!("Document has nothing to translate")));
+    }
+
+    let events: Arc<dyn SendProgress> = Arc::new(send_progress);
+    let mut llm = crate::translation_llm_builder(&settings)?
+        .build(cfg.clone(), events.clone())
+        .await
+        .map_err(TranslationError::LLMError)?;
+    let mut translated = Vec::with_capacity(sample.len());
+    let mut result = Ok(());
+    for (idx, masked, spans) in sample {
+        if events.is_stopped() {
+            result = Err(TranslationError::OtherError(anyhow::anyhow!("Sampling was stopped")));
+            break;
+        }
+        match llm.translate(&masked).await {
+            Ok(translation) => translated.push(SampleSection {
+                section: idx,
+                source: text(&masking::strip_markers(&sections[idx], &cfg.no_translate_markers)),
+                translation: text(&masking::unmask(translation, &spans)),
+            }),
+            Err(e) => {
+                result = Err(TranslationError::LLMError(e));
+                break;
+            }
+        }
+    }
+    if let Err(e) = llm.close().await {
+        log::warn!("Failed to close the LLM: {}", e);
+    }
+    result.map(|()| translated)
+}
+
+/// Sections of the document as the full run would parse them
+async fn parse(
+    settings: &Config,
+    input: &Path,
+    cfg: &TranslationConfig,
+) -> Result<Vec<MarkdownSection>, TranslationError> {
+    let sections = if cfg.transcript {
+        let parser = parser::transcript::TranscriptParser { max_section_len: DEFAULT_MAX_SECTION_LEN };
+        parser.parse(input).await
+    } else if let Some(format) = parser::localization::LocalizationFormat::from_path(input) {
+        let parser = parser::localization::LocalizationParser { max_section_len: DEFAULT_MAX_SECTION_LEN, format };
+        parser.parse(input).await
+    } else if let Some(format) = parser::data::DataFormat::from_path(input, cfg.data_keys.clone()) {
+        let parser = parser::localization::LocalizationParser { max_section_len: DEFAULT_MAX_SECTION_LEN, format };
+        parser.parse(input).await
+    } else {
+        crate::default_parser(settings).parse(input).await
+    };
+    sections.map_err(TranslationError::ParseError)
+}
+
+fn text(section: &MarkdownSection) -> String {
+    section.0.iter().filter(|ss| !ss.is_annotation()).map(|ss| &ss.0).join("\n\n")
+}
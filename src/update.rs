@@ -0,0 +1,64 @@
+use crate::TranslationError;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/frozenspider/rosetta/releases/latest";
+
+/// Release newer than the running version, to be downloaded by the user from its page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Looks up the latest GitHub release, None if the running version is up to date.
+/// Nothing is downloaded, it's up to the user to install the release.
+pub async fn check_for_update(current_version: &str) -> Result<Option<Release>, TranslationError> {
+    let client = reqwest::Client::builder()
+        // GitHub API rejects requests without one
+        .user_agent(format!("rosetta/{current_version}"))
+        .build()
+        .map_err(|e| TranslationError::OtherError(anyhow!("Update check failed: {e}")))?;
+    let release: GitHubRelease = client
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| TranslationError::OtherError(anyhow!("Update check failed: {e}")))?
+        .json()
+        .await
+        .map_err(|e| TranslationError::OtherError(anyhow!("Malformed GitHub release: {e}")))?;
+
+    let version = release.tag_name.trim_start_matches('v').to_owned();
+    Ok(is_newer(&version, current_version).then_some(Release { version, url: release.html_url }))
+}
+
+/// Compares dot-separated versions number by number, pre-release suffixes are ignored
+fn is_newer(version: &str, current_version: &str) -> bool {
+    let numbers = |v: &str| {
+        let release = v.split(['-', '+']).next().unwrap_or_default();
+        release.split('.').map(|n| n.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>()
+    };
+    numbers(version) > numbers(current_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("0.10.0", "0.9.1"));
+        assert!(is_newer("0.2.1", "0.2.0"));
+        assert!(!is_newer("0.2.0", "0.2.0"));
+        assert!(!is_newer("0.2.0-beta", "0.2.0"));
+        assert!(!is_newer("0.1.9", "0.2.0"));
+    }
+}
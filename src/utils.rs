@@ -1,4 +1,21 @@
+pub mod secrets;
+
+use anyhow::Context;
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub(crate) static SENTENCE_BREAK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[.!?]\p{White_Space}+\p{Uppercase}").expect("valid regex"));
+
+/// printf-style (`%s`, `%1$d`, `%@`) and brace-style (`{0}`, `{name}`) placeholders
+static PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"%(?:\d+\$)?[-+#0]*\d*(?:\.\d+)?(?:hh|h|ll|l|z|j|t)?[@diuxXofFeEgGcsSp%]|\{\w*\}")
+        .expect("valid regex")
+});
 
 pub fn substr_up_to_len(s: &str, max_len: usize) -> String {
     if s.len() > max_len {
@@ -7,3 +24,250 @@ pub fn substr_up_to_len(s: &str, max_len: usize) -> String {
         s.to_owned()
     }
 }
+
+/// First non-blank line of the text, for logging
+pub fn first_line(s: &str) -> &str {
+    s.lines().find(|l| !l.trim().is_empty()).unwrap_or_default()
+}
+
+/// Bidirectional text controls, which reorder the rest of the terminal line
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Quoted first line of the text for logging, annotated with the language of the text if it's detected.
+/// Bidi controls are dropped and the line is cut at [MAX_LOG_SRC_LEN](crate::MAX_LOG_SRC_LEN) terminal columns,
+/// CJK characters taking two of them, so that RTL and CJK previews stay readable.
+pub fn log_preview(s: &str) -> String {
+    let line = first_line(s)
+        .trim()
+        .chars()
+        .filter(|&c| !is_bidi_control(c))
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
+    let mut preview = String::new();
+    let mut width = 0;
+    for grapheme in line.graphemes(true) {
+        width += grapheme.width();
+        if width > crate::MAX_LOG_SRC_LEN {
+            preview.push('…');
+            break;
+        }
+        preview += grapheme;
+    }
+    match detect_language(s) {
+        Some(lang) => format!(r#""{preview}" ({lang})"#),
+        None => format!(r#""{preview}""#),
+    }
+}
+
+/// Reads a text file, replacing invalid UTF-8 sequences rather than failing on them.
+pub async fn read_to_string_lossy(path: &Path) -> std::io::Result<String> {
+    let bytes = tokio::fs::read(path).await?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("{} is not valid UTF-8, replacing invalid bytes", path.display());
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    })
+}
+
+/// Detects the language of the text, returning its English name (e.g. "Russian").
+/// Returns `None` if the text is too short or ambiguous for a reliable guess.
+pub fn detect_language(s: &str) -> Option<&'static str> {
+    whatlang::detect(s)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().eng_name())
+}
+
+/// Checks whether a user-supplied language name (or ISO 639-3 code) denotes the given language.
+pub fn is_same_language(user_lang: &str, lang_eng_name: &str) -> bool {
+    let user_lang = user_lang.trim();
+    user_lang.eq_ignore_ascii_case(lang_eng_name)
+        || whatlang::Lang::from_code(user_lang.to_lowercase())
+            .is_some_and(|lang| lang.eng_name() == lang_eng_name)
+}
+
+/// Splits text into sentences, treating a punctuation followed by a capitalized word as a break.
+pub fn split_sentences(s: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut start = 0;
+    for m in SENTENCE_BREAK_REGEX.find_iter(s) {
+        let end = m.start() + 1; // Include the punctuation
+        result.push(s[start..end].trim());
+        start = end;
+    }
+    let rest = s[start..].trim();
+    if !rest.is_empty() {
+        result.push(rest);
+    }
+    result
+}
+
+/// Estimates the number of tokens the text takes for GPT-like BPE tokenizers, without needing their vocabularies.
+/// Each CJK character and punctuation mark is counted as a token, as well as every 4 characters of ASCII words
+/// and every 2 characters of other words, which are split into more tokens.
+pub fn estimate_tokens(s: &str) -> usize {
+    let is_cjk = |c: char| {
+        matches!(c,
+            '\u{1100}'..='\u{11FF}' | '\u{2E80}'..='\u{9FFF}' | '\u{AC00}'..='\u{D7AF}' | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF00}'..='\u{FFEF}' | '\u{20000}'..='\u{2FFFF}')
+    };
+    s.split_word_bounds()
+        .map(|word| {
+            let chars = word.chars().count();
+            if word.chars().all(char::is_whitespace) {
+                0
+            } else if word.chars().any(is_cjk) || !word.chars().any(char::is_alphanumeric) {
+                chars
+            } else if word.is_ascii() {
+                chars.div_ceil(4)
+            } else {
+                chars.div_ceil(2)
+            }
+        })
+        .sum()
+}
+
+/// Checks whether the translation is (nearly) a verbatim copy of the source.
+/// Short texts are never considered echoes, since names and numbers legitimately stay the same.
+pub fn is_echo(src: &str, dst: &str) -> bool {
+    const MIN_WORDS: usize = 4;
+    const MAX_SIMILARITY: f64 = 0.8;
+
+    let words = |s: &str| s.unicode_words().map(|w| w.to_lowercase()).collect::<HashSet<_>>();
+    let src_words = words(src);
+    let dst_words = words(dst);
+    if src_words.len() < MIN_WORDS {
+        return false;
+    }
+
+    let common = src_words.intersection(&dst_words).count();
+    let total = src_words.union(&dst_words).count();
+    common as f64 / total as f64 >= MAX_SIMILARITY
+}
+
+/// Lists placeholders in the text, sorted, since translation may legitimately reorder them.
+pub fn placeholders(s: &str) -> Vec<&str> {
+    let mut result = PLACEHOLDER_REGEX.find_iter(s).map(|m| m.as_str()).collect::<Vec<_>>();
+    result.sort_unstable();
+    result
+}
+
+/// Inline links and images, `[text](url)` and `![alt](url)`
+static LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[[^\]]*\]\([^)]*\)").expect("valid regex"));
+
+/// Describes how the Markdown structure of the translation differs from the one of the source:
+/// heading levels, links and fenced code blocks. None if they match.
+pub fn markdown_mismatch(src: &str, dst: &str) -> Option<String> {
+    let heading_levels = |s: &str| {
+        s.lines()
+            .filter_map(|line| {
+                let level = line.chars().take_while(|&c| c == '#').count();
+                ((1..=6).contains(&level) && line[level..].starts_with(' ')).then_some(level)
+            })
+            .collect::<Vec<_>>()
+    };
+    let fences = |s: &str| {
+        s.lines()
+            .filter(|line| line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~"))
+            .count()
+    };
+    let links = |s: &str| LINK_REGEX.find_iter(s).count();
+
+    let mut issues = vec![];
+    let (src_headings, dst_headings) = (heading_levels(src), heading_levels(dst));
+    if src_headings != dst_headings {
+        issues.push(format!("heading levels {:?} instead of {:?}", dst_headings, src_headings));
+    }
+    if links(src) != links(dst) {
+        issues.push(format!("{} links instead of {}", links(dst), links(src)));
+    }
+    if fences(src) != fences(dst) {
+        issues.push(format!("{} code fences instead of {}", fences(dst), fences(src)));
+    }
+    (!issues.is_empty()).then(|| issues.join(", "))
+}
+
+/// Windows limit of path length for programs that aren't long path aware
+const MAX_PATH: usize = 260;
+
+/// Whether an external tool might fail to open the path on Windows:
+/// it might not be long path aware, and it might only handle characters of the ANSI code page.
+/// Our own file operations don't care, Rust adds `\\?\` prefix to long paths itself.
+pub fn is_fragile_path(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    cfg!(windows) && (path.encode_utf16().count() >= MAX_PATH || !path.is_ascii())
+}
+
+/// Per-user directory for files of Rosetta itself, e.g. `~/.local/share/rosetta` on Linux.
+/// None if there's no home directory to put it in.
+pub fn data_dir() -> Option<PathBuf> {
+    let env_dir = |var: &str| std::env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env_dir("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME")?.join("Library/Application Support")
+    } else {
+        env_dir("XDG_DATA_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".local/share")))?
+    };
+    Some(base.join("rosetta"))
+}
+
+/// Writes the file through a temporary one next to it, so that it's never left half-written
+pub fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(content)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Runs pandoc converting `input` into `output`, or into a buffer if there's no output.
+/// If any of the paths is fragile (see [is_fragile_path]), pandoc works on copies in a temporary directory instead.
+pub fn execute_pandoc(
+    input: &Path,
+    output: Option<&Path>,
+    configure: impl FnOnce(&mut pandoc::Pandoc),
+) -> anyhow::Result<pandoc::PandocOutput> {
+    let staging_dir = match is_fragile_path(input) || output.is_some_and(is_fragile_path) {
+        true => Some(tempfile::tempdir().context("Couldn't create a directory for pandoc files")?),
+        false => None,
+    };
+    let staged = |path: &Path, name: &str| match staging_dir {
+        Some(ref dir) => match path.extension() {
+            Some(ext) => dir.path().join(name).with_extension(ext),
+            None => dir.path().join(name),
+        },
+        None => path.to_owned(),
+    };
+    let pandoc_input = staged(input, "input");
+    let pandoc_output = output.map(|output| staged(output, "output"));
+    if pandoc_input != input {
+        log::info!("Passing {} to pandoc through {}", input.display(), pandoc_input.display());
+        std::fs::copy(input, &pandoc_input).with_context(|| format!("Couldn't copy {} for pandoc", input.display()))?;
+    }
+
+    let mut pandoc = pandoc::new();
+    pandoc.add_input(&pandoc_input);
+    configure(&mut pandoc);
+    pandoc.set_output(match pandoc_output {
+        Some(ref path) => pandoc::OutputKind::File(path.clone()),
+        None => pandoc::OutputKind::Pipe,
+    });
+    let result = pandoc.execute().with_context(|| match output {
+        Some(output) => format!("pandoc couldn't convert {} into {}", input.display(), output.display()),
+        None => format!("pandoc couldn't convert {}", input.display()),
+    })?;
+
+    if let (Some(pandoc_output), Some(output)) = (pandoc_output, output)
+        && pandoc_output != output
+    {
+        std::fs::copy(&pandoc_output, output)
+            .with_context(|| format!("Couldn't copy pandoc output to {}", output.display()))?;
+    }
+    Ok(result)
+}
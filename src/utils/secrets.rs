@@ -0,0 +1,37 @@
+use crate::llm::Provider;
+
+use config::Config;
+
+/// Keyring entries are looked up under this service, with the settings section of the provider as the user,
+/// e.g. `rosetta` / `openai`
+pub const KEYRING_SERVICE: &str = "rosetta";
+
+/// Environment variable holding the API key of the provider, e.g. `OPENAI_API_KEY`
+pub fn env_var(provider: Provider) -> String {
+    format!("{}_API_KEY", provider.settings_section().to_uppercase())
+}
+
+/// API key of the provider from its `api_key` setting, then from the environment variable, then from the OS keyring.
+/// None if none of them has it.
+pub fn api_key(settings: &Config, provider: Provider) -> Option<String> {
+    let section = provider.settings_section();
+    let non_empty = |key: &String| !key.trim().is_empty();
+    settings
+        .get_string(&format!("{section}.api_key"))
+        .ok()
+        .filter(non_empty)
+        .or_else(|| std::env::var(env_var(provider)).ok().filter(non_empty))
+        .or_else(|| keyring_api_key(section))
+}
+
+/// Keyring that can't be accessed, e.g. with no secret service running on Linux, is treated as having no key
+fn keyring_api_key(section: &str) -> Option<String> {
+    match keyring::Entry::new(KEYRING_SERVICE, section).and_then(|entry| entry.get_password()) {
+        Ok(key) => Some(key).filter(|key| !key.trim().is_empty()),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            log::warn!("Couldn't read the {section} API key from the OS keyring: {e}");
+            None
+        }
+    }
+}